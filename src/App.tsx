@@ -161,7 +161,10 @@ const App: React.FC = () => {
     eventSource.onmessage = event => {
       try {
         const data = JSON.parse(event.data);
-        setProgress(data.progress);
+        // log lines without a percentage only carry a message
+        if (typeof data.progress === 'number') {
+          setProgress(data.progress);
+        }
         setMessage(data.message);
         setLogs(prev => [...prev, `[${new Date().toLocaleTimeString()}] ${data.message}`]);
 
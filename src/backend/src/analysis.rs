@@ -0,0 +1,367 @@
+use crate::config::PDF_MARKER_WINDOW;
+use crate::types::ExtractedStrings;
+use std::net::Ipv4Addr;
+
+pub fn byte_histogram(data: &[u8]) -> [usize; 256] {
+    let mut counts = [0usize; 256];
+    for &byte in data {
+        counts[byte as usize] += 1;
+    }
+    counts
+}
+
+// Shannon entropy in bits per byte, from 0 (constant) to 8 (uniformly random)
+pub fn histogram_entropy(counts: &[usize; 256]) -> f64 {
+    let len: usize = counts.iter().sum();
+    if len == 0 {
+        return 0.0;
+    }
+
+    let len = len as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            // rather than -p * log2(p), which gives -0 for a single byte value
+            p * (1.0 / p).log2()
+        })
+        .sum()
+}
+
+// share of 0x00 bytes, 0-1; high values mean padding that dilutes entropy
+pub fn histogram_zero_ratio(counts: &[usize; 256]) -> f64 {
+    let len: usize = counts.iter().sum();
+    if len == 0 {
+        return 0.0;
+    }
+    counts[0] as f64 / len as f64
+}
+
+pub fn entropy(data: &[u8]) -> f64 {
+    histogram_entropy(&byte_histogram(data))
+}
+
+const GUID_LENGTH: usize = 36;
+
+// finds 8-4-4-4-12 hex GUIDs across extracted strings, returned uppercased
+// and de-duplicated in order of appearance
+pub fn extract_guids(strings: &[String]) -> Vec<String> {
+    let mut guids = Vec::new();
+    for text in strings {
+        find_guids(text, &mut guids);
+    }
+    guids
+}
+
+fn find_guids(text: &str, guids: &mut Vec<String>) {
+    let bytes = text.as_bytes();
+
+    let mut i = 0;
+    while i + GUID_LENGTH <= bytes.len() {
+        let candidate = &bytes[i..i + GUID_LENGTH];
+        let bounded = (i == 0 || !bytes[i - 1].is_ascii_hexdigit())
+            && !matches!(bytes.get(i + GUID_LENGTH), Some(b) if b.is_ascii_hexdigit());
+        if bounded && is_guid(candidate) {
+            let guid = String::from_utf8_lossy(candidate).to_ascii_uppercase();
+            if !guids.contains(&guid) {
+                guids.push(guid);
+            }
+            i += GUID_LENGTH;
+        } else {
+            i += 1;
+        }
+    }
+}
+
+// how far apart the rotate, the combining op and the loop branch may be
+const HASH_LOOP_SPAN: usize = 32;
+
+fn is_register_modrm(byte: u8) -> bool {
+    byte & 0xc0 == 0xc0
+}
+
+// finds the shape of an API-name hashing loop in x86/x64 code, like ror13:
+// a rol/ror by a constant, an xor or add folding in the next character, and
+// a short backward jump closing the loop around both; returns the rotate's
+// position in `code`
+pub fn find_hash_loop(code: &[u8]) -> Option<usize> {
+    (0..code.len().saturating_sub(2)).find(|&i| {
+        // C1 /0 ib is rol, C1 /1 ib is ror
+        let rotate = code[i] == 0xc1
+            && is_register_modrm(code[i + 1])
+            && (code[i + 1] >> 3) & 7 <= 1
+            && (1..32).contains(&code[i + 2]);
+        if !rotate {
+            return false;
+        }
+
+        let end = (i + 3 + HASH_LOOP_SPAN).min(code.len().saturating_sub(1));
+        (i + 3..end).any(|j| {
+            // jcc rel8, loop rel8 or jmp rel8
+            let branch = matches!(code[j], 0x70..=0x7f | 0xe2 | 0xeb);
+            let Some(target) = (j + 2).checked_add_signed(code[j + 1] as i8 as isize) else {
+                return false;
+            };
+            if !branch || target > i || i - target > HASH_LOOP_SPAN {
+                return false;
+            }
+            // xor or add between registers, byte or dword sized
+            (target..j).any(|k| {
+                matches!(code[k], 0x00..=0x03 | 0x30..=0x33)
+                    && code.get(k + 1).copied().is_some_and(is_register_modrm)
+            })
+        })
+    })
+}
+
+// a PDF as readers see it: the header near the start, the end-of-file
+// marker near the end, and whatever sits around them ignored
+pub fn contains_pdf(data: &[u8]) -> bool {
+    let head = &data[..data.len().min(PDF_MARKER_WINDOW)];
+    let tail = &data[data.len().saturating_sub(PDF_MARKER_WINDOW)..];
+    head.windows(5).any(|w| w == b"%PDF-") && tail.windows(5).any(|w| w == b"%%EOF")
+}
+
+// finds dotted-quad IPv4 addresses across extracted strings, skipping
+// unspecified, loopback and broadcast ones, de-duplicated in order of appearance
+pub fn extract_ipv4s(strings: &[String]) -> Vec<String> {
+    let mut ips = Vec::new();
+    for text in strings {
+        for token in text.split(|c: char| !(c.is_ascii_digit() || c == '.')) {
+            let Ok(ip) = token.trim_matches('.').parse::<Ipv4Addr>() else {
+                continue;
+            };
+            if ip.is_unspecified() || ip.is_loopback() || ip.is_broadcast() {
+                continue;
+            }
+            let ip = ip.to_string();
+            if !ips.contains(&ip) {
+                ips.push(ip);
+            }
+        }
+    }
+    ips
+}
+
+// a GUID as written in a rules file, with or without braces, in the
+// uppercase form `extract_guids` reports
+pub fn normalize_guid(text: &str) -> Option<String> {
+    let guid = text.trim();
+    let guid = guid
+        .strip_prefix('{')
+        .and_then(|g| g.strip_suffix('}'))
+        .unwrap_or(guid);
+    (guid.len() == GUID_LENGTH && is_guid(guid.as_bytes())).then(|| guid.to_ascii_uppercase())
+}
+
+fn is_guid(candidate: &[u8]) -> bool {
+    candidate.iter().enumerate().all(|(pos, &b)| match pos {
+        8 | 13 | 18 | 23 => b == b'-',
+        _ => b.is_ascii_hexdigit(),
+    })
+}
+
+fn is_printable(byte: u8) -> bool {
+    byte == b'\t' || (0x20..=0x7e).contains(&byte)
+}
+
+// pulls printable ASCII runs and UTF-16LE runs of at least `min_len` characters
+pub fn extract_strings(data: &[u8], min_len: usize) -> ExtractedStrings {
+    let mut ascii = Vec::new();
+    let mut run_start = None;
+    for (i, &byte) in data.iter().enumerate() {
+        match (is_printable(byte), run_start) {
+            (true, None) => run_start = Some(i),
+            (false, Some(start)) => {
+                if i - start >= min_len {
+                    ascii.push(String::from_utf8_lossy(&data[start..i]).into_owned());
+                }
+                run_start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(start) = run_start {
+        if data.len() - start >= min_len {
+            ascii.push(String::from_utf8_lossy(&data[start..]).into_owned());
+        }
+    }
+
+    let mut wide = Vec::new();
+    let mut current = String::new();
+    let mut i = 0;
+    while i < data.len() {
+        if i + 1 < data.len() && is_printable(data[i]) && data[i + 1] == 0 {
+            current.push(data[i] as char);
+            i += 2;
+        } else {
+            if current.len() >= min_len {
+                wide.push(std::mem::take(&mut current));
+            } else {
+                current.clear();
+            }
+            i += 1;
+        }
+    }
+    if current.len() >= min_len {
+        wide.push(current);
+    }
+
+    ExtractedStrings { ascii, wide }
+}
+
+fn truncate(s: &str, max_len: usize) -> String {
+    if s.chars().count() <= max_len {
+        return s.to_string();
+    }
+    let mut truncated: String = s.chars().take(max_len).collect();
+    truncated.push('…');
+    truncated
+}
+
+// bounds what gets reported back to clients; callers keep the full set for
+// indicator matching
+pub fn cap_strings(
+    strings: &ExtractedStrings,
+    max_count: usize,
+    max_len: usize,
+) -> ExtractedStrings {
+    let cap = |list: &[String]| {
+        list.iter()
+            .take(max_count)
+            .map(|s| truncate(s, max_len))
+            .collect()
+    };
+    ExtractedStrings {
+        ascii: cap(&strings.ascii),
+        wide: cap(&strings.wide),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::noise;
+
+    // lodsb; test al, al; jz done; ror edx, 13; add edx, eax; jmp back
+    const ROR13_LOOP: &[u8] = &[
+        0xac, 0x84, 0xc0, 0x74, 0x07, 0xc1, 0xca, 0x0d, 0x01, 0xc2, 0xeb, 0xf4,
+    ];
+
+    #[test]
+    fn finds_a_ror13_hash_loop() {
+        assert_eq!(find_hash_loop(ROR13_LOOP), Some(5));
+
+        let mut code = vec![0x90; 40];
+        code.extend_from_slice(ROR13_LOOP);
+        code.push(0xc3);
+        assert_eq!(find_hash_loop(&code), Some(45));
+    }
+
+    #[test]
+    fn ignores_rotates_outside_a_loop() {
+        // ror edx, 13; add edx, eax; ret
+        assert_eq!(find_hash_loop(&[0xc1, 0xca, 0x0d, 0x01, 0xc2, 0xc3]), None);
+        // the branch jumps forward, so nothing loops
+        assert_eq!(
+            find_hash_loop(&[0xc1, 0xca, 0x0d, 0x01, 0xc2, 0xeb, 0x02]),
+            None
+        );
+        // a shift (C1 /4) isn't a rotate
+        assert_eq!(
+            find_hash_loop(&[0xac, 0xc1, 0xe2, 0x0d, 0x01, 0xc2, 0xeb, 0xf9]),
+            None
+        );
+        assert_eq!(find_hash_loop(&[]), None);
+        assert_eq!(find_hash_loop(&[0xc1]), None);
+    }
+
+    #[test]
+    fn extracts_public_ipv4s_once() {
+        let strings = vec![
+            "connect 10.0.0.5:443 then 10.0.0.5".to_string(),
+            "127.0.0.1 0.0.0.0 255.255.255.255 1.2.3".to_string(),
+            "host=8.8.8.8.".to_string(),
+        ];
+        assert_eq!(extract_ipv4s(&strings), vec!["10.0.0.5", "8.8.8.8"]);
+    }
+
+    #[test]
+    fn constant_data_has_no_entropy() {
+        assert_eq!(entropy(&[]), 0.0);
+        assert_eq!(entropy(&[0u8; 4096]), 0.0);
+        assert_eq!(entropy(b"a"), 0.0);
+    }
+
+    #[test]
+    fn random_data_approaches_eight_bits() {
+        let e = entropy(&noise(64 * 1024));
+        assert!(e > 7.99 && e <= 8.0, "{}", e);
+        // every byte value exactly once is exactly 8, two values evenly is 1
+        let all: Vec<u8> = (0..=255).collect();
+        assert_eq!(entropy(&all), 8.0);
+        assert_eq!(entropy(b"abababab"), 1.0);
+    }
+
+    fn wide(text: &str) -> Vec<u8> {
+        text.bytes().flat_map(|b| [b, 0]).collect()
+    }
+
+    #[test]
+    fn extracts_ascii_runs_of_the_minimum_length() {
+        let data = b"\x01abc\x00abcd\xfftab\there\x00tail";
+        let strings = extract_strings(data, 4);
+        assert_eq!(strings.ascii, ["abcd", "tab\there", "tail"]);
+        assert_eq!(extract_strings(b"", 4).ascii, Vec::<String>::new());
+    }
+
+    #[test]
+    fn extracts_utf16le_runs() {
+        let data = [
+            b"\x90\x90".to_vec(),
+            wide("http://evil.example"),
+            vec![0, 0],
+            wide("ab"),
+            vec![1, 0],
+            wide("last"),
+        ]
+        .concat();
+        let strings = extract_strings(&data, 4);
+        assert_eq!(strings.wide, ["http://evil.example", "last"]);
+        // every other byte is NUL, so nothing long enough reads as ASCII
+        assert!(strings.ascii.is_empty());
+    }
+
+    #[test]
+    fn capping_limits_count_and_length() {
+        let strings = ExtractedStrings {
+            ascii: (0..10).map(|i| format!("string {}", i)).collect(),
+            wide: vec!["x".repeat(20), "short".to_string()],
+        };
+        let capped = cap_strings(&strings, 3, 8);
+        assert_eq!(capped.ascii, ["string 0", "string 1", "string 2"]);
+        assert_eq!(
+            capped.wide,
+            [format!("{}…", "x".repeat(8)), "short".to_string()]
+        );
+        // the input is left whole for indicator matching
+        assert_eq!(strings.ascii.len(), 10);
+    }
+
+    #[test]
+    fn truncation_counts_characters_not_bytes() {
+        assert_eq!(truncate("héllo wörld", 5), "héllo…");
+        assert_eq!(truncate("héllo", 5), "héllo");
+        assert_eq!(truncate("", 0), "");
+        assert_eq!(truncate("a", 0), "…");
+    }
+
+    #[test]
+    fn zero_ratio_counts_null_bytes() {
+        assert_eq!(histogram_zero_ratio(&byte_histogram(&[])), 0.0);
+        assert_eq!(histogram_zero_ratio(&byte_histogram(&[0, 0, 0, 1])), 0.75);
+        assert_eq!(histogram_zero_ratio(&byte_histogram(&[0; 64])), 1.0);
+        assert_eq!(histogram_zero_ratio(&byte_histogram(b"MZ")), 0.0);
+    }
+}
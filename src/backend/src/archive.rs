@@ -0,0 +1,283 @@
+use crate::config::*;
+use crate::types::Threat;
+use std::io::{Cursor, Read};
+use zip::result::ZipError;
+use zip::ZipArchive;
+
+pub fn is_zip(data: &[u8]) -> bool {
+    data.starts_with(b"PK\x03\x04")
+}
+
+// readers locate a zip from its end of central directory record, so one
+// appended to another file still opens; an empty archive doesn't count. A
+// zip carrying a manifest is what a Java runtime loads as a JAR
+pub fn embedded_archive(data: &[u8]) -> Option<&'static str> {
+    let archive = ZipArchive::new(Cursor::new(data)).ok()?;
+    if archive.is_empty() {
+        return None;
+    }
+    if archive
+        .file_names()
+        .any(|name| name.eq_ignore_ascii_case("META-INF/MANIFEST.MF"))
+    {
+        Some("JAR")
+    } else {
+        Some("ZIP")
+    }
+}
+
+// shared across nested archives so a zip of zips can't multiply the caps
+#[derive(Default)]
+struct Budget {
+    entries: usize,
+    bytes: u64,
+}
+
+struct Walk<'a> {
+    scan: &'a dyn Fn(&[u8]) -> Vec<Threat>,
+    budget: Budget,
+    threats: Vec<Threat>,
+    encrypted: Vec<String>,
+}
+
+fn limit_exceeded(reason: String) -> Threat {
+    Threat {
+        threat_type: "Archive Limits Exceeded".to_string(),
+        details: format!("Stopped extracting: {}, possible zip bomb", reason),
+        severity: "suspicious".to_string(),
+        threat_id: "A001".to_string(),
+    }
+}
+
+impl Walk<'_> {
+    // returns false once a cap is hit, which ends the whole walk
+    fn archive(&mut self, data: &[u8], prefix: &str, depth: usize) -> Result<bool, String> {
+        let mut archive = ZipArchive::new(Cursor::new(data)).map_err(|e| e.to_string())?;
+
+        for i in 0..archive.len() {
+            self.budget.entries += 1;
+            if self.budget.entries > MAX_ARCHIVE_ENTRIES {
+                self.threats.push(limit_exceeded(format!(
+                    "more than {} entries",
+                    MAX_ARCHIVE_ENTRIES
+                )));
+                return Ok(false);
+            }
+
+            // the raw view reads metadata without trying to decrypt
+            let (path, is_dir) = match archive.by_index_raw(i) {
+                Ok(entry) => (format!("{}{}", prefix, entry.name()), entry.is_dir()),
+                Err(_) => continue,
+            };
+            if is_dir {
+                continue;
+            }
+
+            // sizes in the headers are attacker-controlled, so count what
+            // actually decompresses and stop one byte past the cap
+            let remaining = MAX_ARCHIVE_UNCOMPRESSED_SIZE - self.budget.bytes;
+            let mut member = Vec::new();
+            let read = match archive.by_index(i) {
+                Ok(entry) => entry.take(remaining + 1).read_to_end(&mut member),
+                Err(ZipError::UnsupportedArchive(ZipError::PASSWORD_REQUIRED)) => {
+                    self.encrypted.push(path);
+                    continue;
+                }
+                Err(e) => Err(e.into()),
+            };
+            if let Err(e) = read {
+                println!("Skipping unreadable archive member {}: {}", path, e);
+                continue;
+            }
+            if member.len() as u64 > remaining {
+                self.threats.push(limit_exceeded(format!(
+                    "more than {} bytes uncompressed",
+                    MAX_ARCHIVE_UNCOMPRESSED_SIZE
+                )));
+                return Ok(false);
+            }
+            self.budget.bytes += member.len() as u64;
+
+            if is_zip(&member) && depth < MAX_ARCHIVE_DEPTH {
+                // a member that only looks like a zip is scanned as a plain file
+                match self.archive(&member, &format!("{}/", path), depth + 1) {
+                    Ok(true) => continue,
+                    Ok(false) => return Ok(false),
+                    Err(_) => {}
+                }
+            }
+
+            for mut threat in (self.scan)(&member) {
+                threat.details = format!("{}: {}", path, threat.details);
+                self.threats.push(threat);
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+// runs `scan` over every member, descending into nested zips, and returns
+// the members' threats with each member path prefixed to its details
+pub fn scan_archive(
+    data: &[u8],
+    scan: &dyn Fn(&[u8]) -> Vec<Threat>,
+) -> Result<Vec<Threat>, String> {
+    let mut walk = Walk {
+        scan,
+        budget: Budget::default(),
+        threats: Vec::new(),
+        encrypted: Vec::new(),
+    };
+    walk.archive(data, "", 1)?;
+
+    if !walk.encrypted.is_empty() {
+        walk.threats.push(Threat {
+            threat_type: "Encrypted Archive Member".to_string(),
+            details: format!(
+                "Password-protected members could not be scanned: {}",
+                walk.encrypted.join(", ")
+            ),
+            severity: "suspicious".to_string(),
+            threat_id: "A002".to_string(),
+        });
+    }
+
+    Ok(walk.threats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::zip_archive;
+    use std::io::Write;
+    use zip::write::FileOptions;
+    use zip::{CompressionMethod, ZipWriter};
+
+    // flags every member containing "EVIL"
+    fn scan(member: &[u8]) -> Vec<Threat> {
+        if !member.windows(4).any(|w| w == b"EVIL") {
+            return vec![];
+        }
+        vec![Threat {
+            threat_type: "Test".to_string(),
+            details: "found EVIL".to_string(),
+            severity: "malicious".to_string(),
+            threat_id: "T001".to_string(),
+        }]
+    }
+
+    fn details(threats: &[Threat]) -> Vec<&str> {
+        threats.iter().map(|t| t.details.as_str()).collect()
+    }
+
+    #[test]
+    fn recognizes_zips_and_jars() {
+        let zip = zip_archive(&[("a.txt", b"a")]);
+        assert!(is_zip(&zip));
+        assert_eq!(embedded_archive(&zip), Some("ZIP"));
+        let jar = zip_archive(&[("META-INF/MANIFEST.MF", b"Manifest-Version: 1.0\n")]);
+        assert_eq!(embedded_archive(&jar), Some("JAR"));
+
+        // found from the end of the data, whatever precedes it
+        let appended = [b"MZ not a zip".to_vec(), zip].concat();
+        assert!(!is_zip(&appended));
+        assert_eq!(embedded_archive(&appended), Some("ZIP"));
+
+        assert_eq!(embedded_archive(&zip_archive(&[])), None);
+        assert_eq!(embedded_archive(b"PK\x03\x04 truncated"), None);
+    }
+
+    #[test]
+    fn nested_members_are_scanned_with_their_path() {
+        let inner = zip_archive(&[("payload.exe", b"MZ...EVIL..."), ("readme.txt", b"fine")]);
+        let outer = zip_archive(&[
+            ("docs/", b""),
+            ("docs/inner.zip", &inner),
+            ("top.bin", b"EVIL"),
+        ]);
+        let threats = scan_archive(&outer, &scan).unwrap();
+        assert_eq!(
+            details(&threats),
+            [
+                "docs/inner.zip/payload.exe: found EVIL",
+                "top.bin: found EVIL"
+            ]
+        );
+        assert!(scan_archive(b"PK\x03\x04 truncated", &scan).is_err());
+    }
+
+    #[test]
+    fn nesting_stops_at_the_depth_limit() {
+        let mut archive = zip_archive(&[("deep.bin", b"EVIL")]);
+        for level in 1..=MAX_ARCHIVE_DEPTH {
+            archive = zip_archive(&[(&format!("level{}.zip", level), &archive)]);
+        }
+        // the innermost zip is past the limit, so it's scanned as one file
+        let threats = scan_archive(&archive, &scan).unwrap();
+        assert_eq!(
+            details(&threats),
+            ["level3.zip/level2.zip/level1.zip: found EVIL"]
+        );
+    }
+
+    #[test]
+    fn too_many_entries_stop_the_walk() {
+        let names: Vec<String> = (0..=MAX_ARCHIVE_ENTRIES)
+            .map(|i| format!("{}.txt", i))
+            .collect();
+        let mut files: Vec<(&str, &[u8])> = names.iter().map(|n| (n.as_str(), &b""[..])).collect();
+        files.push(("last.bin", b"EVIL"));
+        let threats = scan_archive(&zip_archive(&files), &scan).unwrap();
+        assert_eq!(threats.len(), 1);
+        assert_eq!(threats[0].threat_id, "A001");
+        assert_eq!(threats[0].severity, "suspicious");
+        assert_eq!(
+            threats[0].details,
+            format!(
+                "Stopped extracting: more than {} entries, possible zip bomb",
+                MAX_ARCHIVE_ENTRIES
+            )
+        );
+    }
+
+    #[test]
+    fn zip_bombs_stop_at_the_size_cap() {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        writer.start_file("zeros.bin", options).unwrap();
+        let chunk = vec![0u8; 1024 * 1024];
+        for _ in 0..=MAX_ARCHIVE_UNCOMPRESSED_SIZE / chunk.len() as u64 {
+            writer.write_all(&chunk).unwrap();
+        }
+        writer.start_file("after.bin", options).unwrap();
+        writer.write_all(b"EVIL").unwrap();
+        let bomb = writer.finish().unwrap().into_inner();
+        assert!(bomb.len() < 1024 * 1024);
+
+        let threats = scan_archive(&bomb, &scan).unwrap();
+        assert_eq!(threats.len(), 1);
+        assert_eq!(threats[0].threat_id, "A001");
+        assert!(threats[0].details.contains("bytes uncompressed"));
+    }
+
+    #[test]
+    fn encrypted_members_are_flagged_not_fatal() {
+        let mut archive = zip_archive(&[("secret.bin", b"EVIL"), ("open.bin", b"EVIL")]);
+        // set the encrypted bit on the first member's local and central headers
+        let central = archive.windows(4).position(|w| w == b"PK\x01\x02").unwrap();
+        archive[6] |= 1;
+        archive[central + 8] |= 1;
+
+        let threats = scan_archive(&archive, &scan).unwrap();
+        assert_eq!(
+            details(&threats),
+            [
+                "open.bin: found EVIL",
+                "Password-protected members could not be scanned: secret.bin"
+            ]
+        );
+        assert_eq!(threats[1].threat_id, "A002");
+        assert_eq!(threats[1].severity, "suspicious");
+    }
+}
@@ -0,0 +1,103 @@
+use crate::config::*;
+use crate::types::ApiError;
+use crate::utils::{error_response, Cors};
+use std::env;
+use tiny_http::Request;
+
+// optional shared-key auth; with no key configured every request is allowed
+pub struct Auth {
+    api_key: Option<String>,
+    protect_reads: bool,
+}
+
+impl Auth {
+    pub fn from_env() -> Self {
+        let api_key = env::var(API_KEY_ENV).ok();
+        // reads stay protected unless explicitly opened up
+        let protect_reads = !matches!(
+            env::var(PROTECT_READS_ENV).as_deref(),
+            Ok("0") | Ok("false") | Ok("no")
+        );
+        Auth::new(api_key, protect_reads)
+    }
+
+    // an empty key counts as none
+    pub fn new(api_key: Option<String>, protect_reads: bool) -> Self {
+        Auth {
+            api_key: api_key.filter(|key| !key.is_empty()),
+            protect_reads,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.api_key.is_some()
+    }
+
+    // `read_only` marks the SSE and result routes, which can be left open
+    pub fn allows(&self, request: &Request, read_only: bool) -> bool {
+        let key = request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("X-API-Key"))
+            .map(|h| h.value.as_str());
+        self.allows_key(key, read_only)
+    }
+
+    // `allows` for a request that didn't come through tiny_http, given its
+    // X-API-Key header if it sent one
+    pub fn allows_key(&self, key: Option<&str>, read_only: bool) -> bool {
+        let Some(expected) = &self.api_key else {
+            return true;
+        };
+        if read_only && !self.protect_reads {
+            return true;
+        }
+        key.is_some_and(|key| constant_time_eq(key.as_bytes(), expected.as_bytes()))
+    }
+}
+
+// avoids leaking how much of the key matched through response timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+pub fn respond_unauthorized(request: Request, cors: &Cors) {
+    let error = ApiError::new(401, "UNAUTHORIZED", "Missing or invalid API key");
+    let _ = request.respond(error_response(&error, cors));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compares_whole_keys_only() {
+        assert!(constant_time_eq(b"s3cret", b"s3cret"));
+        assert!(constant_time_eq(b"", b""));
+        assert!(!constant_time_eq(b"s3cret", b"s3creT"));
+        assert!(!constant_time_eq(b"s3cret", b"s3cre"));
+        assert!(!constant_time_eq(b"s3cre", b"s3cret"));
+        assert!(!constant_time_eq(b"", b"s3cret"));
+    }
+
+    #[test]
+    fn only_a_non_empty_key_enables_auth() {
+        assert!(Auth::new(Some("s3cret".to_string()), true).is_enabled());
+        assert!(!Auth::new(Some(String::new()), true).is_enabled());
+        assert!(!Auth::new(None, false).is_enabled());
+    }
+
+    #[test]
+    fn reads_can_be_left_open() {
+        let auth = Auth::new(Some("s3cret".to_string()), false);
+        assert!(auth.allows_key(Some("s3cret"), false));
+        assert!(!auth.allows_key(Some("wrong"), false));
+        assert!(!auth.allows_key(None, false));
+        assert!(auth.allows_key(None, true));
+
+        let auth = Auth::new(Some("s3cret".to_string()), true);
+        assert!(!auth.allows_key(None, true));
+        assert!(auth.allows_key(Some("s3cret"), true));
+        assert!(Auth::new(None, true).allows_key(None, false));
+    }
+}
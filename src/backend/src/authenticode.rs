@@ -0,0 +1,190 @@
+// just enough DER walking to pull certificate names out of an Authenticode
+// PKCS#7 SignedData blob; no signature or chain verification happens here
+
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_CONTEXT_0: u8 = 0xa0;
+
+// subjects used by Windows test-signing tooling
+const TEST_CERT_MARKERS: &[&str] = &["WDKTestCert", "Windows Test Signing"];
+
+struct Tlv<'a> {
+    tag: u8,
+    content: &'a [u8],
+    raw: &'a [u8],
+}
+
+// reads one tag-length-value, returning it and the bytes after it
+fn read_tlv(data: &[u8]) -> Option<(Tlv<'_>, &[u8])> {
+    let tag = *data.first()?;
+    let first_len = *data.get(1)?;
+
+    let (len, header_len) = if first_len < 0x80 {
+        (first_len as usize, 2)
+    } else {
+        // long form; indefinite lengths (0x80) aren't valid DER
+        let count = (first_len & 0x7f) as usize;
+        if count == 0 || count > 4 {
+            return None;
+        }
+        let bytes = data.get(2..2 + count)?;
+        let len = bytes.iter().fold(0usize, |acc, &b| (acc << 8) | b as usize);
+        (len, 2 + count)
+    };
+
+    let end = header_len.checked_add(len)?;
+    let raw = data.get(..end)?;
+    Some((
+        Tlv {
+            tag,
+            content: &raw[header_len..],
+            raw,
+        },
+        &data[end..],
+    ))
+}
+
+fn children(data: &[u8]) -> Vec<Tlv<'_>> {
+    let mut items = Vec::new();
+    let mut rest = data;
+    while let Some((tlv, next)) = read_tlv(rest) {
+        items.push(tlv);
+        rest = next;
+    }
+    items
+}
+
+struct CertificateNames<'a> {
+    issuer: &'a [u8],
+    subject: &'a [u8],
+}
+
+fn certificate_names<'a>(certificate: &Tlv<'a>) -> Option<CertificateNames<'a>> {
+    let (tbs, _) = read_tlv(certificate.content)?;
+    let mut fields = children(tbs.content).into_iter();
+
+    // skip the optional explicit version, the serial number and the signature algorithm
+    if fields.next()?.tag == TAG_CONTEXT_0 {
+        fields.next()?;
+    }
+    fields.next()?;
+    let issuer = fields.next()?;
+    let _validity = fields.next()?;
+    let subject = fields.next()?;
+
+    Some(CertificateNames {
+        issuer: issuer.raw,
+        subject: subject.raw,
+    })
+}
+
+fn embedded_certificates(pkcs7: &[u8]) -> Option<Vec<Tlv<'_>>> {
+    let (content_info, _) = read_tlv(pkcs7)?;
+    if content_info.tag != TAG_SEQUENCE {
+        return None;
+    }
+    let (_content_type, rest) = read_tlv(content_info.content)?;
+    let (explicit, _) = read_tlv(rest)?;
+    let (signed_data, _) = read_tlv(explicit.content)?;
+
+    // certificates are the [0] IMPLICIT field of SignedData
+    let certificates = children(signed_data.content)
+        .into_iter()
+        .find(|field| field.tag == TAG_CONTEXT_0)?;
+    Some(
+        children(certificates.content)
+            .into_iter()
+            .filter(|cert| cert.tag == TAG_SEQUENCE)
+            .collect(),
+    )
+}
+
+// classifies the signing certificate as "test", "self-signed" or "chained";
+// the leaf is taken to be the certificate that didn't issue any other one
+pub fn signature_trust(pkcs7: &[u8]) -> Option<&'static str> {
+    let certificates = embedded_certificates(pkcs7)?;
+    let names: Vec<CertificateNames> = certificates.iter().filter_map(certificate_names).collect();
+
+    let leaf = names
+        .iter()
+        .find(|cert| {
+            !names
+                .iter()
+                .any(|other| other.issuer == cert.subject && other.subject != cert.subject)
+        })
+        .or(names.first())?;
+
+    let subject = String::from_utf8_lossy(leaf.subject);
+    if TEST_CERT_MARKERS
+        .iter()
+        .any(|marker| subject.contains(marker))
+    {
+        Some("test")
+    } else if leaf.issuer == leaf.subject {
+        Some("self-signed")
+    } else {
+        Some("chained")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{der, pkcs7_signed_data};
+
+    #[test]
+    fn reads_short_and_long_form_lengths() {
+        let (tlv, rest) = read_tlv(&[0x04, 0x02, 0xaa, 0xbb, 0xcc]).unwrap();
+        assert_eq!((tlv.tag, tlv.content), (0x04, &[0xaa, 0xbb][..]));
+        assert_eq!(rest, [0xcc]);
+
+        let long = der(0x04, &[7; 300]);
+        assert_eq!(&long[..4], [0x04, 0x82, 0x01, 0x2c]);
+        let (tlv, rest) = read_tlv(&long).unwrap();
+        assert_eq!(tlv.content.len(), 300);
+        assert_eq!(tlv.raw.len(), long.len());
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn rejects_truncated_and_indefinite_lengths() {
+        assert!(read_tlv(&[]).is_none());
+        assert!(read_tlv(&[0x30]).is_none());
+        assert!(read_tlv(&[0x30, 0x05, 0x00]).is_none());
+        assert!(read_tlv(&[0x30, 0x80, 0x00, 0x00]).is_none());
+        assert!(read_tlv(&[0x30, 0x85, 1, 1, 1, 1, 1]).is_none());
+        assert!(read_tlv(&[0x30, 0x84, 0xff, 0xff, 0xff, 0xff]).is_none());
+    }
+
+    #[test]
+    fn classifies_self_signed_certificates() {
+        let blob = pkcs7_signed_data(&[("Acme Dev", "Acme Dev")]);
+        assert_eq!(signature_trust(&blob), Some("self-signed"));
+    }
+
+    #[test]
+    fn classifies_chained_certificates_by_their_leaf() {
+        // the root comes first, so the leaf has to be found by name
+        let blob = pkcs7_signed_data(&[
+            ("Example Root CA", "Example Root CA"),
+            ("Example Root CA", "Example Code Signing CA"),
+            ("Example Code Signing CA", "Acme Corp"),
+        ]);
+        assert_eq!(signature_trust(&blob), Some("chained"));
+    }
+
+    #[test]
+    fn classifies_test_certificates() {
+        let blob = pkcs7_signed_data(&[("WDKTestCert builder", "WDKTestCert builder")]);
+        assert_eq!(signature_trust(&blob), Some("test"));
+    }
+
+    #[test]
+    fn ignores_blobs_that_are_not_signed_data() {
+        assert_eq!(signature_trust(&[]), None);
+        assert_eq!(signature_trust(&[0x30, 0x00]), None);
+        assert_eq!(signature_trust(&der(0x04, b"not a sequence")), None);
+        assert_eq!(signature_trust(&pkcs7_signed_data(&[])), None);
+        let blob = pkcs7_signed_data(&[("Acme Dev", "Acme Dev")]);
+        assert_eq!(signature_trust(&blob[..blob.len() / 2]), None);
+    }
+}
@@ -0,0 +1,142 @@
+use crate::types::ScanResult;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+// fields that change without the findings changing, left out of the hash
+const VOLATILE_FIELDS: &[&str] = &["logs", "createdAt", "resultHash"];
+// floats are rounded to this many decimals so rounding noise can't move the hash
+const FLOAT_DECIMALS: i32 = 6;
+
+fn normalize(value: &mut Value) {
+    match value {
+        Value::Array(items) => items.iter_mut().for_each(normalize),
+        Value::Object(fields) => fields.values_mut().for_each(normalize),
+        Value::Number(n) if n.is_f64() => {
+            let scale = 10f64.powi(FLOAT_DECIMALS);
+            let rounded = (n.as_f64().unwrap() * scale).round() / scale;
+            // adding 0.0 turns -0.0 into 0.0
+            *value = serde_json::json!(rounded + 0.0);
+        }
+        _ => {}
+    }
+}
+
+// the result with volatile fields dropped, threats sorted and floats rounded;
+// object keys come out sorted since serde_json maps are ordered by key
+pub fn canonical_result(result: &ScanResult) -> Value {
+    let mut value = serde_json::to_value(result).unwrap();
+    if let Value::Object(fields) = &mut value {
+        for field in VOLATILE_FIELDS {
+            fields.remove(*field);
+        }
+        if let Some(Value::Array(threats)) = fields.get_mut("threats") {
+            threats.sort_by_cached_key(|threat| {
+                let key = |name: &str| threat[name].as_str().unwrap_or_default().to_string();
+                (
+                    key("threatId"),
+                    key("type"),
+                    key("severity"),
+                    key("details"),
+                )
+            });
+        }
+    }
+    normalize(&mut value);
+    value
+}
+
+// SHA-256 over the compact canonical JSON, hex encoded
+pub fn result_hash(result: &ScanResult) -> String {
+    let canonical = canonical_result(result).to_string();
+    format!("{:x}", Sha256::digest(canonical.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scan_result;
+    use crate::types::Threat;
+
+    fn threat(threat_id: &str, details: &str) -> Threat {
+        Threat {
+            threat_type: "Test".to_string(),
+            details: details.to_string(),
+            severity: "suspicious".to_string(),
+            threat_id: threat_id.to_string(),
+        }
+    }
+
+    fn finished() -> ScanResult {
+        let mut result = scan_result("suspicious");
+        result.threats = vec![
+            threat("S002", "b"),
+            threat("P003", ".text"),
+            threat("P003", ".data"),
+        ];
+        result.entropy = 7.25;
+        result.logs = vec!["[100%] Scan complete!".to_string()];
+        result.created_at = "2024-06-01T00:00:00.000Z".to_string();
+        result
+    }
+
+    #[test]
+    fn threat_order_does_not_change_the_hash() {
+        let first = finished();
+        let mut second = finished();
+        second.threats.reverse();
+        assert_eq!(result_hash(&first), result_hash(&second));
+        assert_eq!(canonical_result(&first), canonical_result(&second));
+
+        let canonical = canonical_result(&second);
+        let details: Vec<&str> = canonical["threats"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|t| t["details"].as_str().unwrap())
+            .collect();
+        assert_eq!(details, [".data", ".text", "b"]);
+    }
+
+    #[test]
+    fn volatile_fields_and_float_noise_are_ignored() {
+        let first = finished();
+        let mut second = finished();
+        second.logs.push("Re-fetched".to_string());
+        second.created_at = "2025-01-01T00:00:00.000Z".to_string();
+        second.result_hash = Some(result_hash(&first));
+        second.entropy = 7.25 + 1e-9;
+        assert_eq!(result_hash(&first), result_hash(&second));
+
+        let mut zero = finished();
+        zero.zero_ratio = -0.0;
+        assert_eq!(result_hash(&zero), result_hash(&finished()));
+    }
+
+    #[test]
+    fn real_changes_change_the_hash() {
+        let base = result_hash(&finished());
+        let mut changed = finished();
+        changed.threats[0].details = "c".to_string();
+        assert_ne!(result_hash(&changed), base);
+        let mut changed = finished();
+        changed.entropy = 7.26;
+        assert_ne!(result_hash(&changed), base);
+        let mut changed = finished();
+        changed.threats.pop();
+        assert_ne!(result_hash(&changed), base);
+        assert_eq!(base.len(), 64);
+    }
+
+    #[test]
+    fn canonical_json_has_sorted_keys_and_no_volatile_fields() {
+        let canonical = canonical_result(&finished());
+        let json = canonical.to_string();
+        assert!(!json.contains("\"logs\""));
+        assert!(!json.contains("createdAt"));
+        assert!(!json.contains("resultHash"));
+        let keys: Vec<&String> = canonical.as_object().unwrap().keys().collect();
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(keys, sorted);
+    }
+}
@@ -1,2 +1,577 @@
-pub const MAX_FILE_SIZE: u64 = 100 * 1024 * 1024; // 100MB
-pub const UPLOAD_DIR: &str = "./uploads";
+use std::env;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const DEFAULT_BIND: &str = "0.0.0.0:3001";
+// the WebSocket progress stream has a listener of its own, see `handle_scan_ws`
+pub const DEFAULT_WS_BIND: &str = "0.0.0.0:3002";
+// largest single file accepted, 100MB
+pub const DEFAULT_MAX_FILE_SIZE: u64 = 100 * 1024 * 1024;
+// all files of one multi-file upload together, 500MB
+pub const DEFAULT_MAX_BATCH_SIZE: u64 = 500 * 1024 * 1024;
+// multipart framing (boundaries and part headers) an upload body may carry on
+// top of the batch size, 1MB
+pub const MULTIPART_OVERHEAD: u64 = 1024 * 1024;
+pub const DEFAULT_UPLOAD_DIR: &str = "./uploads";
+pub const RULES_PATH: &str = "./rules.json";
+// chunk size for reading request bodies; larger trades memory for fewer reallocations
+pub const READ_BUFFER_SIZE: usize = 64 * 1024;
+// executable sections above this entropy are likely packed or encrypted
+pub const HIGH_ENTROPY_THRESHOLD: f64 = 7.2;
+// inconsistent file header flags needed before the header counts as crafted
+pub const CRAFTED_HEADER_MIN_ANOMALIES: usize = 2;
+// overlays at least this large are flagged, as are smaller ones above
+// HIGH_ENTROPY_THRESHOLD once there are enough bytes for entropy to mean much
+pub const OVERLAY_LARGE_SIZE: usize = 512 * 1024;
+pub const OVERLAY_MIN_ENTROPY_SIZE: usize = 1024;
+// PDF readers accept the %PDF- header anywhere in this many leading bytes and
+// look for the %%EOF marker within this many trailing ones
+pub const PDF_MARKER_WINDOW: usize = 1024;
+// non-zero bytes a gap between two sections' raw data must hold before it's
+// flagged; linkers only ever zero-fill alignment padding
+pub const SECTION_GAP_MIN_DATA: usize = 16;
+// PEs at least this large with this share of zero bytes get a sparse-file note,
+// escalated to suspicious past the higher ratio
+pub const SPARSE_MIN_SIZE: usize = 1024 * 1024;
+pub const SPARSE_NOTE_RATIO: f64 = 0.8;
+pub const SPARSE_SUSPICIOUS_RATIO: f64 = 0.95;
+// import prefixes that wipe event logs outright
+pub const LOG_CLEARING_IMPORTS: &[&str] = &["ClearEventLog", "EvtClearLog"];
+// winsock imports that open a socket, and ones that connect or move data over it
+pub const SOCKET_CREATE_IMPORTS: &[&str] = &["socket", "WSASocketA", "WSASocketW"];
+pub const SOCKET_IO_IMPORTS: &[&str] = &[
+    "connect",
+    "WSAConnect",
+    "send",
+    "recv",
+    "sendto",
+    "recvfrom",
+    "WSASend",
+    "WSARecv",
+];
+// ntdll system calls used to allocate, write, map and run code in another
+// process, by their Nt name; the Zw aliases are matched as well
+pub const NATIVE_INJECTION_APIS: &[&str] = &[
+    "NtAllocateVirtualMemory",
+    "NtWriteVirtualMemory",
+    "NtProtectVirtualMemory",
+    "NtCreateSection",
+    "NtMapViewOfSection",
+    "NtUnmapViewOfSection",
+    "NtCreateThreadEx",
+    "NtQueueApcThread",
+    "NtSetContextThread",
+    "NtResumeThread",
+];
+// ws2_32/wsock32 exports that are commonly imported by ordinal instead of name
+pub const WINSOCK_ORDINALS: &[(u16, &str)] = &[
+    (4, "connect"),
+    (16, "recv"),
+    (17, "recvfrom"),
+    (19, "send"),
+    (20, "sendto"),
+    (23, "socket"),
+];
+// lowercase system DLLs that ordinary Windows programs import
+pub const COMMON_DLLS: &[&str] = &[
+    "kernel32.dll",
+    "kernelbase.dll",
+    "ntdll.dll",
+    "user32.dll",
+    "gdi32.dll",
+    "gdiplus.dll",
+    "advapi32.dll",
+    "shell32.dll",
+    "shlwapi.dll",
+    "ole32.dll",
+    "oleaut32.dll",
+    "comctl32.dll",
+    "comdlg32.dll",
+    "ws2_32.dll",
+    "wininet.dll",
+    "winhttp.dll",
+    "crypt32.dll",
+    "bcrypt.dll",
+    "secur32.dll",
+    "version.dll",
+    "winmm.dll",
+    "imm32.dll",
+    "uxtheme.dll",
+    "dwmapi.dll",
+    "setupapi.dll",
+    "iphlpapi.dll",
+    "psapi.dll",
+    "userenv.dll",
+    "netapi32.dll",
+    "rpcrt4.dll",
+    "dbghelp.dll",
+    "msvcrt.dll",
+    "mscoree.dll",
+];
+// lowercase name prefixes for runtimes and API sets that ship with many programs
+pub const COMMON_DLL_PREFIXES: &[&str] = &[
+    "api-ms-win-",
+    "ext-ms-",
+    "vcruntime",
+    "msvcp",
+    "msvcr",
+    "ucrtbase",
+    "concrt",
+    "mfc",
+    "libgcc_s",
+    "libstdc++",
+    "libwinpthread",
+];
+// lowercase command fragments used to destroy logs, shadow copies or recovery data
+pub const ANTI_FORENSIC_STRINGS: &[&str] = &[
+    "wevtutil cl",
+    "clear-eventlog",
+    "vssadmin delete shadows",
+    "vssadmin.exe delete shadows",
+    "wmic shadowcopy delete",
+    "fsutil usn deletejournal",
+    "recoveryenabled no",
+];
+// lowercase path fragments that make a DeleteFile import count as log tampering
+pub const EVENT_LOG_PATHS: &[&str] = &[".evtx", "\\winevt\\logs"];
+// shortest printable run kept by string extraction
+pub const MIN_STRING_LENGTH: usize = 4;
+// per-encoding cap on strings returned in a scan result
+pub const MAX_REPORTED_STRINGS: usize = 1000;
+// reported strings longer than this are cut and end with an ellipsis
+pub const MAX_REPORTED_STRING_LENGTH: usize = 256;
+// cap on the rolling stats ring buffer
+pub const STATS_MAX_EVENTS: usize = 100_000;
+// zip-bomb guards, applied across all nested archives in one upload: at most
+// this many entries and 256MB extracted
+pub const MAX_ARCHIVE_ENTRIES: usize = 1000;
+pub const MAX_ARCHIVE_UNCOMPRESSED_SIZE: u64 = 256 * 1024 * 1024;
+// how many zips deep nested archives are opened
+pub const MAX_ARCHIVE_DEPTH: usize = 3;
+// scans still running after this are marked "timeout"
+pub const SCAN_TIMEOUT: Duration = Duration::from_secs(60);
+// on SIGINT/SIGTERM, scans still running after this are marked "cancelled"
+pub const SHUTDOWN_GRACE: Duration = Duration::from_secs(30);
+// address-space cap for the sandboxed analysis process
+pub const SANDBOX_MEMORY_LIMIT: u64 = 2 * 1024 * 1024 * 1024; // 2GB
+
+// how often a sandboxed scan that hasn't reported progress checks whether
+// it was cancelled or timed out
+pub const SANDBOX_POLL_INTERVAL: Duration = Duration::from_millis(100);
+// family tags below this share of matched evidence are dropped
+pub const FAMILY_MIN_CONFIDENCE: f64 = 0.5;
+// files in this range have their analysis phases run side by side when enabled;
+// smaller ones finish faster than threads start, and extracted strings can
+// approach the file's size, so larger ones stay sequential to bound peak
+// memory; 4MB to 256MB
+pub const PARALLEL_MIN_SIZE: usize = 4 * 1024 * 1024;
+pub const PARALLEL_MAX_SIZE: usize = 256 * 1024 * 1024;
+// progress events queued per SSE client before intermediate ones are coalesced
+pub const SSE_BUFFER_CAPACITY: usize = 16;
+// how often a WebSocket progress stream pings the client, which is also how
+// quickly a close frame from the client is noticed
+pub const WS_PING_INTERVAL: Duration = Duration::from_secs(1);
+// a WebSocket client that doesn't answer a ping within this is dropped
+pub const WS_PONG_TIMEOUT: Duration = Duration::from_secs(5);
+// response bodies smaller than this go out uncompressed even when the client
+// accepts gzip, since the saving wouldn't cover the overhead
+pub const GZIP_MIN_SIZE: usize = 1024; // 1KB
+
+// largest GraphQL request body accepted
+pub const MAX_GRAPHQL_BODY_SIZE: u64 = 64 * 1024; // 64KB
+
+// deepest nesting of selection sets, list/object values and list types a
+// GraphQL query may use; the parser recurses per level
+pub const MAX_GRAPHQL_DEPTH: usize = 32;
+// where the "json" and "sqlite" result stores keep scans across restarts
+pub const RESULTS_DIR: &str = "./results";
+pub const DB_PATH: &str = "./peroxide.db";
+// env var holding the API key; when unset the API is open
+pub const API_KEY_ENV: &str = "PEROXIDE_API_KEY";
+// env var that, set to "false", lets SSE and result routes through without the key
+pub const PROTECT_READS_ENV: &str = "PEROXIDE_PROTECT_READS";
+
+// per-threat contributions to the 0-100 scan score
+pub const SCORE_MALICIOUS_WEIGHT: u32 = 40;
+pub const SCORE_SUSPICIOUS_WEIGHT: u32 = 15;
+pub const SCORE_NEUTRAL_WEIGHT: u32 = 3;
+// added when the whole file is above HIGH_ENTROPY_THRESHOLD
+pub const SCORE_HIGH_ENTROPY_BONUS: u32 = 10;
+// added per dangerous import, up to the cap
+pub const SCORE_DANGEROUS_IMPORT_BONUS: u32 = 5;
+pub const SCORE_DANGEROUS_IMPORT_CAP: u32 = 20;
+// import prefixes for injection, hooking and unpacking primitives
+pub const DANGEROUS_IMPORTS: &[&str] = &[
+    "VirtualAllocEx",
+    "WriteProcessMemory",
+    "CreateRemoteThread",
+    "NtUnmapViewOfSection",
+    "ZwUnmapViewOfSection",
+    "QueueUserAPC",
+    "SetThreadContext",
+    "SetWindowsHookEx",
+    "NtCreateThreadEx",
+    "RtlCreateUserThread",
+];
+// section names left behind by common packers, matched case-insensitively;
+// rule files can add more under "packers"
+pub const BUILTIN_PACKERS: &[(&str, &[&str])] = &[
+    ("UPX", &["UPX0", "UPX1", "UPX2", "UPX!"]),
+    ("ASPack", &[".aspack", ".adata"]),
+    ("Themida", &[".themida"]),
+    ("Petite", &[".petite"]),
+    ("MPRESS", &[".MPRESS1", ".MPRESS2"]),
+    ("PECompact", &["PEC2", "PECompact2"]),
+    ("FSG", &["FSG!"]),
+    ("VMProtect", &[".vmp0", ".vmp1", ".vmp2"]),
+];
+// added once when a packer section name and a high-entropy section both show up
+pub const SCORE_PACKED_ENTROPY_BONUS: u32 = 10;
+// bytes after the entry point searched for an API-hashing loop
+pub const API_HASH_SCAN_LENGTH: usize = 4096;
+// importing at most this many functions counts as a near-empty import table
+pub const API_HASH_MAX_IMPORTS: usize = 8;
+// scores at or above these get the "suspicious" and "malicious" verdicts
+pub const DEFAULT_SUSPICIOUS_SCORE: u32 = 20;
+pub const DEFAULT_MALICIOUS_SCORE: u32 = 60;
+
+// score cut-offs for the verdict, see `scanner::verdict`
+#[derive(Clone, Copy)]
+pub struct VerdictThresholds {
+    pub suspicious: u32,
+    pub malicious: u32,
+}
+
+// where scan results are persisted, see `storage`
+#[derive(Clone, Copy, Debug)]
+pub enum StoreBackend {
+    Memory,
+    // `compress` gzips each file, `shard` spreads them over subdirectories
+    Json { compress: bool, shard: bool },
+    Sqlite,
+}
+
+// the parts of Config each scan needs, copied into its worker threads
+#[derive(Clone, Copy)]
+pub struct ScanOptions {
+    pub sandbox: bool,
+    pub parallel: bool,
+    pub verdict: VerdictThresholds,
+    // how long a scan may run before it's marked "timeout", SCAN_TIMEOUT
+    pub timeout: Duration,
+    pub retain_samples: bool,
+}
+
+// deployment settings that can be overridden per environment; everything
+// else above stays a compile-time tunable
+pub struct Config {
+    pub bind: String,
+    pub ws_bind: String,
+    pub upload_dir: PathBuf,
+    pub max_file_size: u64,
+    pub max_batch_size: u64,
+    // run each analysis in a restricted child process, see `sandbox`
+    pub sandbox: bool,
+    // fan independent analysis phases out across threads, see `PARALLEL_MIN_SIZE`
+    pub parallel_analysis: bool,
+    pub verdict: VerdictThresholds,
+    pub store: StoreBackend,
+    // hand back the existing scan for a file uploaded again, unless ?force=true
+    pub dedupe_uploads: bool,
+    // keep uploaded samples on disk after scanning so their integrity can be
+    // re-verified, see GET /api/scan-result/{id}/verify-integrity
+    pub retain_samples: bool,
+    // serve the read-only POST /api/graphql endpoint, see `graphql`
+    pub graphql: bool,
+    // origins allowed to read responses cross-origin; empty allows any
+    pub cors_origins: Vec<String>,
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self, String> {
+        Self::from_vars(|key| env::var(key).ok())
+    }
+
+    // `from_env` over any variable lookup, so it can be tested without
+    // touching the process environment
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, String> {
+        let bind = bind_address(&var, "PEROXIDE_BIND", DEFAULT_BIND)?;
+        let ws_bind = bind_address(&var, "PEROXIDE_WS_BIND", DEFAULT_WS_BIND)?;
+        if ws_bind == bind {
+            return Err(format!(
+                "PEROXIDE_WS_BIND must differ from PEROXIDE_BIND, both are {:?}",
+                bind
+            ));
+        }
+
+        let upload_dir = PathBuf::from(
+            var("PEROXIDE_UPLOAD_DIR").unwrap_or_else(|| DEFAULT_UPLOAD_DIR.to_string()),
+        );
+        if upload_dir.as_os_str().is_empty() {
+            return Err("PEROXIDE_UPLOAD_DIR must not be empty".to_string());
+        }
+
+        let max_file_size = match var("PEROXIDE_MAX_FILE_SIZE") {
+            Some(size) => match size.parse::<u64>() {
+                Ok(size) if size > 0 => size,
+                _ => {
+                    return Err(format!(
+                        "PEROXIDE_MAX_FILE_SIZE must be a positive byte count, got {:?}",
+                        size
+                    ))
+                }
+            },
+            None => DEFAULT_MAX_FILE_SIZE,
+        };
+        let max_batch_size = match var("PEROXIDE_MAX_BATCH_SIZE") {
+            Some(size) => match size.parse::<u64>() {
+                Ok(size) if size > 0 => size,
+                _ => {
+                    return Err(format!(
+                        "PEROXIDE_MAX_BATCH_SIZE must be a positive byte count, got {:?}",
+                        size
+                    ))
+                }
+            },
+            None => DEFAULT_MAX_BATCH_SIZE,
+        };
+
+        let flag = |key| matches!(var(key).as_deref(), Some("1") | Some("true") | Some("yes"));
+        let sandbox = flag("PEROXIDE_SANDBOX");
+        let parallel_analysis = flag("PEROXIDE_PARALLEL_ANALYSIS");
+        let graphql = flag("PEROXIDE_GRAPHQL");
+        let retain_samples = flag("PEROXIDE_RETAIN_SAMPLES");
+        // on unless explicitly turned off
+        let dedupe_uploads = !matches!(
+            var("PEROXIDE_DEDUPE_UPLOADS").as_deref(),
+            Some("0") | Some("false") | Some("no")
+        );
+
+        let score = |key, default| match var(key) {
+            Some(score) => match score.parse::<u32>() {
+                Ok(score) if score <= 100 => Ok(score),
+                _ => Err(format!(
+                    "{} must be a score from 0-100, got {:?}",
+                    key, score
+                )),
+            },
+            None => Ok(default),
+        };
+        let verdict = VerdictThresholds {
+            suspicious: score("PEROXIDE_SUSPICIOUS_SCORE", DEFAULT_SUSPICIOUS_SCORE)?,
+            malicious: score("PEROXIDE_MALICIOUS_SCORE", DEFAULT_MALICIOUS_SCORE)?,
+        };
+        if verdict.suspicious > verdict.malicious {
+            return Err(format!(
+                "PEROXIDE_SUSPICIOUS_SCORE ({}) must not exceed PEROXIDE_MALICIOUS_SCORE ({})",
+                verdict.suspicious, verdict.malicious
+            ));
+        }
+
+        // comma-separated, e.g. "https://peroxide.example.com,http://localhost:5173"
+        let cors_origins = var("PEROXIDE_CORS_ORIGINS")
+            .unwrap_or_default()
+            .split(',')
+            .map(|origin| origin.trim().trim_end_matches('/').to_string())
+            .filter(|origin| !origin.is_empty())
+            .collect();
+
+        let store = match var("PEROXIDE_STORE").as_deref() {
+            None | Some("json") => StoreBackend::Json {
+                compress: flag("PEROXIDE_STORE_COMPRESS"),
+                shard: flag("PEROXIDE_STORE_SHARD"),
+            },
+            Some("memory") => StoreBackend::Memory,
+            Some("sqlite") => StoreBackend::Sqlite,
+            Some(other) => {
+                return Err(format!(
+                    "PEROXIDE_STORE must be memory, json or sqlite, got {:?}",
+                    other
+                ))
+            }
+        };
+
+        Ok(Config {
+            bind,
+            ws_bind,
+            upload_dir,
+            max_file_size,
+            max_batch_size,
+            sandbox,
+            parallel_analysis,
+            verdict,
+            store,
+            dedupe_uploads,
+            retain_samples,
+            graphql,
+            cors_origins,
+        })
+    }
+
+    pub fn scan_options(&self) -> ScanOptions {
+        ScanOptions {
+            sandbox: self.sandbox,
+            parallel: self.parallel_analysis,
+            verdict: self.verdict,
+            timeout: SCAN_TIMEOUT,
+            retain_samples: self.retain_samples,
+        }
+    }
+}
+
+// `name` as host:port, or `default` when it's unset
+fn bind_address(
+    var: &impl Fn(&str) -> Option<String>,
+    name: &str,
+    default: &str,
+) -> Result<String, String> {
+    let bind = var(name).unwrap_or_else(|| default.to_string());
+    let port = bind
+        .rsplit_once(':')
+        .and_then(|(host, port)| (!host.is_empty()).then_some(port))
+        .ok_or_else(|| format!("{} must be host:port, got {:?}", name, bind))?;
+    match port.parse::<u16>() {
+        Ok(port) if port > 0 => Ok(bind),
+        _ => Err(format!("{} port must be 1-65535, got {:?}", name, port)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn config(vars: &[(&str, &str)]) -> Result<Config, String> {
+        let vars: HashMap<&str, &str> = vars.iter().copied().collect();
+        Config::from_vars(|key| vars.get(key).map(|value| value.to_string()))
+    }
+
+    fn error(vars: &[(&str, &str)]) -> String {
+        match config(vars) {
+            Ok(_) => panic!("{:?} was accepted", vars),
+            Err(e) => e,
+        }
+    }
+
+    fn bind(address: &str) -> String {
+        config(&[("PEROXIDE_BIND", address)]).unwrap().bind
+    }
+
+    #[test]
+    fn defaults_without_any_variables() {
+        let config = config(&[]).unwrap();
+        assert_eq!(config.bind, DEFAULT_BIND);
+        assert_eq!(config.ws_bind, DEFAULT_WS_BIND);
+        assert_eq!(config.upload_dir, PathBuf::from(DEFAULT_UPLOAD_DIR));
+        assert_eq!(config.max_file_size, DEFAULT_MAX_FILE_SIZE);
+        assert_eq!(config.max_batch_size, DEFAULT_MAX_BATCH_SIZE);
+        assert!(!config.sandbox && !config.graphql && !config.retain_samples);
+        assert!(config.dedupe_uploads);
+        assert_eq!(config.verdict.suspicious, DEFAULT_SUSPICIOUS_SCORE);
+        assert!(matches!(
+            config.store,
+            StoreBackend::Json {
+                compress: false,
+                shard: false
+            }
+        ));
+        assert!(config.cors_origins.is_empty());
+    }
+
+    #[test]
+    fn reads_valid_values() {
+        let config = config(&[
+            ("PEROXIDE_BIND", "127.0.0.1:8080"),
+            ("PEROXIDE_WS_BIND", "127.0.0.1:8081"),
+            ("PEROXIDE_UPLOAD_DIR", "/var/lib/peroxide"),
+            ("PEROXIDE_MAX_FILE_SIZE", "1024"),
+            ("PEROXIDE_SANDBOX", "true"),
+            ("PEROXIDE_DEDUPE_UPLOADS", "no"),
+            ("PEROXIDE_SUSPICIOUS_SCORE", "10"),
+            ("PEROXIDE_MALICIOUS_SCORE", "10"),
+            ("PEROXIDE_STORE", "json"),
+            ("PEROXIDE_STORE_SHARD", "1"),
+            (
+                "PEROXIDE_CORS_ORIGINS",
+                " https://a.example/ ,, http://localhost:5173",
+            ),
+        ])
+        .unwrap();
+        assert_eq!(config.bind, "127.0.0.1:8080");
+        assert_eq!(config.ws_bind, "127.0.0.1:8081");
+        assert_eq!(config.upload_dir, PathBuf::from("/var/lib/peroxide"));
+        assert_eq!(config.max_file_size, 1024);
+        assert!(config.sandbox);
+        assert!(!config.dedupe_uploads);
+        assert_eq!(config.verdict.malicious, 10);
+        assert!(matches!(
+            config.store,
+            StoreBackend::Json {
+                compress: false,
+                shard: true
+            }
+        ));
+        assert_eq!(
+            config.cors_origins,
+            ["https://a.example", "http://localhost:5173"]
+        );
+
+        // IPv6 hosts keep their colons
+        assert_eq!(bind("[::1]:3001"), "[::1]:3001");
+    }
+
+    #[test]
+    fn rejects_bad_bind_addresses() {
+        for bind in ["3001", ":3001", "localhost"] {
+            assert!(
+                error(&[("PEROXIDE_BIND", bind)]).starts_with("PEROXIDE_BIND must be host:port"),
+                "{}",
+                bind
+            );
+        }
+        for bind in ["0.0.0.0:0", "0.0.0.0:65536", "0.0.0.0:http", "0.0.0.0:"] {
+            assert!(
+                error(&[("PEROXIDE_BIND", bind)]).starts_with("PEROXIDE_BIND port must be 1-65535"),
+                "{}",
+                bind
+            );
+        }
+        assert!(error(&[("PEROXIDE_WS_BIND", "3002")]).starts_with("PEROXIDE_WS_BIND must be"));
+        assert!(error(&[("PEROXIDE_WS_BIND", DEFAULT_BIND)])
+            .starts_with("PEROXIDE_WS_BIND must differ"));
+    }
+
+    #[test]
+    fn rejects_bad_sizes_and_paths() {
+        for size in ["0", "-1", "1MB", ""] {
+            assert!(error(&[("PEROXIDE_MAX_FILE_SIZE", size)])
+                .starts_with("PEROXIDE_MAX_FILE_SIZE must be a positive byte count"));
+            assert!(error(&[("PEROXIDE_MAX_BATCH_SIZE", size)])
+                .starts_with("PEROXIDE_MAX_BATCH_SIZE must be a positive byte count"));
+        }
+        assert_eq!(
+            error(&[("PEROXIDE_UPLOAD_DIR", "")]),
+            "PEROXIDE_UPLOAD_DIR must not be empty"
+        );
+    }
+
+    #[test]
+    fn rejects_bad_scores_and_stores() {
+        assert_eq!(
+            error(&[("PEROXIDE_MALICIOUS_SCORE", "101")]),
+            "PEROXIDE_MALICIOUS_SCORE must be a score from 0-100, got \"101\""
+        );
+        assert!(error(&[
+            ("PEROXIDE_SUSPICIOUS_SCORE", "80"),
+            ("PEROXIDE_MALICIOUS_SCORE", "40")
+        ])
+        .contains("must not exceed"));
+        let store = |name: &str| config(&[("PEROXIDE_STORE", name)]).unwrap().store;
+        assert!(matches!(store("sqlite"), StoreBackend::Sqlite));
+        assert!(matches!(store("memory"), StoreBackend::Memory));
+        assert_eq!(
+            error(&[("PEROXIDE_STORE", "redis")]),
+            "PEROXIDE_STORE must be memory, json or sqlite, got \"redis\""
+        );
+    }
+}
@@ -0,0 +1,874 @@
+use crate::config::MAX_GRAPHQL_DEPTH;
+use crate::storage::{ResultStore, ScanQuery};
+use crate::types::{ScanResult, ScanStore};
+use serde_json::{Map, Value};
+
+// a read-only subset of GraphQL over the scan results: one query operation
+// with fields, aliases, arguments and variables; fragments, directives and
+// mutations are rejected. A scan's fields are the keys of its REST result
+// plus `scanId`, and fields a result doesn't carry resolve to null.
+//
+//   { scans(status: "unsafe") { scanId threats { type severity } } }
+//   query ($id: String!) { scan(id: $id) { status peInfo { machine } } }
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Punct(char),
+    Spread,
+    Name(String),
+    Int(i64),
+    Float(f64),
+    Str(String),
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            // commas are insignificant in GraphQL, like whitespace
+            c if c.is_whitespace() || c == ',' || c == '\u{feff}' => {
+                chars.next();
+            }
+            '#' => while chars.next_if(|&c| c != '\n').is_some() {},
+            '{' | '}' | '(' | ')' | '[' | ']' | ':' | '$' | '!' | '=' | '@' => {
+                chars.next();
+                tokens.push(Token::Punct(c));
+            }
+            '.' => {
+                let dots: String = (0..3).filter_map(|_| chars.next_if_eq(&'.')).collect();
+                if dots != "..." {
+                    return Err("Unexpected \".\"".to_string());
+                }
+                tokens.push(Token::Spread);
+            }
+            '"' => {
+                chars.next();
+                tokens.push(Token::Str(string_literal(&mut chars)?));
+            }
+            c if c == '-' || c.is_ascii_digit() => {
+                let mut number = String::new();
+                while let Some(c) =
+                    chars.next_if(|&c| c.is_ascii_alphanumeric() || matches!(c, '-' | '+' | '.'))
+                {
+                    number.push(c);
+                }
+                let token = if number.contains(['.', 'e', 'E']) {
+                    number.parse().map(Token::Float).ok()
+                } else {
+                    number.parse().map(Token::Int).ok()
+                };
+                tokens.push(token.ok_or_else(|| format!("Invalid number {:?}", number))?);
+            }
+            c if c == '_' || c.is_ascii_alphabetic() => {
+                let mut name = String::new();
+                while let Some(c) = chars.next_if(|&c| c == '_' || c.is_ascii_alphanumeric()) {
+                    name.push(c);
+                }
+                tokens.push(Token::Name(name));
+            }
+            other => return Err(format!("Unexpected character {:?}", other)),
+        }
+    }
+    Ok(tokens)
+}
+
+// the rest of a "..." string after its opening quote; block strings aren't supported
+fn string_literal(chars: &mut impl Iterator<Item = char>) -> Result<String, String> {
+    let mut value = String::new();
+    loop {
+        match chars.next() {
+            None | Some('\n') => return Err("Unterminated string".to_string()),
+            Some('"') => return Ok(value),
+            Some('\\') => match chars.next() {
+                Some('n') => value.push('\n'),
+                Some('t') => value.push('\t'),
+                Some('r') => value.push('\r'),
+                Some('b') => value.push('\u{8}'),
+                Some('f') => value.push('\u{c}'),
+                Some('u') => {
+                    let hex: String = chars.take(4).collect();
+                    let c = u32::from_str_radix(&hex, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| format!("Invalid escape \\u{}", hex))?;
+                    value.push(c);
+                }
+                Some(c @ ('"' | '\\' | '/')) => value.push(c),
+                other => return Err(format!("Invalid escape {:?}", other)),
+            },
+            Some(c) => value.push(c),
+        }
+    }
+}
+
+struct Field {
+    // the response key: the alias when one is given, else the name
+    key: String,
+    name: String,
+    arguments: Map<String, Value>,
+    selection: Vec<Field>,
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    variables: Map<String, Value>,
+    // how many selection sets, values and types enclose the current token
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<&Token, String> {
+        let token = self.tokens.get(self.pos).ok_or("Unexpected end of query")?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(&Token::Punct(c)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), String> {
+        match self.next()? {
+            Token::Punct(p) if *p == c => Ok(()),
+            other => Err(format!("Expected \"{}\", found {:?}", c, other)),
+        }
+    }
+
+    // called on entering a nested construct, paired with `leave`; keeps a
+    // hostile query from recursing the parser off the end of the stack
+    fn enter(&mut self) -> Result<(), String> {
+        self.depth += 1;
+        if self.depth > MAX_GRAPHQL_DEPTH {
+            return Err(format!(
+                "Query is nested deeper than {} levels",
+                MAX_GRAPHQL_DEPTH
+            ));
+        }
+        Ok(())
+    }
+
+    fn leave(&mut self) {
+        self.depth -= 1;
+    }
+
+    fn name(&mut self) -> Result<String, String> {
+        match self.next()? {
+            Token::Name(name) => Ok(name.clone()),
+            other => Err(format!("Expected a name, found {:?}", other)),
+        }
+    }
+
+    // a whole document holding exactly one query operation
+    fn document(&mut self) -> Result<Vec<Field>, String> {
+        let selection = match self.peek() {
+            Some(Token::Punct('{')) => self.selection_set()?,
+            Some(Token::Name(keyword)) if keyword == "query" => {
+                self.pos += 1;
+                if let Some(Token::Name(_)) = self.peek() {
+                    self.pos += 1;
+                }
+                if self.eat('(') {
+                    self.variable_definitions()?;
+                }
+                self.selection_set()?
+            }
+            Some(Token::Name(keyword)) if keyword == "mutation" || keyword == "subscription" => {
+                return Err(format!("Only queries are supported, not {}s", keyword));
+            }
+            Some(Token::Name(keyword)) if keyword == "fragment" => {
+                return Err("Fragments are not supported".to_string());
+            }
+            _ => return Err("Expected a query".to_string()),
+        };
+        if self.peek().is_some() {
+            return Err("Only a single query operation is supported".to_string());
+        }
+        Ok(selection)
+    }
+
+    // `$name: Type = default` entries; types aren't checked, defaults fill
+    // in variables the request didn't send
+    fn variable_definitions(&mut self) -> Result<(), String> {
+        while !self.eat(')') {
+            self.expect('$')?;
+            let name = self.name()?;
+            self.expect(':')?;
+            self.skip_type()?;
+            if self.eat('=') {
+                let default = self.value()?;
+                self.variables.entry(name).or_insert(default);
+            }
+        }
+        Ok(())
+    }
+
+    fn skip_type(&mut self) -> Result<(), String> {
+        if self.eat('[') {
+            self.enter()?;
+            self.skip_type()?;
+            self.expect(']')?;
+            self.leave();
+        } else {
+            self.name()?;
+        }
+        self.eat('!');
+        Ok(())
+    }
+
+    fn selection_set(&mut self) -> Result<Vec<Field>, String> {
+        self.expect('{')?;
+        self.enter()?;
+        let mut fields = Vec::new();
+        while !self.eat('}') {
+            if self.peek() == Some(&Token::Spread) {
+                return Err("Fragments are not supported".to_string());
+            }
+            fields.push(self.field()?);
+        }
+        if fields.is_empty() {
+            return Err("Selection sets must not be empty".to_string());
+        }
+        self.leave();
+        Ok(fields)
+    }
+
+    fn field(&mut self) -> Result<Field, String> {
+        let key = self.name()?;
+        let name = if self.eat(':') {
+            self.name()?
+        } else {
+            key.clone()
+        };
+
+        let mut arguments = Map::new();
+        if self.eat('(') {
+            while !self.eat(')') {
+                let argument = self.name()?;
+                self.expect(':')?;
+                arguments.insert(argument, self.value()?);
+            }
+        }
+        if self.peek() == Some(&Token::Punct('@')) {
+            return Err("Directives are not supported".to_string());
+        }
+        let selection = if self.peek() == Some(&Token::Punct('{')) {
+            self.selection_set()?
+        } else {
+            Vec::new()
+        };
+
+        Ok(Field {
+            key,
+            name,
+            arguments,
+            selection,
+        })
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        if self.eat('$') {
+            let name = self.name()?;
+            return Ok(self.variables.get(&name).cloned().unwrap_or(Value::Null));
+        }
+        if self.eat('[') {
+            self.enter()?;
+            let mut items = Vec::new();
+            while !self.eat(']') {
+                items.push(self.value()?);
+            }
+            self.leave();
+            return Ok(Value::Array(items));
+        }
+        if self.eat('{') {
+            self.enter()?;
+            let mut fields = Map::new();
+            while !self.eat('}') {
+                let name = self.name()?;
+                self.expect(':')?;
+                fields.insert(name, self.value()?);
+            }
+            self.leave();
+            return Ok(Value::Object(fields));
+        }
+        Ok(match self.next()? {
+            Token::Int(n) => Value::from(*n),
+            Token::Float(n) => Value::from(*n),
+            Token::Str(s) => Value::from(s.clone()),
+            Token::Name(name) => match name.as_str() {
+                "true" => Value::Bool(true),
+                "false" => Value::Bool(false),
+                "null" => Value::Null,
+                // enum values are passed on as their name
+                _ => Value::from(name.clone()),
+            },
+            other => return Err(format!("Expected a value, found {:?}", other)),
+        })
+    }
+}
+
+fn parse(query: &str, variables: Map<String, Value>) -> Result<Vec<Field>, String> {
+    Parser {
+        tokens: tokenize(query)?,
+        pos: 0,
+        variables,
+        depth: 0,
+    }
+    .document()
+}
+
+fn has_objects(value: &Value) -> bool {
+    match value {
+        Value::Object(_) => true,
+        Value::Array(items) => items.iter().any(has_objects),
+        _ => false,
+    }
+}
+
+// projects `value` onto the selection, mapping over lists
+fn select(value: &Value, selection: &[Field]) -> Result<Value, String> {
+    let fields = match value {
+        Value::Array(items) => {
+            return items.iter().map(|item| select(item, selection)).collect();
+        }
+        Value::Object(fields) => fields,
+        _ => return Ok(value.clone()),
+    };
+
+    let mut out = Map::new();
+    for field in selection {
+        if !field.arguments.is_empty() {
+            return Err(format!("Field \"{}\" takes no arguments", field.name));
+        }
+        let value = fields.get(&field.name).unwrap_or(&Value::Null);
+        let selected = match (value, field.selection.is_empty()) {
+            (Value::Null, _) => Value::Null,
+            (value, true) if has_objects(value) => {
+                return Err(format!(
+                    "Field \"{}\" must have a selection of subfields",
+                    field.name
+                ));
+            }
+            (value, true) => value.clone(),
+            (value, false) if !has_objects(value) && !matches!(value, Value::Array(_)) => {
+                return Err(format!("Field \"{}\" has no subfields", field.name));
+            }
+            (value, false) => select(value, &field.selection)?,
+        };
+        out.insert(field.key.clone(), selected);
+    }
+    Ok(Value::Object(out))
+}
+
+fn scan_value(scan_id: &str, result: &ScanResult) -> Value {
+    let mut value = serde_json::to_value(result).unwrap();
+    value["scanId"] = Value::from(scan_id);
+    value
+}
+
+fn string_argument(field: &Field, name: &str) -> Result<Option<String>, String> {
+    match field.arguments.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s.clone())),
+        Some(other) => Err(format!(
+            "Argument \"{}\" of \"{}\" must be a string, got {}",
+            name, field.name, other
+        )),
+    }
+}
+
+// the root fields: `scan(id)` reads the live store so running scans show up,
+// then the result store, `scans(sha256, status, verdict, tag, since, until)`
+// queries the result store
+fn resolve_root(
+    field: &Field,
+    scan_store: &ScanStore,
+    storage: &dyn ResultStore,
+) -> Result<Value, String> {
+    const SCANS_ARGUMENTS: &[&str] = &["sha256", "status", "verdict", "tag", "since", "until"];
+    let allowed: &[&str] = match field.name.as_str() {
+        "scan" => &["id"],
+        "scans" => SCANS_ARGUMENTS,
+        other => return Err(format!("Unknown field \"{}\" on Query", other)),
+    };
+    if let Some(unknown) = field
+        .arguments
+        .keys()
+        .find(|a| !allowed.contains(&a.as_str()))
+    {
+        return Err(format!(
+            "Unknown argument \"{}\" on \"{}\"",
+            unknown, field.name
+        ));
+    }
+    if field.selection.is_empty() {
+        return Err(format!(
+            "Field \"{}\" must have a selection of subfields",
+            field.name
+        ));
+    }
+
+    if field.name == "scan" {
+        let id =
+            string_argument(field, "id")?.ok_or("Field \"scan\" requires an \"id\" argument")?;
+        let live = scan_store.lock().unwrap().get(&id).cloned();
+        let scan = match live {
+            Some(result) => Some(result),
+            None => storage.get(&id)?,
+        };
+        return match scan.map(|result| scan_value(&id, &result)) {
+            Some(scan) => select(&scan, &field.selection),
+            None => Ok(Value::Null),
+        };
+    }
+
+    let query = ScanQuery {
+        sha256: string_argument(field, "sha256")?,
+        status: string_argument(field, "status")?,
+        verdict: string_argument(field, "verdict")?,
+        tag: string_argument(field, "tag")?,
+        since: string_argument(field, "since")?,
+        until: string_argument(field, "until")?,
+    };
+    let mut scans = storage.query(&query)?;
+    // newest first, like GET /api/scans
+    scans.sort_by(|(_, a), (_, b)| b.created_at.cmp(&a.created_at));
+    let scans: Vec<Value> = scans
+        .iter()
+        .map(|(scan_id, result)| scan_value(scan_id, result))
+        .collect();
+    select(&Value::Array(scans), &field.selection)
+}
+
+// runs a decoded GraphQL request ({"query": ..., "variables": {...}}) and
+// returns the response body; Err is for requests that can't run at all
+pub fn execute(
+    request: &Value,
+    scan_store: &ScanStore,
+    storage: &dyn ResultStore,
+) -> Result<Value, String> {
+    let query = request["query"]
+        .as_str()
+        .ok_or("Request body needs a \"query\" string")?;
+    let variables = match &request["variables"] {
+        Value::Object(variables) => variables.clone(),
+        Value::Null => Map::new(),
+        _ => return Err("\"variables\" must be an object".to_string()),
+    };
+    let selection = parse(query, variables)?;
+
+    let mut data = Map::new();
+    for field in &selection {
+        match resolve_root(field, scan_store, storage) {
+            Ok(value) => {
+                data.insert(field.key.clone(), value);
+            }
+            Err(message) => {
+                return Ok(serde_json::json!({
+                    "data": null,
+                    "errors": [{"message": message, "path": [field.key]}],
+                }));
+            }
+        }
+    }
+    Ok(serde_json::json!({ "data": data }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::MemoryStore;
+    use crate::testing::scan_result;
+    use crate::types::{FamilyMatch, FileInfo, Threat};
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    fn stored(status: &str, sha256: &str, created_at: &str) -> ScanResult {
+        let mut result = scan_result(status);
+        result.created_at = created_at.to_string();
+        result.verdict = Some(
+            if status == "unsafe" {
+                "malicious"
+            } else {
+                "clean"
+            }
+            .to_string(),
+        );
+        result.file_info = Some(FileInfo {
+            filename: format!("{}.exe", sha256),
+            size: 4,
+            sha256: sha256.to_string(),
+            md5: "m".to_string(),
+            sha1: "s".to_string(),
+            imphash: None,
+            authentihash: None,
+        });
+        result
+    }
+
+    // scan-1 to scan-3 in the result store, a month apart, and scan-1 plus a
+    // still running scan in the live store
+    fn stores() -> (ScanStore, MemoryStore) {
+        let mut unsafe_scan = stored("unsafe", "aa", "2024-01-01T00:00:00.000Z");
+        unsafe_scan.threats.push(Threat {
+            threat_type: "T".to_string(),
+            details: "d".to_string(),
+            severity: "malicious".to_string(),
+            threat_id: "S001".to_string(),
+        });
+        unsafe_scan.family = Some(FamilyMatch {
+            name: "Ladybird".to_string(),
+            confidence: 0.9,
+            evidence: vec![],
+        });
+        let storage = MemoryStore::default();
+        storage.save("scan-1", &unsafe_scan).unwrap();
+        storage
+            .save("scan-2", &stored("safe", "bb", "2024-02-01T00:00:00.000Z"))
+            .unwrap();
+        storage
+            .save(
+                "scan-3",
+                &stored("suspicious", "cc", "2024-03-01T00:00:00.000Z"),
+            )
+            .unwrap();
+        let live = HashMap::from([
+            ("scan-1".to_string(), unsafe_scan),
+            ("scan-running".to_string(), scan_result("scanning")),
+        ]);
+        (Arc::new(Mutex::new(live)), storage)
+    }
+
+    fn execute_request(request: Value) -> Result<Value, String> {
+        let (scan_store, storage) = stores();
+        execute(&request, &scan_store, &storage)
+    }
+
+    fn run(query: &str) -> Result<Value, String> {
+        execute_request(json!({ "query": query }))
+    }
+
+    fn data(query: &str) -> Value {
+        let response = run(query).unwrap();
+        assert!(response.get("errors").is_none(), "{}", response);
+        response["data"].clone()
+    }
+
+    // the message of a query that parsed but failed to resolve
+    fn field_error(query: &str) -> String {
+        let response = run(query).unwrap();
+        assert_eq!(response["data"], Value::Null);
+        response["errors"][0]["message"]
+            .as_str()
+            .unwrap()
+            .to_string()
+    }
+
+    fn scan_ids(scans: &Value) -> Vec<&str> {
+        scans
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|scan| scan["scanId"].as_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn selects_status_and_threat_types() {
+        assert_eq!(
+            data(r#"{ scan(id: "scan-1") { status threats { type } } }"#),
+            json!({"scan": {"status": "unsafe", "threats": [{"type": "T"}]}})
+        );
+    }
+
+    #[test]
+    fn scan_reads_the_live_store_then_the_result_store() {
+        let query = r#"{
+            running: scan(id: "scan-running") { status }
+            stored: scan(id: "scan-2") { scanId status }
+            missing: scan(id: "scan-9") { status }
+        }"#;
+        assert_eq!(
+            data(query),
+            json!({
+                "running": {"status": "scanning"},
+                "stored": {"scanId": "scan-2", "status": "safe"},
+                "missing": null,
+            })
+        );
+        // fields a result doesn't carry are null rather than errors
+        assert_eq!(
+            data(r#"{ scan(id: "scan-running") { fileInfo { sha256 } noSuchField } }"#),
+            json!({"scan": {"fileInfo": null, "noSuchField": null}})
+        );
+    }
+
+    #[test]
+    fn scans_lists_the_result_store_newest_first() {
+        let all = data("{ scans { scanId } }");
+        assert_eq!(scan_ids(&all["scans"]), ["scan-3", "scan-2", "scan-1"]);
+        // running scans aren't stored yet
+        assert!(!scan_ids(&all["scans"]).contains(&"scan-running"));
+    }
+
+    #[test]
+    fn scans_filter_arguments() {
+        let filtered = |arguments: &str| -> Vec<String> {
+            let scans = data(&format!("{{ scans({}) {{ scanId }} }}", arguments));
+            scan_ids(&scans["scans"])
+                .into_iter()
+                .map(str::to_string)
+                .collect()
+        };
+        assert_eq!(filtered(r#"status: "safe""#), ["scan-2"]);
+        assert_eq!(filtered(r#"sha256: "AA""#), ["scan-1"]);
+        assert_eq!(filtered(r#"verdict: "clean""#), ["scan-3", "scan-2"]);
+        assert_eq!(filtered(r#"tag: "ladybird""#), ["scan-1"]);
+        assert_eq!(
+            filtered(r#"since: "2024-01-15T00:00:00.000Z", until: "2024-02-15T00:00:00.000Z""#),
+            ["scan-2"]
+        );
+        assert_eq!(
+            filtered(r#"verdict: "clean" status: "suspicious""#),
+            ["scan-3"]
+        );
+        assert!(filtered(r#"status: "timeout""#).is_empty());
+        // an explicit null is no filter
+        assert_eq!(filtered("status: null").len(), 3);
+    }
+
+    #[test]
+    fn variables_and_their_defaults_fill_arguments() {
+        let query = r#"query Lookup($id: String!, $status: String = "unsafe") {
+            scan(id: $id) { status }
+            scans(status: $status) { scanId }
+        }"#;
+        let response = execute_request(json!({
+            "query": query,
+            "variables": {"id": "scan-2"},
+        }))
+        .unwrap();
+        assert_eq!(
+            response["data"],
+            json!({"scan": {"status": "safe"}, "scans": [{"scanId": "scan-1"}]})
+        );
+
+        // a sent variable wins over the default
+        let response = execute_request(json!({
+            "query": query,
+            "variables": {"id": "scan-1", "status": "suspicious"},
+        }))
+        .unwrap();
+        assert_eq!(response["data"]["scans"], json!([{"scanId": "scan-3"}]));
+
+        // one that's neither sent nor defaulted is null
+        let response = execute_request(json!({
+            "query": "query ($status: String) { scans(status: $status) { scanId } }",
+        }))
+        .unwrap();
+        assert_eq!(response["data"]["scans"].as_array().unwrap().len(), 3);
+
+        let error = execute_request(json!({"query": "{ scans { scanId } }", "variables": [1]}));
+        assert_eq!(error.unwrap_err(), "\"variables\" must be an object");
+        let error = execute_request(json!({"variables": {}}));
+        assert_eq!(error.unwrap_err(), "Request body needs a \"query\" string");
+    }
+
+    #[test]
+    fn aliases_rename_root_and_nested_fields() {
+        let query = r#"{
+            first: scan(id: "scan-1") { state: status kinds: threats { t: type } }
+            second: scan(id: "scan-2") { status }
+        }"#;
+        assert_eq!(
+            data(query),
+            json!({
+                "first": {"state": "unsafe", "kinds": [{"t": "T"}]},
+                "second": {"status": "safe"},
+            })
+        );
+    }
+
+    #[test]
+    fn tolerates_comments_commas_and_escapes() {
+        let query = "# all unsafe scans\n{ scans(status: \"un\\u0073afe\",) { scanId, }, }";
+        assert_eq!(scan_ids(&data(query)["scans"]), ["scan-1"]);
+        assert_eq!(
+            run("{ scan(id: \"scan-1) { status } }").unwrap_err(),
+            "Unterminated string"
+        );
+        assert_eq!(
+            run("{ scan(id: 'scan-1') { status } }").unwrap_err(),
+            "Unexpected character '\\''"
+        );
+    }
+
+    #[test]
+    fn rejects_operations_other_than_a_single_query() {
+        let rejected = [
+            (
+                r#"mutation { deleteScan(id: "scan-1") { status } }"#,
+                "Only queries are supported, not mutations",
+            ),
+            (
+                "subscription { scans { status } }",
+                "Only queries are supported, not subscriptions",
+            ),
+            (
+                "{ scans { scanId } } { scans { status } }",
+                "Only a single query operation is supported",
+            ),
+            ("", "Expected a query"),
+            ("{ }", "Selection sets must not be empty"),
+        ];
+        for (query, message) in rejected {
+            assert_eq!(run(query).unwrap_err(), message, "{}", query);
+        }
+    }
+
+    #[test]
+    fn rejects_fragments_and_directives() {
+        let rejected = [
+            (
+                "fragment F on Scan { status } { scans { ...F } }",
+                "Fragments are not supported",
+            ),
+            (
+                r#"{ scan(id: "scan-1") { ...F } }"#,
+                "Fragments are not supported",
+            ),
+            (
+                r#"{ scan(id: "scan-1") { ... on Scan { status } } }"#,
+                "Fragments are not supported",
+            ),
+            (
+                r#"{ scan(id: "scan-1") { status @include(if: true) } }"#,
+                "Directives are not supported",
+            ),
+        ];
+        for (query, message) in rejected {
+            assert_eq!(run(query).unwrap_err(), message, "{}", query);
+        }
+    }
+
+    #[test]
+    fn unknown_fields_and_arguments_are_errors() {
+        let response = run("{ ok: scans { scanId } bad: deleted { scanId } }").unwrap();
+        assert_eq!(
+            response,
+            json!({
+                "data": null,
+                "errors": [{"message": "Unknown field \"deleted\" on Query", "path": ["bad"]}],
+            })
+        );
+        assert_eq!(
+            field_error(r#"{ scan(id: "scan-1", force: true) { status } }"#),
+            "Unknown argument \"force\" on \"scan\""
+        );
+        assert_eq!(
+            field_error(r#"{ scans(owner: "me") { scanId } }"#),
+            "Unknown argument \"owner\" on \"scans\""
+        );
+        assert_eq!(
+            field_error(r#"{ scan(id: "scan-1") { status(format: "short") } }"#),
+            "Field \"status\" takes no arguments"
+        );
+        assert_eq!(
+            field_error("{ scan { status } }"),
+            "Field \"scan\" requires an \"id\" argument"
+        );
+        assert_eq!(
+            field_error("{ scans(status: 1) { scanId } }"),
+            "Argument \"status\" of \"scans\" must be a string, got 1"
+        );
+    }
+
+    #[test]
+    fn object_fields_need_a_selection_and_leaves_refuse_one() {
+        let cases = [
+            (
+                r#"{ scan(id: "scan-1") }"#,
+                "Field \"scan\" must have a selection of subfields",
+            ),
+            (
+                "{ scans }",
+                "Field \"scans\" must have a selection of subfields",
+            ),
+            (
+                r#"{ scan(id: "scan-1") { threats } }"#,
+                "Field \"threats\" must have a selection of subfields",
+            ),
+            (
+                r#"{ scan(id: "scan-1") { stats } }"#,
+                "Field \"stats\" must have a selection of subfields",
+            ),
+            (
+                r#"{ scan(id: "scan-1") { status { length } } }"#,
+                "Field \"status\" has no subfields",
+            ),
+        ];
+        for (query, message) in cases {
+            assert_eq!(field_error(query), message, "{}", query);
+        }
+        // lists of plain values need no selection
+        assert_eq!(
+            data(r#"{ scan(id: "scan-1") { guids } }"#),
+            json!({"scan": {"guids": []}})
+        );
+    }
+
+    #[test]
+    fn rejects_deeply_nested_values() {
+        // ~60KB of nested lists used to overflow the stack
+        let depth = 30_000;
+        let query = format!(
+            "{{ scan(id: {}\"scan-1\"{}) {{ status }} }}",
+            "[".repeat(depth),
+            "]".repeat(depth)
+        );
+        let error = run(&query).unwrap_err();
+        assert!(error.contains("nested deeper"), "{}", error);
+        // the same store still answers afterwards
+        assert!(run(r#"{ scan(id: "scan-1") { status } }"#).is_ok());
+    }
+
+    #[test]
+    fn rejects_deeply_nested_selections_and_types() {
+        let depth = MAX_GRAPHQL_DEPTH + 1;
+        let selections = format!("{}status{}", "{ a ".repeat(depth), " }".repeat(depth));
+        assert!(run(&selections).unwrap_err().contains("nested deeper"));
+
+        let types = format!(
+            "query ($id: {}String{}) {{ scan(id: $id) {{ status }} }}",
+            "[".repeat(depth),
+            "]".repeat(depth)
+        );
+        assert!(run(&types).unwrap_err().contains("nested deeper"));
+    }
+
+    #[test]
+    fn accepts_nesting_up_to_the_limit() {
+        let query = format!(
+            "{{ scan(id: {}\"scan-1\"{}) {{ status }} }}",
+            "[".repeat(MAX_GRAPHQL_DEPTH - 1),
+            "]".repeat(MAX_GRAPHQL_DEPTH - 1)
+        );
+        // a list id is the wrong type, but it gets past the parser
+        let response = run(&query).unwrap();
+        assert!(response["errors"][0]["message"]
+            .as_str()
+            .unwrap()
+            .contains("must be a string"));
+    }
+}
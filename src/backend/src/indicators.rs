@@ -1,34 +1,1030 @@
-use crate::types::Threat;
-
-pub fn check_indicators(content: &str) -> Vec<Threat> {
-    let mut threats = Vec::new();
-
-    if content.contains("malware") || content.contains("virus") {
-        threats.push(Threat {
-            threat_type: "Suspicious String".to_string(),
-            details: "File contains suspicious keywords".to_string(),
-            severity: "suspicious".to_string(),
-            threat_id: "S001".to_string(),
-        });
-    }
-
-    if content.contains("CreateRemoteThread") && content.contains("VirtualAllocEx") {
-        threats.push(Threat {
-            threat_type: "Process Injection API".to_string(),
-            details: "Contains process injection function calls".to_string(),
-            severity: "malicious".to_string(),
-            threat_id: "S002".to_string(),
-        });
-    }
-
-    if content.contains("RegSetValue") && content.contains("RegCreateKey") {
-        threats.push(Threat {
-            threat_type: "Registry Modification".to_string(),
-            details: "Contains registry manipulation functions".to_string(),
-            severity: "suspicious".to_string(),
-            threat_id: "S003".to_string(),
-        });
-    }
-
-    threats
-}
+use crate::analysis::*;
+use crate::archive::embedded_archive;
+use crate::config::*;
+use crate::pe::*;
+use crate::rules::Rule;
+use crate::types::{ImportedDll, Resource, Threat};
+
+// IDs of the threats the scorer weighs together, see `scanner::threat_score`
+pub const HIGH_ENTROPY_SECTION_ID: &str = "P003";
+pub const PACKER_DETECTED_ID: &str = "P014";
+
+// `imported_functions` are names from the import table, so API rules only
+// fire on real imports rather than stray text; content and regex rules run on
+// the extracted `strings`, so matches can't straddle binary garbage
+pub fn check_indicators(
+    imported_functions: &[String],
+    strings: &[String],
+    rules: &[Rule],
+) -> Vec<Threat> {
+    rules
+        .iter()
+        .filter(|rule| rule.is_match(imported_functions, strings))
+        .map(|rule| rule.to_threat())
+        .collect()
+}
+
+pub fn check_pe_anomalies(content: &[u8], headers: &PeHeaders) -> Vec<Threat> {
+    let mut threats = Vec::new();
+
+    // signing tools fix up the checksum, so a bad one means the file changed after signing
+    if has_signature(content, headers) {
+        let computed = compute_checksum(content, headers.checksum_offset);
+        if headers.checksum == 0 || headers.checksum != computed {
+            threats.push(Threat {
+                threat_type: "Signed but Checksum Invalid".to_string(),
+                details: format!(
+                    "Signed binary has checksum 0x{:08x}, expected 0x{:08x}",
+                    headers.checksum, computed
+                ),
+                severity: "suspicious".to_string(),
+                threat_id: "P001".to_string(),
+            });
+        }
+    }
+
+    let anomalies = validate_certificates(content, headers);
+    if !anomalies.is_empty() {
+        threats.push(Threat {
+            threat_type: "Malformed Certificate Table".to_string(),
+            details: format!("Certificate table anomalies: {}", anomalies.join("; ")),
+            severity: "suspicious".to_string(),
+            threat_id: "P002".to_string(),
+        });
+    }
+
+    let anomalies = validate_relocations(content, headers);
+    if !anomalies.is_empty() {
+        threats.push(Threat {
+            threat_type: "Malformed Relocations".to_string(),
+            details: format!("Relocation anomalies: {}", anomalies.join("; ")),
+            severity: "suspicious".to_string(),
+            threat_id: "P005".to_string(),
+        });
+    }
+
+    // fewer entries hide directories from tools that assume 16, more can make
+    // naive parsers read the section table as directories
+    if headers.number_of_rva_and_sizes != IMAGE_NUMBEROF_DIRECTORY_ENTRIES {
+        threats.push(Threat {
+            threat_type: "Non-standard Data Directory Count".to_string(),
+            details: format!(
+                "Optional header declares {} data directories instead of {}",
+                headers.number_of_rva_and_sizes, IMAGE_NUMBEROF_DIRECTORY_ENTRIES
+            ),
+            severity: "suspicious".to_string(),
+            threat_id: "P008".to_string(),
+        });
+    }
+
+    let anomalies = headers.header_anomalies();
+    if anomalies.len() >= CRAFTED_HEADER_MIN_ANOMALIES {
+        threats.push(Threat {
+            threat_type: "Crafted Header".to_string(),
+            details: format!("Inconsistent header flags: {}", anomalies.join("; ")),
+            severity: "suspicious".to_string(),
+            threat_id: "P012".to_string(),
+        });
+    }
+
+    threats
+}
+
+pub fn check_section_entropy(content: &[u8], headers: &PeHeaders) -> Vec<Threat> {
+    let mut threats = Vec::new();
+
+    for section in headers.sections.iter().filter(|s| s.is_executable()) {
+        let section_entropy = entropy(section.raw_data(content));
+        if section_entropy > HIGH_ENTROPY_THRESHOLD {
+            threats.push(Threat {
+                threat_type: "High Entropy Section".to_string(),
+                details: format!(
+                    "Executable section {} has entropy {:.2}, likely packed or encrypted",
+                    section.name, section_entropy
+                ),
+                severity: "suspicious".to_string(),
+                threat_id: HIGH_ENTROPY_SECTION_ID.to_string(),
+            });
+        }
+    }
+
+    threats
+}
+
+// `packer` is what `RuleSet::detect_packer` found
+pub fn check_packer(packer: Option<&(String, String)>) -> Vec<Threat> {
+    let Some((name, section)) = packer else {
+        return vec![];
+    };
+    vec![Threat {
+        threat_type: "Packer Detected".to_string(),
+        details: format!("Section {} matches the {} packer", section, name),
+        severity: "suspicious".to_string(),
+        threat_id: PACKER_DETECTED_ID.to_string(),
+    }]
+}
+
+// droppers ship their second stage as a resource and write it out at runtime
+pub fn check_resources(resources: &[Resource]) -> Vec<Threat> {
+    let embedded: Vec<String> = resources
+        .iter()
+        .filter(|r| r.signature.as_deref() == Some("MZ"))
+        .map(|r| format!("{}/{} ({} bytes)", r.resource_type, r.name, r.size))
+        .collect();
+    if embedded.is_empty() {
+        return vec![];
+    }
+
+    vec![Threat {
+        threat_type: "Executable in Resources".to_string(),
+        details: format!("Resources start with an MZ header: {}", embedded.join(", ")),
+        severity: "suspicious".to_string(),
+        threat_id: "P011".to_string(),
+    }]
+}
+
+// a PE that is also a valid file of another format slips past scanners that
+// only look at the first magic bytes
+pub fn check_polyglot(content: &[u8]) -> Vec<Threat> {
+    let mut formats = Vec::new();
+    formats.extend(embedded_archive(content));
+    if contains_pdf(content) {
+        formats.push("PDF");
+    }
+    if formats.is_empty() {
+        return vec![];
+    }
+
+    vec![Threat {
+        threat_type: "Polyglot File".to_string(),
+        details: format!("File is valid as PE and {}", formats.join(", ")),
+        severity: "suspicious".to_string(),
+        threat_id: "P013".to_string(),
+    }]
+}
+
+// appended payloads are how droppers and installers carry their cargo
+pub fn check_overlay(overlay: &[&[u8]]) -> Vec<Threat> {
+    let size: usize = overlay.iter().map(|piece| piece.len()).sum();
+    let mut counts = [0; 256];
+    for piece in overlay {
+        for (count, n) in counts.iter_mut().zip(byte_histogram(piece)) {
+            *count += n;
+        }
+    }
+    let overlay_entropy = histogram_entropy(&counts);
+
+    let high_entropy = size >= OVERLAY_MIN_ENTROPY_SIZE && overlay_entropy > HIGH_ENTROPY_THRESHOLD;
+    if size < OVERLAY_LARGE_SIZE && !high_entropy {
+        return vec![];
+    }
+
+    vec![Threat {
+        threat_type: "Overlay Data".to_string(),
+        details: format!(
+            "{} bytes appended after the last section, entropy {:.2}",
+            size, overlay_entropy
+        ),
+        severity: "suspicious".to_string(),
+        threat_id: "P010".to_string(),
+    }]
+}
+
+// `gaps` is what `section_gaps` found; data parked there is loaded with the
+// file but belongs to no section, so tools walking the sections never see it
+pub fn check_section_padding(gaps: &[(usize, &[u8], &str)]) -> Vec<Threat> {
+    let hidden: Vec<String> = gaps
+        .iter()
+        .filter_map(|(offset, gap, section)| {
+            let data = gap.iter().filter(|&&b| b != 0).count();
+            if data < SECTION_GAP_MIN_DATA {
+                return None;
+            }
+            Some(format!(
+                "0x{:x}-0x{:x} after {} ({} non-zero bytes, entropy {:.2})",
+                offset,
+                offset + gap.len(),
+                section,
+                data,
+                entropy(gap)
+            ))
+        })
+        .collect();
+    if hidden.is_empty() {
+        return vec![];
+    }
+
+    vec![Threat {
+        threat_type: "Hidden Data in Section Padding".to_string(),
+        details: format!("Data between sections at {}", hidden.join(", ")),
+        severity: "suspicious".to_string(),
+        threat_id: "P015".to_string(),
+    }]
+}
+
+// small PEs are mostly alignment padding, so only large files are judged
+pub fn check_sparse(content: &[u8]) -> Vec<Threat> {
+    if content.len() < SPARSE_MIN_SIZE {
+        return vec![];
+    }
+
+    let zero_ratio = histogram_zero_ratio(&byte_histogram(content));
+    let severity = if zero_ratio >= SPARSE_SUSPICIOUS_RATIO {
+        "suspicious"
+    } else if zero_ratio >= SPARSE_NOTE_RATIO {
+        "neutral"
+    } else {
+        return vec![];
+    };
+
+    vec![Threat {
+        threat_type: "Sparse/Padded File".to_string(),
+        details: format!(
+            "{:.1}% of {} bytes are zero, possibly padded to evade size or entropy checks",
+            zero_ratio * 100.0,
+            content.len()
+        ),
+        severity: severity.to_string(),
+        threat_id: "P007".to_string(),
+    }]
+}
+
+// `known_bad` comes from the rules file, see `RuleSet::guids`
+pub fn check_guids(guids: &[String], known_bad: &[String]) -> Vec<Threat> {
+    guids
+        .iter()
+        .filter(|guid| known_bad.contains(guid))
+        .map(|guid| Threat {
+            threat_type: "Known Malicious GUID".to_string(),
+            details: format!("Contains GUID {} associated with known malware", guid),
+            severity: "malicious".to_string(),
+            threat_id: "S004".to_string(),
+        })
+        .collect()
+}
+
+// bundled or custom helper DLLs aren't malicious by themselves, so this is
+// only a neutral note for the analyst to follow up on
+pub fn check_uncommon_dlls(imports: &[ImportedDll]) -> Vec<Threat> {
+    let uncommon: Vec<&str> = imports
+        .iter()
+        .map(|import| import.dll.as_str())
+        .filter(|dll| {
+            let dll = dll.to_lowercase();
+            !COMMON_DLLS.contains(&dll.as_str())
+                && !COMMON_DLL_PREFIXES
+                    .iter()
+                    .any(|prefix| dll.starts_with(prefix))
+        })
+        .collect();
+
+    if uncommon.is_empty() {
+        return vec![];
+    }
+
+    vec![Threat {
+        threat_type: "Uncommon DLL Imports".to_string(),
+        details: format!("Imports from uncommon DLLs: {}", uncommon.join(", ")),
+        severity: "neutral".to_string(),
+        threat_id: "P006".to_string(),
+    }]
+}
+
+// import and string evidence are gathered separately; either alone is
+// suspicious, both together is treated as malicious
+pub fn check_anti_forensics(imported_functions: &[String], strings: &[String]) -> Vec<Threat> {
+    let lowered: Vec<String> = strings.iter().map(|s| s.to_lowercase()).collect();
+    let touches_logs = lowered
+        .iter()
+        .any(|s| EVENT_LOG_PATHS.iter().any(|path| s.contains(path)));
+
+    let mut import_evidence: Vec<&str> = imported_functions
+        .iter()
+        .filter(|f| {
+            LOG_CLEARING_IMPORTS.iter().any(|api| f.starts_with(api))
+                || (touches_logs && f.starts_with("DeleteFile"))
+        })
+        .map(|f| f.as_str())
+        .collect();
+    import_evidence.sort_unstable();
+    import_evidence.dedup();
+
+    let string_evidence: Vec<&str> = ANTI_FORENSIC_STRINGS
+        .iter()
+        .copied()
+        .filter(|needle| lowered.iter().any(|s| s.contains(needle)))
+        .collect();
+
+    if import_evidence.is_empty() && string_evidence.is_empty() {
+        return vec![];
+    }
+
+    let severity = if !import_evidence.is_empty() && !string_evidence.is_empty() {
+        "malicious"
+    } else {
+        "suspicious"
+    };
+    let evidence: Vec<&str> = import_evidence.into_iter().chain(string_evidence).collect();
+
+    vec![Threat {
+        threat_type: "Anti-Forensics".to_string(),
+        details: format!("Clears logs or shadow copies: {}", evidence.join(", ")),
+        severity: severity.to_string(),
+        threat_id: "S007".to_string(),
+    }]
+}
+
+// most legitimate vendor binaries are signed, so this only adds context
+pub fn check_unsigned(signed: bool) -> Vec<Threat> {
+    if signed {
+        return Vec::new();
+    }
+    vec![Threat {
+        threat_type: "Unsigned Executable".to_string(),
+        details: "No Authenticode signature in the security directory".to_string(),
+        severity: "neutral".to_string(),
+        threat_id: "P009".to_string(),
+    }]
+}
+
+// ntdll's Nt/Zw system call stubs imported directly, skipping the Win32
+// layer that hooks and monitoring sit on; suspicious alone, malicious once
+// they add up to an injection chain with other native or Win32 primitives
+pub fn check_native_api(imports: &[ImportedDll]) -> Vec<Threat> {
+    let mut native: Vec<&str> = imports
+        .iter()
+        .filter(|dll| dll.dll.eq_ignore_ascii_case("ntdll.dll"))
+        .flat_map(|dll| dll.functions.iter().map(|f| f.as_str()))
+        .filter(|f| f.starts_with("Nt") || f.starts_with("Zw"))
+        .collect();
+    if native.is_empty() {
+        return vec![];
+    }
+    native.sort_unstable();
+    native.dedup();
+
+    let is_native_injection = |f: &str| {
+        let name = match f.strip_prefix("Zw") {
+            Some(rest) => format!("Nt{}", rest),
+            None => f.to_string(),
+        };
+        NATIVE_INJECTION_APIS.contains(&name.as_str())
+    };
+    let native_injection = native.iter().any(|f| is_native_injection(f));
+    let mut injection: Vec<&str> = imports
+        .iter()
+        .flat_map(|dll| dll.functions.iter().map(|f| f.as_str()))
+        .filter(|f| {
+            is_native_injection(f) || DANGEROUS_IMPORTS.iter().any(|api| f.starts_with(api))
+        })
+        .collect();
+    injection.sort_unstable();
+    injection.dedup();
+
+    let severity = if native_injection && injection.len() >= 2 {
+        "malicious"
+    } else {
+        "suspicious"
+    };
+    let mut details = format!("Imports ntdll system calls directly: {}", native.join(", "));
+    if severity == "malicious" {
+        details.push_str(&format!("; injection chain: {}", injection.join(", ")));
+    }
+
+    vec![Threat {
+        threat_type: "Direct Native API Usage".to_string(),
+        details,
+        severity: severity.to_string(),
+        threat_id: "S010".to_string(),
+    }]
+}
+
+// resolves winsock imports made by ordinal ("#23") to their export names
+fn import_name<'a>(dll: &str, function: &'a str) -> &'a str {
+    let winsock = matches!(dll.to_lowercase().as_str(), "ws2_32.dll" | "wsock32.dll");
+    function
+        .strip_prefix('#')
+        .filter(|_| winsock)
+        .and_then(|ordinal| ordinal.parse::<u16>().ok())
+        .and_then(|ordinal| WINSOCK_ORDINALS.iter().find(|(n, _)| *n == ordinal))
+        .map_or(function, |(_, name)| name)
+}
+
+// a socket both opened and used directly, rather than through an HTTP
+// library; suspicious alone, malicious with a hardcoded IP to talk to
+pub fn check_raw_network(imports: &[ImportedDll], strings: &[String]) -> Vec<Threat> {
+    let names: Vec<&str> = imports
+        .iter()
+        .flat_map(|dll| dll.functions.iter().map(|f| import_name(&dll.dll, f)))
+        .collect();
+    let mut creates: Vec<&str> = names
+        .iter()
+        .copied()
+        .filter(|f| SOCKET_CREATE_IMPORTS.contains(f))
+        .collect();
+    let mut io: Vec<&str> = names
+        .iter()
+        .copied()
+        .filter(|f| SOCKET_IO_IMPORTS.contains(f))
+        .collect();
+    if creates.is_empty() || io.is_empty() {
+        return vec![];
+    }
+    creates.sort_unstable();
+    creates.dedup();
+    io.sort_unstable();
+    io.dedup();
+
+    let mut details = format!("Uses raw sockets: {}", [creates, io].concat().join(", "));
+    let ips = extract_ipv4s(strings);
+    let severity = if ips.is_empty() {
+        "suspicious"
+    } else {
+        details.push_str(&format!("; hardcoded IPs: {}", ips.join(", ")));
+        "malicious"
+    };
+
+    vec![Threat {
+        threat_type: "Raw Network Communication".to_string(),
+        details,
+        severity: severity.to_string(),
+        threat_id: "S008".to_string(),
+    }]
+}
+
+// malware that resolves its APIs by hashed name imports little beyond the
+// resolver, keeps no readable API names and hashes export names in a tight
+// loop; each alone is common, so all three have to line up
+pub fn check_api_hashing(
+    imported_functions: &[String],
+    strings: &[String],
+    hash_loop: Option<usize>,
+) -> Vec<Threat> {
+    let Some(offset) = hash_loop else {
+        return vec![];
+    };
+    let resolvers: Vec<&str> = imported_functions
+        .iter()
+        .map(|f| f.as_str())
+        .filter(|f| *f == "GetProcAddress" || f.starts_with("LoadLibrary"))
+        .collect();
+    let readable_names = strings
+        .iter()
+        .any(|s| DANGEROUS_IMPORTS.iter().any(|api| s.starts_with(api)));
+    if resolvers.is_empty() || imported_functions.len() > API_HASH_MAX_IMPORTS || readable_names {
+        return vec![];
+    }
+
+    vec![Threat {
+        threat_type: "API Hashing".to_string(),
+        details: format!(
+            "Only {} imports ({}), no API names in strings, hashing loop at offset 0x{:x}",
+            imported_functions.len(),
+            resolvers.join(", "),
+            offset
+        ),
+        severity: "malicious".to_string(),
+        threat_id: "S009".to_string(),
+    }]
+}
+
+pub fn check_signature_trust(trust: Option<&str>) -> Vec<Threat> {
+    let mut threats = Vec::new();
+
+    if let Some(trust @ ("self-signed" | "test")) = trust {
+        threats.push(Threat {
+            threat_type: "Self-Signed Certificate".to_string(),
+            details: format!(
+                "Signed with a {} certificate rather than one chained to a CA",
+                trust
+            ),
+            severity: "neutral".to_string(),
+            threat_id: "P004".to_string(),
+        });
+    }
+
+    threats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{
+        noise, relocation_block, win_certificate, zip_archive, PeBuilder, FIRST_EXTRA_RVA,
+        SECTION_CODE, SECTION_DATA, SECTION_READ_ONLY,
+    };
+
+    // IDs of the header anomalies found in a built PE
+    fn anomaly_ids(pe: &[u8]) -> Vec<String> {
+        let headers = parse_headers(pe).unwrap();
+        check_pe_anomalies(pe, &headers)
+            .into_iter()
+            .map(|t| t.threat_id)
+            .collect()
+    }
+
+    fn signed() -> PeBuilder {
+        PeBuilder::new().certificate(&win_certificate(b"\x30\x00", 0x200, 2))
+    }
+
+    fn imports(dlls: &[&str]) -> Vec<ImportedDll> {
+        dlls.iter()
+            .map(|dll| ImportedDll {
+                dll: dll.to_string(),
+                functions: vec![],
+            })
+            .collect()
+    }
+
+    fn owned(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn uncommon_dlls_are_a_neutral_note() {
+        let threats = check_uncommon_dlls(&imports(&["KERNEL32.dll", "helper.dll", "evil32.dll"]));
+        assert_eq!(threats.len(), 1);
+        assert_eq!(threats[0].threat_id, "P006");
+        assert_eq!(threats[0].severity, "neutral");
+        assert_eq!(
+            threats[0].details,
+            "Imports from uncommon DLLs: helper.dll, evil32.dll"
+        );
+    }
+
+    #[test]
+    fn system_and_runtime_dlls_are_common() {
+        let dlls = [
+            "kernel32.dll",
+            "USER32.DLL",
+            "api-ms-win-core-synch-l1-2-0.dll",
+            "VCRUNTIME140.dll",
+            "msvcp140.dll",
+            "ucrtbase.dll",
+        ];
+        assert!(check_uncommon_dlls(&imports(&dlls)).is_empty());
+        assert!(check_uncommon_dlls(&[]).is_empty());
+    }
+
+    #[test]
+    fn packer_threat_names_the_packer() {
+        assert!(check_packer(None).is_empty());
+        let threats = check_packer(Some(&("UPX".to_string(), "UPX1".to_string())));
+        assert_eq!(threats.len(), 1);
+        assert_eq!(threats[0].threat_id, PACKER_DETECTED_ID);
+        assert_eq!(threats[0].details, "Section UPX1 matches the UPX packer");
+    }
+
+    #[test]
+    fn polyglot_names_every_other_format() {
+        let pe = PeBuilder::new().build();
+        assert!(check_polyglot(&pe).is_empty());
+
+        let zip = zip_archive(&[("readme.txt", b"hello")]);
+        let threats = check_polyglot(&[pe.as_slice(), &zip].concat());
+        assert_eq!(threats.len(), 1);
+        assert_eq!(threats[0].threat_id, "P013");
+        assert_eq!(threats[0].details, "File is valid as PE and ZIP");
+
+        let jar = zip_archive(&[("META-INF/MANIFEST.MF", b"Manifest-Version: 1.0\r\n")]);
+        let threats = check_polyglot(&[pe.as_slice(), &jar].concat());
+        assert_eq!(threats[0].details, "File is valid as PE and JAR");
+
+        // the PDF header hides in the DOS stub, the trailer after the zip
+        let mut both = [pe.as_slice(), &zip, b"%%EOF\n"].concat();
+        both[0x40..0x48].copy_from_slice(b"%PDF-1.7");
+        let threats = check_polyglot(&both);
+        assert_eq!(threats[0].details, "File is valid as PE and ZIP, PDF");
+    }
+
+    #[test]
+    fn pdf_markers_must_sit_near_the_ends() {
+        let mut data = vec![0u8; 4 * PDF_MARKER_WINDOW];
+        data[..5].copy_from_slice(b"%PDF-");
+        let end = data.len();
+        data[end - 6..end - 1].copy_from_slice(b"%%EOF");
+        assert!(contains_pdf(&data));
+
+        data[..5].fill(0);
+        data[PDF_MARKER_WINDOW..PDF_MARKER_WINDOW + 5].copy_from_slice(b"%PDF-");
+        assert!(!contains_pdf(&data));
+        assert!(!contains_pdf(b"%PDF-"));
+    }
+
+    #[test]
+    fn signed_binary_with_a_valid_checksum_passes() {
+        assert!(anomaly_ids(&signed().checksum().build()).is_empty());
+    }
+
+    #[test]
+    fn signed_binary_changed_after_signing_is_flagged() {
+        let mut pe = signed().checksum().build();
+        let text = parse_headers(&pe).unwrap().sections[0].raw_offset as usize;
+        pe[text] ^= 0xff;
+        let headers = parse_headers(&pe).unwrap();
+        let threats = check_pe_anomalies(&pe, &headers);
+        assert_eq!(threats.len(), 1);
+        assert_eq!(threats[0].threat_id, "P001");
+        assert_eq!(threats[0].severity, "suspicious");
+        assert!(threats[0]
+            .details
+            .starts_with("Signed binary has checksum 0x"));
+
+        // signing always fills the checksum in, so zero counts as wrong too
+        assert_eq!(anomaly_ids(&signed().build()), vec!["P001"]);
+    }
+
+    #[test]
+    fn unsigned_binary_checksum_is_not_checked() {
+        // linkers leave it zero unless asked, so it means nothing unsigned
+        assert!(anomaly_ids(&PeBuilder::new().build()).is_empty());
+        let mut pe = PeBuilder::new().checksum().build();
+        pe[0x300] ^= 0xff;
+        assert!(anomaly_ids(&pe).is_empty());
+    }
+
+    #[test]
+    fn malformed_certificate_table_is_suspicious() {
+        let mut table = win_certificate(&[0x30; 8], 0x200, 2);
+        table[..4].copy_from_slice(&0x0010_0000u32.to_le_bytes());
+        let pe = PeBuilder::new().certificate(&table).checksum().build();
+        let headers = parse_headers(&pe).unwrap();
+        let threats = check_pe_anomalies(&pe, &headers);
+        assert_eq!(threats.len(), 1);
+        assert_eq!(threats[0].threat_type, "Malformed Certificate Table");
+        assert_eq!(threats[0].threat_id, "P002");
+        assert_eq!(threats[0].severity, "suspicious");
+    }
+
+    #[test]
+    fn out_of_range_relocations_are_suspicious() {
+        let table = relocation_block(0x7000, &[0x40]);
+        let pe = PeBuilder::new()
+            .section(".reloc", &table, SECTION_READ_ONLY)
+            .directory(
+                IMAGE_DIRECTORY_ENTRY_BASERELOC,
+                FIRST_EXTRA_RVA,
+                table.len() as u32,
+            )
+            .build();
+        let headers = parse_headers(&pe).unwrap();
+        let threats = check_pe_anomalies(&pe, &headers);
+        assert_eq!(threats.len(), 1);
+        assert_eq!(threats[0].threat_type, "Malformed Relocations");
+        assert_eq!(threats[0].threat_id, "P005");
+        assert_eq!(threats[0].severity, "suspicious");
+        assert!(threats[0]
+            .details
+            .contains("RVA 0x7040 outside any section"));
+    }
+
+    #[test]
+    fn high_entropy_executable_sections_are_flagged() {
+        let pe = PeBuilder::new()
+            .section(".packed", &noise(4096), SECTION_CODE)
+            .section(".rsrc", &noise(4096), SECTION_DATA)
+            .build();
+        let threats = check_section_entropy(&pe, &parse_headers(&pe).unwrap());
+        assert_eq!(threats.len(), 1);
+        assert_eq!(threats[0].threat_id, HIGH_ENTROPY_SECTION_ID);
+        assert_eq!(threats[0].severity, "suspicious");
+        assert!(threats[0]
+            .details
+            .starts_with("Executable section .packed has entropy 7.9"));
+
+        let plain = PeBuilder::new().build();
+        assert!(check_section_entropy(&plain, &parse_headers(&plain).unwrap()).is_empty());
+    }
+
+    #[test]
+    fn shadow_copy_deletion_string_is_suspicious() {
+        let strings = owned(&["cmd.exe /c VSSADMIN Delete Shadows /all /quiet"]);
+        let threats = check_anti_forensics(&owned(&["CreateProcessW"]), &strings);
+        assert_eq!(threats.len(), 1);
+        assert_eq!(threats[0].threat_type, "Anti-Forensics");
+        assert_eq!(threats[0].threat_id, "S007");
+        assert_eq!(threats[0].severity, "suspicious");
+        assert_eq!(
+            threats[0].details,
+            "Clears logs or shadow copies: vssadmin delete shadows"
+        );
+    }
+
+    #[test]
+    fn log_clearing_imports_and_strings_together_are_malicious() {
+        let imports = owned(&["ClearEventLogW", "OpenEventLogW", "ClearEventLogW"]);
+        let threats = check_anti_forensics(&imports, &[]);
+        assert_eq!(threats[0].severity, "suspicious");
+        assert_eq!(
+            threats[0].details,
+            "Clears logs or shadow copies: ClearEventLogW"
+        );
+
+        let strings = owned(&["wevtutil cl Security"]);
+        let threats = check_anti_forensics(&imports, &strings);
+        assert_eq!(threats[0].severity, "malicious");
+        assert_eq!(
+            threats[0].details,
+            "Clears logs or shadow copies: ClearEventLogW, wevtutil cl"
+        );
+    }
+
+    #[test]
+    fn deleting_files_only_counts_next_to_event_log_paths() {
+        let imports = owned(&["DeleteFileW", "CreateFileW"]);
+        assert!(check_anti_forensics(&imports, &owned(&["C:\\temp\\setup.log"])).is_empty());
+
+        let strings = owned(&["C:\\Windows\\System32\\winevt\\Logs\\Security.evtx"]);
+        let threats = check_anti_forensics(&imports, &strings);
+        assert_eq!(threats.len(), 1);
+        assert_eq!(threats[0].severity, "suspicious");
+        assert!(threats[0].details.ends_with(": DeleteFileW"));
+    }
+
+    // `len` bytes, the first `data` of them noise and the rest zero
+    fn padded(data: usize, len: usize) -> Vec<u8> {
+        let mut content = noise(data);
+        content.resize(len, 0);
+        content
+    }
+
+    #[test]
+    fn heavily_zero_padded_files_are_suspicious() {
+        let threats = check_sparse(&padded(32 * 1024, SPARSE_MIN_SIZE * 2));
+        assert_eq!(threats.len(), 1);
+        assert_eq!(threats[0].threat_type, "Sparse/Padded File");
+        assert_eq!(threats[0].threat_id, "P007");
+        assert_eq!(threats[0].severity, "suspicious");
+        assert!(threats[0]
+            .details
+            .contains(&format!("of {} bytes are zero", SPARSE_MIN_SIZE * 2)));
+    }
+
+    #[test]
+    fn sparse_severity_follows_the_zero_ratio() {
+        // 90% zero is only worth a note
+        let threats = check_sparse(&padded(SPARSE_MIN_SIZE / 10, SPARSE_MIN_SIZE));
+        assert_eq!(threats[0].severity, "neutral");
+        assert!(threats[0].details.starts_with("90.0% of"));
+
+        assert!(check_sparse(&padded(SPARSE_MIN_SIZE / 2, SPARSE_MIN_SIZE)).is_empty());
+        // small files are mostly alignment padding anyway
+        assert!(check_sparse(&vec![0; SPARSE_MIN_SIZE - 1]).is_empty());
+    }
+
+    #[test]
+    fn fewer_than_sixteen_data_directories_is_suspicious() {
+        let pe = PeBuilder::new().data_directories(6).build();
+        let headers = parse_headers(&pe).unwrap();
+        let threats = check_pe_anomalies(&pe, &headers);
+        assert_eq!(threats.len(), 1);
+        assert_eq!(threats[0].threat_type, "Non-standard Data Directory Count");
+        assert_eq!(threats[0].threat_id, "P008");
+        assert_eq!(threats[0].severity, "suspicious");
+        assert_eq!(
+            threats[0].details,
+            "Optional header declares 6 data directories instead of 16"
+        );
+
+        assert!(anomaly_ids(&PeBuilder::new().data_directories(17).build())
+            .contains(&"P008".to_string()));
+        assert!(anomaly_ids(&PeBuilder::new().build()).is_empty());
+    }
+
+    fn dll(name: &str, functions: &[&str]) -> ImportedDll {
+        ImportedDll {
+            dll: name.to_string(),
+            functions: owned(functions),
+        }
+    }
+
+    #[test]
+    fn raw_sockets_with_a_hardcoded_ip_are_malicious() {
+        let imports = [dll(
+            "WS2_32.dll",
+            &["WSAStartup", "socket", "connect", "send", "recv", "send"],
+        )];
+        let threats = check_raw_network(&imports, &owned(&["host 185.220.101.4 port 443"]));
+        assert_eq!(threats.len(), 1);
+        assert_eq!(threats[0].threat_type, "Raw Network Communication");
+        assert_eq!(threats[0].threat_id, "S008");
+        assert_eq!(threats[0].severity, "malicious");
+        assert_eq!(
+            threats[0].details,
+            "Uses raw sockets: socket, connect, recv, send; hardcoded IPs: 185.220.101.4"
+        );
+
+        let threats = check_raw_network(&imports, &owned(&["no address here"]));
+        assert_eq!(threats[0].severity, "suspicious");
+        assert_eq!(
+            threats[0].details,
+            "Uses raw sockets: socket, connect, recv, send"
+        );
+    }
+
+    #[test]
+    fn raw_sockets_need_both_creation_and_io() {
+        let ip = owned(&["10.0.0.1"]);
+        assert!(check_raw_network(&[dll("ws2_32.dll", &["WSAStartup", "socket"])], &ip).is_empty());
+        assert!(check_raw_network(&[dll("ws2_32.dll", &["send", "recv"])], &ip).is_empty());
+        assert!(check_raw_network(&[], &ip).is_empty());
+    }
+
+    #[test]
+    fn winsock_ordinals_resolve_to_names() {
+        // socket and connect by ordinal, as many winsock imports are
+        let imports = [dll("WSOCK32.dll", &["#23", "#4"])];
+        let threats = check_raw_network(&imports, &[]);
+        assert_eq!(threats[0].details, "Uses raw sockets: socket, connect");
+
+        // other DLLs' ordinals mean something else
+        assert!(check_raw_network(&[dll("helper.dll", &["#23", "#4"])], &[]).is_empty());
+    }
+
+    #[test]
+    fn large_or_high_entropy_overlays_are_suspicious() {
+        assert!(check_overlay(&[]).is_empty());
+        assert!(check_overlay(&[&[0x41; 4096]]).is_empty());
+
+        let packed = noise(OVERLAY_MIN_ENTROPY_SIZE);
+        let threats = check_overlay(&[&packed]);
+        assert_eq!(threats.len(), 1);
+        assert_eq!(threats[0].threat_type, "Overlay Data");
+        assert_eq!(threats[0].threat_id, "P010");
+        assert_eq!(threats[0].severity, "suspicious");
+        assert!(threats[0].details.starts_with(&format!(
+            "{} bytes appended after the last section, entropy 7.",
+            packed.len()
+        )));
+        // too short for its entropy to mean much
+        assert!(check_overlay(&[&packed[..OVERLAY_MIN_ENTROPY_SIZE / 2]]).is_empty());
+
+        let large = vec![0; OVERLAY_LARGE_SIZE];
+        let threats = check_overlay(&[
+            &large[..OVERLAY_LARGE_SIZE / 2],
+            &large[OVERLAY_LARGE_SIZE / 2..],
+        ]);
+        assert_eq!(
+            threats[0].details,
+            format!(
+                "{} bytes appended after the last section, entropy 0.00",
+                OVERLAY_LARGE_SIZE
+            )
+        );
+    }
+
+    fn resource(resource_type: &str, name: &str, signature: Option<&str>) -> Resource {
+        Resource {
+            resource_type: resource_type.to_string(),
+            name: name.to_string(),
+            language: 0,
+            size: 4096,
+            signature: signature.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn executable_resources_are_suspicious() {
+        let resources = [
+            resource("ICON", "#1", Some("PNG")),
+            resource("RCDATA", "PAYLOAD", Some("MZ")),
+            resource("BIN", "#101", Some("MZ")),
+        ];
+        let threats = check_resources(&resources);
+        assert_eq!(threats.len(), 1);
+        assert_eq!(threats[0].threat_type, "Executable in Resources");
+        assert_eq!(threats[0].threat_id, "P011");
+        assert_eq!(threats[0].severity, "suspicious");
+        assert_eq!(
+            threats[0].details,
+            "Resources start with an MZ header: RCDATA/PAYLOAD (4096 bytes), BIN/#101 (4096 bytes)"
+        );
+
+        assert!(check_resources(&resources[..1]).is_empty());
+        assert!(check_resources(&[resource("RCDATA", "#1", Some("PK"))]).is_empty());
+    }
+
+    #[test]
+    fn several_header_anomalies_are_a_crafted_header() {
+        // large address aware x86 without 32BIT_MACHINE, and a DLL with its
+        // relocations stripped
+        let pe = PeBuilder::new().characteristics(0x2023).build();
+        let headers = parse_headers(&pe).unwrap();
+        let threats = check_pe_anomalies(&pe, &headers);
+        assert_eq!(threats.len(), 1);
+        assert_eq!(threats[0].threat_type, "Crafted Header");
+        assert_eq!(threats[0].threat_id, "P012");
+        assert_eq!(threats[0].severity, "suspicious");
+        assert_eq!(
+            threats[0].details,
+            "Inconsistent header flags: large address aware 32-bit image without \
+             32BIT_MACHINE; DLL with relocations stripped"
+        );
+
+        // one alone is explainable
+        assert!(anomaly_ids(&PeBuilder::new().characteristics(0x0022).build()).is_empty());
+    }
+
+    #[test]
+    fn native_calls_alone_are_suspicious() {
+        let imports = [
+            dll("kernel32.dll", &["ExitProcess"]),
+            dll(
+                "ntdll.dll",
+                &[
+                    "NtQuerySystemInformation",
+                    "RtlMoveMemory",
+                    "ZwClose",
+                    "ZwClose",
+                ],
+            ),
+        ];
+        let threats = check_native_api(&imports);
+        assert_eq!(threats.len(), 1);
+        assert_eq!(threats[0].threat_type, "Direct Native API Usage");
+        assert_eq!(threats[0].threat_id, "S010");
+        assert_eq!(threats[0].severity, "suspicious");
+        assert_eq!(
+            threats[0].details,
+            "Imports ntdll system calls directly: NtQuerySystemInformation, ZwClose"
+        );
+
+        // one injection primitive isn't yet a chain
+        let threats = check_native_api(&[dll("ntdll.dll", &["NtCreateThreadEx"])]);
+        assert_eq!(threats[0].severity, "suspicious");
+    }
+
+    #[test]
+    fn native_calls_in_an_injection_chain_are_malicious() {
+        let imports = [
+            dll(
+                "NTDLL.DLL",
+                &["ZwProtectVirtualMemory", "NtMapViewOfSection"],
+            ),
+            dll("kernel32.dll", &["WriteProcessMemory"]),
+        ];
+        let threats = check_native_api(&imports);
+        assert_eq!(threats[0].severity, "malicious");
+        assert_eq!(
+            threats[0].details,
+            "Imports ntdll system calls directly: NtMapViewOfSection, ZwProtectVirtualMemory; \
+             injection chain: NtMapViewOfSection, WriteProcessMemory, ZwProtectVirtualMemory"
+        );
+
+        // a native call that isn't an injection primitive needs two others
+        let imports = [
+            dll("ntdll.dll", &["NtQuerySystemInformation"]),
+            dll("kernel32.dll", &["VirtualAllocEx", "CreateRemoteThread"]),
+        ];
+        assert_eq!(check_native_api(&imports)[0].severity, "suspicious");
+    }
+
+    #[test]
+    fn native_names_outside_ntdll_are_ignored() {
+        let imports = [
+            dll("ntdll.dll", &["RtlCreateUserThread", "LdrLoadDll"]),
+            dll("wrapper.dll", &["NtCreateThreadEx", "ZwMapViewOfSection"]),
+        ];
+        assert!(check_native_api(&imports).is_empty());
+        assert!(check_native_api(&[]).is_empty());
+    }
+
+    #[test]
+    fn zero_filled_section_gaps_are_padding() {
+        let zeros = [0u8; 0x100];
+        let mut stray = [0u8; 0x100];
+        stray[..SECTION_GAP_MIN_DATA - 1].fill(0x90);
+        assert!(check_section_padding(&[]).is_empty());
+        assert!(
+            check_section_padding(&[(0x300, &zeros, ".text"), (0x500, &stray, ".idata")])
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn data_stuffed_between_sections_is_flagged() {
+        let hidden = noise(0x100);
+        let mut text = vec![0u8; 0x80];
+        text[..SECTION_GAP_MIN_DATA].fill(0x41);
+        let threats = check_section_padding(&[
+            (0x300, &hidden, ".text"),
+            (0x500, &[0u8; 0x40], ".idata"),
+            (0x780, &text, ".data"),
+        ]);
+        assert_eq!(threats.len(), 1);
+        assert_eq!(threats[0].threat_type, "Hidden Data in Section Padding");
+        assert_eq!(threats[0].threat_id, "P015");
+        assert_eq!(threats[0].severity, "suspicious");
+        let nonzero = hidden.iter().filter(|&&b| b != 0).count();
+        assert_eq!(
+            threats[0].details,
+            format!(
+                "Data between sections at 0x300-0x400 after .text ({} non-zero bytes, \
+                 entropy {:.2}), 0x780-0x800 after .data (16 non-zero bytes, entropy {:.2})",
+                nonzero,
+                entropy(&hidden),
+                entropy(&text)
+            )
+        );
+    }
+}
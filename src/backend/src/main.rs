@@ -6,24 +6,66 @@ mod utils;
 use utils::*;
 mod scanner;
 use scanner::*;
+mod analysis;
+mod archive;
+mod authenticode;
 mod indicators;
+mod pe;
+use pe::*;
+mod rules;
+use rules::*;
+mod stats;
+use stats::*;
+mod sse;
+use sse::*;
+mod storage;
+use storage::*;
+mod auth;
+use auth::*;
+mod stix;
+use stix::*;
+mod sandbox;
+use sandbox::*;
+mod canonical;
+use canonical::*;
+mod report;
+use report::*;
+mod graphql;
+#[cfg(test)]
+mod testing;
 
-use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
 use std::sync::{Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tiny_http::{Header, Method, Response, Server};
+use tungstenite::handshake::server as ws;
+use tungstenite::{HandshakeError, Message, WebSocket};
 use uuid::Uuid;
 
-fn handle_options(request: tiny_http::Request) {
+fn handle_options(request: tiny_http::Request, cors: &Cors) {
     let response = Response::from_string("");
-    let response = add_cors_headers(response);
+    let response = add_cors_headers(response, cors);
     let _ = request.respond(response);
 }
 
-fn handle_upload(mut request: tiny_http::Request, scan_store: ScanStore) {
+#[allow(clippy::too_many_arguments)]
+fn handle_upload(
+    mut request: tiny_http::Request,
+    cors: &Cors,
+    config: &Config,
+    scan_store: ScanStore,
+    storage: SharedStorage,
+    rules: Arc<RuleSet>,
+    stats: SharedStats,
+    metrics: SharedMetrics,
+    query: &str,
+) {
     let content_type = request
         .headers()
         .iter()
@@ -32,14 +74,8 @@ fn handle_upload(mut request: tiny_http::Request, scan_store: ScanStore) {
         .unwrap_or("");
 
     if !content_type.starts_with("multipart/form-data") {
-        let error_response = serde_json::json!({"error": "Expected multipart/form-data"});
-        let response = Response::from_string(error_response.to_string())
-            .with_status_code(400)
-            .with_header(
-                Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap(),
-            );
-        let response = add_cors_headers(response);
-        let _ = request.respond(response);
+        let error = ApiError::new(400, "INVALID_CONTENT_TYPE", "Expected multipart/form-data");
+        let _ = request.respond(error_response(&error, cors));
         return;
     }
 
@@ -49,251 +85,2288 @@ fn handle_upload(mut request: tiny_http::Request, scan_store: ScanStore) {
         .unwrap_or("")
         .to_string();
 
-    let mut body = Vec::new();
-    if request.as_reader().read_to_end(&mut body).is_err() {
-        let error_response = serde_json::json!({"error": "Failed to read request body"});
-        let response = Response::from_string(error_response.to_string()).with_status_code(400);
-        let response = add_cors_headers(response);
-        let _ = request.respond(response);
+    // refuse a declared oversize body before reading any of it, and cap the
+    // read for bodies that don't declare their length or lie about it; the
+    // per-file and batch checks below still apply to what gets through. Only
+    // requests that opt in with `batch=true` may send more than one file's
+    // worth, so a plain upload can't make us buffer a whole batch
+    let batch_allowed = query_param(query, "batch").as_deref() == Some("true");
+    let (body_limit, error) = if batch_allowed {
+        let body_limit = config.max_batch_size + MULTIPART_OVERHEAD;
+        let error = ApiError::new(
+            413,
+            "BATCH_TOO_LARGE",
+            format!(
+                "Upload exceeds maximum batch size of {}MB",
+                config.max_batch_size / 1024 / 1024
+            ),
+        );
+        (body_limit, error)
+    } else {
+        let body_limit = config.max_file_size + MULTIPART_OVERHEAD;
+        let error = ApiError::new(
+            413,
+            "FILE_TOO_LARGE",
+            format!(
+                "File size exceeds maximum limit of {}MB; send batches with ?batch=true",
+                config.max_file_size / 1024 / 1024
+            ),
+        );
+        (body_limit, error)
+    };
+    let too_large = || {
+        println!("Upload body exceeds limit of {} bytes", body_limit);
+        error_response(&error, cors)
+    };
+
+    let content_length = request.body_length();
+    if content_length.is_some_and(|len| len as u64 > body_limit) {
+        let _ = request.respond(too_large());
         return;
     }
 
-    let (filename, file_data) = match parse_multipart(&body, &boundary) {
-        Ok(result) => result,
+    // one byte over the cap is enough to tell an oversized body apart
+    let mut reader = request.as_reader().take(body_limit + 1);
+    let body = match read_body(&mut reader, content_length, body_limit) {
+        Ok(body) if body.len() as u64 > body_limit => {
+            let _ = request.respond(too_large());
+            return;
+        }
+        Ok(body) => body,
+        Err(_) => {
+            let error = ApiError::new(400, "BODY_READ_FAILED", "Failed to read request body");
+            let _ = request.respond(error_response(&error, cors));
+            return;
+        }
+    };
+
+    let files = match parse_multipart(&body, &boundary) {
+        Ok(files) => files,
         Err(e) => {
-            let error_response = serde_json::json!({"error": e});
-            let response = Response::from_string(error_response.to_string())
-                .with_status_code(400)
-                .with_header(
-                    Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap(),
-                );
-            let response = add_cors_headers(response);
-            let _ = request.respond(response);
+            let error = ApiError::new(400, "INVALID_MULTIPART", e);
+            let _ = request.respond(error_response(&error, cors));
             return;
         }
     };
+    drop(body);
 
-    let file_size = file_data.len() as u64;
+    let batch_size: u64 = files.iter().map(|(_, data)| data.len() as u64).sum();
     println!(
-        "Upload request received: {} ({} bytes)",
-        filename, file_size
+        "Upload request received: {} file(s), {} bytes",
+        files.len(),
+        batch_size
     );
 
-    if file_size > MAX_FILE_SIZE {
+    // the whole request is refused if any one file is over the limit
+    if let Some((filename, file_data)) = files
+        .iter()
+        .find(|(_, data)| data.len() as u64 > config.max_file_size)
+    {
         println!(
-            "File size {} exceeds limit of {} bytes",
-            file_size, MAX_FILE_SIZE
+            "File {} size {} exceeds limit of {} bytes",
+            filename,
+            file_data.len(),
+            config.max_file_size
         );
-        let error_response = serde_json::json!({
-            "error": format!("File size exceeds maximum limit of {}MB", MAX_FILE_SIZE / 1024 / 1024)
-        });
-        let response = Response::from_string(error_response.to_string())
-            .with_status_code(400)
-            .with_header(
-                Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap(),
-            );
-        let response = add_cors_headers(response);
-        let _ = request.respond(response);
+        let error = ApiError::new(
+            400,
+            "FILE_TOO_LARGE",
+            format!(
+                "File size exceeds maximum limit of {}MB: {}",
+                config.max_file_size / 1024 / 1024,
+                filename
+            ),
+        );
+        let _ = request.respond(error_response(&error, cors));
+        return;
+    }
+
+    if batch_size > config.max_batch_size {
+        println!(
+            "Batch size {} exceeds limit of {} bytes",
+            batch_size, config.max_batch_size
+        );
+        let error = ApiError::new(
+            400,
+            "BATCH_TOO_LARGE",
+            format!(
+                "Upload exceeds maximum batch size of {}MB",
+                config.max_batch_size / 1024 / 1024
+            ),
+        );
+        let _ = request.respond(error_response(&error, cors));
         return;
     }
 
+    let force = query_param(query, "force").as_deref() == Some("true");
+    let batch = files.len() > 1;
+
+    // every part is written to disk before any scan starts, so a failed save
+    // refuses the whole batch without leaving scans behind that the client
+    // never hears about
+    let mut staged: Vec<StagedUpload> = Vec::with_capacity(files.len());
+    for (filename, file_data) in files {
+        match stage_upload(config, &scan_store, force, &staged, filename, file_data) {
+            Ok(upload) => staged.push(upload),
+            Err(e) => {
+                println!("Failed to save file: {}", e);
+                for upload in &staged {
+                    if let StagedUpload::Saved { file_path, .. } = upload {
+                        let _ = fs::remove_file(file_path);
+                    }
+                }
+                let error = ApiError::new(500, "SAVE_FAILED", "Failed to save file");
+                let _ = request.respond(error_response(&error, cors));
+                return;
+            }
+        }
+    }
+
+    let scans: Vec<UploadResponse> = staged
+        .into_iter()
+        .map(|upload| match upload {
+            StagedUpload::Reused(scan_id) => UploadResponse {
+                scan_id,
+                cached: true,
+            },
+            StagedUpload::Saved {
+                scan_id,
+                file_path,
+                file_info,
+            } => {
+                start_scan(
+                    config,
+                    &scan_store,
+                    &storage,
+                    &rules,
+                    &stats,
+                    &metrics,
+                    &scan_id,
+                    file_path,
+                    file_info,
+                );
+                UploadResponse {
+                    scan_id,
+                    cached: false,
+                }
+            }
+        })
+        .collect();
+
+    // a single file keeps the original object response; a batch gets an
+    // array with one entry per file part, in upload order
+    let body = if batch {
+        serde_json::to_string(&scans).unwrap()
+    } else {
+        serde_json::to_string(&scans[0]).unwrap()
+    };
+    let response = json_response(&request, body);
+    let response = add_cors_headers(response, cors);
+    let _ = request.respond(response);
+}
+
+// an uploaded file that's ready to scan, or the existing scan of an
+// identical one
+enum StagedUpload {
+    Reused(String),
+    Saved {
+        scan_id: String,
+        file_path: PathBuf,
+        file_info: FileInfo,
+    },
+}
+
+// hashes one uploaded file and writes it to disk under a new scan ID, unless
+// an identical file already has a scan, in the store or earlier in `staged`;
+// Err only when the sample can't be written
+fn stage_upload(
+    config: &Config,
+    scan_store: &ScanStore,
+    force: bool,
+    staged: &[StagedUpload],
+    filename: String,
+    file_data: Vec<u8>,
+) -> std::io::Result<StagedUpload> {
+    let file_size = file_data.len() as u64;
+    println!("Received file: {} ({} bytes)", filename, file_size);
+
     let sha256 = calculate_sha256(&file_data);
+
+    // uploads are handled one at a time on the accept loop, so an identical
+    // upload arriving while this one's scan runs finds it here and joins it;
+    // a file repeated within one batch joins its first copy
+    if config.dedupe_uploads && !force {
+        let earlier = staged.iter().find_map(|upload| match upload {
+            StagedUpload::Saved {
+                scan_id, file_info, ..
+            } if file_info.sha256 == sha256 => Some(scan_id.clone()),
+            _ => None,
+        });
+        if let Some(scan_id) = earlier.or_else(|| find_reusable_scan(scan_store, &sha256)) {
+            println!("Reusing scan {} for SHA256 {}", scan_id, sha256);
+            return Ok(StagedUpload::Reused(scan_id));
+        }
+    }
+
+    let (md5, sha1) = calculate_md5_sha1(&file_data);
     let scan_id = format!("scan-{}", Uuid::new_v4());
     println!("Generated scan ID: {}", scan_id);
 
-    let file_path = PathBuf::from(UPLOAD_DIR).join(format!("{}_{}", scan_id, filename));
-    if let Err(e) = fs::write(&file_path, &file_data) {
-        println!("Failed to save file: {}", e);
-        let error_response = serde_json::json!({"error": "Failed to save file"});
-        let response = Response::from_string(error_response.to_string())
-            .with_status_code(500)
-            .with_header(
-                Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap(),
-            );
-        let response = add_cors_headers(response);
-        let _ = request.respond(response);
-        return;
-    }
+    let file_path = sample_path(&config.upload_dir, &scan_id, &filename);
+    fs::write(&file_path, &file_data)?;
 
     println!("File saved: {:?}", file_path);
     println!("SHA256: {}", sha256);
 
     let file_info = FileInfo {
-        filename: filename.clone(),
+        filename,
         size: file_size,
-        sha256: sha256.clone(),
+        sha256,
+        md5,
+        sha1,
+        imphash: None,
+        authentihash: authentihash(&file_data),
     };
+    Ok(StagedUpload::Saved {
+        scan_id,
+        file_path,
+        file_info,
+    })
+}
 
+// records a saved upload as "scanning" and starts its scan in the background
+#[allow(clippy::too_many_arguments)]
+fn start_scan(
+    config: &Config,
+    scan_store: &ScanStore,
+    storage: &SharedStorage,
+    rules: &Arc<RuleSet>,
+    stats: &SharedStats,
+    metrics: &SharedMetrics,
+    scan_id: &str,
+    file_path: PathBuf,
+    file_info: FileInfo,
+) {
     let result = ScanResult {
         status: "scanning".to_string(),
+        verdict: None,
         threats: vec![],
         stats: ScanStats {
             threats_found: 0,
             malicious: 0,
             suspicious: 0,
             neutral: 0,
+            score: 0,
         },
         logs: vec!["[0%] Initializing scan...".to_string()],
         file_info: Some(file_info.clone()),
+        pe_info: None,
+        imports: vec![],
+        resources: vec![],
+        signed: false,
+        certificate_size: None,
+        overlay_size: 0,
+        entropy: 0.0,
+        zero_ratio: 0.0,
+        guids: vec![],
+        strings: Default::default(),
+        strings_count: 0,
+        family: None,
+        packer: None,
+        result_hash: None,
+        created_at: timestamp_now(),
+        cancel_flag: Default::default(),
     };
 
     {
         let mut store = scan_store.lock().unwrap();
-        store.insert(scan_id.clone(), result);
+        store.insert(scan_id.to_string(), result);
     }
+    persist(storage, scan_store, scan_id);
+    record_event(stats, StatsEvent::Upload);
+    metrics.lock().unwrap().scan_started(file_info.size);
 
-    scan_file(file_path, file_info, scan_id.clone(), scan_store.clone());
-
-    let response_data = UploadResponse { scan_id };
-    let response = Response::from_string(serde_json::to_string(&response_data).unwrap())
-        .with_header(Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap());
-    let response = add_cors_headers(response);
-    let _ = request.respond(response);
+    scan_file(
+        file_path,
+        file_info,
+        scan_id.to_string(),
+        scan_store.clone(),
+        storage.clone(),
+        rules.clone(),
+        stats.clone(),
+        metrics.clone(),
+        config.scan_options(),
+    );
 }
 
-fn handle_scan_status(request: tiny_http::Request, scan_store: ScanStore, scan_id: String) {
+fn handle_scan_status(
+    request: tiny_http::Request,
+    cors: &Cors,
+    scan_store: ScanStore,
+    scan_id: String,
+) {
     println!("SSE connection established for scan: {}", scan_id);
 
     {
         let store = scan_store.lock().unwrap();
         if !store.contains_key(&scan_id) {
-            let error_response = serde_json::json!({"error": "Scan not found"});
-            let response = Response::from_string(error_response.to_string()).with_status_code(404);
-            let response = add_cors_headers(response);
-            let _ = request.respond(response);
+            let error = ApiError::new(404, "SCAN_NOT_FOUND", "Scan not found");
+            let _ = request.respond(error_response(&error, cors));
             return;
         }
     }
 
-    let mut sse_data = String::new();
-    let mut last_progress = 0;
-    let mut scan_complete = false;
+    // stream from a dedicated thread so the accept loop keeps serving requests
+    let cors = cors.clone();
+    thread::spawn(move || stream_scan_status(request, cors, scan_store, scan_id));
+}
 
-    // poll for updates until scan is complete
-    while !scan_complete {
-        thread::sleep(Duration::from_millis(100));
+fn stream_scan_status(
+    request: tiny_http::Request,
+    cors: Cors,
+    scan_store: ScanStore,
+    scan_id: String,
+) {
+    let buffer = Arc::new(SseBuffer::new());
+    {
+        let buffer = buffer.clone();
+        let scan_id = scan_id.clone();
+        thread::spawn(move || watch_scan(scan_store, scan_id, buffer));
+    }
 
-        let store = scan_store.lock().unwrap();
-        if let Some(result) = store.get(&scan_id) {
-            for (i, log) in result.logs.iter().enumerate() {
-                if i >= last_progress {
-                    // extract progress from log message
-                    let progress = if let Some(start) = log.find('[') {
-                        if let Some(end) = log.find('%') {
-                            log[start + 1..end].parse::<u32>().unwrap_or(0)
-                        } else {
-                            0
-                        }
-                    } else {
-                        0
-                    };
-
-                    let message = if let Some(bracket_end) = log.find(']') {
-                        &log[bracket_end + 2..]
-                    } else {
-                        log.as_str()
-                    };
-
-                    let update = ProgressUpdate {
-                        progress,
-                        message: message.to_string(),
-                    };
-                    sse_data.push_str(&format!(
-                        "data: {}\n\n",
-                        serde_json::to_string(&update).unwrap()
-                    ));
-                    last_progress = i + 1;
+    // tiny_http's chunked encoder buffers until 8KB, so write the response
+    // head and chunk framing ourselves and flush after every event
+    let head = Response::empty(200)
+        .with_header(Header::from_bytes(&b"Content-Type"[..], &b"text/event-stream"[..]).unwrap())
+        .with_header(Header::from_bytes(&b"Cache-Control"[..], &b"no-cache"[..]).unwrap());
+    let head = add_cors_headers(head, &cors);
+
+    let mut writer = request.into_writer();
+    let mut sent = write!(writer, "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n");
+    for header in head.headers() {
+        sent = sent.and_then(|_| write!(writer, "{}: {}\r\n", header.field, header.value));
+    }
+    sent = sent
+        .and_then(|_| write!(writer, "\r\n"))
+        .and_then(|_| writer.flush());
+
+    while sent.is_ok() {
+        let Some(update) = buffer.pop() else {
+            // terminating chunk, the connection stays usable for keep-alive
+            sent = write!(writer, "0\r\n\r\n").and_then(|_| writer.flush());
+            break;
+        };
+        let event = format!("data: {}\n\n", serde_json::to_string(&update).unwrap());
+        sent = write!(writer, "{:x}\r\n{}\r\n", event.len(), event).and_then(|_| writer.flush());
+    }
+
+    if sent.is_err() {
+        println!("SSE client for scan {} disconnected", scan_id);
+    }
+    buffer.close();
+}
+
+// accepts WebSocket connections on their own listener, one thread each;
+// tiny_http hands back upgraded streams without their socket, so a pong
+// timeout couldn't be set on those
+fn serve_websockets(listener: TcpListener, app: Arc<App>) {
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let app = app.clone();
+        thread::spawn(move || handle_scan_ws(stream, &app));
+    }
+}
+
+// the SSE progress stream over a WebSocket at GET /api/scan-ws/{scanId}, for
+// clients and proxies that cope with those better. Every server frame is a
+// JSON text frame, either a progress update exactly as sent over SSE
+//   {"progress": 60, "message": "Performing signature analysis..."}
+// or, as the last frame once the scan has finished, the full scan result
+//   {"result": {"status": "unsafe", "threats": [...], ...}}
+// after which the server closes the socket. Frames sent by the client are
+// ignored apart from close, which ends the stream.
+fn handle_scan_ws(stream: TcpStream, app: &App) {
+    // reads only happen during the handshake and while waiting for a pong,
+    // so this bounds both; writes that stall as long mean the peer is gone
+    let timeouts = stream
+        .set_read_timeout(Some(WS_PONG_TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(WS_PONG_TIMEOUT)));
+    let Ok(mut reply) = timeouts.and_then(|_| stream.try_clone()) else {
+        return;
+    };
+
+    let mut scan_id = String::new();
+    // tungstenite's handshake callback fixes the error type
+    #[allow(clippy::result_large_err)]
+    let check = |request: &ws::Request, response: ws::Response| {
+        let header = |name| request.headers().get(name).and_then(|h| h.to_str().ok());
+        let Some(id) = request.uri().path().strip_prefix("/api/scan-ws/") else {
+            return Err(ws_error(&ApiError::new(404, "NOT_FOUND", "Not found")));
+        };
+        scan_id = id.split('/').next().unwrap_or_default().to_string();
+        println!("WebSocket connection requested for scan: {}", scan_id);
+
+        // browsers send Origin with every WebSocket handshake but don't apply
+        // CORS to the socket, so a configured allow-list is enforced here;
+        // clients without an Origin aren't browsers and aren't restricted
+        let origin = header("Origin");
+        if origin.is_some() && !Cors::for_origin(origin, &app.config.cors_origins).allows_origin() {
+            let error = ApiError::new(403, "ORIGIN_NOT_ALLOWED", "Origin not allowed");
+            return Err(ws_error(&error));
+        }
+        if !app.auth.allows_key(header("X-API-Key"), true) {
+            let error = ApiError::new(401, "UNAUTHORIZED", "Missing or invalid API key");
+            return Err(ws_error(&error));
+        }
+        if !app.scan_store.lock().unwrap().contains_key(&scan_id) {
+            let error = ApiError::new(404, "SCAN_NOT_FOUND", "Scan not found");
+            return Err(ws_error(&error));
+        }
+        Ok(response)
+    };
+
+    match tungstenite::accept_hdr(stream, check) {
+        Ok(socket) => stream_scan_ws(socket, app.scan_store.clone(), scan_id),
+        // `check` turned it down and the error has been sent
+        Err(HandshakeError::Failure(tungstenite::Error::Http(_))) => {}
+        // not a WebSocket upgrade at all, or one that never finished
+        Err(_) => {
+            let error = ApiError::new(
+                400,
+                "WEBSOCKET_UPGRADE_REQUIRED",
+                "Expected a WebSocket upgrade",
+            );
+            let response = ws_error(&error);
+            let mut head = Vec::new();
+            let _ = ws::write_response(&mut head, &response);
+            let body = response.body().as_deref().unwrap_or_default();
+            let _ = reply
+                .write_all(&head)
+                .and_then(|_| reply.write_all(body.as_bytes()));
+        }
+    }
+}
+
+// `error` as a handshake response, JSON like every other API error
+fn ws_error(error: &ApiError) -> ws::ErrorResponse {
+    let body = serde_json::to_string(error).unwrap();
+    tungstenite::http::Response::builder()
+        .status(error.status)
+        .header("Content-Type", "application/json")
+        .header("Content-Length", body.len())
+        .body(Some(body))
+        .unwrap()
+}
+
+fn stream_scan_ws(mut socket: WebSocket<TcpStream>, scan_store: ScanStore, scan_id: String) {
+    let buffer = Arc::new(SseBuffer::new());
+    {
+        let buffer = buffer.clone();
+        let scan_store = scan_store.clone();
+        let scan_id = scan_id.clone();
+        thread::spawn(move || watch_scan(scan_store, scan_id, buffer));
+    }
+
+    // rather than a reader thread sharing the socket, the client is pinged on
+    // an interval and its frames are read up to the pong
+    let mut last_ping = Instant::now();
+    let end = loop {
+        match buffer.pop_timeout(WS_PING_INTERVAL) {
+            Ok(update) => {
+                let frame = serde_json::to_string(&update).unwrap();
+                if socket.send(Message::Text(frame)).is_err() {
+                    break WsEnd::Disconnected;
                 }
             }
+            Err(RecvTimeoutError::Disconnected) => break WsEnd::Finished,
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+        if last_ping.elapsed() >= WS_PING_INTERVAL {
+            last_ping = Instant::now();
+            if let Err(end) = ping_ws(&mut socket) {
+                break end;
+            }
+        }
+    };
+    buffer.close();
 
-            if result.status != "scanning" {
-                scan_complete = true;
+    match end {
+        // the scan ended, so finish with the result (unless it was deleted)
+        WsEnd::Finished => {
+            let frame = scan_store
+                .lock()
+                .unwrap()
+                .get(&scan_id)
+                .map(|result| serde_json::json!({ "result": result }).to_string());
+            let sent = match frame {
+                Some(frame) => socket.send(Message::Text(frame)).is_ok(),
+                None => true,
+            };
+            if sent && socket.close(None).is_ok() {
+                // wait for the client to acknowledge the close
+                while socket.read().is_ok() {}
             }
         }
+        // flushing sends the close reply tungstenite queued
+        WsEnd::ClientClosed => {
+            println!("WebSocket client for scan {} closed the stream", scan_id);
+            let _ = socket.flush();
+        }
+        WsEnd::Disconnected => println!("WebSocket client for scan {} disconnected", scan_id),
+        WsEnd::Unresponsive => println!(
+            "WebSocket client for scan {} didn't answer a ping within {:?}",
+            scan_id, WS_PONG_TIMEOUT
+        ),
     }
+}
 
-    let response = Response::from_string(sse_data)
-        .with_header(Header::from_bytes(&b"Content-Type"[..], &b"text/event-stream"[..]).unwrap())
-        .with_header(Header::from_bytes(&b"Cache-Control"[..], &b"no-cache"[..]).unwrap())
-        .with_header(Header::from_bytes(&b"Connection"[..], &b"keep-alive"[..]).unwrap());
-    let response = add_cors_headers(response);
-    let _ = request.respond(response);
+enum WsEnd {
+    Finished,
+    ClientClosed,
+    Disconnected,
+    // no pong within WS_PONG_TIMEOUT
+    Unresponsive,
+}
+
+// pings the client and reads its frames up to the pong
+fn ping_ws(socket: &mut WebSocket<TcpStream>) -> Result<(), WsEnd> {
+    if socket.send(Message::Ping(vec![])).is_err() {
+        return Err(WsEnd::Disconnected);
+    }
+    loop {
+        match socket.read() {
+            Ok(Message::Pong(_)) => return Ok(()),
+            Ok(Message::Close(_)) => return Err(WsEnd::ClientClosed),
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e))
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+            {
+                return Err(WsEnd::Unresponsive)
+            }
+            Err(_) => return Err(WsEnd::Disconnected),
+        }
+    }
 }
 
-fn handle_scan_result(request: tiny_http::Request, scan_store: ScanStore, scan_id: String) {
+fn handle_scan_result(
+    request: tiny_http::Request,
+    cors: &Cors,
+    scan_store: ScanStore,
+    scan_id: String,
+    format: Option<&str>,
+) {
     println!("Fetching result for scan: {}", scan_id);
 
     let store = scan_store.lock().unwrap();
-    match store.get(&scan_id) {
-        Some(result) => {
-            let response = Response::from_string(serde_json::to_string(result).unwrap())
+    let body = match (store.get(&scan_id), format) {
+        (Some(result), None | Some("json")) => Ok(serde_json::to_string(result).unwrap()),
+        (Some(result), Some("stix" | "canonical")) if result.status == "scanning" => {
+            Err(ApiError::new(409, "SCAN_RUNNING", "Scan is still running"))
+        }
+        (Some(result), Some("canonical")) => Ok(canonical_result(result).to_string()),
+        (Some(result), Some("stix")) => match stix_bundle(&scan_id, result) {
+            Some(bundle) => Ok(bundle.to_string()),
+            None => Err(ApiError::new(
+                422,
+                "NO_FILE_HASHES",
+                "Scan has no file hashes to export",
+            )),
+        },
+        (Some(_), Some(other)) => Err(ApiError::new(
+            400,
+            "UNSUPPORTED_FORMAT",
+            format!("Unsupported format: {}", other),
+        )),
+        (None, _) => Err(ApiError::new(404, "SCAN_NOT_FOUND", "Scan not found")),
+    };
+
+    match body {
+        Ok(body) => {
+            let response = json_response(&request, body);
+            let response = add_cors_headers(response, cors);
+            let _ = request.respond(response);
+        }
+        Err(error) => {
+            let _ = request.respond(error_response(&error, cors));
+        }
+    }
+}
+
+fn handle_scan_cancel(
+    request: tiny_http::Request,
+    cors: &Cors,
+    scan_store: ScanStore,
+    scan_id: String,
+) {
+    println!("Cancel requested for scan: {}", scan_id);
+
+    let status = {
+        let store = scan_store.lock().unwrap();
+        match store.get(&scan_id) {
+            // the scanner notices the flag at its next phase boundary
+            Some(result) if result.status == "scanning" => {
+                result.cancel_flag.store(true, Ordering::SeqCst);
+                Some("cancelling".to_string())
+            }
+            // already finished, nothing to do
+            Some(result) => Some(result.status.clone()),
+            None => None,
+        }
+    };
+
+    match status {
+        Some(status) => {
+            let response_data = CancelResponse { scan_id, status };
+            let response = json_response(&request, serde_json::to_string(&response_data).unwrap());
+            let response = add_cors_headers(response, cors);
+            let _ = request.respond(response);
+        }
+        None => {
+            let error = ApiError::new(404, "SCAN_NOT_FOUND", "Scan not found");
+            let _ = request.respond(error_response(&error, cors));
+        }
+    }
+}
+
+// serves the strings cached on the result, since the sample itself is usually gone
+fn handle_scan_report(
+    request: tiny_http::Request,
+    cors: &Cors,
+    scan_store: ScanStore,
+    scan_id: String,
+    format: Option<&str>,
+) {
+    println!("Fetching report for scan: {}", scan_id);
+
+    let report = {
+        let store = scan_store.lock().unwrap();
+        match (store.get(&scan_id), format) {
+            (Some(result), Some("csv" | "txt")) if result.status == "scanning" => {
+                Err(ApiError::new(409, "SCAN_RUNNING", "Scan is still running"))
+            }
+            (Some(result), Some("csv")) => Ok((csv_report(&scan_id, result), "text/csv", "csv")),
+            (Some(result), Some("txt")) => Ok((text_report(&scan_id, result), "text/plain", "txt")),
+            (Some(_), _) => Err(ApiError::new(
+                400,
+                "UNSUPPORTED_FORMAT",
+                "format must be csv or txt",
+            )),
+            (None, _) => Err(ApiError::new(404, "SCAN_NOT_FOUND", "Scan not found")),
+        }
+    };
+
+    match report {
+        Ok((body, content_type, extension)) => {
+            let disposition = format!("attachment; filename=\"{}.{}\"", scan_id, extension);
+            let response = compressed_response(&request, body)
+                .with_header(
+                    Header::from_bytes(
+                        &b"Content-Type"[..],
+                        format!("{}; charset=utf-8", content_type).as_bytes(),
+                    )
+                    .unwrap(),
+                )
                 .with_header(
-                    Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap(),
+                    Header::from_bytes(&b"Content-Disposition"[..], disposition.as_bytes())
+                        .unwrap(),
                 );
-            let response = add_cors_headers(response);
+            let response = add_cors_headers(response, cors);
+            let _ = request.respond(response);
+        }
+        Err(error) => {
+            let _ = request.respond(error_response(&error, cors));
+        }
+    }
+}
+
+fn handle_scan_strings(
+    request: tiny_http::Request,
+    cors: &Cors,
+    scan_store: ScanStore,
+    scan_id: String,
+    min_len: Option<&str>,
+) {
+    println!("Fetching strings for scan: {}", scan_id);
+
+    // extraction already dropped anything shorter than MIN_STRING_LENGTH
+    let min_len = match min_len.map(|v| v.parse::<usize>()) {
+        None => MIN_STRING_LENGTH,
+        Some(Ok(n)) if n > 0 => n.max(MIN_STRING_LENGTH),
+        Some(_) => {
+            let error = ApiError::new(
+                400,
+                "INVALID_PARAMETER",
+                "min_len must be a positive integer",
+            );
+            let _ = request.respond(error_response(&error, cors));
+            return;
+        }
+    };
+
+    let response_data = {
+        let store = scan_store.lock().unwrap();
+        match store.get(&scan_id) {
+            Some(result) if result.status == "scanning" => {
+                Err(ApiError::new(409, "SCAN_RUNNING", "Scan is still running"))
+            }
+            Some(result) => {
+                let filter = |strings: &[String]| {
+                    strings
+                        .iter()
+                        .filter(|s| s.chars().count() >= min_len)
+                        .cloned()
+                        .collect()
+                };
+                Ok(StringsResponse {
+                    scan_id,
+                    min_len,
+                    ascii: filter(&result.strings.ascii),
+                    wide: filter(&result.strings.wide),
+                    total_count: result.strings_count,
+                })
+            }
+            None => Err(ApiError::new(404, "SCAN_NOT_FOUND", "Scan not found")),
+        }
+    };
+
+    match response_data {
+        Ok(response_data) => {
+            let response = json_response(&request, serde_json::to_string(&response_data).unwrap());
+            let response = add_cors_headers(response, cors);
             let _ = request.respond(response);
         }
+        Err(error) => {
+            let _ = request.respond(error_response(&error, cors));
+        }
+    }
+}
+
+fn handle_verify_integrity(
+    request: tiny_http::Request,
+    cors: &Cors,
+    config: &Config,
+    scan_store: ScanStore,
+    scan_id: String,
+) {
+    println!("Verifying sample integrity for scan: {}", scan_id);
+
+    let file_info = {
+        let store = scan_store.lock().unwrap();
+        store.get(&scan_id).and_then(|r| r.file_info.clone())
+    };
+
+    let file_info = match file_info {
+        Some(file_info) => file_info,
         None => {
-            let error_response = serde_json::json!({"error": "Scan not found"});
-            let response = Response::from_string(error_response.to_string()).with_status_code(404);
-            let response = add_cors_headers(response);
+            let error = ApiError::new(404, "SCAN_NOT_FOUND", "Scan not found");
+            let _ = request.respond(error_response(&error, cors));
+            return;
+        }
+    };
+
+    let actual_sha256 = match calculate_sha256_file(&sample_path(
+        &config.upload_dir,
+        &scan_id,
+        &file_info.filename,
+    )) {
+        Ok(hash) => hash,
+        Err(_) => {
+            let error = ApiError::new(404, "SAMPLE_NOT_RETAINED", "Sample not retained");
+            let _ = request.respond(error_response(&error, cors));
+            return;
+        }
+    };
+
+    let response_data = IntegrityResponse {
+        scan_id,
+        verified: actual_sha256 == file_info.sha256,
+        expected_sha256: file_info.sha256,
+        actual_sha256,
+    };
+    let response = json_response(&request, serde_json::to_string(&response_data).unwrap());
+    let response = add_cors_headers(response, cors);
+    let _ = request.respond(response);
+}
+
+// filters on sha256, status, verdict and a created_at range (since/until)
+// are answered by the result store
+fn handle_list_scans(
+    request: tiny_http::Request,
+    cors: &Cors,
+    storage: SharedStorage,
+    query: &str,
+) {
+    let filter = ScanQuery {
+        sha256: query_param(query, "sha256"),
+        status: query_param(query, "status"),
+        verdict: query_param(query, "verdict"),
+        tag: query_param(query, "tag"),
+        since: query_param(query, "since"),
+        until: query_param(query, "until"),
+    };
+
+    let scans = match storage.query(&filter) {
+        Ok(scans) => scans,
+        Err(e) => {
+            println!("Failed to query stored scans: {}", e);
+            let error = ApiError::new(500, "QUERY_FAILED", "Failed to query scans");
+            let _ = request.respond(error_response(&error, cors));
+            return;
+        }
+    };
+
+    let mut summaries: Vec<ScanSummary> = scans
+        .into_iter()
+        .map(|(scan_id, result)| ScanSummary {
+            scan_id,
+            status: result.status,
+            filename: result.file_info.map(|f| f.filename).unwrap_or_default(),
+            threats_found: result.stats.threats_found,
+            timestamp: result.created_at,
+        })
+        .collect();
+    // newest first
+    summaries.sort_by(|a, b| b.timestamp.cmp(&a.timestamp));
+
+    let response = json_response(&request, serde_json::to_string(&summaries).unwrap());
+    let response = add_cors_headers(response, cors);
+    let _ = request.respond(response);
+}
+
+fn handle_delete_scan(
+    request: tiny_http::Request,
+    cors: &Cors,
+    config: &Config,
+    scan_store: ScanStore,
+    storage: SharedStorage,
+    scan_id: String,
+) {
+    println!("Deleting scan: {}", scan_id);
+
+    let removed = {
+        let mut store = scan_store.lock().unwrap();
+        store.remove(&scan_id)
+    };
+
+    match removed {
+        Some(result) => {
+            // a running scan stops at its next checkpoint once its entry is gone
+            result.cancel_flag.store(true, Ordering::SeqCst);
+            if let Some(file_info) = &result.file_info {
+                let _ = fs::remove_file(sample_path(
+                    &config.upload_dir,
+                    &scan_id,
+                    &file_info.filename,
+                ));
+            }
+            if let Err(e) = storage.remove(&scan_id) {
+                println!("Failed to remove stored scan {}: {}", scan_id, e);
+            }
+
+            let response_data = DeleteResponse {
+                scan_id,
+                deleted: true,
+            };
+            let response = json_response(&request, serde_json::to_string(&response_data).unwrap());
+            let response = add_cors_headers(response, cors);
             let _ = request.respond(response);
         }
+        None => {
+            let error = ApiError::new(404, "SCAN_NOT_FOUND", "Scan not found");
+            let _ = request.respond(error_response(&error, cors));
+        }
+    }
+}
+
+fn handle_stats(request: tiny_http::Request, cors: &Cors, stats: SharedStats) {
+    let response_data = stats.lock().unwrap().snapshot(Instant::now());
+    let response = json_response(&request, serde_json::to_string(&response_data).unwrap());
+    let response = add_cors_headers(response, cors);
+    let _ = request.respond(response);
+}
+
+fn handle_graphql(
+    mut request: tiny_http::Request,
+    cors: &Cors,
+    scan_store: ScanStore,
+    storage: SharedStorage,
+) {
+    // one byte over the cap is enough to tell an oversized body apart
+    let mut reader = request.as_reader().take(MAX_GRAPHQL_BODY_SIZE + 1);
+    let body = match read_body(&mut reader, None, MAX_GRAPHQL_BODY_SIZE) {
+        Ok(body) if body.len() as u64 > MAX_GRAPHQL_BODY_SIZE => {
+            let error = ApiError::new(
+                400,
+                "QUERY_TOO_LARGE",
+                "Query exceeds the request size limit",
+            );
+            let _ = request.respond(error_response(&error, cors));
+            return;
+        }
+        Ok(body) => body,
+        Err(_) => {
+            let error = ApiError::new(400, "BODY_READ_FAILED", "Failed to read request body");
+            let _ = request.respond(error_response(&error, cors));
+            return;
+        }
+    };
+    let graphql_request: serde_json::Value = match serde_json::from_slice(&body) {
+        Ok(graphql_request) => graphql_request,
+        Err(e) => {
+            let error = ApiError::new(400, "INVALID_JSON", format!("Invalid request body: {}", e));
+            let _ = request.respond(error_response(&error, cors));
+            return;
+        }
+    };
+
+    // once there's a request to run, its errors use GraphQL's own
+    // {"errors": [...]} shape, which is what GraphQL clients look for
+    let response = match graphql::execute(&graphql_request, &scan_store, storage.as_ref()) {
+        Ok(response_data) => json_response(&request, response_data.to_string()),
+        Err(e) => {
+            let error_response = serde_json::json!({"errors": [{"message": e}]});
+            json_response(&request, error_response.to_string()).with_status_code(400)
+        }
+    };
+    let response = add_cors_headers(response, cors);
+    let _ = request.respond(response);
+}
+
+fn handle_health(request: tiny_http::Request, cors: &Cors, started: Instant) {
+    let response_data = HealthResponse {
+        status: "ok".to_string(),
+        uptime_secs: started.elapsed().as_secs(),
+    };
+    let response = json_response(&request, serde_json::to_string(&response_data).unwrap());
+    let response = add_cors_headers(response, cors);
+    let _ = request.respond(response);
+}
+
+fn handle_metrics(request: tiny_http::Request, cors: &Cors, metrics: SharedMetrics) {
+    let response_data = metrics.lock().unwrap().snapshot();
+    let response = json_response(&request, serde_json::to_string(&response_data).unwrap());
+    let response = add_cors_headers(response, cors);
+    let _ = request.respond(response);
+}
+
+// state shared by every request handler, built once in `main`
+struct App {
+    config: Config,
+    scan_store: ScanStore,
+    storage: SharedStorage,
+    rules: Arc<RuleSet>,
+    stats: SharedStats,
+    metrics: SharedMetrics,
+    auth: Auth,
+    started: Instant,
+}
+
+// routes one request to its handler; called from the accept loop in `main`
+fn handle_request(app: &App, request: tiny_http::Request) {
+    let App {
+        config,
+        scan_store,
+        storage,
+        rules,
+        stats,
+        metrics,
+        auth,
+        started,
+    } = app;
+    let cors = Cors::new(&request, &config.cors_origins);
+
+    if request.method() == &Method::Options {
+        handle_options(request, &cors);
+        return;
+    }
+
+    let url = request.url().to_string();
+    let (url, query) = url.split_once('?').unwrap_or((url.as_str(), ""));
+    let parts: Vec<&str> = url.split('/').collect();
+
+    // GET /api/health, open so liveness probes work without the key
+    if request.method() == &Method::Get && url == "/api/health" {
+        handle_health(request, &cors, *started);
+        return;
+    }
+
+    // uploads and admin routes always need the key, SSE and results only if configured
+    let read_only = request.method() == &Method::Get
+        && parts.len() >= 3
+        && parts[1] == "api"
+        && matches!(
+            parts[2],
+            "scan-status" | "scan-result" | "scan-strings" | "scan-report"
+        );
+    if !auth.allows(&request, read_only) {
+        respond_unauthorized(request, &cors);
+        return;
+    }
+
+    // POST /api/upload
+    if request.method() == &Method::Post && url == "/api/upload" {
+        handle_upload(
+            request,
+            &cors,
+            config,
+            scan_store.clone(),
+            storage.clone(),
+            rules.clone(),
+            stats.clone(),
+            metrics.clone(),
+            query,
+        );
+    }
+    // GET /api/scans
+    else if request.method() == &Method::Get && url == "/api/scans" {
+        handle_list_scans(request, &cors, storage.clone(), query);
+    }
+    // DELETE /api/scan/{scanId}
+    else if request.method() == &Method::Delete
+        && parts.len() >= 4
+        && parts[1] == "api"
+        && parts[2] == "scan"
+    {
+        let scan_id = parts[3].to_string();
+        handle_delete_scan(
+            request,
+            &cors,
+            config,
+            scan_store.clone(),
+            storage.clone(),
+            scan_id,
+        );
+    }
+    // GET /api/stats
+    else if request.method() == &Method::Get && url == "/api/stats" {
+        handle_stats(request, &cors, stats.clone());
+    }
+    // POST /api/graphql
+    else if request.method() == &Method::Post && url == "/api/graphql" && config.graphql {
+        handle_graphql(request, &cors, scan_store.clone(), storage.clone());
+    }
+    // GET /api/metrics
+    else if request.method() == &Method::Get && url == "/api/metrics" {
+        handle_metrics(request, &cors, metrics.clone());
+    }
+    // POST /api/scan-cancel/{scanId}
+    else if request.method() == &Method::Post
+        && parts.len() >= 4
+        && parts[1] == "api"
+        && parts[2] == "scan-cancel"
+    {
+        let scan_id = parts[3].to_string();
+        handle_scan_cancel(request, &cors, scan_store.clone(), scan_id);
+    }
+    // GET /api/scan-status/{scanId}
+    else if request.method() == &Method::Get
+        && parts.len() >= 4
+        && parts[1] == "api"
+        && parts[2] == "scan-status"
+    {
+        let scan_id = parts[3].to_string();
+        handle_scan_status(request, &cors, scan_store.clone(), scan_id);
+    }
+    // GET /api/scan-strings/{scanId}
+    else if request.method() == &Method::Get
+        && parts.len() >= 4
+        && parts[1] == "api"
+        && parts[2] == "scan-strings"
+    {
+        let scan_id = parts[3].to_string();
+        handle_scan_strings(
+            request,
+            &cors,
+            scan_store.clone(),
+            scan_id,
+            query_param(query, "min_len").as_deref(),
+        );
+    }
+    // GET /api/scan-report/{scanId}?format=csv|txt
+    else if request.method() == &Method::Get
+        && parts.len() >= 4
+        && parts[1] == "api"
+        && parts[2] == "scan-report"
+    {
+        let scan_id = parts[3].to_string();
+        handle_scan_report(
+            request,
+            &cors,
+            scan_store.clone(),
+            scan_id,
+            query_param(query, "format").as_deref(),
+        );
+    }
+    // GET /api/scan-result/{scanId}/verify-integrity
+    else if request.method() == &Method::Get
+        && parts.len() >= 5
+        && parts[1] == "api"
+        && parts[2] == "scan-result"
+        && parts[4] == "verify-integrity"
+    {
+        let scan_id = parts[3].to_string();
+        handle_verify_integrity(request, &cors, config, scan_store.clone(), scan_id);
+    }
+    // GET /api/scan-result/{scanId}
+    else if request.method() == &Method::Get
+        && parts.len() >= 4
+        && parts[1] == "api"
+        && parts[2] == "scan-result"
+    {
+        let scan_id = parts[3].to_string();
+        handle_scan_result(
+            request,
+            &cors,
+            scan_store.clone(),
+            scan_id,
+            query_param(query, "format").as_deref(),
+        );
+    } else {
+        let error = ApiError::new(404, "NOT_FOUND", "Not found");
+        let _ = request.respond(error_response(&error, &cors));
     }
 }
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() >= 3 && args[1] == ANALYZE_FLAG {
+        let parallel = args[3..].iter().any(|arg| arg == PARALLEL_FLAG);
+        std::process::exit(run_child(Path::new(&args[2]), parallel));
+    }
+
     println!("Starting PEroxide backend server...");
 
-    fs::create_dir_all(UPLOAD_DIR).expect("Failed to create upload directory");
+    let config = match Config::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    fs::create_dir_all(&config.upload_dir).expect("Failed to create upload directory");
 
-    let server = Server::http("0.0.0.0:3001").unwrap();
-    let scan_store: ScanStore = Arc::new(Mutex::new(HashMap::new()));
+    let server = Arc::new(Server::http(&config.bind).unwrap());
+    let ws_listener = TcpListener::bind(&config.ws_bind).unwrap();
+    if !matches!(config.store, StoreBackend::Sqlite) && Path::new(DB_PATH).exists() {
+        println!(
+            "⚠️  {} exists but isn't read; set PEROXIDE_STORE=sqlite to keep using it",
+            DB_PATH
+        );
+    }
+    let storage = match open_storage(config.store) {
+        Ok(storage) => storage,
+        Err(e) => {
+            eprintln!("Failed to open {:?} result store: {}", config.store, e);
+            std::process::exit(1);
+        }
+    };
+    let scans = load_all(storage.as_ref()).expect("Failed to load stored scans");
+    println!(
+        "Loaded {} stored scans from the {:?} result store",
+        scans.len(),
+        config.store
+    );
+    let scan_store: ScanStore = Arc::new(Mutex::new(scans));
+    let rules = Arc::new(load_rules(Path::new(RULES_PATH)));
+    let stats: SharedStats = Arc::new(Mutex::new(StatsTracker::default()));
+    let metrics: SharedMetrics = Arc::new(Mutex::new(Metrics::default()));
+    let started = Instant::now();
+    let auth = Auth::from_env();
 
-    println!("🚀 Server starting on http://0.0.0.0:3001");
+    println!("🚀 Server starting on http://{}", config.bind);
+    println!(
+        "🔌 WebSocket progress on ws://{}/api/scan-ws/{{scanId}}",
+        config.ws_bind
+    );
     println!("📡 Ready to receive file scan requests");
-    println!("📁 Upload directory: {}", UPLOAD_DIR);
+    println!("📁 Upload directory: {}", config.upload_dir.display());
+    println!(
+        "📦 Max file size: {} bytes ({}MB)",
+        config.max_file_size,
+        config.max_file_size / 1024 / 1024
+    );
+    println!(
+        "📦 Max batch size: {} bytes ({}MB)",
+        config.max_batch_size,
+        config.max_batch_size / 1024 / 1024
+    );
+    println!(
+        "⚖️  Verdict thresholds: suspicious >= {}, malicious >= {}",
+        config.verdict.suspicious, config.verdict.malicious
+    );
+    if config.sandbox {
+        println!("🧪 Analysis runs in a sandboxed child process");
+    }
+    if config.parallel_analysis {
+        println!("🧵 Analysis phases run in parallel for large files");
+    }
+    if config.graphql {
+        println!("🔎 Read-only GraphQL endpoint at POST /api/graphql");
+    }
+    if config.retain_samples {
+        println!("🗄️  Samples are retained for integrity verification");
+    }
+    if auth.is_enabled() {
+        println!("🔒 API key required via X-API-Key header");
+    }
+    let app = Arc::new(App {
+        config,
+        scan_store,
+        storage,
+        rules,
+        stats,
+        metrics,
+        auth,
+        started,
+    });
+    {
+        let app = app.clone();
+        thread::spawn(move || serve_websockets(ws_listener, app));
+    }
+
+    // SIGINT/SIGTERM wake the accept loop below; a second one skips the drain
+    let shutting_down = Arc::new(AtomicBool::new(false));
+    {
+        let server = Arc::downgrade(&server);
+        let shutting_down = shutting_down.clone();
+        ctrlc::set_handler(move || {
+            if shutting_down.swap(true, Ordering::SeqCst) {
+                std::process::exit(1);
+            }
+            if let Some(server) = server.upgrade() {
+                server.unblock();
+            }
+        })
+        .expect("Failed to install signal handler");
+    }
 
     for request in server.incoming_requests() {
-        let scan_store = scan_store.clone();
+        if shutting_down.load(Ordering::SeqCst) {
+            break;
+        }
+        handle_request(&app, request);
+    }
 
-        if request.method() == &Method::Options {
-            handle_options(request);
-            continue;
+    // dropping the server closes the listener so nothing new is accepted
+    drop(server);
+    println!("🛑 Shutting down, waiting for running scans...");
+    shut_down(&app, SHUTDOWN_GRACE);
+    println!("👋 Shutdown complete");
+}
+
+// once nothing new is accepted: gives running scans `grace` to finish, then
+// clears the upload directory of everything but retained samples
+fn shut_down(app: &App, grace: Duration) {
+    drain_scans(&app.scan_store, &app.storage, &app.config.upload_dir, grace);
+    clean_upload_dir(
+        &app.scan_store,
+        &app.config.upload_dir,
+        app.config.retain_samples,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{noise, scan_result, temp_dir};
+    use std::collections::HashMap;
+    use std::net::SocketAddr;
+    use std::time::Duration;
+    use tungstenite::client::IntoClientRequest;
+
+    struct TestServer {
+        addr: SocketAddr,
+        // where WebSockets are served
+        ws_addr: SocketAddr,
+        app: Arc<App>,
+        scan_store: ScanStore,
+        upload_dir: PathBuf,
+    }
+
+    impl Drop for TestServer {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.upload_dir);
         }
+    }
+
+    // serves the default configuration, as adjusted by `configure`, on an
+    // ephemeral port with an in-memory store; the server lives until the
+    // test process exits
+    fn serve(configure: impl FnOnce(&mut Config)) -> TestServer {
+        serve_with_auth(Auth::new(None, true), configure)
+    }
 
-        let url = request.url().to_string();
-        let parts: Vec<&str> = url.split('/').collect();
+    fn serve_with_auth(auth: Auth, configure: impl FnOnce(&mut Config)) -> TestServer {
+        let upload_dir = temp_dir();
+        let mut config = Config {
+            bind: "127.0.0.1:0".to_string(),
+            ws_bind: "127.0.0.1:0".to_string(),
+            upload_dir: upload_dir.clone(),
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            sandbox: false,
+            parallel_analysis: false,
+            verdict: VerdictThresholds {
+                suspicious: DEFAULT_SUSPICIOUS_SCORE,
+                malicious: DEFAULT_MALICIOUS_SCORE,
+            },
+            store: StoreBackend::Memory,
+            dedupe_uploads: true,
+            retain_samples: false,
+            graphql: true,
+            cors_origins: vec![],
+        };
+        configure(&mut config);
 
-        // POST /api/upload
-        if request.method() == &Method::Post && url == "/api/upload" {
-            handle_upload(request, scan_store.clone());
-            continue;
+        let server = Server::http(&config.bind).unwrap();
+        let addr = server.server_addr().to_ip().unwrap();
+        let ws_listener = TcpListener::bind(&config.ws_bind).unwrap();
+        let ws_addr = ws_listener.local_addr().unwrap();
+        let app = Arc::new(App {
+            config,
+            scan_store: Arc::new(Mutex::new(HashMap::new())),
+            storage: Arc::new(MemoryStore::default()),
+            rules: Arc::new(RuleSet::default()),
+            stats: Arc::new(Mutex::new(StatsTracker::default())),
+            metrics: Arc::new(Mutex::new(Metrics::default())),
+            auth,
+            started: Instant::now(),
+        });
+        let scan_store = app.scan_store.clone();
+        {
+            let app = app.clone();
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    handle_request(&app, request);
+                }
+            });
         }
-        // GET /api/scan-status/{scanId}
-        else if request.method() == &Method::Get
-            && parts.len() >= 4
-            && parts[1] == "api"
-            && parts[2] == "scan-status"
         {
-            let scan_id = parts[3].to_string();
-            handle_scan_status(request, scan_store.clone(), scan_id);
-            continue;
+            let app = app.clone();
+            thread::spawn(move || serve_websockets(ws_listener, app));
+        }
+        TestServer {
+            addr,
+            ws_addr,
+            app,
+            scan_store,
+            upload_dir,
+        }
+    }
+
+    // one HTTP/1.0 exchange, so the response is never chunked and the
+    // connection closes after it; `head` is the request line plus any headers
+    fn send(addr: SocketAddr, head: &str, body: &[u8]) -> (u16, String, Vec<u8>) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(30)))
+            .unwrap();
+        write!(stream, "{}\r\nHost: test\r\n\r\n", head).unwrap();
+        // the server may answer and close before taking the whole body
+        let _ = stream.write_all(body);
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+
+        let split = response
+            .windows(4)
+            .position(|w| w == b"\r\n\r\n")
+            .expect("incomplete response");
+        let headers = String::from_utf8_lossy(&response[..split]).to_string();
+        let status = headers[9..12].parse().unwrap();
+        (status, headers, response[split + 4..].to_vec())
+    }
+
+    fn send_json(addr: SocketAddr, head: &str, body: &[u8]) -> (u16, serde_json::Value) {
+        let (status, _, body) = send(addr, head, body);
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn multipart(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (filename, content) in files {
+            write!(
+                body,
+                "--{}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\
+                 Content-Type: application/octet-stream\r\n\r\n",
+                TEST_BOUNDARY, filename
+            )
+            .unwrap();
+            body.extend_from_slice(content);
+            body.extend_from_slice(b"\r\n");
+        }
+        write!(body, "--{}--\r\n", TEST_BOUNDARY).unwrap();
+        body
+    }
+
+    const TEST_BOUNDARY: &str = "peroxide-test-boundary";
+
+    fn upload(server: &TestServer, files: &[(&str, &[u8])]) -> (u16, serde_json::Value) {
+        upload_to(server, "/api/upload", files)
+    }
+
+    fn upload_to(
+        server: &TestServer,
+        path: &str,
+        files: &[(&str, &[u8])],
+    ) -> (u16, serde_json::Value) {
+        let body = multipart(files);
+        let head = format!(
+            "POST {} HTTP/1.0\r\nContent-Type: multipart/form-data; boundary={}\r\n\
+             Content-Length: {}",
+            path,
+            TEST_BOUNDARY,
+            body.len()
+        );
+        send_json(server.addr, &head, &body)
+    }
+
+    // polls the store until the scan leaves "scanning"
+    fn wait_for_scan(server: &TestServer, scan_id: &str) -> ScanResult {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        loop {
+            let result = server.scan_store.lock().unwrap()[scan_id].clone();
+            if result.status != "scanning" {
+                return result;
+            }
+            assert!(Instant::now() < deadline, "scan {} never finished", scan_id);
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    // the open socket, or the HTTP status the handshake was refused with;
+    // `headers` are added to the handshake request
+    fn ws_connect(
+        server: &TestServer,
+        scan_id: &str,
+        headers: &[(&'static str, &str)],
+    ) -> Result<WebSocket<tungstenite::stream::MaybeTlsStream<TcpStream>>, u16> {
+        let url = format!("ws://{}/api/scan-ws/{}", server.ws_addr, scan_id);
+        let mut request = url.into_client_request().unwrap();
+        for (name, value) in headers {
+            request.headers_mut().insert(*name, value.parse().unwrap());
+        }
+        match tungstenite::connect(request) {
+            Ok((socket, _)) => Ok(socket),
+            Err(tungstenite::Error::Http(response)) => Err(response.status().as_u16()),
+            Err(other) => panic!("WebSocket handshake failed: {}", other),
+        }
+    }
+
+    #[test]
+    fn websocket_sends_the_result_and_closes() {
+        let server = serve(|_| {});
+        server
+            .scan_store
+            .lock()
+            .unwrap()
+            .insert("scan-done".to_string(), scan_result("safe"));
+
+        let mut socket = ws_connect(&server, "scan-done", &[]).unwrap();
+        let mut frames = Vec::new();
+        loop {
+            match socket.read() {
+                Ok(Message::Text(text)) => frames.push(text),
+                Ok(Message::Close(_)) | Err(_) => break,
+                Ok(_) => {}
+            }
+        }
+        let last: serde_json::Value = serde_json::from_str(frames.last().unwrap()).unwrap();
+        assert_eq!(last["result"]["status"], "safe");
+    }
+
+    #[test]
+    fn websocket_needs_an_upgrade_and_a_known_scan() {
+        let server = serve(|_| {});
+        server
+            .scan_store
+            .lock()
+            .unwrap()
+            .insert("scan-done".to_string(), scan_result("safe"));
+
+        let (status, body) = send_json(server.ws_addr, "GET /api/scan-ws/scan-done HTTP/1.0", b"");
+        assert_eq!(status, 400);
+        assert_eq!(body["code"], "WEBSOCKET_UPGRADE_REQUIRED");
+        // the HTTP server doesn't serve WebSockets
+        let (status, _) = send_json(server.addr, "GET /api/scan-ws/scan-done HTTP/1.0", b"");
+        assert_eq!(status, 404);
+
+        assert_eq!(ws_connect(&server, "scan-missing", &[]).err(), Some(404));
+    }
+
+    #[test]
+    fn websocket_enforces_the_origin_allow_list() {
+        let server = serve(|config| config.cors_origins = vec!["https://good.example".to_string()]);
+        server
+            .scan_store
+            .lock()
+            .unwrap()
+            .insert("scan-done".to_string(), scan_result("safe"));
+
+        let refused = ws_connect(&server, "scan-done", &[("Origin", "https://evil.example")]);
+        assert_eq!(refused.err(), Some(403));
+        assert!(ws_connect(&server, "scan-done", &[("Origin", "https://good.example")]).is_ok());
+        // non-browser clients send no Origin at all
+        assert!(ws_connect(&server, "scan-done", &[]).is_ok());
+    }
+
+    #[test]
+    fn websocket_needs_the_key_when_reads_are_protected() {
+        let server = serve_with_auth(Auth::new(Some("s3cret".to_string()), true), |_| {});
+        server
+            .scan_store
+            .lock()
+            .unwrap()
+            .insert("scan-done".to_string(), scan_result("safe"));
+
+        assert_eq!(ws_connect(&server, "scan-done", &[]).err(), Some(401));
+        let wrong = ws_connect(&server, "scan-done", &[("X-API-Key", "wrong")]);
+        assert_eq!(wrong.err(), Some(401));
+        assert!(ws_connect(&server, "scan-done", &[("X-API-Key", "s3cret")]).is_ok());
+
+        let server = serve_with_auth(Auth::new(Some("s3cret".to_string()), false), |_| {});
+        server
+            .scan_store
+            .lock()
+            .unwrap()
+            .insert("scan-done".to_string(), scan_result("safe"));
+        assert!(ws_connect(&server, "scan-done", &[]).is_ok());
+    }
+
+    #[test]
+    fn websocket_drops_a_client_that_never_answers_pings() {
+        let server = serve(|_| {});
+        server
+            .scan_store
+            .lock()
+            .unwrap()
+            .insert("scan-running".to_string(), scan_result("scanning"));
+
+        let mut stream = TcpStream::connect(server.ws_addr).unwrap();
+        let limit = WS_PING_INTERVAL + WS_PONG_TIMEOUT + Duration::from_secs(5);
+        stream.set_read_timeout(Some(limit)).unwrap();
+        write!(
+            stream,
+            "GET /api/scan-ws/scan-running HTTP/1.1\r\nHost: test\r\nUpgrade: websocket\r\n\
+             Connection: Upgrade\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+             Sec-WebSocket-Version: 13\r\n\r\n"
+        )
+        .unwrap();
+
+        // read the handshake and the pings without ever answering; the
+        // server has to close the connection before the read times out
+        let started = Instant::now();
+        let mut received = Vec::new();
+        stream
+            .read_to_end(&mut received)
+            .expect("server kept the connection open");
+        assert!(received.starts_with(b"HTTP/1.1 101"));
+        assert!(started.elapsed() >= WS_PONG_TIMEOUT);
+    }
+
+    #[test]
+    fn retained_sample_verifies_until_tampered_with() {
+        let server = serve(|config| config.retain_samples = true);
+        let (status, body) = upload(&server, &[("sample.txt", b"hello sample")]);
+        assert_eq!(status, 200);
+        let scan_id = body["scanId"].as_str().unwrap().to_string();
+        wait_for_scan(&server, &scan_id);
+
+        let head = format!("GET /api/scan-result/{}/verify-integrity HTTP/1.0", scan_id);
+        let (status, body) = send_json(server.addr, &head, b"");
+        assert_eq!(status, 200);
+        assert_eq!(body["verified"], true);
+        assert_eq!(body["expectedSha256"], body["actualSha256"]);
+
+        let path = sample_path(&server.upload_dir, &scan_id, "sample.txt");
+        fs::write(&path, b"tampered sample").unwrap();
+        let (status, body) = send_json(server.addr, &head, b"");
+        assert_eq!(status, 200);
+        assert_eq!(body["verified"], false);
+        assert_eq!(body["actualSha256"], calculate_sha256(b"tampered sample"));
+    }
+
+    #[test]
+    fn samples_are_removed_unless_retained() {
+        let server = serve(|_| {});
+        let (_, body) = upload(&server, &[("sample.txt", b"hello sample")]);
+        let scan_id = body["scanId"].as_str().unwrap().to_string();
+        wait_for_scan(&server, &scan_id);
+
+        let head = format!("GET /api/scan-result/{}/verify-integrity HTTP/1.0", scan_id);
+        let (status, body) = send_json(server.addr, &head, b"");
+        assert_eq!(status, 404);
+        assert_eq!(body["code"], "SAMPLE_NOT_RETAINED");
+    }
+
+    #[test]
+    fn oversize_content_length_is_refused_before_reading() {
+        let server = serve(|config| config.max_file_size = 1024);
+        let cases = [
+            (
+                "/api/upload",
+                1024 + MULTIPART_OVERHEAD + 1,
+                "FILE_TOO_LARGE",
+            ),
+            (
+                "/api/upload?batch=true",
+                DEFAULT_MAX_BATCH_SIZE + MULTIPART_OVERHEAD + 1,
+                "BATCH_TOO_LARGE",
+            ),
+        ];
+        for (path, declared, code) in cases {
+            let head = format!(
+                "POST {} HTTP/1.0\r\nContent-Type: multipart/form-data; boundary={}\r\n\
+                 Content-Length: {}",
+                path, TEST_BOUNDARY, declared
+            );
+            // none of the body is sent, so only the header can be judged
+            let (status, body) = send_json(server.addr, &head, b"");
+            assert_eq!(status, 413);
+            assert_eq!(body["code"], code);
+        }
+    }
+
+    #[test]
+    fn only_batches_may_exceed_one_file() {
+        let server = serve(|config| config.max_file_size = 64 * 1024);
+        let part = vec![0x41u8; 60 * 1024];
+        let names: Vec<String> = (0..20).map(|i| format!("part{}.bin", i)).collect();
+        let files: Vec<(&str, &[u8])> = names.iter().map(|n| (n.as_str(), &part[..])).collect();
+
+        let (status, body) = upload(&server, &files);
+        assert_eq!(status, 413);
+        assert_eq!(body["code"], "FILE_TOO_LARGE");
+
+        let (status, body) = upload_to(&server, "/api/upload?batch=true", &files);
+        assert_eq!(status, 200);
+        assert_eq!(body.as_array().unwrap().len(), 20);
+    }
+
+    #[test]
+    fn undeclared_oversize_stream_is_cut_off() {
+        let server = serve(|config| config.max_file_size = 1024);
+        let body = multipart(&[("big.bin", &vec![0u8; MULTIPART_OVERHEAD as usize + 4096])]);
+        let mut stream = TcpStream::connect(server.addr).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(30)))
+            .unwrap();
+        write!(
+            stream,
+            "POST /api/upload HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\
+             Content-Type: multipart/form-data; boundary={}\r\n\
+             Transfer-Encoding: chunked\r\n\r\n{:x}\r\n",
+            TEST_BOUNDARY,
+            body.len()
+        )
+        .unwrap();
+        let _ = stream.write_all(&body);
+        let _ = stream.write_all(b"\r\n0\r\n\r\n");
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+        assert!(response.starts_with(b"HTTP/1.1 413"));
+    }
+
+    #[test]
+    fn batch_upload_starts_a_scan_per_file() {
+        let server = serve(|_| {});
+        let files: [(&str, &[u8]); 3] = [
+            ("first.txt", b"first sample"),
+            ("second.txt", b"second sample"),
+            ("again.txt", b"first sample"),
+        ];
+        let (status, body) = upload(&server, &files);
+        assert_eq!(status, 200);
+        let scans = body.as_array().unwrap();
+        assert_eq!(scans.len(), 3);
+        assert_ne!(scans[0]["scanId"], scans[1]["scanId"]);
+        // the repeated file joins the first copy's scan
+        assert_eq!(scans[2]["scanId"], scans[0]["scanId"]);
+        assert_eq!(scans[2]["cached"], true);
+
+        for (scan, (filename, _)) in scans.iter().zip(&files[..2]) {
+            let result = wait_for_scan(&server, scan["scanId"].as_str().unwrap());
+            assert_eq!(result.file_info.unwrap().filename, *filename);
         }
-        // GET /api/scan-result/{scanId}
-        else if request.method() == &Method::Get
-            && parts.len() >= 4
-            && parts[1] == "api"
-            && parts[2] == "scan-result"
+
+        // a single file still gets a plain object
+        let (_, body) = upload(&server, &[("third.txt", b"third sample")]);
+        assert!(body["scanId"].is_string());
+    }
+
+    #[test]
+    fn batch_with_an_oversize_part_is_refused() {
+        let server = serve(|config| config.max_file_size = 1024);
+        let big = vec![0u8; 2048];
+        let files: [(&str, &[u8]); 2] = [("small.txt", b"small"), ("big.bin", &big)];
+        let (status, body) = upload_to(&server, "/api/upload?batch=true", &files);
+        assert_eq!(status, 400);
+        assert_eq!(body["code"], "FILE_TOO_LARGE");
+        assert!(server.scan_store.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn failed_save_starts_no_scans() {
+        let server = serve(|_| {});
+        // too long for a file name once the scan ID is prepended
+        let long_name = "x".repeat(250);
+        let files: [(&str, &[u8]); 2] = [("fine.txt", b"fine sample"), (&long_name, b"other")];
+        let (status, body) = upload(&server, &files);
+        assert_eq!(status, 500);
+        assert_eq!(body["code"], "SAVE_FAILED");
+        assert!(server.scan_store.lock().unwrap().is_empty());
+        assert_eq!(fs::read_dir(&server.upload_dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn graphql_transport_errors_use_the_api_error_shape() {
+        let server = serve(|_| {});
+        let post = |body: &[u8]| {
+            let head = format!(
+                "POST /api/graphql HTTP/1.0\r\nContent-Type: application/json\r\n\
+                 Content-Length: {}",
+                body.len()
+            );
+            send_json(server.addr, &head, body)
+        };
+
+        let (status, body) = post(b"{not json");
+        assert_eq!(status, 400);
+        assert_eq!(body["code"], "INVALID_JSON");
+        assert!(body["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("Invalid request body"));
+        assert_eq!(body.as_object().unwrap().len(), 2);
+
+        let oversized = vec![b' '; MAX_GRAPHQL_BODY_SIZE as usize + 1];
+        let (status, body) = post(&oversized);
+        assert_eq!(status, 400);
+        assert_eq!(body["code"], "QUERY_TOO_LARGE");
+
+        // a query that can't run is a GraphQL error, not a transport one
+        let (status, body) = post(br#"{"query": "{ scan("}"#);
+        assert_eq!(status, 400);
+        assert!(body["errors"][0]["message"].is_string());
+        assert!(body.get("code").is_none());
+    }
+
+    #[test]
+    fn api_errors_carry_a_code_and_message() {
+        let server = serve(|_| {});
+        let (status, body) = send_json(
+            server.addr,
+            "GET /api/scan-result/scan-missing HTTP/1.0",
+            b"",
+        );
+        assert_eq!(status, 404);
+        assert_eq!(
+            body,
+            serde_json::json!({"code": "SCAN_NOT_FOUND", "error": "Scan not found"})
+        );
+
+        let head = "POST /api/upload HTTP/1.0\r\nContent-Type: text/plain\r\nContent-Length: 2";
+        let (status, body) = send_json(server.addr, head, b"hi");
+        assert_eq!(status, 400);
+        assert_eq!(body["code"], "INVALID_CONTENT_TYPE");
+        assert_eq!(body["error"], "Expected multipart/form-data");
+    }
+
+    #[test]
+    fn cancel_endpoint_flags_running_scans_only() {
+        let server = serve(|_| {});
+        let running = scan_result("scanning");
+        let flag = running.cancel_flag.clone();
         {
-            let scan_id = parts[3].to_string();
-            handle_scan_result(request, scan_store.clone(), scan_id);
-            continue;
-        } else {
-            let error_response = serde_json::json!({"error": "Not found"});
-            let response = Response::from_string(error_response.to_string()).with_status_code(404);
-            let response = add_cors_headers(response);
-            let _ = request.respond(response);
+            let mut store = server.scan_store.lock().unwrap();
+            store.insert("scan-running".to_string(), running);
+            store.insert("scan-done".to_string(), scan_result("safe"));
+        }
+
+        let (status, body) = send_json(
+            server.addr,
+            "POST /api/scan-cancel/scan-running HTTP/1.0",
+            b"",
+        );
+        assert_eq!(status, 200);
+        assert_eq!(
+            body,
+            serde_json::json!({"scanId": "scan-running", "status": "cancelling"})
+        );
+        assert!(flag.load(Ordering::SeqCst));
+
+        // a finished scan just reports how it ended, however often it's asked
+        for _ in 0..2 {
+            let (status, body) =
+                send_json(server.addr, "POST /api/scan-cancel/scan-done HTTP/1.0", b"");
+            assert_eq!(status, 200);
+            assert_eq!(body["status"], "safe");
+        }
+        let done_flag = server.scan_store.lock().unwrap()["scan-done"]
+            .cancel_flag
+            .clone();
+        assert!(!done_flag.load(Ordering::SeqCst));
+
+        let (status, body) = send_json(
+            server.addr,
+            "POST /api/scan-cancel/scan-missing HTTP/1.0",
+            b"",
+        );
+        assert_eq!(status, 404);
+        assert_eq!(body["code"], "SCAN_NOT_FOUND");
+    }
+
+    #[test]
+    fn deleted_scans_leave_the_list() {
+        let server = serve(|config| config.retain_samples = true);
+        let mut scan_ids = Vec::new();
+        for (name, content) in [("first.bin", b"first"), ("second.bin", b"secnd")] {
+            let (status, body) = upload(&server, &[(name, content)]);
+            assert_eq!(status, 200);
+            let scan_id = body["scanId"].as_str().unwrap().to_string();
+            wait_for_scan(&server, &scan_id);
+            scan_ids.push(scan_id);
+            // the list is ordered by created_at, which has millisecond resolution
+            thread::sleep(Duration::from_millis(5));
+        }
+
+        let (status, headers, body) = send(server.addr, "GET /api/scans HTTP/1.0", b"");
+        assert_eq!(status, 200);
+        assert!(
+            headers.contains("Access-Control-Allow-Origin"),
+            "{}",
+            headers
+        );
+        let list: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let listed: Vec<&str> = list
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|scan| scan["scanId"].as_str().unwrap())
+            .collect();
+        assert_eq!(listed, [scan_ids[1].as_str(), scan_ids[0].as_str()]);
+        assert_eq!(list[0]["filename"], "second.bin");
+        assert_eq!(list[0]["status"], "safe");
+        assert_eq!(list[0]["threatsFound"], 0);
+
+        let sample = sample_path(&server.upload_dir, &scan_ids[0], "first.bin");
+        assert!(sample.exists());
+        let delete = format!("DELETE /api/scan/{} HTTP/1.0", scan_ids[0]);
+        let (status, body) = send_json(server.addr, &delete, b"");
+        assert_eq!(status, 200);
+        assert_eq!(
+            body,
+            serde_json::json!({"scanId": scan_ids[0], "deleted": true})
+        );
+        assert!(!sample.exists());
+
+        let (_, list) = send_json(server.addr, "GET /api/scans HTTP/1.0", b"");
+        assert_eq!(list.as_array().unwrap().len(), 1);
+        assert_eq!(list[0]["scanId"], scan_ids[1].as_str());
+
+        let status_of = format!("GET /api/scan-status/{} HTTP/1.0", scan_ids[0]);
+        let (status, body) = send_json(server.addr, &status_of, b"");
+        assert_eq!(status, 404);
+        assert_eq!(body["code"], "SCAN_NOT_FOUND");
+
+        // a second delete finds nothing
+        let (status, body) = send_json(server.addr, &delete, b"");
+        assert_eq!(status, 404);
+        assert_eq!(body["code"], "SCAN_NOT_FOUND");
+    }
+
+    #[test]
+    fn scan_completes_while_an_sse_client_never_reads() {
+        let server = serve(|_| {});
+        let (_, body) = upload(&server, &[("sample.bin", &noise(256 * 1024))]);
+        let scan_id = body["scanId"].as_str().unwrap();
+
+        // subscribe and then never read a byte
+        let started = Instant::now();
+        let mut stalled = TcpStream::connect(server.addr).unwrap();
+        write!(
+            stalled,
+            "GET /api/scan-status/{} HTTP/1.1\r\nHost: test\r\n\r\n",
+            scan_id
+        )
+        .unwrap();
+
+        let result = wait_for_scan(&server, scan_id);
+        assert_eq!(result.status, "safe");
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        // the server is still free for other clients
+        let (status, _) = send_json(server.addr, "GET /api/health HTTP/1.0", b"");
+        assert_eq!(status, 200);
+        drop(stalled);
+    }
+
+    #[test]
+    fn api_key_guards_uploads_and_admin_routes() {
+        let server = serve_with_auth(Auth::new(Some("s3cret".to_string()), true), |_| {});
+        server
+            .scan_store
+            .lock()
+            .unwrap()
+            .insert("scan-done".to_string(), scan_result("safe"));
+
+        let body = multipart(&[("sample.bin", b"sample")]);
+        let upload = |key: Option<&str>| {
+            let mut head = format!(
+                "POST /api/upload HTTP/1.0\r\nContent-Type: multipart/form-data; boundary={}\r\n\
+                 Content-Length: {}",
+                TEST_BOUNDARY,
+                body.len()
+            );
+            if let Some(key) = key {
+                head.push_str(&format!("\r\nX-API-Key: {}", key));
+            }
+            send_json(server.addr, &head, &body)
+        };
+        for key in [None, Some("wrong"), Some("s3cre"), Some("s3cret2")] {
+            let (status, body) = upload(key);
+            assert_eq!(status, 401, "{:?}", key);
+            assert_eq!(body["code"], "UNAUTHORIZED");
+        }
+        let (status, body) = upload(Some("s3cret"));
+        assert_eq!(status, 200);
+        assert!(body["scanId"].is_string());
+
+        let (status, _) = send_json(server.addr, "GET /api/scans HTTP/1.0", b"");
+        assert_eq!(status, 401);
+        let (status, _) = send_json(server.addr, "DELETE /api/scan/scan-done HTTP/1.0", b"");
+        assert_eq!(status, 401);
+        let (status, _) = send_json(
+            server.addr,
+            "GET /api/scans HTTP/1.0\r\nx-api-key: s3cret",
+            b"",
+        );
+        assert_eq!(status, 200);
+
+        // reads are protected by default, health never is
+        let (status, _) = send_json(server.addr, "GET /api/scan-result/scan-done HTTP/1.0", b"");
+        assert_eq!(status, 401);
+        let (status, _) = send_json(server.addr, "GET /api/health HTTP/1.0", b"");
+        assert_eq!(status, 200);
+    }
+
+    #[test]
+    fn reads_can_be_left_open() {
+        let server = serve_with_auth(Auth::new(Some("s3cret".to_string()), false), |_| {});
+        server
+            .scan_store
+            .lock()
+            .unwrap()
+            .insert("scan-done".to_string(), scan_result("safe"));
+
+        let (status, body) = send_json(server.addr, "GET /api/scan-result/scan-done HTTP/1.0", b"");
+        assert_eq!(status, 200);
+        assert_eq!(body["status"], "safe");
+        // admin routes still need the key
+        let (status, _) = send_json(server.addr, "GET /api/scans HTTP/1.0", b"");
+        assert_eq!(status, 401);
+    }
+
+    #[test]
+    fn empty_api_key_disables_auth() {
+        let server = serve_with_auth(Auth::new(Some(String::new()), true), |_| {});
+        let (status, _) = send_json(server.addr, "GET /api/scans HTTP/1.0", b"");
+        assert_eq!(status, 200);
+    }
+
+    #[test]
+    fn scan_result_can_be_exported_as_stix() {
+        let server = serve(|_| {});
+        let (_, body) = upload(&server, &[("sample.bin", b"sample")]);
+        let scan_id = body["scanId"].as_str().unwrap();
+        let result = wait_for_scan(&server, scan_id);
+        let sha256 = result.file_info.unwrap().sha256;
+
+        let get = |format: &str| {
+            let head = format!("GET /api/scan-result/{}{} HTTP/1.0", scan_id, format);
+            send_json(server.addr, &head, b"")
+        };
+        let (status, bundle) = get("?format=stix");
+        assert_eq!(status, 200);
+        assert_eq!(bundle["type"], "bundle");
+        assert_eq!(bundle["objects"][0]["type"], "file");
+        assert_eq!(bundle["objects"][0]["hashes"]["SHA-256"], sha256);
+
+        // JSON stays the default
+        let (status, plain) = get("");
+        assert_eq!(status, 200);
+        assert_eq!(plain["status"], "safe");
+        assert_eq!(get("?format=json").1, plain);
+
+        let (status, body) = get("?format=yaml");
+        assert_eq!(status, 400);
+        assert_eq!(body["code"], "UNSUPPORTED_FORMAT");
+
+        server
+            .scan_store
+            .lock()
+            .unwrap()
+            .insert("scan-running".to_string(), scan_result("scanning"));
+        let (status, body) = send_json(
+            server.addr,
+            "GET /api/scan-result/scan-running?format=stix HTTP/1.0",
+            b"",
+        );
+        assert_eq!(status, 409);
+        assert_eq!(body["code"], "SCAN_RUNNING");
+    }
+
+    #[test]
+    fn scan_report_downloads_as_csv_or_text() {
+        let server = serve(|_| {});
+        let (_, body) = upload(&server, &[("sample.bin", b"sample")]);
+        let scan_id = body["scanId"].as_str().unwrap();
+        wait_for_scan(&server, scan_id);
+
+        let head = format!("GET /api/scan-report/{}?format=csv HTTP/1.0", scan_id);
+        let (status, headers, body) = send(server.addr, &head, b"");
+        assert_eq!(status, 200);
+        assert!(
+            headers.contains("Content-Type: text/csv; charset=utf-8"),
+            "{}",
+            headers
+        );
+        assert!(
+            headers.contains(&format!(
+                "Content-Disposition: attachment; filename=\"{}.csv\"",
+                scan_id
+            )),
+            "{}",
+            headers
+        );
+        assert!(body.starts_with(b"scanId,filename,sha256,type,severity,details\r\n"));
+
+        let head = format!("GET /api/scan-report/{}?format=txt HTTP/1.0", scan_id);
+        let (status, headers, body) = send(server.addr, &head, b"");
+        assert_eq!(status, 200);
+        assert!(headers.contains("Content-Type: text/plain; charset=utf-8"));
+        assert!(String::from_utf8(body).unwrap().contains(scan_id));
+
+        for format in ["", "?format=pdf"] {
+            let head = format!("GET /api/scan-report/{}{} HTTP/1.0", scan_id, format);
+            let (status, body) = send_json(server.addr, &head, b"");
+            assert_eq!(status, 400);
+            assert_eq!(body["code"], "UNSUPPORTED_FORMAT");
         }
+        let (status, body) = send_json(
+            server.addr,
+            "GET /api/scan-report/scan-missing?format=csv HTTP/1.0",
+            b"",
+        );
+        assert_eq!(status, 404);
+        assert_eq!(body["code"], "SCAN_NOT_FOUND");
+    }
+
+    fn upload_dir_files(server: &TestServer) -> Vec<PathBuf> {
+        fs::read_dir(&server.upload_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect()
+    }
+
+    #[test]
+    fn shutdown_cancels_unfinished_scans_and_leaves_no_temp_files() {
+        let server = serve(|_| {});
+        let (_, body) = upload(&server, &[("slow.bin", &noise(64 * 1024))]);
+        let scan_id = body["scanId"].as_str().unwrap();
+        fs::write(server.upload_dir.join("orphan.bin"), b"left by a crash").unwrap();
+        assert!(!upload_dir_files(&server).is_empty());
+
+        // no grace: the scan is still in its final pause
+        shut_down(&server.app, Duration::ZERO);
+        let result = server.scan_store.lock().unwrap()[scan_id].clone();
+        assert_eq!(result.status, "cancelled");
+        assert_eq!(
+            result.logs.last().unwrap(),
+            "Scan cancelled by server shutdown"
+        );
+        let stored = server.app.storage.list().unwrap();
+        assert_eq!(stored[0].1.status, "cancelled");
+        assert!(upload_dir_files(&server).is_empty());
+
+        // the worker notices and stops without undoing any of that
+        thread::sleep(Duration::from_millis(1500));
+        assert_eq!(
+            server.scan_store.lock().unwrap()[scan_id].status,
+            "cancelled"
+        );
+        assert!(upload_dir_files(&server).is_empty());
+    }
+
+    #[test]
+    fn shutdown_lets_running_scans_finish() {
+        let server = serve(|config| config.retain_samples = true);
+        let (_, body) = upload(&server, &[("sample.bin", b"sample")]);
+        let scan_id = body["scanId"].as_str().unwrap();
+        fs::write(server.upload_dir.join("orphan.bin"), b"left by a crash").unwrap();
+
+        shut_down(&server.app, Duration::from_secs(30));
+        assert_eq!(server.scan_store.lock().unwrap()[scan_id].status, "safe");
+        // only the finished scan's retained sample is kept
+        assert_eq!(
+            upload_dir_files(&server),
+            [sample_path(&server.upload_dir, scan_id, "sample.bin")]
+        );
+    }
+
+    #[test]
+    fn reupload_of_a_scanned_file_returns_the_cached_scan() {
+        let server = serve(|_| {});
+        let (status, first) = upload(&server, &[("sample.bin", b"same bytes")]);
+        assert_eq!(status, 200);
+        assert_eq!(first["cached"], false);
+        let scan_id = first["scanId"].as_str().unwrap();
+        wait_for_scan(&server, scan_id);
+
+        let (status, again) = upload(&server, &[("renamed.bin", b"same bytes")]);
+        assert_eq!(status, 200);
+        assert_eq!(again["scanId"], scan_id);
+        assert_eq!(again["cached"], true);
+        assert_eq!(server.scan_store.lock().unwrap().len(), 1);
+
+        // different contents still get their own scan
+        let (_, other) = upload(&server, &[("sample.bin", b"other bytes")]);
+        assert_ne!(other["scanId"], scan_id);
+        assert_eq!(other["cached"], false);
+    }
+
+    #[test]
+    fn forced_or_undeduplicated_uploads_scan_again() {
+        let server = serve(|_| {});
+        let (_, first) = upload(&server, &[("sample.bin", b"same bytes")]);
+        let scan_id = first["scanId"].as_str().unwrap();
+        wait_for_scan(&server, scan_id);
+
+        let (status, forced) = upload_to(
+            &server,
+            "/api/upload?force=true",
+            &[("sample.bin", b"same bytes")],
+        );
+        assert_eq!(status, 200);
+        assert_ne!(forced["scanId"], scan_id);
+        assert_eq!(forced["cached"], false);
+        let forced_id = forced["scanId"].as_str().unwrap();
+        assert_eq!(wait_for_scan(&server, forced_id).status, "safe");
+
+        // only "true" forces
+        let (_, not_forced) = upload_to(
+            &server,
+            "/api/upload?force=1",
+            &[("sample.bin", b"same bytes")],
+        );
+        assert_eq!(not_forced["cached"], true);
+
+        let server = serve(|config| config.dedupe_uploads = false);
+        let (_, first) = upload(&server, &[("sample.bin", b"same bytes")]);
+        wait_for_scan(&server, first["scanId"].as_str().unwrap());
+        let (_, again) = upload(&server, &[("sample.bin", b"same bytes")]);
+        assert_ne!(again["scanId"], first["scanId"]);
+        assert_eq!(again["cached"], false);
+    }
+
+    #[test]
+    fn concurrent_identical_uploads_share_one_scan() {
+        let server = Arc::new(serve(|_| {}));
+        let uploads: Vec<_> = (0..8)
+            .map(|_| {
+                let server = server.clone();
+                thread::spawn(move || upload(&server, &[("sample.bin", b"same bytes")]).1)
+            })
+            .collect();
+        let responses: Vec<serde_json::Value> =
+            uploads.into_iter().map(|u| u.join().unwrap()).collect();
+
+        let scan_id = &responses[0]["scanId"];
+        assert!(responses.iter().all(|r| &r["scanId"] == scan_id));
+        let started = responses.iter().filter(|r| r["cached"] == false).count();
+        assert_eq!(started, 1);
+        assert_eq!(server.scan_store.lock().unwrap().len(), 1);
+        assert_eq!(
+            wait_for_scan(&server, scan_id.as_str().unwrap()).status,
+            "safe"
+        );
+    }
+
+    #[test]
+    fn large_json_responses_are_gzipped_on_request() {
+        let server = serve(|_| {});
+        let mut result = scan_result("safe");
+        result.logs = (0..200).map(|i| format!("[50%] step {}", i)).collect();
+        server
+            .scan_store
+            .lock()
+            .unwrap()
+            .insert("scan-large".to_string(), result);
+
+        let (status, headers, plain) =
+            send(server.addr, "GET /api/scan-result/scan-large HTTP/1.0", b"");
+        assert_eq!(status, 200);
+        assert!(!headers.contains("Content-Encoding"));
+        let expected: serde_json::Value = serde_json::from_slice(&plain).unwrap();
+        assert_eq!(expected["logs"].as_array().unwrap().len(), 200);
+
+        let (status, headers, gzipped) = send(
+            server.addr,
+            "GET /api/scan-result/scan-large HTTP/1.0\r\nAccept-Encoding: gzip",
+            b"",
+        );
+        assert_eq!(status, 200);
+        assert!(headers.contains("Content-Encoding: gzip"));
+        assert!(gzipped.len() < plain.len());
+        let mut json = Vec::new();
+        flate2::read::GzDecoder::new(&gzipped[..])
+            .read_to_end(&mut json)
+            .unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&json).unwrap(),
+            expected
+        );
+
+        // the event stream is written as it goes and never compressed
+        let (status, headers, _) = send(
+            server.addr,
+            "GET /api/scan-status/scan-large HTTP/1.0\r\nAccept-Encoding: gzip",
+            b"",
+        );
+        assert_eq!(status, 200);
+        assert!(headers.contains("text/event-stream"));
+        assert!(!headers.contains("Content-Encoding"));
+    }
+
+    #[test]
+    fn scans_can_be_listed_by_family_tag() {
+        let server = serve(|_| {});
+        let mut tagged = scan_result("unsafe");
+        tagged.family = Some(FamilyMatch {
+            name: "Ladybird".to_string(),
+            confidence: 0.9,
+            evidence: vec![],
+        });
+        server.app.storage.save("scan-tagged", &tagged).unwrap();
+        server
+            .app
+            .storage
+            .save("scan-plain", &scan_result("unsafe"))
+            .unwrap();
+
+        let listed = |path: &str| -> Vec<String> {
+            let (status, body) = send_json(server.addr, &format!("GET {} HTTP/1.0", path), b"");
+            assert_eq!(status, 200);
+            body.as_array()
+                .unwrap()
+                .iter()
+                .map(|scan| scan["scanId"].as_str().unwrap().to_string())
+                .collect()
+        };
+        assert_eq!(listed("/api/scans?tag=ladybird"), ["scan-tagged"]);
+        assert!(listed("/api/scans?tag=Emotet").is_empty());
+        assert_eq!(listed("/api/scans?status=unsafe").len(), 2);
     }
 }
@@ -0,0 +1,1459 @@
+use crate::types::{ImportedDll, PeInfo, Resource};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+
+pub const IMAGE_DIRECTORY_ENTRY_IMPORT: usize = 1;
+pub const IMAGE_DIRECTORY_ENTRY_RESOURCE: usize = 2;
+pub const IMAGE_DIRECTORY_ENTRY_SECURITY: usize = 4;
+pub const IMAGE_DIRECTORY_ENTRY_BASERELOC: usize = 5;
+// the loader never looks past this many directories, whatever the header says
+pub const IMAGE_NUMBEROF_DIRECTORY_ENTRIES: u32 = 16;
+
+// upper bounds so crafted tables can't make us loop forever
+const MAX_IMPORT_DESCRIPTORS: usize = 1024;
+const MAX_IMPORTS_PER_DLL: usize = 8192;
+const MAX_NAME_LENGTH: usize = 512;
+const MAX_RELOCATION_ANOMALIES: usize = 8;
+const MAX_RESOURCES: usize = 4096;
+
+// type, name and language; a well-formed tree is never deeper
+const RESOURCE_TREE_DEPTH: usize = 3;
+// high bit of a resource entry's name (a string) or target (a subdirectory)
+const RESOURCE_FLAG: u32 = 0x8000_0000;
+
+const PE32_MAGIC: u16 = 0x10b;
+const PE32_PLUS_MAGIC: u16 = 0x20b;
+
+pub fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset.checked_add(2)?)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+pub fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+pub fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    let bytes = data.get(offset..offset.checked_add(8)?)?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+// reads a NUL-terminated ASCII string, giving up on overly long or unterminated ones
+pub fn read_cstring(data: &[u8], offset: usize) -> Option<String> {
+    let bytes = data.get(offset..)?;
+    let len = bytes.iter().take(MAX_NAME_LENGTH).position(|&b| b == 0)?;
+    Some(String::from_utf8_lossy(&bytes[..len]).into_owned())
+}
+
+pub const IMAGE_FILE_RELOCS_STRIPPED: u16 = 0x0001;
+pub const IMAGE_FILE_EXECUTABLE_IMAGE: u16 = 0x0002;
+pub const IMAGE_FILE_LARGE_ADDRESS_AWARE: u16 = 0x0020;
+pub const IMAGE_FILE_32BIT_MACHINE: u16 = 0x0100;
+pub const IMAGE_FILE_DLL: u16 = 0x2000;
+
+pub const IMAGE_SCN_CNT_CODE: u32 = 0x0000_0020;
+pub const IMAGE_SCN_MEM_EXECUTE: u32 = 0x2000_0000;
+
+pub struct Section {
+    pub name: String,
+    pub virtual_address: u32,
+    pub virtual_size: u32,
+    pub raw_offset: u32,
+    pub raw_size: u32,
+    pub characteristics: u32,
+}
+
+impl Section {
+    pub fn is_executable(&self) -> bool {
+        self.characteristics & (IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE) != 0
+    }
+
+    // the section's bytes on disk, clamped to what the file actually holds
+    pub fn raw_data<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        let start = (self.raw_offset as usize).min(data.len());
+        let end = start.saturating_add(self.raw_size as usize).min(data.len());
+        &data[start..end]
+    }
+}
+
+// header fields we care about, plus file offsets needed to reach the rest
+pub struct PeHeaders {
+    pub machine: u16,
+    pub number_of_sections: u16,
+    pub timestamp: u32,
+    // IMAGE_FILE_* flags from the file header
+    pub characteristics: u16,
+    pub subsystem: u16,
+    pub is_64bit: bool,
+    pub checksum: u32,
+    pub checksum_offset: usize,
+    // AddressOfEntryPoint, an RVA
+    pub entry_point: u32,
+    pub data_directories_offset: usize,
+    // as declared, which may be more or fewer than the standard 16
+    pub number_of_rva_and_sizes: u32,
+    // the section table starts here, so no directory entry may reach it
+    pub data_directories_end: usize,
+    pub size_of_headers: u32,
+    pub sections: Vec<Section>,
+}
+
+impl PeHeaders {
+    // returns (virtual address, size) of a data directory entry; entries past
+    // the declared count, the standard 16 or the optional header don't exist
+    pub fn data_directory(&self, data: &[u8], index: usize) -> Option<(u32, u32)> {
+        let count = self
+            .number_of_rva_and_sizes
+            .min(IMAGE_NUMBEROF_DIRECTORY_ENTRIES);
+        if index >= count as usize {
+            return None;
+        }
+        let offset = self.data_directories_offset + index * 8;
+        if offset + 8 > self.data_directories_end {
+            return None;
+        }
+        Some((read_u32(data, offset)?, read_u32(data, offset + 4)?))
+    }
+
+    pub fn rva_to_offset(&self, rva: u32) -> Option<usize> {
+        for section in &self.sections {
+            let size = section.virtual_size.max(section.raw_size);
+            if rva >= section.virtual_address && rva - section.virtual_address < size {
+                let delta = rva - section.virtual_address;
+                // past the raw data the section is zero-filled in memory only
+                if delta >= section.raw_size {
+                    return None;
+                }
+                return Some(section.raw_offset as usize + delta as usize);
+            }
+        }
+        if rva < self.size_of_headers {
+            return Some(rva as usize);
+        }
+        None
+    }
+
+    // up to `len` bytes of code starting at the entry point, with its file offset
+    pub fn entry_code<'a>(&self, data: &'a [u8], len: usize) -> Option<(usize, &'a [u8])> {
+        let start = self.rva_to_offset(self.entry_point)?;
+        let end = start.saturating_add(len).min(data.len());
+        Some((start, data.get(start..end)?))
+    }
+
+    pub fn contains_rva(&self, rva: u32) -> bool {
+        rva < self.size_of_headers
+            || self.sections.iter().any(|section| {
+                let size = section.virtual_size.max(section.raw_size);
+                rva >= section.virtual_address && rva - section.virtual_address < size
+            })
+    }
+
+    // file header flags that contradict each other or the architecture; each is
+    // explainable alone, several together point at a hand-crafted header
+    pub fn header_anomalies(&self) -> Vec<String> {
+        let flags = self.characteristics;
+        let mut anomalies = Vec::new();
+
+        let machine_is_64bit = matches!(self.machine, 0x8664 | 0xaa64 | 0x0200);
+        if machine_is_64bit != self.is_64bit {
+            anomalies.push(format!(
+                "{} machine with a {} optional header",
+                machine_name(self.machine),
+                if self.is_64bit { "PE32+" } else { "PE32" }
+            ));
+        }
+        // linkers always set it for 64-bit images unless told not to
+        if self.is_64bit && flags & IMAGE_FILE_LARGE_ADDRESS_AWARE == 0 {
+            anomalies.push("64-bit image not large address aware".to_string());
+        }
+        if !self.is_64bit
+            && flags & IMAGE_FILE_LARGE_ADDRESS_AWARE != 0
+            && flags & IMAGE_FILE_32BIT_MACHINE == 0
+        {
+            anomalies.push("large address aware 32-bit image without 32BIT_MACHINE".to_string());
+        }
+        if flags & IMAGE_FILE_DLL != 0 && flags & IMAGE_FILE_RELOCS_STRIPPED != 0 {
+            anomalies.push("DLL with relocations stripped".to_string());
+        }
+        if flags & IMAGE_FILE_EXECUTABLE_IMAGE == 0 {
+            anomalies.push("EXECUTABLE_IMAGE not set".to_string());
+        }
+
+        anomalies
+    }
+
+    pub fn info(&self) -> PeInfo {
+        PeInfo {
+            machine: machine_name(self.machine),
+            number_of_sections: self.number_of_sections,
+            timestamp: self.timestamp,
+            subsystem: subsystem_name(self.subsystem),
+            is_64bit: self.is_64bit,
+            characteristics: self.characteristics,
+            large_address_aware: self.characteristics & IMAGE_FILE_LARGE_ADDRESS_AWARE != 0,
+            header_anomalies: self.header_anomalies(),
+            number_of_rva_and_sizes: self.number_of_rva_and_sizes,
+            signature_trust: None,
+        }
+    }
+}
+
+fn machine_name(machine: u16) -> String {
+    match machine {
+        0x014c => "x86".to_string(),
+        0x8664 => "x64".to_string(),
+        0x01c0 => "ARM".to_string(),
+        0x01c4 => "ARMv7".to_string(),
+        0xaa64 => "ARM64".to_string(),
+        0x0200 => "IA64".to_string(),
+        _ => format!("unknown (0x{:04x})", machine),
+    }
+}
+
+fn subsystem_name(subsystem: u16) -> String {
+    match subsystem {
+        1 => "native".to_string(),
+        2 => "windows_gui".to_string(),
+        3 => "windows_cui".to_string(),
+        9 => "windows_ce_gui".to_string(),
+        10 => "efi_application".to_string(),
+        11 => "efi_boot_service_driver".to_string(),
+        12 => "efi_runtime_driver".to_string(),
+        14 => "xbox".to_string(),
+        16 => "windows_boot_application".to_string(),
+        _ => format!("unknown ({})", subsystem),
+    }
+}
+
+pub fn parse_headers(data: &[u8]) -> Result<PeHeaders, String> {
+    if data.len() < 0x40 || &data[0..2] != b"MZ" {
+        return Err("Missing DOS header".to_string());
+    }
+
+    let nt_offset = read_u32(data, 0x3c).ok_or("Truncated DOS header")? as usize;
+    if data.get(nt_offset..nt_offset + 4) != Some(&b"PE\0\0"[..]) {
+        return Err("Invalid NT header signature".to_string());
+    }
+
+    let file_header_offset = nt_offset + 4;
+    let machine = read_u16(data, file_header_offset).ok_or("Truncated file header")?;
+    let number_of_sections =
+        read_u16(data, file_header_offset + 2).ok_or("Truncated file header")?;
+    let timestamp = read_u32(data, file_header_offset + 4).ok_or("Truncated file header")?;
+    let characteristics = read_u16(data, file_header_offset + 18).ok_or("Truncated file header")?;
+
+    let optional_header_offset = nt_offset + 24;
+    let magic = read_u16(data, optional_header_offset).ok_or("Truncated optional header")?;
+    let is_64bit = match magic {
+        PE32_MAGIC => false,
+        PE32_PLUS_MAGIC => true,
+        _ => return Err(format!("Unknown optional header magic 0x{:x}", magic)),
+    };
+
+    let entry_point =
+        read_u32(data, optional_header_offset + 16).ok_or("Truncated optional header")?;
+    let checksum_offset = optional_header_offset + 64;
+    let checksum = read_u32(data, checksum_offset).ok_or("Truncated optional header")?;
+    let subsystem =
+        read_u16(data, optional_header_offset + 68).ok_or("Truncated optional header")?;
+
+    let (rva_count_offset, data_directories_offset) = if is_64bit {
+        (optional_header_offset + 108, optional_header_offset + 112)
+    } else {
+        (optional_header_offset + 92, optional_header_offset + 96)
+    };
+    let number_of_rva_and_sizes =
+        read_u32(data, rva_count_offset).ok_or("Truncated optional header")?;
+    let size_of_headers =
+        read_u32(data, optional_header_offset + 60).ok_or("Truncated optional header")?;
+
+    let size_of_optional_header =
+        read_u16(data, file_header_offset + 16).ok_or("Truncated file header")? as usize;
+    let section_table_offset = optional_header_offset + size_of_optional_header;
+    let mut sections = Vec::new();
+    for i in 0..number_of_sections as usize {
+        let offset = section_table_offset + i * 40;
+        let name_bytes = data
+            .get(offset..offset + 8)
+            .ok_or("Truncated section table")?;
+        let name_len = name_bytes.iter().position(|&b| b == 0).unwrap_or(8);
+        sections.push(Section {
+            name: String::from_utf8_lossy(&name_bytes[..name_len]).into_owned(),
+            virtual_size: read_u32(data, offset + 8).ok_or("Truncated section table")?,
+            virtual_address: read_u32(data, offset + 12).ok_or("Truncated section table")?,
+            raw_size: read_u32(data, offset + 16).ok_or("Truncated section table")?,
+            raw_offset: read_u32(data, offset + 20).ok_or("Truncated section table")?,
+            characteristics: read_u32(data, offset + 36).ok_or("Truncated section table")?,
+        });
+    }
+
+    Ok(PeHeaders {
+        machine,
+        number_of_sections,
+        timestamp,
+        characteristics,
+        subsystem,
+        is_64bit,
+        checksum,
+        checksum_offset,
+        entry_point,
+        data_directories_offset,
+        number_of_rva_and_sizes,
+        data_directories_end: section_table_offset,
+        size_of_headers,
+        sections,
+    })
+}
+
+// same algorithm as imagehlp's CheckSumMappedFile: 16-bit one's complement
+// sum over the file with the checksum field skipped, plus the file length
+pub fn compute_checksum(data: &[u8], checksum_offset: usize) -> u32 {
+    let mut sum: u64 = 0;
+    let mut i = 0;
+    while i < data.len() {
+        if i >= checksum_offset && i < checksum_offset + 4 {
+            i += 2;
+            continue;
+        }
+        let word = if i + 1 < data.len() {
+            u16::from_le_bytes([data[i], data[i + 1]]) as u64
+        } else {
+            data[i] as u64
+        };
+        sum += word;
+        sum = (sum & 0xffff) + (sum >> 16);
+        i += 2;
+    }
+    sum = (sum & 0xffff) + (sum >> 16);
+    (sum as u32).wrapping_add(data.len() as u32)
+}
+
+// walks IMAGE_IMPORT_DESCRIPTORs, resolving each DLL's imports by name or ordinal
+pub fn parse_imports(data: &[u8], headers: &PeHeaders) -> Vec<ImportedDll> {
+    let mut imports = Vec::new();
+
+    let descriptor_offset = match headers.data_directory(data, IMAGE_DIRECTORY_ENTRY_IMPORT) {
+        Some((rva, _)) if rva != 0 => match headers.rva_to_offset(rva) {
+            Some(offset) => offset,
+            None => return imports,
+        },
+        _ => return imports,
+    };
+
+    for i in 0..MAX_IMPORT_DESCRIPTORS {
+        let offset = descriptor_offset + i * 20;
+        let (original_first_thunk, timestamp, name_rva, first_thunk) = match (
+            read_u32(data, offset),
+            read_u32(data, offset + 4),
+            read_u32(data, offset + 12),
+            read_u32(data, offset + 16),
+        ) {
+            (Some(a), Some(b), Some(c), Some(d)) => (a, b, c, d),
+            _ => break,
+        };
+        if original_first_thunk == 0 && name_rva == 0 && first_thunk == 0 {
+            break;
+        }
+
+        let dll = match headers
+            .rva_to_offset(name_rva)
+            .and_then(|o| read_cstring(data, o))
+        {
+            Some(name) if !name.is_empty() => name,
+            _ => continue,
+        };
+
+        // a bound IAT holds resolved addresses rather than names, so only the
+        // lookup table can be trusted there; forwarder chains also index into
+        // the IAT and don't affect the names we read from the lookup table
+        let bound = timestamp != 0;
+        let thunk_rva = if original_first_thunk != 0 {
+            original_first_thunk
+        } else if !bound {
+            first_thunk
+        } else {
+            imports.push(ImportedDll {
+                dll,
+                functions: vec![],
+            });
+            continue;
+        };
+
+        let functions = match headers.rva_to_offset(thunk_rva) {
+            Some(thunk_offset) => parse_thunks(data, headers, thunk_offset),
+            None => vec![],
+        };
+        imports.push(ImportedDll { dll, functions });
+    }
+
+    imports
+}
+
+fn parse_thunks(data: &[u8], headers: &PeHeaders, thunk_offset: usize) -> Vec<String> {
+    let mut functions = Vec::new();
+    let thunk_size = if headers.is_64bit { 8 } else { 4 };
+
+    for i in 0..MAX_IMPORTS_PER_DLL {
+        let offset = thunk_offset + i * thunk_size;
+        let (thunk, by_ordinal) = if headers.is_64bit {
+            match read_u64(data, offset) {
+                Some(v) => (v, v & (1 << 63) != 0),
+                None => break,
+            }
+        } else {
+            match read_u32(data, offset) {
+                Some(v) => (v as u64, v & (1 << 31) != 0),
+                None => break,
+            }
+        };
+        if thunk == 0 {
+            break;
+        }
+
+        if by_ordinal {
+            functions.push(format!("#{}", thunk & 0xffff));
+        } else if let Some(name) = headers
+            .rva_to_offset(thunk as u32)
+            .and_then(|o| read_cstring(data, o + 2))
+        {
+            // skip the 2-byte hint preceding the name
+            functions.push(name);
+        }
+    }
+
+    functions
+}
+
+fn resource_type_name(id: u32) -> String {
+    match id {
+        1 => "CURSOR".to_string(),
+        2 => "BITMAP".to_string(),
+        3 => "ICON".to_string(),
+        4 => "MENU".to_string(),
+        5 => "DIALOG".to_string(),
+        6 => "STRING".to_string(),
+        9 => "ACCELERATOR".to_string(),
+        10 => "RCDATA".to_string(),
+        12 => "GROUP_CURSOR".to_string(),
+        14 => "GROUP_ICON".to_string(),
+        16 => "VERSION".to_string(),
+        23 => "HTML".to_string(),
+        24 => "MANIFEST".to_string(),
+        _ => format!("#{}", id),
+    }
+}
+
+// recognizes what a resource holds from its first bytes
+fn resource_signature(bytes: &[u8]) -> Option<String> {
+    let signature = if bytes.starts_with(b"MZ") {
+        "MZ"
+    } else if bytes.starts_with(b"PK\x03\x04") {
+        "PK"
+    } else if bytes.starts_with(b"\x89PNG") {
+        "PNG"
+    } else if bytes.starts_with(b"<?xml") {
+        "XML"
+    } else {
+        return None;
+    };
+    Some(signature.to_string())
+}
+
+struct ResourceWalk<'a> {
+    data: &'a [u8],
+    headers: &'a PeHeaders,
+    // file offset of the root directory; every offset in the tree is relative to it
+    base: usize,
+    // directory offsets already entered, so a loop in the tree is walked once
+    visited: HashSet<u32>,
+    resources: Vec<Resource>,
+}
+
+impl ResourceWalk<'_> {
+    // entry names are length-prefixed UTF-16LE strings
+    fn name(&self, offset: u32) -> Option<String> {
+        let at = self.base.checked_add(offset as usize)?;
+        let len = read_u16(self.data, at)? as usize;
+        let bytes = self.data.get(at + 2..(at + 2).checked_add(len * 2)?)?;
+        let units: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        Some(String::from_utf16_lossy(&units))
+    }
+
+    // `path` holds the (raw name field, label) of each level above
+    fn directory(&mut self, offset: u32, path: &mut Vec<(u32, String)>) {
+        if path.len() >= RESOURCE_TREE_DEPTH || !self.visited.insert(offset) {
+            return;
+        }
+        let at = self.base.saturating_add(offset as usize);
+        let (Some(named), Some(ids)) = (read_u16(self.data, at + 12), read_u16(self.data, at + 14))
+        else {
+            return;
+        };
+
+        for i in 0..named as usize + ids as usize {
+            if self.resources.len() >= MAX_RESOURCES {
+                return;
+            }
+            let entry = at + 16 + i * 8;
+            let (Some(name), Some(target)) =
+                (read_u32(self.data, entry), read_u32(self.data, entry + 4))
+            else {
+                return;
+            };
+
+            let label = if name & RESOURCE_FLAG != 0 {
+                self.name(name & !RESOURCE_FLAG)
+                    .unwrap_or_else(|| "?".to_string())
+            } else if path.is_empty() {
+                resource_type_name(name)
+            } else {
+                format!("#{}", name)
+            };
+            path.push((name, label));
+            if target & RESOURCE_FLAG != 0 {
+                self.directory(target & !RESOURCE_FLAG, path);
+            } else if path.len() == RESOURCE_TREE_DEPTH {
+                self.leaf(target, path);
+            }
+            path.pop();
+        }
+    }
+
+    fn leaf(&mut self, offset: u32, path: &[(u32, String)]) {
+        let at = self.base.saturating_add(offset as usize);
+        let (Some(rva), Some(size)) = (read_u32(self.data, at), read_u32(self.data, at + 4)) else {
+            return;
+        };
+        let bytes = self
+            .headers
+            .rva_to_offset(rva)
+            .and_then(|start| self.data.get(start..))
+            .map(|rest| &rest[..rest.len().min(size as usize)])
+            .unwrap_or_default();
+
+        let language = path[2].0;
+        self.resources.push(Resource {
+            resource_type: path[0].1.clone(),
+            name: path[1].1.clone(),
+            language: if language & RESOURCE_FLAG != 0 {
+                0
+            } else {
+                language
+            },
+            size,
+            signature: resource_signature(bytes),
+        });
+    }
+}
+
+// walks the type / name / language resource tree and lists each resource
+pub fn parse_resources(data: &[u8], headers: &PeHeaders) -> Vec<Resource> {
+    let base = match headers.data_directory(data, IMAGE_DIRECTORY_ENTRY_RESOURCE) {
+        Some((rva, size)) if rva != 0 && size != 0 => match headers.rva_to_offset(rva) {
+            Some(base) => base,
+            None => return Vec::new(),
+        },
+        _ => return Vec::new(),
+    };
+
+    let mut walk = ResourceWalk {
+        data,
+        headers,
+        base,
+        visited: HashSet::new(),
+        resources: Vec::new(),
+    };
+    walk.directory(0, &mut Vec::new());
+    walk.resources
+}
+
+// checks IMAGE_BASE_RELOCATION blocks for impossible sizes and entries
+// that patch addresses outside every section
+pub fn validate_relocations(data: &[u8], headers: &PeHeaders) -> Vec<String> {
+    let mut anomalies = Vec::new();
+
+    let (rva, size) = match headers.data_directory(data, IMAGE_DIRECTORY_ENTRY_BASERELOC) {
+        Some((rva, size)) if rva != 0 && size != 0 => (rva, size as usize),
+        _ => return anomalies,
+    };
+    let start = match headers.rva_to_offset(rva) {
+        Some(offset) => offset,
+        None => {
+            anomalies.push(format!(
+                "relocation directory RVA 0x{:x} is not mapped",
+                rva
+            ));
+            return anomalies;
+        }
+    };
+    let end = start.saturating_add(size).min(data.len());
+
+    let mut pos = start;
+    while pos + 8 <= end && anomalies.len() < MAX_RELOCATION_ANOMALIES {
+        let page_rva = read_u32(data, pos).unwrap_or(0);
+        let block_size = read_u32(data, pos + 4).unwrap_or(0) as usize;
+
+        if block_size < 8 || block_size & 1 != 0 || pos + block_size > end {
+            anomalies.push(format!(
+                "block at 0x{:x} has impossible size {}",
+                pos, block_size
+            ));
+            break;
+        }
+
+        for entry_offset in (pos + 8..pos + block_size).step_by(2) {
+            let entry = read_u16(data, entry_offset).unwrap_or(0);
+            // type 0 (IMAGE_REL_BASED_ABSOLUTE) is padding
+            if entry >> 12 == 0 {
+                continue;
+            }
+            let target = page_rva.wrapping_add((entry & 0x0fff) as u32);
+            if !headers.contains_rva(target) {
+                anomalies.push(format!(
+                    "block at 0x{:x} relocates RVA 0x{:x} outside any section",
+                    pos, target
+                ));
+                break;
+            }
+        }
+
+        pos += block_size;
+    }
+
+    anomalies
+}
+
+const WIN_CERT_REVISION_1_0: u16 = 0x0100;
+const WIN_CERT_REVISION_2_0: u16 = 0x0200;
+
+// walks the WIN_CERTIFICATE entries in the security directory and
+// describes any structural problems found
+pub fn validate_certificates(data: &[u8], headers: &PeHeaders) -> Vec<String> {
+    let mut anomalies = Vec::new();
+
+    let (offset, size) = match headers.data_directory(data, IMAGE_DIRECTORY_ENTRY_SECURITY) {
+        Some((offset, size)) if offset != 0 && size != 0 => (offset as usize, size as usize),
+        _ => return anomalies,
+    };
+
+    let table_end = offset.saturating_add(size);
+    if table_end > data.len() {
+        anomalies.push(format!(
+            "certificate table at 0x{:x} ({} bytes) extends past end of file",
+            offset, size
+        ));
+    }
+    let table_end = table_end.min(data.len());
+
+    let mut entries = 0;
+    let mut pos = offset;
+    while pos + 8 <= table_end {
+        let length = read_u32(data, pos).unwrap_or(0);
+        let revision = read_u16(data, pos + 4).unwrap_or(0);
+        let certificate_type = read_u16(data, pos + 6).unwrap_or(0);
+
+        if length <= 8 {
+            anomalies.push(format!("zero-length certificate at 0x{:x}", pos));
+            break;
+        }
+        if pos + length as usize > table_end {
+            anomalies.push(format!(
+                "certificate at 0x{:x} declares {} bytes, only {} available",
+                pos,
+                length,
+                table_end - pos
+            ));
+            break;
+        }
+        if revision != WIN_CERT_REVISION_1_0 && revision != WIN_CERT_REVISION_2_0 {
+            anomalies.push(format!(
+                "certificate at 0x{:x} has unknown revision 0x{:04x}",
+                pos, revision
+            ));
+        }
+        if !(1..=4).contains(&certificate_type) {
+            anomalies.push(format!(
+                "certificate at 0x{:x} has unknown type 0x{:04x}",
+                pos, certificate_type
+            ));
+        }
+
+        entries += 1;
+
+        // entries are padded to an 8-byte boundary
+        pos += (length as usize + 7) & !7;
+    }
+
+    if entries == 0 && anomalies.is_empty() {
+        anomalies.push(format!(
+            "certificate table at 0x{:x} holds no entries",
+            offset
+        ));
+    }
+
+    anomalies
+}
+
+const WIN_CERT_TYPE_PKCS_SIGNED_DATA: u16 = 0x0002;
+
+// the PKCS#7 blobs of well-formed Authenticode certificate entries
+pub fn signature_blobs<'a>(data: &'a [u8], headers: &PeHeaders) -> Vec<&'a [u8]> {
+    let mut blobs = Vec::new();
+
+    let (offset, size) = match headers.data_directory(data, IMAGE_DIRECTORY_ENTRY_SECURITY) {
+        Some((offset, size)) if offset != 0 && size != 0 => (offset as usize, size as usize),
+        _ => return blobs,
+    };
+    let table_end = offset.saturating_add(size).min(data.len());
+
+    let mut pos = offset;
+    while pos + 8 <= table_end {
+        let length = read_u32(data, pos).unwrap_or(0) as usize;
+        if length <= 8 || pos + length > table_end {
+            break;
+        }
+        if read_u16(data, pos + 6) == Some(WIN_CERT_TYPE_PKCS_SIGNED_DATA) {
+            blobs.push(&data[pos + 8..pos + length]);
+        }
+        pos += (length + 7) & !7;
+    }
+
+    blobs
+}
+
+// bytes past the end of the last section's raw data, split around the
+// certificate table since signing appends that there too
+pub fn overlay<'a>(data: &'a [u8], headers: &PeHeaders) -> Vec<&'a [u8]> {
+    let start = headers
+        .sections
+        .iter()
+        .filter(|s| s.raw_size != 0)
+        .map(|s| (s.raw_offset as usize).saturating_add(s.raw_size as usize))
+        .max()
+        .unwrap_or(0)
+        .max(headers.size_of_headers as usize);
+    if start >= data.len() {
+        return Vec::new();
+    }
+
+    let (cert_start, cert_end) = match headers.data_directory(data, IMAGE_DIRECTORY_ENTRY_SECURITY)
+    {
+        Some((offset, size)) if offset != 0 && size != 0 => (
+            offset as usize,
+            (offset as usize).saturating_add(size as usize),
+        ),
+        _ => return vec![&data[start..]],
+    };
+    [
+        (start, cert_start.min(data.len())),
+        (cert_end.max(start), data.len()),
+    ]
+    .into_iter()
+    .filter(|(from, to)| from < to)
+    .map(|(from, to)| &data[from..to])
+    .collect()
+}
+
+// (offset, bytes, preceding section name) for each stretch of the file that
+// lies between one section's raw data and the next, which only alignment
+// padding should fill
+pub fn section_gaps<'a>(data: &'a [u8], headers: &'a PeHeaders) -> Vec<(usize, &'a [u8], &'a str)> {
+    let mut sections: Vec<&Section> = headers
+        .sections
+        .iter()
+        .filter(|s| s.raw_size != 0)
+        .collect();
+    sections.sort_by_key(|s| s.raw_offset);
+
+    let mut gaps = Vec::new();
+    let mut end = 0;
+    let mut previous = None;
+    for section in sections {
+        let start = (section.raw_offset as usize).min(data.len());
+        if let Some(name) = previous {
+            if start > end {
+                gaps.push((end, &data[end..start], name));
+            }
+        }
+        let section_end = start
+            .saturating_add(section.raw_size as usize)
+            .min(data.len());
+        if section_end >= end {
+            end = section_end;
+            previous = Some(section.name.as_str());
+        }
+    }
+    gaps
+}
+
+// byte length of the certificate table, None when the security directory is empty
+pub fn certificate_table_size(data: &[u8], headers: &PeHeaders) -> Option<u32> {
+    match headers.data_directory(data, IMAGE_DIRECTORY_ENTRY_SECURITY) {
+        Some((offset, size)) if offset != 0 && size != 0 => Some(size),
+        _ => None,
+    }
+}
+
+pub fn has_signature(data: &[u8], headers: &PeHeaders) -> bool {
+    match headers.data_directory(data, IMAGE_DIRECTORY_ENTRY_SECURITY) {
+        // the security directory address is a file offset, not an RVA
+        Some((offset, size)) => offset != 0 && size != 0,
+        None => false,
+    }
+}
+
+// the PE image hash used by Authenticode: everything except the checksum,
+// the security directory entry and the certificate table itself
+pub fn authentihash(data: &[u8]) -> Option<String> {
+    let headers = parse_headers(data).ok()?;
+
+    let security_entry = headers.data_directories_offset + IMAGE_DIRECTORY_ENTRY_SECURITY * 8;
+    let mut excluded = vec![(headers.checksum_offset, headers.checksum_offset + 4)];
+    if let Some((offset, size)) = headers.data_directory(data, IMAGE_DIRECTORY_ENTRY_SECURITY) {
+        excluded.push((security_entry, security_entry + 8));
+        if offset != 0 && size != 0 {
+            let start = (offset as usize).min(data.len());
+            let end = start.saturating_add(size as usize).min(data.len());
+            excluded.push((start, end));
+        }
+    }
+    excluded.sort();
+
+    let mut hasher = Sha256::new();
+    let mut pos = 0;
+    for (start, end) in excluded {
+        if start > pos {
+            hasher.update(&data[pos..start]);
+        }
+        pos = pos.max(end);
+    }
+    if pos < data.len() {
+        hasher.update(&data[pos..]);
+    }
+    Some(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{
+        noise, pe_checksum, relocation_block, resource_section, win_certificate, PeBuilder,
+        FIRST_EXTRA_RVA, SECTION_READ_ONLY,
+    };
+
+    #[test]
+    fn checksum_folds_words_and_adds_the_length() {
+        // 0x0201 + 0x0003, then the three-byte length
+        assert_eq!(compute_checksum(&[1, 2, 3], 16), 0x0207);
+        // the four checksum bytes are skipped
+        assert_eq!(
+            compute_checksum(&[1, 0, 0xff, 0xff, 0xff, 0xff, 2, 0], 2),
+            3 + 8
+        );
+        // carries wrap around into the low word
+        assert_eq!(compute_checksum(&[0xff, 0xff, 0x02, 0x00], 16), 0x0002 + 4);
+        assert_eq!(compute_checksum(&[], 0), 0);
+    }
+
+    #[test]
+    fn checksum_matches_the_one_written_at_build() {
+        let pe = PeBuilder::new()
+            .import("kernel32.dll", &["ExitProcess"])
+            .checksum()
+            .build();
+        let headers = parse_headers(&pe).unwrap();
+        assert_ne!(headers.checksum, 0);
+        assert_eq!(
+            compute_checksum(&pe, headers.checksum_offset),
+            headers.checksum
+        );
+        assert_eq!(pe_checksum(&pe, headers.checksum_offset), headers.checksum);
+    }
+
+    #[test]
+    fn signature_needs_a_non_empty_security_directory() {
+        let unsigned = PeBuilder::new().build();
+        assert!(!has_signature(
+            &unsigned,
+            &parse_headers(&unsigned).unwrap()
+        ));
+
+        let signed = PeBuilder::new()
+            .certificate(&win_certificate(b"\x30\x00", 0x200, 2))
+            .build();
+        assert!(has_signature(&signed, &parse_headers(&signed).unwrap()));
+
+        // with only four directories there's no security entry to read
+        let short = PeBuilder::new()
+            .certificate(&win_certificate(b"\x30\x00", 0x200, 2))
+            .data_directories(4)
+            .build();
+        assert!(!has_signature(&short, &parse_headers(&short).unwrap()));
+    }
+
+    #[test]
+    fn authentihash_ignores_the_signature() {
+        let unsigned = PeBuilder::new()
+            .import("kernel32.dll", &["ExitProcess"])
+            .build();
+        let signed_once = PeBuilder::new()
+            .import("kernel32.dll", &["ExitProcess"])
+            .certificate(&win_certificate(b"\x30\x03\x02\x01\x01", 0x200, 2))
+            .checksum()
+            .build();
+        let signed_twice = PeBuilder::new()
+            .import("kernel32.dll", &["ExitProcess"])
+            .certificate(&win_certificate(&[0x30; 40], 0x200, 2))
+            .checksum()
+            .build();
+
+        let hash = authentihash(&unsigned).unwrap();
+        assert_eq!(hash.len(), 64);
+        assert_eq!(authentihash(&signed_once).unwrap(), hash);
+        assert_eq!(authentihash(&signed_twice).unwrap(), hash);
+        assert_ne!(signed_once, signed_twice);
+    }
+
+    #[test]
+    fn authentihash_covers_the_image() {
+        let original = PeBuilder::new().build();
+        let patched = PeBuilder::new().text(&[0xcc; 16]).build();
+        assert_ne!(authentihash(&original), authentihash(&patched));
+        assert_eq!(authentihash(b"MZ not a PE"), None);
+    }
+
+    #[test]
+    fn parses_a_minimal_pe32() {
+        let pe = PeBuilder::new().build();
+        let info = parse_headers(&pe).unwrap().info();
+        assert_eq!(info.machine, "x86");
+        assert_eq!(info.number_of_sections, 2);
+        assert_eq!(info.timestamp, 0x5f00_0000);
+        assert_eq!(info.subsystem, "windows_cui");
+        assert!(!info.is_64bit);
+        assert!(info.header_anomalies.is_empty());
+    }
+
+    #[test]
+    fn parses_a_minimal_pe32_plus() {
+        let pe = PeBuilder::new().pe64().characteristics(0x0022).build();
+        let headers = parse_headers(&pe).unwrap();
+        assert!(headers.is_64bit);
+        assert_eq!(headers.info().machine, "x64");
+        let names: Vec<&str> = headers.sections.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, [".text", ".idata"]);
+        assert_eq!(
+            headers.rva_to_offset(0x1000),
+            Some(headers.sections[0].raw_offset as usize)
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_and_malformed_headers() {
+        let pe = PeBuilder::new().build();
+        let error = |data: &[u8]| parse_headers(data).err().unwrap();
+
+        assert_eq!(error(b""), "Missing DOS header");
+        assert_eq!(error(&pe[..2]), "Missing DOS header");
+        assert_eq!(error(&pe[..0x40]), "Invalid NT header signature");
+        assert_eq!(error(&pe[..0x90]), "Truncated file header");
+        assert_eq!(error(&pe[..0xa0]), "Truncated optional header");
+        // the section table starts right after the optional header
+        let table = PeBuilder::optional_header_offset() + 96 + 16 * 8;
+        assert_eq!(error(&pe[..table + 20]), "Truncated section table");
+
+        let mut bad_lfanew = pe.clone();
+        bad_lfanew[0x3c..0x40].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(error(&bad_lfanew), "Invalid NT header signature");
+
+        let mut bad_magic = pe.clone();
+        bad_magic[PeBuilder::optional_header_offset()] = 0x07;
+        assert_eq!(error(&bad_magic), "Unknown optional header magic 0x107");
+    }
+
+    fn certificate_anomalies(table: &[u8]) -> Vec<String> {
+        let pe = PeBuilder::new().certificate(table).build();
+        validate_certificates(&pe, &parse_headers(&pe).unwrap())
+    }
+
+    #[test]
+    fn well_formed_certificate_tables_pass() {
+        assert!(certificate_anomalies(&win_certificate(&[0x30; 13], 0x200, 2)).is_empty());
+        let two = [
+            win_certificate(&[0x30; 5], 0x100, 1),
+            win_certificate(&[0x30; 8], 0x200, 2),
+        ]
+        .concat();
+        assert!(certificate_anomalies(&two).is_empty());
+    }
+
+    #[test]
+    fn flags_oversized_certificate_lengths() {
+        let mut table = win_certificate(&[0x30; 8], 0x200, 2);
+        table[..4].copy_from_slice(&0x7fff_fff0u32.to_le_bytes());
+        let anomalies = certificate_anomalies(&table);
+        assert_eq!(anomalies.len(), 1);
+        assert!(
+            anomalies[0].ends_with("declares 2147483632 bytes, only 16 available"),
+            "{}",
+            anomalies[0]
+        );
+
+        // a directory reaching past the end of the file
+        let mut pe = PeBuilder::new()
+            .certificate(&win_certificate(&[0x30; 8], 0x200, 2))
+            .build();
+        let size_field = PeBuilder::optional_header_offset() + 96 + 4 * 8 + 4;
+        pe[size_field..size_field + 4].copy_from_slice(&0x1000u32.to_le_bytes());
+        let anomalies = validate_certificates(&pe, &parse_headers(&pe).unwrap());
+        assert!(
+            anomalies[0].contains("extends past end of file"),
+            "{:?}",
+            anomalies
+        );
+    }
+
+    #[test]
+    fn flags_zero_length_and_unknown_certificates() {
+        let anomalies = certificate_anomalies(&[0u8; 16]);
+        assert_eq!(anomalies.len(), 1);
+        assert!(anomalies[0].starts_with("zero-length certificate at 0x"));
+
+        let anomalies = certificate_anomalies(&win_certificate(&[0x30; 8], 0x300, 9));
+        assert_eq!(anomalies.len(), 2);
+        assert!(anomalies[0].ends_with("unknown revision 0x0300"));
+        assert!(anomalies[1].ends_with("unknown type 0x0009"));
+
+        // too short for even one entry header
+        let anomalies = certificate_anomalies(&[0u8; 4]);
+        assert_eq!(anomalies.len(), 1);
+        assert!(anomalies[0].ends_with("holds no entries"));
+    }
+
+    fn imports_of(pe: &[u8]) -> Vec<(String, Vec<String>)> {
+        parse_imports(pe, &parse_headers(pe).unwrap())
+            .into_iter()
+            .map(|i| (i.dll, i.functions))
+            .collect()
+    }
+
+    #[test]
+    fn resolves_imports_by_name_and_ordinal() {
+        for builder in [PeBuilder::new(), PeBuilder::new().pe64()] {
+            let pe = builder
+                .import("kernel32.dll", &["VirtualAllocEx", "CreateRemoteThread"])
+                .import("ws2_32.dll", &["#23", "connect"])
+                .build();
+            assert_eq!(
+                imports_of(&pe),
+                vec![
+                    (
+                        "kernel32.dll".to_string(),
+                        vec![
+                            "VirtualAllocEx".to_string(),
+                            "CreateRemoteThread".to_string()
+                        ]
+                    ),
+                    (
+                        "ws2_32.dll".to_string(),
+                        vec!["#23".to_string(), "connect".to_string()]
+                    ),
+                ]
+            );
+        }
+        assert!(imports_of(&PeBuilder::new().build()).is_empty());
+    }
+
+    #[test]
+    fn falls_back_to_the_iat_unless_bound() {
+        let pe = PeBuilder::new()
+            .import("kernel32.dll", &["ExitProcess"])
+            .build();
+        let descriptor = parse_headers(&pe).unwrap().rva_to_offset(0x2000).unwrap();
+
+        // no lookup table: the unbound IAT still holds name RVAs
+        let mut unbound = pe.clone();
+        unbound[descriptor..descriptor + 4].fill(0);
+        assert_eq!(imports_of(&unbound)[0].1, ["ExitProcess"]);
+
+        // a bound IAT holds addresses, so only the DLL is known
+        let mut bound = unbound.clone();
+        bound[descriptor + 4..descriptor + 8].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            imports_of(&bound),
+            vec![("kernel32.dll".to_string(), vec![])]
+        );
+    }
+
+    #[test]
+    fn skips_descriptors_that_point_nowhere() {
+        let pe = PeBuilder::new()
+            .import("kernel32.dll", &["ExitProcess"])
+            .import("user32.dll", &["MessageBoxA"])
+            .build();
+        let descriptor = parse_headers(&pe).unwrap().rva_to_offset(0x2000).unwrap();
+
+        let mut unmapped = pe.clone();
+        unmapped[descriptor + 12..descriptor + 16].copy_from_slice(&0x0fff_0000u32.to_le_bytes());
+        let imports = imports_of(&unmapped);
+        assert_eq!(imports.len(), 1);
+        assert_eq!(imports[0].0, "user32.dll");
+
+        // an import directory outside every section yields nothing
+        let mut lost = pe.clone();
+        let entry = PeBuilder::optional_header_offset() + 96 + 8;
+        lost[entry..entry + 4].copy_from_slice(&0x0fff_0000u32.to_le_bytes());
+        assert!(imports_of(&lost).is_empty());
+    }
+
+    // a PE whose relocation directory covers `table` in its own section
+    fn relocation_anomalies(table: &[u8]) -> Vec<String> {
+        let pe = PeBuilder::new()
+            .section(".reloc", table, SECTION_READ_ONLY)
+            .directory(
+                IMAGE_DIRECTORY_ENTRY_BASERELOC,
+                FIRST_EXTRA_RVA,
+                table.len() as u32,
+            )
+            .build();
+        validate_relocations(&pe, &parse_headers(&pe).unwrap())
+    }
+
+    #[test]
+    fn relocations_inside_sections_pass() {
+        let table = [
+            relocation_block(0x1000, &[0x0, 0x8, 0x1fc]),
+            relocation_block(0x2000, &[0x10]),
+        ]
+        .concat();
+        assert!(relocation_anomalies(&table).is_empty());
+        // absolute entries are padding and never patch anything
+        let mut padding = relocation_block(0x9000, &[]);
+        padding.extend_from_slice(&[0; 4]);
+        padding[4] = 12;
+        assert!(relocation_anomalies(&padding).is_empty());
+    }
+
+    #[test]
+    fn flags_relocations_outside_every_section() {
+        let table = [
+            relocation_block(0x1000, &[0x8]),
+            relocation_block(0x9000, &[0x10, 0x20]),
+        ]
+        .concat();
+        let anomalies = relocation_anomalies(&table);
+        // one report per block, however many entries are bad
+        assert_eq!(
+            anomalies,
+            ["block at 0x60c relocates RVA 0x9010 outside any section"]
+        );
+
+        // the gap between the end of .text and the next section
+        let anomalies = relocation_anomalies(&relocation_block(0x1000, &[0x300]));
+        assert!(anomalies[0].ends_with("relocates RVA 0x1300 outside any section"));
+    }
+
+    #[test]
+    fn flags_blocks_with_impossible_sizes() {
+        for size in [0u32, 7, 13, 0x1000] {
+            let mut block = relocation_block(0x1000, &[0x8, 0x10]);
+            block[4..8].copy_from_slice(&size.to_le_bytes());
+            let anomalies = relocation_anomalies(&block);
+            assert_eq!(anomalies.len(), 1);
+            assert!(
+                anomalies[0].ends_with(&format!("has impossible size {}", size)),
+                "{:?}",
+                anomalies
+            );
+        }
+
+        let pe = PeBuilder::new()
+            .directory(IMAGE_DIRECTORY_ENTRY_BASERELOC, 0x9000, 12)
+            .build();
+        assert_eq!(
+            validate_relocations(&pe, &parse_headers(&pe).unwrap()),
+            ["relocation directory RVA 0x9000 is not mapped"]
+        );
+    }
+
+    #[test]
+    fn only_declared_data_directories_are_read() {
+        let pe = PeBuilder::new()
+            .import("KERNEL32.dll", &["ExitProcess"])
+            .certificate(&win_certificate(&[0x30; 8], 0x200, 2))
+            .data_directories(2)
+            .build();
+        let headers = parse_headers(&pe).unwrap();
+        assert_eq!(headers.number_of_rva_and_sizes, 2);
+        assert_eq!(headers.info().number_of_rva_and_sizes, 2);
+        assert!(headers
+            .data_directory(&pe, IMAGE_DIRECTORY_ENTRY_IMPORT)
+            .is_some());
+        // the certificate table is in the file, but no entry declares it
+        assert!(headers
+            .data_directory(&pe, IMAGE_DIRECTORY_ENTRY_SECURITY)
+            .is_none());
+        assert!(!has_signature(&pe, &headers));
+        assert_eq!(imports_of(&pe)[0].0, "KERNEL32.dll");
+
+        let pe = PeBuilder::new()
+            .import("KERNEL32.dll", &["ExitProcess"])
+            .data_directories(0)
+            .build();
+        assert!(imports_of(&pe).is_empty());
+    }
+
+    #[test]
+    fn directory_count_cannot_reach_past_the_optional_header() {
+        // more than 16 declared: the extra entries are never consulted
+        let pe = PeBuilder::new().data_directories(32).build();
+        let headers = parse_headers(&pe).unwrap();
+        assert_eq!(headers.number_of_rva_and_sizes, 32);
+        assert!(headers.data_directory(&pe, 15).is_some());
+        assert!(headers.data_directory(&pe, 16).is_none());
+
+        // 16 declared in an optional header sized for 2, so the rest of the
+        // "directories" would be the section table
+        let mut pe = PeBuilder::new().data_directories(2).build();
+        let count = PeBuilder::optional_header_offset() + 92;
+        pe[count..count + 4].copy_from_slice(&16u32.to_le_bytes());
+        let headers = parse_headers(&pe).unwrap();
+        assert_eq!(headers.number_of_rva_and_sizes, 16);
+        assert!(headers.data_directory(&pe, 1).is_some());
+        assert!(headers
+            .data_directory(&pe, IMAGE_DIRECTORY_ENTRY_BASERELOC)
+            .is_none());
+        assert!(validate_relocations(&pe, &headers).is_empty());
+        assert!(headers.data_directory(&pe, 2).is_none());
+        assert!(parse_resources(&pe, &headers).is_empty());
+    }
+
+    fn overlay_of(pe: &[u8]) -> Vec<Vec<u8>> {
+        overlay(pe, &parse_headers(pe).unwrap())
+            .into_iter()
+            .map(<[u8]>::to_vec)
+            .collect()
+    }
+
+    #[test]
+    fn overlay_is_whatever_follows_the_last_section() {
+        assert!(overlay_of(&PeBuilder::new().build()).is_empty());
+        let pe = PeBuilder::new().overlay(b"appended payload").build();
+        assert_eq!(overlay_of(&pe), [b"appended payload".to_vec()]);
+        // truncated inside a section leaves nothing trailing
+        let pe = PeBuilder::new().build();
+        let headers = parse_headers(&pe).unwrap();
+        assert!(overlay(&pe[..pe.len() - 1], &headers).is_empty());
+    }
+
+    #[test]
+    fn overlay_leaves_out_the_certificate_table() {
+        let certificate = win_certificate(&[0x30; 64], 0x200, 2);
+        let signed = PeBuilder::new().certificate(&certificate).build();
+        assert!(overlay_of(&signed).is_empty());
+
+        let pe = PeBuilder::new()
+            .certificate(&certificate)
+            .overlay(b"after the signature")
+            .build();
+        assert_eq!(overlay_of(&pe), [b"after the signature".to_vec()]);
+
+        // data wedged between the sections and the certificate table counts too
+        let mut pe = PeBuilder::new().certificate(&certificate).build();
+        let headers = parse_headers(&pe).unwrap();
+        let (offset, size) = headers
+            .data_directory(&pe, IMAGE_DIRECTORY_ENTRY_SECURITY)
+            .unwrap();
+        let table = pe.split_off(offset as usize);
+        pe.extend_from_slice(b"wedged");
+        pe.extend_from_slice(&table);
+        let entry = headers.data_directories_offset + IMAGE_DIRECTORY_ENTRY_SECURITY * 8;
+        pe[entry..entry + 4].copy_from_slice(&(offset + 6).to_le_bytes());
+        assert_eq!(size as usize, table.len());
+        assert_eq!(overlay_of(&pe), [b"wedged".to_vec()]);
+    }
+
+    // a PE with `tree` as its .rsrc section and resource directory
+    fn with_resources(tree: &[u8]) -> Vec<u8> {
+        PeBuilder::new()
+            .section(".rsrc", tree, SECTION_READ_ONLY)
+            .directory(
+                IMAGE_DIRECTORY_ENTRY_RESOURCE,
+                FIRST_EXTRA_RVA,
+                tree.len() as u32,
+            )
+            .build()
+    }
+
+    fn resources_of(pe: &[u8]) -> Vec<(String, String, u32, u32, Option<String>)> {
+        parse_resources(pe, &parse_headers(pe).unwrap())
+            .into_iter()
+            .map(|r| (r.resource_type, r.name, r.language, r.size, r.signature))
+            .collect()
+    }
+
+    #[test]
+    fn lists_an_icon_resource() {
+        let icon = b"\x89PNG\r\n\x1a\n icon pixels";
+        let pe = with_resources(&resource_section(FIRST_EXTRA_RVA, &[(3, "#1", 1033, icon)]));
+        assert_eq!(
+            resources_of(&pe),
+            [(
+                "ICON".to_string(),
+                "#1".to_string(),
+                1033,
+                icon.len() as u32,
+                Some("PNG".to_string())
+            )]
+        );
+        assert!(resources_of(&PeBuilder::new().build()).is_empty());
+    }
+
+    #[test]
+    fn names_types_and_recognizes_embedded_files() {
+        let payload = PeBuilder::new().build();
+        let tree = resource_section(
+            FIRST_EXTRA_RVA,
+            &[
+                (10, "PAYLOAD", 0, &payload),
+                (24, "#1", 1033, b"<?xml version='1.0'?><assembly/>"),
+                (99, "#7", 0, b"opaque"),
+            ],
+        );
+        let resources = resources_of(&with_resources(&tree));
+        assert_eq!(resources.len(), 3);
+        assert_eq!(resources[0].0, "RCDATA");
+        assert_eq!(resources[0].1, "PAYLOAD");
+        assert_eq!(resources[0].3, payload.len() as u32);
+        assert_eq!(resources[0].4.as_deref(), Some("MZ"));
+        assert_eq!(resources[1].0, "MANIFEST");
+        assert_eq!(resources[1].4.as_deref(), Some("XML"));
+        assert_eq!(resources[2].0, "#99");
+        assert_eq!(resources[2].1, "#7");
+        assert_eq!(resources[2].4, None);
+    }
+
+    #[test]
+    fn survives_circular_and_dangling_trees() {
+        // the name level points back at the root
+        let mut tree = resource_section(FIRST_EXTRA_RVA, &[(3, "#1", 0, b"data")]);
+        let name_entry_target = 24 + 20;
+        tree[name_entry_target..name_entry_target + 4]
+            .copy_from_slice(&RESOURCE_FLAG.to_le_bytes());
+        assert!(resources_of(&with_resources(&tree)).is_empty());
+
+        // a data entry pointing outside the image is still listed, unread
+        let mut tree = resource_section(FIRST_EXTRA_RVA, &[(3, "#1", 0, b"MZ..")]);
+        let data_entry = 24 + 24 + 24;
+        tree[data_entry..data_entry + 4].copy_from_slice(&0x0009_0000u32.to_le_bytes());
+        let resources = resources_of(&with_resources(&tree));
+        assert_eq!(resources.len(), 1);
+        assert_eq!(resources[0].4, None);
+
+        // a root claiming far more entries than there are bytes
+        let mut tree = resource_section(FIRST_EXTRA_RVA, &[(3, "#1", 0, b"data")]);
+        tree[14..16].copy_from_slice(&u16::MAX.to_le_bytes());
+        let resources = resources_of(&with_resources(&tree));
+        assert!(resources.len() <= MAX_RESOURCES);
+    }
+
+    fn header_anomalies_of(builder: PeBuilder) -> Vec<String> {
+        parse_headers(&builder.build()).unwrap().header_anomalies()
+    }
+
+    #[test]
+    fn consistent_headers_have_no_anomalies() {
+        assert!(header_anomalies_of(PeBuilder::new()).is_empty());
+        // large address aware x86 with 32BIT_MACHINE is an ordinary /LARGEADDRESSAWARE build
+        assert!(header_anomalies_of(PeBuilder::new().characteristics(0x0122)).is_empty());
+        assert!(header_anomalies_of(PeBuilder::new().pe64().characteristics(0x0022)).is_empty());
+        // a DLL that keeps its relocations
+        assert!(header_anomalies_of(PeBuilder::new().characteristics(0x2102)).is_empty());
+    }
+
+    #[test]
+    fn flags_inconsistent_flag_combinations() {
+        assert_eq!(
+            header_anomalies_of(PeBuilder::new().pe64().characteristics(0x0002)),
+            ["64-bit image not large address aware"]
+        );
+        assert_eq!(
+            header_anomalies_of(PeBuilder::new().characteristics(0x2023)),
+            [
+                "large address aware 32-bit image without 32BIT_MACHINE",
+                "DLL with relocations stripped"
+            ]
+        );
+        assert_eq!(
+            header_anomalies_of(PeBuilder::new().characteristics(0x0100)),
+            ["EXECUTABLE_IMAGE not set"]
+        );
+
+        let mut pe = PeBuilder::new().characteristics(0x0122).build();
+        // an x64 machine over a PE32 optional header
+        pe[0x84..0x86].copy_from_slice(&0x8664u16.to_le_bytes());
+        let headers = parse_headers(&pe).unwrap();
+        assert_eq!(
+            headers.header_anomalies(),
+            ["x64 machine with a PE32 optional header"]
+        );
+        assert!(headers.info().large_address_aware);
+        assert_eq!(headers.info().header_anomalies.len(), 1);
+    }
+
+    // `builder`'s image with one section header's raw offset and size replaced
+    fn with_raw_data(builder: &PeBuilder, index: usize, offset: u32, size: u32) -> Vec<u8> {
+        let mut pe = builder.build();
+        let header = builder.section_header_offset(index);
+        pe[header + 16..header + 20].copy_from_slice(&size.to_le_bytes());
+        pe[header + 20..header + 24].copy_from_slice(&offset.to_le_bytes());
+        pe
+    }
+
+    fn gaps_of(pe: &[u8]) -> Vec<(usize, Vec<u8>, String)> {
+        let headers = parse_headers(pe).unwrap();
+        section_gaps(pe, &headers)
+            .into_iter()
+            .map(|(offset, gap, section)| (offset, gap.to_vec(), section.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn linked_sections_leave_no_gaps() {
+        assert!(gaps_of(&PeBuilder::new().build()).is_empty());
+        let builder = PeBuilder::new().section(".data", &[1; 0x300], SECTION_READ_ONLY);
+        assert!(gaps_of(&builder.build()).is_empty());
+    }
+
+    #[test]
+    fn data_past_a_sections_raw_size_is_a_gap() {
+        let mut text = vec![0xc3; 0x100];
+        text.extend(noise(0x100));
+        let builder = PeBuilder::new().text(&text);
+        // .text claims only its first half; .idata still starts at 0x400
+        let pe = with_raw_data(&builder, 0, 0x200, 0x100);
+        assert_eq!(gaps_of(&pe), [(0x300, noise(0x100), ".text".to_string())]);
+    }
+
+    #[test]
+    fn gaps_follow_raw_offsets_not_header_order() {
+        let builder = PeBuilder::new().section(".data", &[1; 0x200], SECTION_READ_ONLY);
+        // .idata squeezed inside .text leaves its old place to no section
+        let pe = with_raw_data(&builder, 1, 0x280, 0x80);
+        assert_eq!(gaps_of(&pe), [(0x400, vec![0; 0x200], ".text".to_string())]);
+
+        // a section with no raw data is left out, so what follows .idata is overlay
+        let pe = with_raw_data(&builder, 2, 0x600, 0);
+        assert!(gaps_of(&pe).is_empty());
+
+        // raw data pointing past the end of the file is cut off there
+        let pe = with_raw_data(&builder, 2, 0x10000, 0x200);
+        let gaps = gaps_of(&pe);
+        assert_eq!(gaps.len(), 1);
+        assert_eq!((gaps[0].0, gaps[0].1.len()), (0x600, 0x200));
+    }
+}
@@ -0,0 +1,170 @@
+use crate::types::ScanResult;
+use std::fmt::Write;
+
+const CSV_HEADER: &str = "scanId,filename,sha256,type,severity,details";
+
+// RFC 4180: fields holding a comma, quote or line break are quoted, with
+// quotes inside doubled
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// one row per threat under CSV_HEADER, CRLF line endings
+pub fn csv_report(scan_id: &str, result: &ScanResult) -> String {
+    let (filename, sha256) = match &result.file_info {
+        Some(info) => (info.filename.as_str(), info.sha256.as_str()),
+        None => ("", ""),
+    };
+
+    let mut csv = format!("{}\r\n", CSV_HEADER);
+    for threat in &result.threats {
+        let row = [
+            scan_id,
+            filename,
+            sha256,
+            &threat.threat_type,
+            &threat.severity,
+            &threat.details,
+        ]
+        .map(csv_field)
+        .join(",");
+        csv.push_str(&row);
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+pub fn text_report(scan_id: &str, result: &ScanResult) -> String {
+    let mut text = String::new();
+    let _ = writeln!(text, "PEroxide scan report");
+    let _ = writeln!(text, "Scan ID:  {}", scan_id);
+    if let Some(info) = &result.file_info {
+        let _ = writeln!(text, "File:     {} ({} bytes)", info.filename, info.size);
+        let _ = writeln!(text, "SHA-256:  {}", info.sha256);
+        let _ = writeln!(text, "SHA-1:    {}", info.sha1);
+        let _ = writeln!(text, "MD5:      {}", info.md5);
+    }
+    let _ = writeln!(text, "Scanned:  {}", result.created_at);
+    let _ = match &result.verdict {
+        Some(verdict) => writeln!(
+            text,
+            "Status:   {} (verdict {}, score {})",
+            result.status, verdict, result.stats.score
+        ),
+        None => writeln!(text, "Status:   {}", result.status),
+    };
+    if let Some(family) = &result.family {
+        let _ = writeln!(text, "Family:   {}", family.name);
+    }
+
+    let _ = writeln!(
+        text,
+        "\nThreats: {} ({} malicious, {} suspicious, {} neutral)",
+        result.stats.threats_found,
+        result.stats.malicious,
+        result.stats.suspicious,
+        result.stats.neutral
+    );
+    for threat in &result.threats {
+        let _ = writeln!(
+            text,
+            "  [{}] {:<10} {}: {}",
+            threat.threat_id, threat.severity, threat.threat_type, threat.details
+        );
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scan_result;
+    use crate::types::{FileInfo, Threat};
+
+    fn reported() -> ScanResult {
+        let mut result = scan_result("unsafe");
+        result.created_at = "2024-07-01T00:00:00.000Z".to_string();
+        result.verdict = Some("malicious".to_string());
+        result.stats.score = 55;
+        result.stats.threats_found = 2;
+        result.stats.malicious = 1;
+        result.stats.suspicious = 1;
+        result.file_info = Some(FileInfo {
+            filename: "invoice, final.exe".to_string(),
+            size: 2048,
+            sha256: "ab".repeat(32),
+            md5: "cd".repeat(16),
+            sha1: "ef".repeat(20),
+            imphash: None,
+            authentihash: None,
+        });
+        result.threats = vec![
+            Threat {
+                threat_type: "Process Injection".to_string(),
+                details: "Imports \"WriteProcessMemory\", CreateRemoteThread\nand more".to_string(),
+                severity: "malicious".to_string(),
+                threat_id: "S002".to_string(),
+            },
+            Threat {
+                threat_type: "Overlay Data".to_string(),
+                details: "4096 bytes appended".to_string(),
+                severity: "suspicious".to_string(),
+                threat_id: "P010".to_string(),
+            },
+        ];
+        result
+    }
+
+    #[test]
+    fn csv_quotes_fields_that_need_it() {
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field("cr\r"), "\"cr\r\"");
+        assert_eq!(csv_field(""), "");
+    }
+
+    #[test]
+    fn csv_has_a_header_and_a_row_per_threat() {
+        let csv = csv_report("scan-r", &reported());
+        let sha256 = "ab".repeat(32);
+        assert_eq!(
+            csv,
+            format!(
+                "scanId,filename,sha256,type,severity,details\r\n\
+                 scan-r,\"invoice, final.exe\",{sha},Process Injection,malicious,\
+                 \"Imports \"\"WriteProcessMemory\"\", CreateRemoteThread\nand more\"\r\n\
+                 scan-r,\"invoice, final.exe\",{sha},Overlay Data,suspicious,4096 bytes appended\r\n",
+                sha = sha256
+            )
+        );
+
+        // no threats, no file info: just the header
+        assert_eq!(
+            csv_report("scan-e", &scan_result("error")),
+            "scanId,filename,sha256,type,severity,details\r\n"
+        );
+    }
+
+    #[test]
+    fn text_report_summarizes_the_scan() {
+        let text = text_report("scan-r", &reported());
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "PEroxide scan report");
+        assert_eq!(lines[1], "Scan ID:  scan-r");
+        assert_eq!(lines[2], "File:     invoice, final.exe (2048 bytes)");
+        assert!(text.contains("Status:   unsafe (verdict malicious, score 55)\n"));
+        assert!(text.contains("\nThreats: 2 (1 malicious, 1 suspicious, 0 neutral)\n"));
+        assert!(text.contains("  [P010] suspicious Overlay Data: 4096 bytes appended\n"));
+        assert!(!text.contains("Family:"));
+
+        let text = text_report("scan-e", &scan_result("error"));
+        assert!(text.contains("Status:   error\n"));
+        assert!(!text.contains("SHA-256"));
+    }
+}
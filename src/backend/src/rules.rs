@@ -0,0 +1,547 @@
+use crate::analysis::normalize_guid;
+use crate::config::*;
+use crate::types::{FamilyMatch, Threat};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+const VALID_SEVERITIES: &[&str] = &["malicious", "suspicious", "neutral"];
+
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchLogic {
+    And,
+    #[default]
+    Or,
+}
+
+// where a rule's strings are looked for: the extracted ASCII and UTF-16LE
+// strings, or imported function names
+#[derive(Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchTarget {
+    #[default]
+    Content,
+    Imports,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Rule {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub threat_type: String,
+    pub details: String,
+    pub severity: String,
+    #[serde(default)]
+    pub matches: Vec<String>,
+    // patterns matched against extracted ASCII and wide strings
+    #[serde(default)]
+    pub regex: Vec<String>,
+    #[serde(default)]
+    pub logic: MatchLogic,
+    #[serde(default)]
+    pub target: MatchTarget,
+    #[serde(skip)]
+    compiled: Vec<Regex>,
+}
+
+// evidence that points at a known malware family; any list may be empty
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FamilySignature {
+    pub name: String,
+    #[serde(default)]
+    pub imphashes: Vec<String>,
+    #[serde(default)]
+    pub mutexes: Vec<String>,
+    #[serde(default)]
+    pub strings: Vec<String>,
+}
+
+// section names that identify a packer, on top of BUILTIN_PACKERS
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PackerSignature {
+    pub name: String,
+    pub sections: Vec<String>,
+}
+
+// evidence weights for family confidence; an imphash is the strongest tie
+const IMPHASH_WEIGHT: f64 = 3.0;
+const MUTEX_WEIGHT: f64 = 2.0;
+const STRING_WEIGHT: f64 = 1.0;
+
+impl FamilySignature {
+    // fraction of this signature's weighted evidence present in the sample
+    pub fn score(&self, imphash: Option<&str>, strings: &[String]) -> (f64, Vec<String>) {
+        let mut total = 0.0;
+        let mut matched = 0.0;
+        let mut evidence = Vec::new();
+
+        if !self.imphashes.is_empty() {
+            total += IMPHASH_WEIGHT;
+            if let Some(hash) = imphash.filter(|h| self.imphashes.iter().any(|i| i == h)) {
+                matched += IMPHASH_WEIGHT;
+                evidence.push(format!("imphash {}", hash));
+            }
+        }
+
+        if !self.mutexes.is_empty() {
+            total += MUTEX_WEIGHT;
+            if let Some(mutex) = self
+                .mutexes
+                .iter()
+                .find(|m| strings.iter().any(|s| s.contains(m.as_str())))
+            {
+                matched += MUTEX_WEIGHT;
+                evidence.push(format!("mutex {}", mutex));
+            }
+        }
+
+        for needle in &self.strings {
+            total += STRING_WEIGHT;
+            if strings.iter().any(|s| s.contains(needle.as_str())) {
+                matched += STRING_WEIGHT;
+                evidence.push(format!("string \"{}\"", needle));
+            }
+        }
+
+        if total == 0.0 {
+            return (0.0, evidence);
+        }
+        (matched / total, evidence)
+    }
+}
+
+// serializes in the rules file format, so `parse_rules` reads it back
+#[derive(Clone, Default, Serialize)]
+pub struct RuleSet {
+    pub rules: Vec<Rule>,
+    pub families: Vec<FamilySignature>,
+    // only the rule file's; BUILTIN_PACKERS are always checked too
+    pub packers: Vec<PackerSignature>,
+    // GUIDs tied to known malware builds or families, uppercase and unbraced
+    pub guids: Vec<String>,
+}
+
+impl RuleSet {
+    // best-scoring family at or above FAMILY_MIN_CONFIDENCE
+    pub fn match_family(&self, imphash: Option<&str>, strings: &[String]) -> Option<FamilyMatch> {
+        self.families
+            .iter()
+            .map(|family| {
+                let (confidence, evidence) = family.score(imphash, strings);
+                FamilyMatch {
+                    name: family.name.clone(),
+                    confidence,
+                    evidence,
+                }
+            })
+            .filter(|m| m.confidence >= FAMILY_MIN_CONFIDENCE)
+            .max_by(|a, b| a.confidence.total_cmp(&b.confidence))
+    }
+
+    // the first packer, built-in ones before the rule file's, with a
+    // section named after it; returns the packer and the matching section
+    pub fn detect_packer(&self, section_names: &[String]) -> Option<(String, String)> {
+        let find = |patterns: &[&str]| {
+            section_names
+                .iter()
+                .find(|s| patterns.iter().any(|p| p.eq_ignore_ascii_case(s)))
+                .cloned()
+        };
+
+        for (name, patterns) in BUILTIN_PACKERS {
+            if let Some(section) = find(patterns) {
+                return Some((name.to_string(), section));
+            }
+        }
+        for packer in &self.packers {
+            let patterns: Vec<&str> = packer.sections.iter().map(String::as_str).collect();
+            if let Some(section) = find(&patterns) {
+                return Some((packer.name.clone(), section));
+            }
+        }
+        None
+    }
+}
+
+#[derive(Deserialize)]
+struct RuleFile {
+    rules: Vec<Rule>,
+    #[serde(default)]
+    families: Vec<FamilySignature>,
+    #[serde(default)]
+    packers: Vec<PackerSignature>,
+    #[serde(default)]
+    guids: Vec<String>,
+}
+
+impl Rule {
+    pub fn compile(&mut self) -> Result<(), String> {
+        self.compiled = self
+            .regex
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern).map_err(|e| {
+                    format!("Rule {} has invalid regex \"{}\": {}", self.id, pattern, e)
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(())
+    }
+
+    pub fn is_match(&self, imported_functions: &[String], strings: &[String]) -> bool {
+        if self.matches.is_empty() && self.compiled.is_empty() {
+            return false;
+        }
+
+        // import names match by prefix so "RegSetValue" covers the A/W/Ex variants
+        let found = |needle: &String| match self.target {
+            MatchTarget::Content => strings.iter().any(|s| s.contains(needle.as_str())),
+            MatchTarget::Imports => imported_functions
+                .iter()
+                .any(|f| f.starts_with(needle.as_str())),
+        };
+        let regex_found = |regex: &Regex| strings.iter().any(|s| regex.is_match(s));
+
+        match self.logic {
+            MatchLogic::And => {
+                self.matches.iter().all(found) && self.compiled.iter().all(regex_found)
+            }
+            MatchLogic::Or => {
+                self.matches.iter().any(found) || self.compiled.iter().any(regex_found)
+            }
+        }
+    }
+
+    pub fn to_threat(&self) -> Threat {
+        Threat {
+            threat_type: self.threat_type.clone(),
+            details: self.details.clone(),
+            severity: self.severity.clone(),
+            threat_id: self.id.clone(),
+        }
+    }
+}
+
+pub fn builtin_rules() -> Vec<Rule> {
+    vec![
+        Rule {
+            id: "S001".to_string(),
+            threat_type: "Suspicious String".to_string(),
+            details: "File contains suspicious keywords".to_string(),
+            severity: "suspicious".to_string(),
+            matches: vec!["malware".to_string(), "virus".to_string()],
+            logic: MatchLogic::Or,
+            target: MatchTarget::Content,
+            regex: vec![],
+            compiled: vec![],
+        },
+        Rule {
+            id: "S002".to_string(),
+            threat_type: "Process Injection API".to_string(),
+            details: "Imports process injection functions".to_string(),
+            severity: "malicious".to_string(),
+            matches: vec![
+                "CreateRemoteThread".to_string(),
+                "VirtualAllocEx".to_string(),
+            ],
+            logic: MatchLogic::And,
+            target: MatchTarget::Imports,
+            regex: vec![],
+            compiled: vec![],
+        },
+        Rule {
+            id: "S003".to_string(),
+            threat_type: "Registry Modification".to_string(),
+            details: "Contains registry manipulation functions".to_string(),
+            severity: "suspicious".to_string(),
+            matches: vec!["RegSetValue".to_string(), "RegCreateKey".to_string()],
+            logic: MatchLogic::And,
+            target: MatchTarget::Content,
+            regex: vec![],
+            compiled: vec![],
+        },
+    ]
+}
+
+pub fn parse_rules(json: &str) -> Result<RuleSet, String> {
+    let mut file: RuleFile =
+        serde_json::from_str(json).map_err(|e| format!("Invalid rules: {}", e))?;
+
+    for rule in &mut file.rules {
+        if !VALID_SEVERITIES.contains(&rule.severity.as_str()) {
+            return Err(format!(
+                "Rule {} has invalid severity \"{}\"",
+                rule.id, rule.severity
+            ));
+        }
+        rule.compile()?;
+    }
+
+    let guids = file
+        .guids
+        .iter()
+        .map(|guid| normalize_guid(guid).ok_or_else(|| format!("Invalid GUID \"{}\"", guid)))
+        .collect::<Result<_, _>>()?;
+
+    Ok(RuleSet {
+        rules: file.rules,
+        families: file.families,
+        packers: file.packers,
+        guids,
+    })
+}
+
+// falls back to the built-in rules when the file is missing or invalid
+pub fn load_rules(path: &Path) -> RuleSet {
+    let rule_set = match fs::read_to_string(path) {
+        Ok(json) => match parse_rules(&json) {
+            Ok(rule_set) => rule_set,
+            Err(e) => {
+                println!("Rejected {}: {}, using built-in rules", path.display(), e);
+                RuleSet {
+                    rules: builtin_rules(),
+                    ..Default::default()
+                }
+            }
+        },
+        Err(_) => {
+            println!("No rules file at {}, using built-in rules", path.display());
+            RuleSet {
+                rules: builtin_rules(),
+                ..Default::default()
+            }
+        }
+    };
+
+    println!(
+        "📜 Loaded {} detection rules, {} family signatures, {} packer signatures, {} known-bad GUIDs",
+        rule_set.rules.len(),
+        rule_set.families.len(),
+        rule_set.packers.len(),
+        rule_set.guids.len()
+    );
+    rule_set
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::temp_dir;
+
+    fn rule(logic: &str, target: &str) -> Rule {
+        let json = format!(
+            r#"{{"rules": [{{"id": "T001", "type": "Test", "details": "d", "severity": "suspicious",
+                "matches": ["alpha", "beta"], "logic": "{}", "target": "{}"}}]}}"#,
+            logic, target
+        );
+        parse_rules(&json).unwrap().rules.remove(0)
+    }
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn loads_the_shipped_rules_file() {
+        let json = fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/rules.json")).unwrap();
+        let rules = parse_rules(&json).unwrap();
+        assert!(rules.rules.iter().any(|r| r.id == "S002"));
+        assert!(rules
+            .rules
+            .iter()
+            .all(|r| VALID_SEVERITIES.contains(&r.severity.as_str())));
+    }
+
+    #[test]
+    fn and_needs_every_match_or_needs_one() {
+        let and = rule("and", "content");
+        assert!(and.is_match(&[], &strings(&["has alpha", "and beta"])));
+        assert!(!and.is_match(&[], &strings(&["only alpha"])));
+
+        let or = rule("or", "content");
+        assert!(or.is_match(&[], &strings(&["only beta"])));
+        assert!(!or.is_match(&[], &strings(&["gamma"])));
+    }
+
+    #[test]
+    fn import_rules_match_import_names_by_prefix() {
+        let or = rule("or", "imports");
+        assert!(or.is_match(&strings(&["alphaW"]), &[]));
+        // the same text among the strings doesn't count
+        assert!(!or.is_match(&[], &strings(&["alpha", "beta"])));
+        assert!(!or.is_match(&strings(&["xalpha"]), &[]));
+    }
+
+    #[test]
+    fn invalid_severity_rejects_the_file() {
+        let json = r#"{"rules": [{"id": "T001", "type": "Test", "details": "d",
+            "severity": "critical", "matches": ["x"]}]}"#;
+        assert_eq!(
+            parse_rules(json).err().unwrap(),
+            "Rule T001 has invalid severity \"critical\""
+        );
+        assert!(parse_rules("not json").is_err());
+    }
+
+    #[test]
+    fn missing_or_invalid_files_fall_back_to_builtin_rules() {
+        let dir = temp_dir();
+        let builtin: Vec<String> = builtin_rules().into_iter().map(|r| r.id).collect();
+        let ids =
+            |rules: RuleSet| -> Vec<String> { rules.rules.into_iter().map(|r| r.id).collect() };
+
+        assert_eq!(ids(load_rules(&dir.join("missing.json"))), builtin);
+
+        let invalid = dir.join("invalid.json");
+        fs::write(
+            &invalid,
+            r#"{"rules": [{"id": "X", "type": "t", "details": "d", "severity": "bad"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(ids(load_rules(&invalid)), builtin);
+
+        let valid = dir.join("rules.json");
+        fs::write(
+            &valid,
+            r#"{"rules": [{"id": "X1", "type": "t", "details": "d", "severity": "neutral"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(ids(load_rules(&valid)), ["X1"]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn guids_are_normalized() {
+        let rules = parse_rules(
+            r#"{"rules": [], "guids": ["{3f2504e0-4f89-11d3-9a0c-0305e82c3301}", " 6B29FC40-CA47-1067-B31D-00DD010662DA "]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            rules.guids,
+            [
+                "3F2504E0-4F89-11D3-9A0C-0305E82C3301",
+                "6B29FC40-CA47-1067-B31D-00DD010662DA"
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_guids_are_rejected() {
+        for guid in [
+            "3f2504e0-4f89-11d3-9a0c",
+            "{3f2504e0-4f89-11d3-9a0c-0305e82c3301",
+            "not-a-guid",
+        ] {
+            let json = format!(r#"{{"rules": [], "guids": ["{}"]}}"#, guid);
+            assert!(parse_rules(&json).is_err(), "{}", guid);
+        }
+    }
+
+    #[test]
+    fn guids_default_to_none() {
+        assert!(parse_rules(r#"{"rules": []}"#).unwrap().guids.is_empty());
+    }
+
+    fn regex_rule(patterns: &[&str]) -> Result<Rule, String> {
+        let json = serde_json::json!({"rules": [{
+            "id": "R001", "type": "Test", "details": "d", "severity": "suspicious",
+            "regex": patterns,
+        }]});
+        Ok(parse_rules(&json.to_string())?.rules.remove(0))
+    }
+
+    #[test]
+    fn regex_rules_match_ipv4_literals() {
+        let rule = regex_rule(&[r"\b(?:\d{1,3}\.){3}\d{1,3}\b"]).unwrap();
+        assert!(rule.is_match(&[], &strings(&["connect to 192.168.10.4:8080"])));
+        assert!(!rule.is_match(&[], &strings(&["version 1.2.3"])));
+    }
+
+    #[test]
+    fn regex_rules_match_http_urls() {
+        let rule = regex_rule(&[r"http://[\w.-]+"]).unwrap();
+        assert!(rule.is_match(&[], &strings(&["GET http://c2.example/gate.php"])));
+        assert!(!rule.is_match(&[], &strings(&["https://example.com"])));
+        // import names are never searched by content rules
+        assert!(!rule.is_match(&strings(&["http://imports"]), &[]));
+    }
+
+    #[test]
+    fn invalid_regex_names_the_rule() {
+        let error = regex_rule(&["(unclosed"]).err().unwrap();
+        assert!(
+            error.starts_with("Rule R001 has invalid regex \"(unclosed\""),
+            "{}",
+            error
+        );
+    }
+
+    fn families() -> RuleSet {
+        parse_rules(
+            r#"{"rules": [], "families": [
+                {"name": "Alpha", "imphashes": ["aaaa"], "mutexes": ["Global\\AlphaMtx"],
+                 "strings": ["alpha-panel", "alpha.bin"]},
+                {"name": "Beta", "strings": ["beta-c2", "beta-key", "beta-drop"]}
+            ]}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn family_confidence_weighs_the_evidence() {
+        let rules = families();
+        let alpha = &rules.families[0];
+        // imphash 3 + mutex 2 + strings 1 each
+        let (confidence, evidence) = alpha.score(Some("aaaa"), &[]);
+        assert_eq!(confidence, 3.0 / 7.0);
+        assert_eq!(evidence, ["imphash aaaa"]);
+
+        let (confidence, evidence) = alpha.score(
+            Some("aaaa"),
+            &strings(&["open Global\\AlphaMtx now", "alpha-panel"]),
+        );
+        assert_eq!(confidence, 6.0 / 7.0);
+        assert_eq!(
+            evidence,
+            [
+                "imphash aaaa",
+                "mutex Global\\AlphaMtx",
+                "string \"alpha-panel\""
+            ]
+        );
+
+        let empty = FamilySignature {
+            name: "Empty".to_string(),
+            imphashes: vec![],
+            mutexes: vec![],
+            strings: vec![],
+        };
+        assert_eq!(empty.score(Some("aaaa"), &strings(&["x"])).0, 0.0);
+    }
+
+    #[test]
+    fn best_family_above_the_threshold_wins() {
+        let rules = families();
+        // 3/7 is below FAMILY_MIN_CONFIDENCE
+        assert!(rules.match_family(Some("aaaa"), &[]).is_none());
+
+        let tag = rules
+            .match_family(Some("aaaa"), &strings(&["alpha.bin"]))
+            .unwrap();
+        assert_eq!(tag.name, "Alpha");
+        assert_eq!(tag.confidence, 4.0 / 7.0);
+
+        // Beta's 2/3 beats Alpha's 4/7
+        let tag = rules
+            .match_family(Some("aaaa"), &strings(&["alpha.bin", "beta-c2 beta-key"]))
+            .unwrap();
+        assert_eq!(tag.name, "Beta");
+        assert_eq!(tag.evidence.len(), 2);
+
+        assert!(RuleSet::default()
+            .match_family(Some("aaaa"), &strings(&["alpha.bin"]))
+            .is_none());
+    }
+}
@@ -0,0 +1,275 @@
+use crate::config::*;
+use crate::rules::{parse_rules, RuleSet};
+use crate::scanner::{analyze, Analysis};
+use crate::utils::map_file;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+
+// argument that turns the server binary into a one-shot analysis child
+pub const ANALYZE_FLAG: &str = "--analyze";
+// optional trailing argument passing `ScanOptions::parallel` to the child
+pub const PARALLEL_FLAG: &str = "--parallel";
+
+// what the child writes to stdout, one JSON object per line
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ChildMessage {
+    Progress { progress: u32, message: String },
+    Result(Box<Analysis>),
+    Error(String),
+}
+
+// applied between fork and exec; seccomp filtering is left to the deployment
+// (e.g. a systemd unit) since building BPF filters by hand is its own project
+#[cfg(target_os = "linux")]
+fn restrict(command: &mut Command) {
+    use std::os::unix::process::CommandExt;
+
+    fn limit(resource: libc::__rlimit_resource_t, value: u64) -> io::Result<()> {
+        let rlimit = libc::rlimit {
+            rlim_cur: value,
+            rlim_max: value,
+        };
+        if unsafe { libc::setrlimit(resource, &rlimit) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    unsafe {
+        command.pre_exec(|| {
+            // the child can never gain privileges through setuid binaries
+            if libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            limit(libc::RLIMIT_CPU, SCAN_TIMEOUT.as_secs())?;
+            limit(libc::RLIMIT_AS, SANDBOX_MEMORY_LIMIT)?;
+            // no file writes and no core dumps; the stdout pipe isn't a file
+            limit(libc::RLIMIT_FSIZE, 0)?;
+            limit(libc::RLIMIT_CORE, 0)?;
+            Ok(())
+        });
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn restrict(_command: &mut Command) {}
+
+// analyzes `file_path` in a restricted child process; Ok(None) means `progress`
+// asked to stop or `stop` was set, and any crash or bad output from the child
+// is an Err
+pub fn run_sandboxed(
+    file_path: &Path,
+    rules: &RuleSet,
+    parallel: bool,
+    stop: &AtomicBool,
+    progress: &mut dyn FnMut(u32, &str) -> bool,
+) -> Result<Option<Analysis>, String> {
+    let exe = env::current_exe().map_err(|e| format!("cannot locate server binary: {}", e))?;
+    let mut command = Command::new(exe);
+    command
+        .arg(ANALYZE_FLAG)
+        .arg(file_path)
+        .args(parallel.then_some(PARALLEL_FLAG));
+    restrict(&mut command);
+    run_child_command(command, rules, stop, progress)
+}
+
+// the child's stdout is read on its own thread, so a child that goes quiet
+// (stuck in a loop or blocked on I/O) is still killed once `stop` is set,
+// which the scan timeout and the cancel endpoint both do
+fn run_child_command(
+    mut command: Command,
+    rules: &RuleSet,
+    stop: &AtomicBool,
+    progress: &mut dyn FnMut(u32, &str) -> bool,
+) -> Result<Option<Analysis>, String> {
+    command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+    let mut child = command
+        .spawn()
+        .map_err(|e| format!("cannot start analysis process: {}", e))?;
+    let kill = |child: &mut Child| {
+        let _ = child.kill();
+        let _ = child.wait();
+    };
+
+    // rules go over stdin so the child matches exactly what the server loaded;
+    // dropping the handle closes the pipe
+    if let Some(mut stdin) = child.stdin.take() {
+        let rules_json = serde_json::to_string(rules).unwrap();
+        if let Err(e) = stdin.write_all(rules_json.as_bytes()) {
+            kill(&mut child);
+            return Err(format!("cannot send rules to analysis process: {}", e));
+        }
+    }
+
+    let (tx, rx) = mpsc::channel();
+    if let Some(stdout) = child.stdout.take() {
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if tx.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    let mut outcome = None;
+    loop {
+        let line = match rx.recv_timeout(SANDBOX_POLL_INTERVAL) {
+            Ok(line) => line,
+            Err(RecvTimeoutError::Timeout) if stop.load(Ordering::SeqCst) => {
+                kill(&mut child);
+                return Ok(None);
+            }
+            Err(RecvTimeoutError::Timeout) => continue,
+            // stdout closed, the child is done
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+        match serde_json::from_str::<ChildMessage>(&line) {
+            Ok(ChildMessage::Progress {
+                progress: percent,
+                message,
+            }) => {
+                if !progress(percent, &message) {
+                    kill(&mut child);
+                    return Ok(None);
+                }
+            }
+            Ok(ChildMessage::Result(analysis)) => outcome = Some(Ok(*analysis)),
+            Ok(ChildMessage::Error(e)) => outcome = Some(Err(e)),
+            // log output from the analysis code, pass it through
+            Err(_) => println!("{}", line),
+        }
+    }
+
+    let status = child
+        .wait()
+        .map_err(|e| format!("lost analysis process: {}", e))?;
+    match outcome {
+        Some(Ok(analysis)) if status.success() => Ok(Some(analysis)),
+        Some(Err(e)) => Err(e),
+        _ => Err(format!("analysis process exited abnormally ({})", status)),
+    }
+}
+
+// entry point for `peroxide --analyze <path> [--parallel]`, returns the exit code
+pub fn run_child(file_path: &Path, parallel: bool) -> i32 {
+    let stdout = io::stdout();
+    let emit = |message: ChildMessage| {
+        let mut out = stdout.lock();
+        let _ = writeln!(out, "{}", serde_json::to_string(&message).unwrap());
+        let _ = out.flush();
+    };
+
+    let mut rules_json = String::new();
+    let rules = match io::stdin()
+        .read_to_string(&mut rules_json)
+        .map_err(|e| e.to_string())
+        .and_then(|_| parse_rules(&rules_json))
+    {
+        Ok(rules) => rules,
+        Err(e) => {
+            emit(ChildMessage::Error(format!("Error reading rules: {}", e)));
+            return 1;
+        }
+    };
+
+    let content = match map_file(file_path) {
+        Ok(content) => content,
+        Err(e) => {
+            emit(ChildMessage::Error(format!("Error reading file: {}", e)));
+            return 1;
+        }
+    };
+
+    let analysis = analyze(&content, &rules, parallel, &mut |progress, message| {
+        emit(ChildMessage::Progress {
+            progress,
+            message: message.to_string(),
+        });
+        true
+    });
+    match analysis {
+        Some(analysis) => {
+            emit(ChildMessage::Result(Box::new(analysis)));
+            0
+        }
+        None => 1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    fn shell(script: &str) -> Command {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(script);
+        command
+    }
+
+    fn run(script: &str, stop: &AtomicBool) -> Result<Option<Analysis>, String> {
+        run_child_command(shell(script), &RuleSet::default(), stop, &mut |_, _| true)
+    }
+
+    #[test]
+    fn crashing_child_is_an_error() {
+        let stop = AtomicBool::new(false);
+        let error = run("cat > /dev/null; kill -SEGV $$", &stop).err().unwrap();
+        assert!(error.contains("exited abnormally"), "{}", error);
+    }
+
+    #[test]
+    fn child_error_message_is_passed_on() {
+        let stop = AtomicBool::new(false);
+        let script = r#"cat > /dev/null; echo '{"error":"Error reading file: gone"}'; exit 1"#;
+        assert_eq!(
+            run(script, &stop).err().unwrap(),
+            "Error reading file: gone"
+        );
+    }
+
+    #[test]
+    fn progress_is_reported_and_can_stop_the_child() {
+        let stop = AtomicBool::new(false);
+        let script = r#"cat > /dev/null; echo '{"progress":{"progress":40,"message":"Hashing"}}'; exec sleep 30"#;
+        let mut seen = Vec::new();
+        let started = Instant::now();
+        let outcome = run_child_command(shell(script), &RuleSet::default(), &stop, &mut |p, m| {
+            seen.push((p, m.to_string()));
+            false
+        });
+        assert!(matches!(outcome, Ok(None)));
+        assert_eq!(seen, [(40, "Hashing".to_string())]);
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn silent_child_is_killed_when_stopped() {
+        let stop = AtomicBool::new(false);
+        let started = Instant::now();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                thread::sleep(Duration::from_millis(300));
+                stop.store(true, Ordering::SeqCst);
+            });
+            // never prints anything, like a child blocked on a read
+            assert!(matches!(run("exec sleep 30", &stop), Ok(None)));
+        });
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+}
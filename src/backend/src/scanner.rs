@@ -1,102 +1,1455 @@
-use crate::indicators::*;
-use crate::types::*;
-use crate::utils::*;
-
-use std::fs;
-use std::path::PathBuf;
-use std::thread;
-use std::time::Duration;
-
-pub fn scan_file(file_path: PathBuf, file_info: FileInfo, scan_id: String, scan_store: ScanStore) {
-    thread::spawn(move || {
-        send_progress(&scan_id, 10, "Reading file content...", &scan_store);
-
-        let content = match fs::read(&file_path) {
-            Ok(c) => c,
-            Err(e) => {
-                let mut store = scan_store.lock().unwrap();
-                if let Some(result) = store.get_mut(&scan_id) {
-                    result.status = "error".to_string();
-                    result.logs.push(format!("Error reading file: {}", e));
-                }
-                let _ = fs::remove_file(&file_path);
-                return;
-            }
-        };
-
-        send_progress(&scan_id, 30, "Scanning file headers...", &scan_store);
-
-        let mut threats = Vec::new();
-
-        if content.len() >= 2 {
-            let signature = &content[0..2];
-            if signature == b"MZ" {
-                send_progress(
-                    &scan_id,
-                    50,
-                    "PE executable detected, analyzing...",
-                    &scan_store,
-                );
-            }
-        }
-
-        send_progress(
-            &scan_id,
-            60,
-            "Performing signature analysis...",
-            &scan_store,
-        );
-
-        let content_str = String::from_utf8_lossy(&content);
-
-        let detected_threats = check_indicators(&content_str);
-        threats.extend(detected_threats);
-
-        send_progress(&scan_id, 90, "Finalizing results...", &scan_store);
-
-        thread::sleep(Duration::from_secs(1));
-
-        send_progress(&scan_id, 100, "Scan complete!", &scan_store);
-
-        let malicious_count = threats.iter().filter(|t| t.severity == "malicious").count();
-        let suspicious_count = threats
-            .iter()
-            .filter(|t| t.severity == "suspicious")
-            .count();
-        let neutral_count = threats.iter().filter(|t| t.severity == "neutral").count();
-
-        // Only mark as "unsafe" if there are malicious indicators
-        let status = if malicious_count > 0 {
-            "unsafe"
-        } else if suspicious_count > 0 || neutral_count > 0 {
-            "suspicious"
-        } else {
-            "safe"
-        };
-
-        let result = ScanResult {
-            status: status.to_string(),
-            threats: threats.clone(),
-            stats: ScanStats {
-                threats_found: threats.len(),
-                malicious: malicious_count,
-                suspicious: suspicious_count,
-                neutral: neutral_count,
-            },
-            logs: {
-                let store = scan_store.lock().unwrap();
-                store
-                    .get(&scan_id)
-                    .map(|r| r.logs.clone())
-                    .unwrap_or_default()
-            },
-            file_info: Some(file_info),
-        };
-
-        let mut store = scan_store.lock().unwrap();
-        store.insert(scan_id.clone(), result);
-
-        let _ = fs::remove_file(&file_path);
-        println!("Scan complete for {}, file cleaned up", scan_id);
-    });
-}
+use crate::analysis::*;
+use crate::archive::*;
+use crate::authenticode::*;
+use crate::canonical::*;
+use crate::config::*;
+use crate::indicators::*;
+use crate::pe::*;
+use crate::rules::RuleSet;
+use crate::sandbox::*;
+use crate::stats::*;
+use crate::storage::*;
+use crate::types::*;
+use crate::utils::*;
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// if a cancel was requested, marks the scan cancelled and removes the upload
+fn check_cancelled(
+    scan_id: &str,
+    file_path: &Path,
+    scan_store: &ScanStore,
+    storage: &SharedStorage,
+) -> bool {
+    let cancelled = {
+        let mut store = scan_store.lock().unwrap();
+        match store.get_mut(scan_id) {
+            // already timed out or failed, or deleted outright, just stop
+            Some(result) if result.status != "scanning" => return true,
+            None => return true,
+            Some(result) if result.cancel_flag.load(Ordering::SeqCst) => {
+                result.status = "cancelled".to_string();
+                result.logs.push("Scan cancelled".to_string());
+                true
+            }
+            _ => false,
+        }
+    };
+
+    if cancelled {
+        persist(storage, scan_store, scan_id);
+        let _ = fs::remove_file(file_path);
+        println!("Scan cancelled for {}, file cleaned up", scan_id);
+    }
+    cancelled
+}
+
+// moves a running scan to a terminal failure status and removes the upload;
+// the cancel flag also stops a worker that is still going. A scan that already
+// ended keeps its status, so a sandbox killed at the timeout stays "timeout"
+fn fail_scan(
+    scan_id: &str,
+    file_path: &Path,
+    scan_store: &ScanStore,
+    storage: &SharedStorage,
+    status: &str,
+    message: &str,
+) {
+    {
+        let mut store = scan_store.lock().unwrap();
+        match store.get_mut(scan_id) {
+            Some(result) if result.status == "scanning" => {
+                result.status = status.to_string();
+                result.logs.push(message.to_string());
+                result.cancel_flag.store(true, Ordering::SeqCst);
+            }
+            _ => return,
+        }
+    }
+    persist(storage, scan_store, scan_id);
+    let _ = fs::remove_file(file_path);
+    println!("Scan {} for {}, file cleaned up", status, scan_id);
+}
+
+struct PeAnalysis {
+    threats: Vec<Threat>,
+    info: PeInfo,
+    imports: Vec<ImportedDll>,
+    resources: Vec<Resource>,
+    signed: bool,
+    certificate_size: Option<u32>,
+    overlay_size: u64,
+    // file offset of an API-hashing loop near the entry point
+    hash_loop: Option<usize>,
+    packer: Option<String>,
+}
+
+fn analyze_pe(content: &[u8], rules: &RuleSet) -> Result<PeAnalysis, String> {
+    let headers = parse_headers(content)?;
+    let mut threats = Vec::new();
+    threats.extend(check_pe_anomalies(content, &headers));
+    threats.extend(check_section_entropy(content, &headers));
+    let section_names: Vec<String> = headers.sections.iter().map(|s| s.name.clone()).collect();
+    let packer = rules.detect_packer(&section_names);
+    threats.extend(check_packer(packer.as_ref()));
+    threats.extend(check_sparse(content));
+    threats.extend(check_section_padding(&section_gaps(content, &headers)));
+    let overlay = overlay(content, &headers);
+    threats.extend(check_overlay(&overlay));
+    threats.extend(check_polyglot(content));
+    let imports = parse_imports(content, &headers);
+    threats.extend(check_uncommon_dlls(&imports));
+    let resources = parse_resources(content, &headers);
+    threats.extend(check_resources(&resources));
+
+    let blobs = signature_blobs(content, &headers);
+    let signed = !blobs.is_empty();
+    let trust = blobs.first().and_then(|blob| signature_trust(blob));
+    threats.extend(check_unsigned(signed));
+    threats.extend(check_signature_trust(trust));
+
+    let mut info = headers.info();
+    info.signature_trust = trust.map(|t| t.to_string());
+    Ok(PeAnalysis {
+        threats,
+        info,
+        imports,
+        resources,
+        signed,
+        certificate_size: certificate_table_size(content, &headers),
+        overlay_size: overlay.iter().map(|piece| piece.len() as u64).sum(),
+        hash_loop: headers
+            .entry_code(content, API_HASH_SCAN_LENGTH)
+            .and_then(|(start, code)| Some(start + find_hash_loop(code)?)),
+        packer: packer.map(|(name, _)| name),
+    })
+}
+
+// the per-file checks from `run_scan`, without progress reporting, for archive members
+fn member_threats(content: &[u8], rules: &RuleSet) -> Vec<Threat> {
+    let mut threats = Vec::new();
+    let mut imports = Vec::new();
+    let mut hash_loop = None;
+    if content.starts_with(b"MZ") {
+        if let Ok(pe) = analyze_pe(content, rules) {
+            threats.extend(pe.threats);
+            imports = pe.imports;
+            hash_loop = pe.hash_loop;
+        }
+    }
+    let imported_functions: Vec<String> = imports
+        .iter()
+        .flat_map(|i| i.functions.iter().cloned())
+        .collect();
+
+    let strings = extract_strings(content, MIN_STRING_LENGTH);
+    let all_strings: Vec<String> = strings.ascii.into_iter().chain(strings.wide).collect();
+    threats.extend(check_indicators(
+        &imported_functions,
+        &all_strings,
+        &rules.rules,
+    ));
+    threats.extend(check_anti_forensics(&imported_functions, &all_strings));
+    threats.extend(check_raw_network(&imports, &all_strings));
+    threats.extend(check_native_api(&imports));
+    threats.extend(check_api_hashing(
+        &imported_functions,
+        &all_strings,
+        hash_loop,
+    ));
+    threats.extend(check_guids(&extract_guids(&all_strings), &rules.guids));
+    threats
+}
+
+// everything the analysis phases produce; the sandboxed child sends it back as JSON
+#[derive(Serialize, Deserialize)]
+pub struct Analysis {
+    threats: Vec<Threat>,
+    pe_info: Option<PeInfo>,
+    imports: Vec<ImportedDll>,
+    resources: Vec<Resource>,
+    signed: bool,
+    certificate_size: Option<u32>,
+    overlay_size: u64,
+    imphash: Option<String>,
+    entropy: f64,
+    zero_ratio: f64,
+    guids: Vec<String>,
+    strings: ExtractedStrings,
+    strings_count: usize,
+    family: Option<FamilyMatch>,
+    packer: Option<String>,
+}
+
+// the format-specific phase: PE headers and imports, or archive members
+#[derive(Default)]
+struct Structure {
+    threats: Vec<Threat>,
+    pe_info: Option<PeInfo>,
+    imports: Vec<ImportedDll>,
+    resources: Vec<Resource>,
+    signed: bool,
+    certificate_size: Option<u32>,
+    overlay_size: u64,
+    hash_loop: Option<usize>,
+    packer: Option<String>,
+    // reported as progress, the rest of the analysis still runs
+    error: Option<String>,
+}
+
+fn analyze_structure(content: &[u8], rules: &RuleSet) -> Structure {
+    if content.starts_with(b"MZ") {
+        match analyze_pe(content, rules) {
+            Ok(pe) => Structure {
+                threats: pe.threats,
+                pe_info: Some(pe.info),
+                imports: pe.imports,
+                resources: pe.resources,
+                signed: pe.signed,
+                certificate_size: pe.certificate_size,
+                overlay_size: pe.overlay_size,
+                hash_loop: pe.hash_loop,
+                packer: pe.packer,
+                error: None,
+            },
+            Err(e) => Structure {
+                error: Some(format!("Failed to parse PE headers: {}", e)),
+                ..Default::default()
+            },
+        }
+    } else if is_zip(content) {
+        match scan_archive(content, &|member| member_threats(member, rules)) {
+            Ok(threats) => Structure {
+                threats,
+                ..Default::default()
+            },
+            Err(e) => Structure {
+                error: Some(format!("Failed to read archive: {}", e)),
+                ..Default::default()
+            },
+        }
+    } else {
+        Structure::default()
+    }
+}
+
+// runs the analysis phases over `content`, reporting each through `progress`,
+// which returns false to stop early (the result is then None); with `parallel`
+// the structure, string and histogram phases of a large file run side by side,
+// and progress is only reported before and after they join
+pub fn analyze(
+    content: &[u8],
+    rules: &RuleSet,
+    parallel: bool,
+    progress: &mut dyn FnMut(u32, &str) -> bool,
+) -> Option<Analysis> {
+    if !progress(30, "Scanning file headers...") {
+        return None;
+    }
+
+    let detected = if content.starts_with(b"MZ") {
+        Some("PE executable detected, analyzing...")
+    } else if is_zip(content) {
+        Some("ZIP archive detected, scanning members...")
+    } else {
+        None
+    };
+    if let Some(message) = detected {
+        if !progress(50, message) {
+            return None;
+        }
+    }
+
+    let parallel = parallel && (PARALLEL_MIN_SIZE..=PARALLEL_MAX_SIZE).contains(&content.len());
+    let (structure, strings, histogram) = if parallel {
+        thread::scope(|scope| {
+            let strings = scope.spawn(|| extract_strings(content, MIN_STRING_LENGTH));
+            let histogram = scope.spawn(|| byte_histogram(content));
+            let structure = analyze_structure(content, rules);
+            (
+                structure,
+                strings.join().unwrap(),
+                histogram.join().unwrap(),
+            )
+        })
+    } else {
+        (
+            analyze_structure(content, rules),
+            extract_strings(content, MIN_STRING_LENGTH),
+            byte_histogram(content),
+        )
+    };
+
+    if let Some(e) = &structure.error {
+        if !progress(50, e) {
+            return None;
+        }
+    }
+    let mut threats = structure.threats;
+    let pe_info = structure.pe_info;
+    let imports = structure.imports;
+    let resources = structure.resources;
+    let signed = structure.signed;
+    let certificate_size = structure.certificate_size;
+    let overlay_size = structure.overlay_size;
+    let packer = structure.packer;
+
+    if !progress(60, "Performing signature analysis...") {
+        return None;
+    }
+
+    let imphash = calculate_imphash(&imports);
+
+    let imported_functions: Vec<String> = imports
+        .iter()
+        .flat_map(|i| i.functions.iter().cloned())
+        .collect();
+
+    let all_strings: Vec<String> = strings.ascii.iter().chain(&strings.wide).cloned().collect();
+
+    let detected_threats = check_indicators(&imported_functions, &all_strings, &rules.rules);
+    threats.extend(detected_threats);
+    threats.extend(check_anti_forensics(&imported_functions, &all_strings));
+    threats.extend(check_raw_network(&imports, &all_strings));
+    threats.extend(check_native_api(&imports));
+    threats.extend(check_api_hashing(
+        &imported_functions,
+        &all_strings,
+        structure.hash_loop,
+    ));
+
+    let guids = extract_guids(&all_strings);
+    threats.extend(check_guids(&guids, &rules.guids));
+
+    let family = rules.match_family(imphash.as_deref(), &all_strings);
+
+    if !progress(90, "Finalizing results...") {
+        return None;
+    }
+
+    thread::sleep(Duration::from_secs(1));
+
+    if !progress(100, "Scan complete!") {
+        return None;
+    }
+
+    Some(Analysis {
+        threats,
+        pe_info,
+        imports,
+        resources,
+        signed,
+        certificate_size,
+        overlay_size,
+        imphash,
+        entropy: histogram_entropy(&histogram),
+        zero_ratio: histogram_zero_ratio(&histogram),
+        guids,
+        strings: cap_strings(&strings, MAX_REPORTED_STRINGS, MAX_REPORTED_STRING_LENGTH),
+        strings_count: all_strings.len(),
+        family,
+        packer,
+    })
+}
+
+// sums the weighted threat counts plus bonuses for a high-entropy file, a
+// known packer alongside a high-entropy section, and dangerous imports,
+// capped at 100
+fn threat_score(threats: &[Threat], entropy: f64, imported_functions: &[String]) -> u32 {
+    let mut score: u32 = threats
+        .iter()
+        .map(|t| match t.severity.as_str() {
+            "malicious" => SCORE_MALICIOUS_WEIGHT,
+            "suspicious" => SCORE_SUSPICIOUS_WEIGHT,
+            "neutral" => SCORE_NEUTRAL_WEIGHT,
+            _ => 0,
+        })
+        .sum();
+
+    if entropy > HIGH_ENTROPY_THRESHOLD {
+        score += SCORE_HIGH_ENTROPY_BONUS;
+    }
+    let has = |id: &str| threats.iter().any(|t| t.threat_id == id);
+    if has(PACKER_DETECTED_ID) && has(HIGH_ENTROPY_SECTION_ID) {
+        score += SCORE_PACKED_ENTROPY_BONUS;
+    }
+
+    let dangerous = imported_functions
+        .iter()
+        .filter(|f| DANGEROUS_IMPORTS.iter().any(|d| f.starts_with(d)))
+        .count() as u32;
+    score += (dangerous * SCORE_DANGEROUS_IMPORT_BONUS).min(SCORE_DANGEROUS_IMPORT_CAP);
+
+    score.min(100)
+}
+
+fn verdict(score: u32, thresholds: VerdictThresholds) -> &'static str {
+    if score >= thresholds.malicious {
+        "malicious"
+    } else if score >= thresholds.suspicious {
+        "suspicious"
+    } else {
+        "clean"
+    }
+}
+
+// runs the analysis in-process or in a sandboxed child, returning None if the
+// scan was cancelled or failed
+fn run_scan(
+    file_path: PathBuf,
+    mut file_info: FileInfo,
+    scan_id: String,
+    scan_store: ScanStore,
+    storage: SharedStorage,
+    rules: Arc<RuleSet>,
+    options: ScanOptions,
+) -> Option<ScanResult> {
+    if check_cancelled(&scan_id, &file_path, &scan_store, &storage) {
+        return None;
+    }
+    send_progress(&scan_id, 10, "Reading file content...", &scan_store);
+
+    let mut progress = |percent: u32, message: &str| {
+        if check_cancelled(&scan_id, &file_path, &scan_store, &storage) {
+            return false;
+        }
+        send_progress(&scan_id, percent, message, &scan_store);
+        true
+    };
+
+    let analysis = if options.sandbox {
+        // set by the cancel endpoint and by `fail_scan` on timeout
+        let stop = scan_store
+            .lock()
+            .unwrap()
+            .get(&scan_id)
+            .map(|result| result.cancel_flag.clone())
+            .unwrap_or_default();
+        match run_sandboxed(&file_path, &rules, options.parallel, &stop, &mut progress) {
+            Ok(Some(analysis)) => analysis,
+            Ok(None) => {
+                check_cancelled(&scan_id, &file_path, &scan_store, &storage);
+                return None;
+            }
+            Err(e) => {
+                fail_scan(
+                    &scan_id,
+                    &file_path,
+                    &scan_store,
+                    &storage,
+                    "error",
+                    &format!("Sandboxed analysis failed: {}", e),
+                );
+                return None;
+            }
+        }
+    } else {
+        let content = match map_file(&file_path) {
+            Ok(c) => c,
+            Err(e) => {
+                fail_scan(
+                    &scan_id,
+                    &file_path,
+                    &scan_store,
+                    &storage,
+                    "error",
+                    &format!("Error reading file: {}", e),
+                );
+                return None;
+            }
+        };
+        analyze(&content, &rules, options.parallel, &mut progress)?
+    };
+
+    let threats = analysis.threats;
+    let malicious_count = threats.iter().filter(|t| t.severity == "malicious").count();
+    let suspicious_count = threats
+        .iter()
+        .filter(|t| t.severity == "suspicious")
+        .count();
+    let neutral_count = threats.iter().filter(|t| t.severity == "neutral").count();
+
+    // Only mark as "unsafe" if there are malicious indicators
+    let status = if malicious_count > 0 {
+        "unsafe"
+    } else if suspicious_count > 0 || neutral_count > 0 {
+        "suspicious"
+    } else {
+        "safe"
+    };
+
+    let imported_functions: Vec<String> = analysis
+        .imports
+        .iter()
+        .flat_map(|i| i.functions.iter().cloned())
+        .collect();
+    let score = threat_score(&threats, analysis.entropy, &imported_functions);
+
+    let (logs, created_at) = {
+        let store = scan_store.lock().unwrap();
+        store
+            .get(&scan_id)
+            .map(|r| (r.logs.clone(), r.created_at.clone()))
+            .unwrap_or_default()
+    };
+
+    file_info.imphash = analysis.imphash;
+    let mut result = ScanResult {
+        status: status.to_string(),
+        verdict: Some(verdict(score, options.verdict).to_string()),
+        stats: ScanStats {
+            threats_found: threats.len(),
+            malicious: malicious_count,
+            suspicious: suspicious_count,
+            neutral: neutral_count,
+            score,
+        },
+        threats,
+        logs,
+        file_info: Some(file_info),
+        pe_info: analysis.pe_info,
+        imports: analysis.imports,
+        resources: analysis.resources,
+        signed: analysis.signed,
+        certificate_size: analysis.certificate_size,
+        overlay_size: analysis.overlay_size,
+        entropy: analysis.entropy,
+        zero_ratio: analysis.zero_ratio,
+        guids: analysis.guids,
+        strings: analysis.strings,
+        strings_count: analysis.strings_count,
+        family: analysis.family,
+        packer: analysis.packer,
+        result_hash: None,
+        created_at,
+        cancel_flag: Default::default(),
+    };
+    result.result_hash = Some(result_hash(&result));
+
+    Some(result)
+}
+
+// the analysis runs on a worker thread so a pathological file can't hold
+// the scan open past its timeout
+#[allow(clippy::too_many_arguments)]
+pub fn scan_file(
+    file_path: PathBuf,
+    file_info: FileInfo,
+    scan_id: String,
+    scan_store: ScanStore,
+    storage: SharedStorage,
+    rules: Arc<RuleSet>,
+    stats: SharedStats,
+    metrics: SharedMetrics,
+    options: ScanOptions,
+) {
+    thread::spawn(move || {
+        supervise_scan(
+            file_path,
+            file_info,
+            scan_id.clone(),
+            scan_store.clone(),
+            storage,
+            rules,
+            stats,
+            options,
+        );
+        let status = scan_store
+            .lock()
+            .unwrap()
+            .get(&scan_id)
+            .map(|result| result.status.clone());
+        metrics.lock().unwrap().scan_finished(status);
+    });
+}
+
+// on shutdown: waits up to `grace` for running scans to finish, then marks
+// the rest cancelled so their stored status isn't left at "scanning"
+pub fn drain_scans(
+    scan_store: &ScanStore,
+    storage: &SharedStorage,
+    upload_dir: &Path,
+    grace: Duration,
+) {
+    let running = || -> Vec<(String, Option<FileInfo>)> {
+        scan_store
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, result)| result.status == "scanning")
+            .map(|(id, result)| (id.clone(), result.file_info.clone()))
+            .collect()
+    };
+
+    let deadline = Instant::now() + grace;
+    let mut pending = running();
+    while !pending.is_empty() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(100));
+        pending = running();
+    }
+
+    for (scan_id, file_info) in pending {
+        let filename = file_info.map(|info| info.filename).unwrap_or_default();
+        fail_scan(
+            &scan_id,
+            &sample_path(upload_dir, &scan_id, &filename),
+            scan_store,
+            storage,
+            "cancelled",
+            "Scan cancelled by server shutdown",
+        );
+    }
+}
+
+// removes everything in the upload directory except retained samples of
+// finished scans, such as files left by scans that never completed
+pub fn clean_upload_dir(scan_store: &ScanStore, upload_dir: &Path, retain_samples: bool) {
+    let keep: HashSet<PathBuf> = if retain_samples {
+        scan_store
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, result)| result.status != "scanning")
+            .filter_map(|(id, result)| {
+                let info = result.file_info.as_ref()?;
+                Some(sample_path(upload_dir, id, &info.filename))
+            })
+            .collect()
+    } else {
+        HashSet::new()
+    };
+
+    let Ok(entries) = fs::read_dir(upload_dir) else {
+        return;
+    };
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.is_file() && !keep.contains(&path) {
+            println!("Removing orphaned upload {:?}", path);
+            let _ = fs::remove_file(&path);
+        }
+    }
+}
+
+// waits for the scan worker under the scan timeout and stores whatever it produced
+#[allow(clippy::too_many_arguments)]
+fn supervise_scan(
+    file_path: PathBuf,
+    file_info: FileInfo,
+    scan_id: String,
+    scan_store: ScanStore,
+    storage: SharedStorage,
+    rules: Arc<RuleSet>,
+    stats: SharedStats,
+    options: ScanOptions,
+) {
+    let (tx, rx) = mpsc::channel();
+    {
+        let file_path = file_path.clone();
+        let scan_id = scan_id.clone();
+        let scan_store = scan_store.clone();
+        let storage = storage.clone();
+        thread::spawn(move || {
+            let _ = tx.send(run_scan(
+                file_path, file_info, scan_id, scan_store, storage, rules, options,
+            ));
+        });
+    }
+
+    let result = match rx.recv_timeout(options.timeout) {
+        Ok(Some(result)) => result,
+        // cancelled or failed, the worker already updated the store
+        Ok(None) => return,
+        Err(RecvTimeoutError::Timeout) => {
+            fail_scan(
+                &scan_id,
+                &file_path,
+                &scan_store,
+                &storage,
+                "timeout",
+                &format!("Scan timed out after {:?}", options.timeout),
+            );
+            return;
+        }
+        Err(RecvTimeoutError::Disconnected) => {
+            fail_scan(
+                &scan_id,
+                &file_path,
+                &scan_store,
+                &storage,
+                "error",
+                "Scan worker exited unexpectedly",
+            );
+            return;
+        }
+    };
+
+    if check_cancelled(&scan_id, &file_path, &scan_store, &storage) {
+        return;
+    }
+
+    // cleaned up before the result goes in, so a finished scan never still
+    // has a sample on disk that isn't meant to be retained
+    if !options.retain_samples {
+        let _ = fs::remove_file(&file_path);
+    }
+    let status = result.status.clone();
+    {
+        let mut store = scan_store.lock().unwrap();
+        store.insert(scan_id.clone(), result);
+    }
+    persist(&storage, &scan_store, &scan_id);
+    record_event(&stats, StatsEvent::Completion(status));
+
+    if options.retain_samples {
+        println!("Scan complete for {}, sample retained", scan_id);
+    } else {
+        println!("Scan complete for {}, file cleaned up", scan_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::{builtin_rules, parse_rules};
+    use crate::testing::{
+        noise, peak_heap, pkcs7_signed_data, resource_section, scan_result, temp_dir,
+        win_certificate, zip_archive, PeBuilder, FIRST_EXTRA_RVA, SECTION_READ_ONLY,
+    };
+    use std::collections::HashMap;
+    use std::io::Write;
+    use std::sync::Mutex;
+
+    fn file_info(filename: &str) -> FileInfo {
+        FileInfo {
+            filename: filename.to_string(),
+            size: 0,
+            sha256: String::new(),
+            md5: String::new(),
+            sha1: String::new(),
+            imphash: None,
+            authentihash: None,
+        }
+    }
+
+    // scans `content` to completion or timeout and returns the stored result
+    // and whether the sample was left on disk
+    fn supervise(content: &[u8], timeout: Duration) -> (ScanResult, bool) {
+        supervise_with(content, timeout, |_| {})
+    }
+
+    // as `supervise`, with `prepare` applied to the stored result first
+    fn supervise_with(
+        content: &[u8],
+        timeout: Duration,
+        prepare: impl FnOnce(&mut ScanResult),
+    ) -> (ScanResult, bool) {
+        let dir = temp_dir();
+        let path = dir.join("sample.bin");
+        fs::write(&path, content).unwrap();
+
+        let scan_store: ScanStore = Arc::new(Mutex::new(HashMap::new()));
+        let mut pending = scan_result("scanning");
+        prepare(&mut pending);
+        scan_store
+            .lock()
+            .unwrap()
+            .insert("scan-test".to_string(), pending);
+        let options = ScanOptions {
+            sandbox: false,
+            parallel: false,
+            verdict: VerdictThresholds {
+                suspicious: DEFAULT_SUSPICIOUS_SCORE,
+                malicious: DEFAULT_MALICIOUS_SCORE,
+            },
+            timeout,
+            retain_samples: false,
+        };
+        supervise_scan(
+            path.clone(),
+            file_info("sample.bin"),
+            "scan-test".to_string(),
+            scan_store.clone(),
+            Arc::new(MemoryStore::default()),
+            Arc::new(RuleSet::default()),
+            Arc::new(Mutex::new(StatsTracker::default())),
+            options,
+        );
+        let result = scan_store.lock().unwrap()["scan-test"].clone();
+        let retained = path.exists();
+        let _ = fs::remove_dir_all(&dir);
+        (result, retained)
+    }
+
+    #[test]
+    fn slow_scan_ends_in_timeout() {
+        // 16MB of noise keeps the string and entropy phases busy well past 1ms
+        let content: Vec<u8> = (0..16 * 1024 * 1024u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        let (result, retained) = supervise(&content, Duration::from_millis(1));
+        assert_eq!(result.status, "timeout");
+        assert_eq!(result.logs.last().unwrap(), "Scan timed out after 1ms");
+        assert!(result.cancel_flag.load(Ordering::SeqCst));
+        assert!(!retained);
+    }
+
+    #[test]
+    fn fast_scan_completes() {
+        let (result, _) = supervise(b"just some text", SCAN_TIMEOUT);
+        assert_eq!(result.status, "safe");
+        assert!(result.result_hash.is_some());
+    }
+
+    fn analyze_with(content: &[u8], rules: &RuleSet) -> Analysis {
+        analyze(content, rules, false, &mut |_, _| true).unwrap()
+    }
+
+    #[test]
+    fn known_bad_guid_from_the_rules_is_flagged() {
+        let rules =
+            parse_rules(r#"{"rules": [], "guids": ["{3f2504e0-4f89-11d3-9a0c-0305e82c3301}"]}"#)
+                .unwrap();
+        let content = b"build id 3F2504E0-4F89-11D3-9A0C-0305E82C3301 and 6B29FC40-CA47-1067-B31D-00DD010662DA";
+        let analysis = analyze_with(content, &rules);
+        assert_eq!(analysis.guids.len(), 2);
+        let flagged: Vec<&Threat> = analysis
+            .threats
+            .iter()
+            .filter(|t| t.threat_id == "S004")
+            .collect();
+        assert_eq!(flagged.len(), 1);
+        assert!(flagged[0]
+            .details
+            .contains("3F2504E0-4F89-11D3-9A0C-0305E82C3301"));
+        assert_eq!(flagged[0].severity, "malicious");
+
+        // nothing is listed without a rules file entry
+        let analysis = analyze_with(content, &RuleSet::default());
+        assert!(analysis.threats.iter().all(|t| t.threat_id != "S004"));
+    }
+
+    #[test]
+    fn unsigned_executable_gets_a_neutral_note() {
+        let pe = PeBuilder::new()
+            .import("kernel32.dll", &["ExitProcess"])
+            .build();
+        let analysis = analyze_with(&pe, &RuleSet::default());
+        let unsigned: Vec<&Threat> = analysis
+            .threats
+            .iter()
+            .filter(|t| t.threat_id == "P009")
+            .collect();
+        assert_eq!(unsigned.len(), 1);
+        assert_eq!(unsigned[0].severity, "neutral");
+        // like any finding, a neutral note keeps the scan from being "safe"
+        assert_eq!(supervise(&pe, SCAN_TIMEOUT).0.status, "suspicious");
+    }
+
+    #[test]
+    fn status_follows_the_worst_severity_found() {
+        let status = |content: &[u8]| {
+            let (result, _) = supervise(content, SCAN_TIMEOUT);
+            let mut severities: Vec<String> =
+                result.threats.iter().map(|t| t.severity.clone()).collect();
+            severities.sort();
+            severities.dedup();
+            (result.status, severities)
+        };
+        assert_eq!(status(b"plain text"), ("safe".to_string(), vec![]));
+
+        let notes_only = PeBuilder::new()
+            .import("kernel32.dll", &["ExitProcess"])
+            .build();
+        let (notes_status, severities) = status(&notes_only);
+        assert_eq!(severities, ["neutral"]);
+        assert_eq!(notes_status, "suspicious");
+
+        let (suspicious, severities) = status(b"\0vssadmin delete shadows /all\0");
+        assert_eq!(severities, ["suspicious"]);
+        assert_eq!(suspicious, "suspicious");
+
+        let injecting = PeBuilder::new()
+            .import("ntdll.dll", &["NtCreateThreadEx", "NtMapViewOfSection"])
+            .build();
+        let (unsafe_status, severities) = status(&injecting);
+        assert!(severities.contains(&"malicious".to_string()));
+        assert_eq!(unsafe_status, "unsafe");
+    }
+
+    #[test]
+    fn uncommon_dll_gets_a_neutral_note() {
+        let pe = PeBuilder::new()
+            .import("kernel32.dll", &["ExitProcess"])
+            .import("helper.dll", &["DoWork"])
+            .build();
+        let analysis = analyze_with(&pe, &RuleSet::default());
+        let note = analysis.threats.iter().find(|t| t.threat_id == "P006");
+        assert_eq!(note.unwrap().severity, "neutral");
+        assert_eq!(supervise(&pe, SCAN_TIMEOUT).0.status, "suspicious");
+    }
+
+    #[test]
+    fn hashed_api_resolution_is_malicious() {
+        // lodsb; test al, al; jz done; ror edx, 13; add edx, eax; jmp back; ret
+        let code = [
+            0xac, 0x84, 0xc0, 0x74, 0x07, 0xc1, 0xca, 0x0d, 0x01, 0xc2, 0xeb, 0xf4, 0xc3,
+        ];
+        let pe = PeBuilder::new()
+            .import("kernel32.dll", &["LoadLibraryA", "GetProcAddress"])
+            .text(&code)
+            .build();
+        let analysis = analyze_with(&pe, &RuleSet::default());
+        let hashing: Vec<_> = analysis
+            .threats
+            .iter()
+            .filter(|t| t.threat_id == "S009")
+            .collect();
+        assert_eq!(hashing.len(), 1);
+        assert_eq!(hashing[0].severity, "malicious");
+        assert!(hashing[0].details.contains("LoadLibraryA, GetProcAddress"));
+
+        let plain = PeBuilder::new()
+            .import("kernel32.dll", &["LoadLibraryA", "GetProcAddress"])
+            .build();
+        let analysis = analyze_with(&plain, &RuleSet::default());
+        assert!(analysis.threats.iter().all(|t| t.threat_id != "S009"));
+    }
+
+    fn threat(threat_id: &str, severity: &str) -> Threat {
+        Threat {
+            threat_type: String::new(),
+            details: String::new(),
+            severity: severity.to_string(),
+            threat_id: threat_id.to_string(),
+        }
+    }
+
+    #[test]
+    fn packer_with_high_entropy_scores_higher() {
+        let both = [
+            threat(PACKER_DETECTED_ID, "suspicious"),
+            threat(HIGH_ENTROPY_SECTION_ID, "suspicious"),
+        ];
+        let apart = threat_score(&both[..1], 0.0, &[]) + threat_score(&both[1..], 0.0, &[]);
+        let together = threat_score(&both, 0.0, &[]);
+        assert_eq!(together, apart + SCORE_PACKED_ENTROPY_BONUS);
+    }
+
+    #[test]
+    fn pe_that_is_also_a_zip_is_a_polyglot() {
+        let zip = zip_archive(&[("payload.txt", b"second format")]);
+        let pe = PeBuilder::new().overlay(&zip).build();
+        let analysis = analyze_with(&pe, &RuleSet::default());
+        let polyglot = analysis
+            .threats
+            .iter()
+            .find(|t| t.threat_id == "P013")
+            .expect("no polyglot threat");
+        assert_eq!(polyglot.severity, "suspicious");
+        assert_eq!(polyglot.details, "File is valid as PE and ZIP");
+    }
+
+    #[test]
+    fn pe_info_is_reported_or_left_out_with_a_log_line() {
+        let pe = PeBuilder::new().build();
+        let (result, _) = supervise(&pe, SCAN_TIMEOUT);
+        assert_eq!(result.pe_info.unwrap().machine, "x86");
+
+        let (result, _) = supervise(&pe[..0xa0], SCAN_TIMEOUT);
+        assert!(result.pe_info.is_none());
+        assert!(result
+            .logs
+            .iter()
+            .any(|line| line.contains("Failed to parse PE headers: Truncated optional header")));
+    }
+
+    #[test]
+    fn native_api_imports_are_reported() {
+        let native = |threats: &[Threat]| {
+            threats
+                .iter()
+                .find(|t| t.threat_id == "S010")
+                .map(|t| t.severity.clone())
+        };
+        let file = PeBuilder::new()
+            .import("ntdll.dll", &["NtCreateThreadEx", "NtMapViewOfSection"])
+            .build();
+        let analysis = analyze_with(&file, &RuleSet::default());
+        assert_eq!(native(&analysis.threats).as_deref(), Some("malicious"));
+
+        let file = PeBuilder::new()
+            .import("ntdll.dll", &["NtQueryInformationProcess"])
+            .build();
+        let analysis = analyze_with(&file, &RuleSet::default());
+        assert_eq!(native(&analysis.threats).as_deref(), Some("suspicious"));
+    }
+
+    #[test]
+    fn large_mapped_samples_are_analyzed_in_bounded_memory() {
+        let dir = temp_dir();
+        let path = dir.join("large.exe");
+        let mut file = fs::File::create(&path).unwrap();
+        file.write_all(
+            &PeBuilder::new()
+                .import("kernel32.dll", &["ExitProcess"])
+                .build(),
+        )
+        .unwrap();
+        file.set_len(64 * 1024 * 1024).unwrap();
+        drop(file);
+
+        let (analysis, heap) = peak_heap(|| {
+            let content = map_file(&path).unwrap();
+            analyze_with(&content, &RuleSet::default())
+        });
+        assert_eq!(analysis.imports[0].functions, ["ExitProcess"]);
+        assert!(analysis.overlay_size > 60 * 1024 * 1024);
+        assert!(heap < 8 * 1024 * 1024, "{} bytes of heap", heap);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn data_hidden_between_sections_is_reported() {
+        let mut text = vec![0xc3; 0x100];
+        text.extend(noise(0x100));
+        let builder = PeBuilder::new().text(&text);
+        let mut file = builder.build();
+        // .text's header claims only the code, leaving the noise to no section
+        let raw_size = builder.section_header_offset(0) + 16;
+        file[raw_size..raw_size + 4].copy_from_slice(&0x100u32.to_le_bytes());
+
+        let analysis = analyze_with(&file, &RuleSet::default());
+        let hidden = analysis.threats.iter().find(|t| t.threat_id == "P015");
+        assert!(hidden
+            .unwrap()
+            .details
+            .starts_with("Data between sections at 0x300-0x400 after .text"));
+        let clean = analyze_with(&builder.build(), &RuleSet::default());
+        assert!(!clean.threats.iter().any(|t| t.threat_id == "P015"));
+    }
+
+    #[test]
+    fn injection_rule_fires_on_imports_not_text() {
+        let rules = RuleSet {
+            rules: builtin_rules(),
+            ..Default::default()
+        };
+        let injection =
+            |analysis: &Analysis| analysis.threats.iter().any(|t| t.threat_id == "S002");
+
+        let imported = PeBuilder::new()
+            .import("kernel32.dll", &["VirtualAllocEx", "CreateRemoteThread"])
+            .build();
+        let analysis = analyze_with(&imported, &rules);
+        assert!(injection(&analysis));
+        assert_eq!(analysis.imports[0].functions.len(), 2);
+
+        // the same names as plain data don't count
+        let mentioned = PeBuilder::new()
+            .section(
+                ".rdata",
+                b"VirtualAllocEx\0CreateRemoteThread\0",
+                SECTION_READ_ONLY,
+            )
+            .build();
+        assert!(!injection(&analyze_with(&mentioned, &rules)));
+    }
+
+    #[test]
+    fn whole_file_entropy_is_reported() {
+        let analysis = analyze_with(&noise(16 * 1024), &RuleSet::default());
+        assert!(analysis.entropy > 7.9, "{}", analysis.entropy);
+        let analysis = analyze_with(&[0u8; 1024], &RuleSet::default());
+        assert_eq!(analysis.entropy, 0.0);
+    }
+
+    #[test]
+    fn self_signed_sample_gets_a_neutral_note() {
+        let blob = pkcs7_signed_data(&[("Acme Dev", "Acme Dev")]);
+        let pe = PeBuilder::new()
+            .certificate(&win_certificate(&blob, 0x200, 2))
+            .checksum()
+            .build();
+        let analysis = analyze_with(&pe, &RuleSet::default());
+        let info = analysis.pe_info.as_ref().unwrap();
+        assert_eq!(info.signature_trust.as_deref(), Some("self-signed"));
+        let note = analysis
+            .threats
+            .iter()
+            .find(|t| t.threat_id == "P004")
+            .expect("no self-signed note");
+        assert_eq!(note.severity, "neutral");
+        assert_eq!(supervise(&pe, SCAN_TIMEOUT).0.status, "suspicious");
+    }
+
+    #[test]
+    fn regex_rules_run_on_wide_strings() {
+        let rules = parse_rules(
+            r#"{"rules": [{"id": "R001", "type": "URL", "details": "d", "severity": "suspicious",
+                "regex": ["http://[a-z.]+"]}]}"#,
+        )
+        .unwrap();
+        let url: Vec<u8> = "http://c2.example".bytes().flat_map(|b| [b, 0]).collect();
+        let analysis = analyze_with(&[b"\x00\x01".to_vec(), url].concat(), &rules);
+        assert!(analysis.threats.iter().any(|t| t.threat_id == "R001"));
+        assert!(analysis.strings.ascii.is_empty());
+    }
+
+    #[test]
+    fn cancelled_scan_stops_and_removes_the_sample() {
+        let pe = PeBuilder::new().build();
+        // the cancel endpoint set the flag after the upload was accepted
+        let (result, retained) = supervise_with(&pe, SCAN_TIMEOUT, |pending| {
+            pending.cancel_flag.store(true, Ordering::SeqCst)
+        });
+        assert_eq!(result.status, "cancelled");
+        assert_eq!(result.logs.last().unwrap(), "Scan cancelled");
+        assert!(result.threats.is_empty());
+        assert!(!retained);
+    }
+
+    #[test]
+    fn cancel_flag_is_checked_between_phases() {
+        let scan_store: ScanStore = Arc::new(Mutex::new(HashMap::new()));
+        let storage: SharedStorage = Arc::new(MemoryStore::default());
+        let dir = temp_dir();
+        let path = dir.join("sample.bin");
+        fs::write(&path, b"sample").unwrap();
+        let result = scan_result("scanning");
+        let flag = result.cancel_flag.clone();
+        scan_store
+            .lock()
+            .unwrap()
+            .insert("scan-test".to_string(), result);
+
+        assert!(!check_cancelled("scan-test", &path, &scan_store, &storage));
+        assert!(path.exists());
+
+        flag.store(true, Ordering::SeqCst);
+        assert!(check_cancelled("scan-test", &path, &scan_store, &storage));
+        assert!(!path.exists());
+        assert_eq!(scan_store.lock().unwrap()["scan-test"].status, "cancelled");
+        let persisted = storage.list().unwrap();
+        assert_eq!(persisted[0].1.status, "cancelled");
+
+        // once ended, later checks just stop without logging again
+        assert!(check_cancelled("scan-test", &path, &scan_store, &storage));
+        assert_eq!(scan_store.lock().unwrap()["scan-test"].logs.len(), 2);
+        assert!(check_cancelled(
+            "scan-missing",
+            &path,
+            &scan_store,
+            &storage
+        ));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn sample_matching_a_family_signature_is_tagged() {
+        let rules = parse_rules(
+            r#"{"rules": [], "families": [{"name": "Ladybird",
+                "mutexes": ["LdbMutex_7"], "strings": ["ladybird-gate.php"]}]}"#,
+        )
+        .unwrap();
+        let pe = PeBuilder::new()
+            .section(
+                ".data",
+                b"\0LdbMutex_7\0\0http://c2.example/ladybird-gate.php\0",
+                SECTION_READ_ONLY,
+            )
+            .build();
+        let family = analyze_with(&pe, &rules).family.unwrap();
+        assert_eq!(family.name, "Ladybird");
+        assert_eq!(family.confidence, 1.0);
+        assert_eq!(
+            family.evidence,
+            ["mutex LdbMutex_7", "string \"ladybird-gate.php\""]
+        );
+
+        assert!(analyze_with(&PeBuilder::new().build(), &rules)
+            .family
+            .is_none());
+    }
+
+    #[test]
+    fn shadow_copy_deletion_is_flagged_in_a_scan() {
+        let pe = PeBuilder::new()
+            .import("KERNEL32.dll", &["CreateProcessW"])
+            .section(
+                ".rdata",
+                b"\0vssadmin delete shadows /all /quiet\0",
+                SECTION_READ_ONLY,
+            )
+            .build();
+        let analysis = analyze_with(&pe, &RuleSet::default());
+        let flagged: Vec<&Threat> = analysis
+            .threats
+            .iter()
+            .filter(|t| t.threat_id == "S007")
+            .collect();
+        assert_eq!(flagged.len(), 1);
+        assert_eq!(flagged[0].severity, "suspicious");
+    }
+
+    #[test]
+    fn reported_strings_are_capped_but_all_are_analyzed() {
+        let mut data = Vec::new();
+        for i in 0..MAX_REPORTED_STRINGS + 500 {
+            data.extend_from_slice(format!("filler string {}\0", i).as_bytes());
+        }
+        data.extend_from_slice(&[b'A'; MAX_REPORTED_STRING_LENGTH * 2]);
+        data.push(0);
+        // only past the reporting cap
+        data.extend_from_slice(b"vssadmin delete shadows /all\0");
+
+        let analysis = analyze_with(&data, &RuleSet::default());
+        assert_eq!(analysis.strings.ascii.len(), MAX_REPORTED_STRINGS);
+        assert!(analysis.strings_count > MAX_REPORTED_STRINGS + 500);
+        assert!(analysis
+            .strings
+            .ascii
+            .iter()
+            .all(|s| s.chars().count() <= MAX_REPORTED_STRING_LENGTH + 1));
+        assert!(analysis.threats.iter().any(|t| t.threat_id == "S007"));
+
+        let long = vec![b'A'; MAX_REPORTED_STRING_LENGTH * 2];
+        let analysis = analyze_with(&long, &RuleSet::default());
+        let reported = &analysis.strings.ascii[0];
+        assert!(reported.ends_with('…'));
+        assert_eq!(reported.chars().count(), MAX_REPORTED_STRING_LENGTH + 1);
+    }
+
+    #[test]
+    fn zipped_sample_is_scanned_member_by_member() {
+        let pe = PeBuilder::new()
+            .section(
+                ".rdata",
+                b"\0vssadmin delete shadows /all\0",
+                SECTION_READ_ONLY,
+            )
+            .build();
+        let inner = zip_archive(&[("bin/dropper.exe", &pe)]);
+        let outer = zip_archive(&[("invoice.zip", &inner), ("notes.txt", b"nothing here")]);
+
+        let analysis = analyze_with(&outer, &RuleSet::default());
+        // the stored zip's own bytes match too, without a member path
+        let from_member: Vec<&Threat> = analysis
+            .threats
+            .iter()
+            .filter(|t| t.details.starts_with("invoice.zip/bin/dropper.exe: "))
+            .collect();
+        assert!(from_member.iter().any(|t| t.threat_id == "S007"));
+        assert!(analysis
+            .threats
+            .iter()
+            .all(|t| !t.details.starts_with("notes.txt")));
+    }
+
+    #[test]
+    fn zero_padded_pe_is_flagged_as_sparse() {
+        let pe = PeBuilder::new()
+            .section(".data", &vec![0; 2 * SPARSE_MIN_SIZE], SECTION_READ_ONLY)
+            .build();
+        let analysis = analyze_with(&pe, &RuleSet::default());
+        assert!(analysis.zero_ratio > SPARSE_SUSPICIOUS_RATIO);
+        // padding drags the whole-file entropy down with it
+        assert!(analysis.entropy < 0.5);
+        let sparse = analysis
+            .threats
+            .iter()
+            .find(|t| t.threat_id == "P007")
+            .unwrap();
+        assert_eq!(sparse.severity, "suspicious");
+    }
+
+    fn keyword_rule() -> RuleSet {
+        parse_rules(
+            r#"{"rules": [{"id": "K001", "type": "Keyword", "details": "d", "severity": "suspicious",
+                "matches": ["keylogger"], "logic": "or", "target": "content"}]}"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn utf16le_keywords_are_detected() {
+        let keyword: Vec<u8> = "keylogger.dll".bytes().flat_map(|b| [b, 0]).collect();
+        let analysis = analyze_with(&[b"\x90\x90".to_vec(), keyword].concat(), &keyword_rule());
+        assert!(analysis.threats.iter().any(|t| t.threat_id == "K001"));
+        assert_eq!(analysis.strings.wide, ["keylogger.dll"]);
+        assert_eq!(analysis.strings_count, 1);
+    }
+
+    #[test]
+    fn keywords_split_by_binary_garbage_do_not_match() {
+        // lossy UTF-8 over the raw bytes used to join these into a match
+        let analysis = analyze_with(b"\x01key\x00logger\x02", &keyword_rule());
+        assert!(analysis.threats.iter().all(|t| t.threat_id != "K001"));
+        let analysis = analyze_with(b"\x01keylogger\x02", &keyword_rule());
+        assert!(analysis.threats.iter().any(|t| t.threat_id == "K001"));
+    }
+
+    #[test]
+    fn scan_result_counts_ascii_and_wide_strings() {
+        let wide: Vec<u8> = "wide string".bytes().flat_map(|b| [b, 0]).collect();
+        let content = [b"first ascii\0second ascii\0".to_vec(), wide].concat();
+        let (result, _) = supervise(&content, SCAN_TIMEOUT);
+        assert_eq!(result.strings_count, 3);
+    }
+
+    #[test]
+    fn score_weighs_threats_by_severity() {
+        assert_eq!(threat_score(&[], 0.0, &[]), 0);
+        let mixed = [
+            threat("S001", "malicious"),
+            threat("S002", "suspicious"),
+            threat("P006", "neutral"),
+            threat("X001", "unknown"),
+        ];
+        assert_eq!(
+            threat_score(&mixed, 0.0, &[]),
+            SCORE_MALICIOUS_WEIGHT + SCORE_SUSPICIOUS_WEIGHT + SCORE_NEUTRAL_WEIGHT
+        );
+        let notes = [threat("P006", "neutral"), threat("P010", "neutral")];
+        assert_eq!(threat_score(&notes, 0.0, &[]), 2 * SCORE_NEUTRAL_WEIGHT);
+        // three malicious findings are already past the cap
+        let malicious = vec![threat("S001", "malicious"); 3];
+        assert_eq!(threat_score(&malicious, 0.0, &[]), 100);
+    }
+
+    #[test]
+    fn score_adds_entropy_and_import_bonuses() {
+        let suspicious = [threat("S002", "suspicious")];
+        assert_eq!(
+            threat_score(&suspicious, HIGH_ENTROPY_THRESHOLD + 0.1, &[]),
+            SCORE_SUSPICIOUS_WEIGHT + SCORE_HIGH_ENTROPY_BONUS
+        );
+        assert_eq!(
+            threat_score(&suspicious, HIGH_ENTROPY_THRESHOLD, &[]),
+            SCORE_SUSPICIOUS_WEIGHT
+        );
+
+        let imports: Vec<String> = ["VirtualAllocEx", "WriteProcessMemory", "GetTickCount"]
+            .iter()
+            .map(|f| f.to_string())
+            .collect();
+        assert_eq!(
+            threat_score(&[], 0.0, &imports),
+            2 * SCORE_DANGEROUS_IMPORT_BONUS
+        );
+        let many: Vec<String> = DANGEROUS_IMPORTS
+            .iter()
+            .map(|f| format!("{}W", f))
+            .collect();
+        assert!(many.len() as u32 * SCORE_DANGEROUS_IMPORT_BONUS > SCORE_DANGEROUS_IMPORT_CAP);
+        assert_eq!(threat_score(&[], 0.0, &many), SCORE_DANGEROUS_IMPORT_CAP);
+    }
+
+    #[test]
+    fn verdict_follows_the_configured_thresholds() {
+        let defaults = VerdictThresholds {
+            suspicious: DEFAULT_SUSPICIOUS_SCORE,
+            malicious: DEFAULT_MALICIOUS_SCORE,
+        };
+        assert_eq!(verdict(0, defaults), "clean");
+        assert_eq!(verdict(DEFAULT_SUSPICIOUS_SCORE - 1, defaults), "clean");
+        assert_eq!(verdict(DEFAULT_SUSPICIOUS_SCORE, defaults), "suspicious");
+        assert_eq!(verdict(DEFAULT_MALICIOUS_SCORE, defaults), "malicious");
+        assert_eq!(verdict(100, defaults), "malicious");
+
+        let strict = VerdictThresholds {
+            suspicious: 1,
+            malicious: 10,
+        };
+        assert_eq!(verdict(SCORE_NEUTRAL_WEIGHT, strict), "suspicious");
+        assert_eq!(verdict(SCORE_SUSPICIOUS_WEIGHT, strict), "malicious");
+    }
+
+    #[test]
+    fn scan_result_carries_score_and_verdict() {
+        let content = b"\0vssadmin delete shadows /all\0";
+        let (result, _) = supervise(content, SCAN_TIMEOUT);
+        assert_eq!(result.stats.score, SCORE_SUSPICIOUS_WEIGHT);
+        let defaults = VerdictThresholds {
+            suspicious: DEFAULT_SUSPICIOUS_SCORE,
+            malicious: DEFAULT_MALICIOUS_SCORE,
+        };
+        assert_eq!(
+            result.verdict.as_deref(),
+            Some(verdict(SCORE_SUSPICIOUS_WEIGHT, defaults))
+        );
+    }
+
+    #[test]
+    fn socket_imports_and_an_embedded_ip_are_raw_network_c2() {
+        let pe = PeBuilder::new()
+            .import("ws2_32.dll", &["WSAStartup", "socket", "connect", "send"])
+            .section(".rdata", b"\x00203.0.113.77\x00", SECTION_READ_ONLY)
+            .build();
+        let analysis = analyze_with(&pe, &RuleSet::default());
+        let network = analysis
+            .threats
+            .iter()
+            .find(|t| t.threat_id == "S008")
+            .unwrap();
+        assert_eq!(network.severity, "malicious");
+        assert!(network.details.ends_with("hardcoded IPs: 203.0.113.77"));
+    }
+
+    #[test]
+    fn overlay_size_is_reported_without_the_signature() {
+        let payload = noise(8 * 1024);
+        let pe = PeBuilder::new()
+            .certificate(&win_certificate(&[0x30; 64], 0x200, 2))
+            .overlay(&payload)
+            .build();
+        let analysis = analyze_with(&pe, &RuleSet::default());
+        assert_eq!(analysis.overlay_size, payload.len() as u64);
+        assert!(analysis.threats.iter().any(|t| t.threat_id == "P010"));
+        let (result, _) = supervise(&pe, SCAN_TIMEOUT);
+        assert_eq!(result.overlay_size, payload.len() as u64);
+
+        let plain = analyze_with(&PeBuilder::new().build(), &RuleSet::default());
+        assert_eq!(plain.overlay_size, 0);
+        assert!(plain.threats.iter().all(|t| t.threat_id != "P010"));
+    }
+
+    #[test]
+    fn pe_in_a_resource_is_reported() {
+        let payload = PeBuilder::new().build();
+        let tree = resource_section(
+            FIRST_EXTRA_RVA,
+            &[
+                (3, "#1", 1033, b"\x89PNG icon"),
+                (10, "STAGE2", 0, &payload),
+            ],
+        );
+        let pe = PeBuilder::new()
+            .section(".rsrc", &tree, SECTION_READ_ONLY)
+            .directory(
+                IMAGE_DIRECTORY_ENTRY_RESOURCE,
+                FIRST_EXTRA_RVA,
+                tree.len() as u32,
+            )
+            .build();
+        let analysis = analyze_with(&pe, &RuleSet::default());
+        assert_eq!(analysis.resources.len(), 2);
+        let embedded = analysis
+            .threats
+            .iter()
+            .find(|t| t.threat_id == "P011")
+            .unwrap();
+        assert!(
+            embedded.details.ends_with("RCDATA/STAGE2 (1536 bytes)"),
+            "{}",
+            embedded.details
+        );
+    }
+}
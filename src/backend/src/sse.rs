@@ -0,0 +1,298 @@
+use crate::config::*;
+use crate::types::{ProgressUpdate, ScanStore};
+use std::collections::VecDeque;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// bounded hand-off between the store poller and one SSE connection; pushes
+// never block, so a slow client only ever loses intermediate progress events
+pub struct SseBuffer {
+    state: Mutex<SseState>,
+    ready: Condvar,
+}
+
+#[derive(Default)]
+struct SseState {
+    events: VecDeque<ProgressUpdate>,
+    closed: bool,
+}
+
+impl SseBuffer {
+    pub fn new() -> Self {
+        SseBuffer {
+            state: Mutex::new(SseState::default()),
+            ready: Condvar::new(),
+        }
+    }
+
+    // when full, the newest queued event is replaced so the client still
+    // catches up to the latest progress once it drains the buffer
+    pub fn push(&self, update: ProgressUpdate) {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return;
+        }
+        if state.events.len() >= SSE_BUFFER_CAPACITY {
+            state.events.pop_back();
+        }
+        state.events.push_back(update);
+        self.ready.notify_one();
+    }
+
+    // blocks until an event is queued; None once closed and drained
+    pub fn pop(&self) -> Option<ProgressUpdate> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(update) = state.events.pop_front() {
+                return Some(update);
+            }
+            if state.closed {
+                return None;
+            }
+            state = self.ready.wait(state).unwrap();
+        }
+    }
+
+    // like pop, but gives up after `timeout` so the caller can do other work
+    // between events; Disconnected once closed and drained
+    pub fn pop_timeout(&self, timeout: Duration) -> Result<ProgressUpdate, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(update) = state.events.pop_front() {
+                return Ok(update);
+            }
+            if state.closed {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state = self.ready.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+
+    // called by the poller when the scan ends, or by the writer when the client goes away
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+}
+
+// polls the store for new log lines of `scan_id` and feeds them to `buffer`
+// until the scan ends, it's deleted or the buffer is closed; run on its own
+// thread so a slow client never holds the store lock or falls behind it
+pub fn watch_scan(scan_store: ScanStore, scan_id: String, buffer: Arc<SseBuffer>) {
+    let mut last_progress = 0;
+    while !buffer.is_closed() {
+        thread::sleep(Duration::from_millis(100));
+
+        let store = scan_store.lock().unwrap();
+        let Some(result) = store.get(&scan_id) else {
+            // deleted while we were streaming
+            break;
+        };
+
+        for log in &result.logs[last_progress..] {
+            buffer.push(progress_update(log));
+        }
+        last_progress = result.logs.len();
+
+        if result.status != "scanning" {
+            break;
+        }
+    }
+    buffer.close();
+}
+
+// a log line as sent to clients; lines that aren't "[NN%] message" go out
+// as a bare message so they can't reset a client's progress bar
+pub fn progress_update(log: &str) -> ProgressUpdate {
+    match parse_progress(log) {
+        Some((progress, message)) => ProgressUpdate {
+            progress: Some(progress),
+            message: message.to_string(),
+        },
+        None => ProgressUpdate {
+            progress: None,
+            message: log.to_string(),
+        },
+    }
+}
+
+// splits "[NN%] message" into its percentage (0-100) and message
+pub fn parse_progress(log: &str) -> Option<(u32, &str)> {
+    let (percent, message) = log.strip_prefix('[')?.split_once("%]")?;
+    if percent.is_empty() || !percent.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let percent = percent.parse::<u32>().ok().filter(|p| *p <= 100)?;
+    Some((percent, message.strip_prefix(' ').unwrap_or(message)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scan_result;
+    use std::collections::HashMap;
+
+    #[test]
+    fn parses_well_formed_lines() {
+        assert_eq!(
+            parse_progress("[0%] Initializing scan..."),
+            Some((0, "Initializing scan..."))
+        );
+        assert_eq!(
+            parse_progress("[100%] Scan complete"),
+            Some((100, "Scan complete"))
+        );
+        assert_eq!(parse_progress("[42%]"), Some((42, "")));
+    }
+
+    #[test]
+    fn rejects_malformed_percentages() {
+        assert_eq!(parse_progress("[abc%] Scanning"), None);
+        assert_eq!(parse_progress("[101%] Scanning"), None);
+        assert_eq!(parse_progress("[%] Scanning"), None);
+        assert_eq!(parse_progress("[-5%] Scanning"), None);
+        assert_eq!(parse_progress("[ 5%] Scanning"), None);
+        assert_eq!(parse_progress("[99999999999%] Scanning"), None);
+    }
+
+    #[test]
+    fn rejects_missing_brackets() {
+        assert_eq!(parse_progress("50%] Scanning"), None);
+        assert_eq!(parse_progress("[50% Scanning"), None);
+        assert_eq!(parse_progress(" [50%] Scanning"), None);
+    }
+
+    #[test]
+    fn lines_without_a_percentage_keep_their_text() {
+        assert_eq!(parse_progress("Scan cancelled"), None);
+        assert_eq!(parse_progress(""), None);
+
+        let update = progress_update("Scan timed out after 60s");
+        assert_eq!(update.progress, None);
+        assert_eq!(update.message, "Scan timed out after 60s");
+
+        let update = progress_update("[60%] Performing signature analysis...");
+        assert_eq!(update.progress, Some(60));
+        assert_eq!(update.message, "Performing signature analysis...");
+    }
+
+    fn messages(buffer: &SseBuffer) -> Vec<String> {
+        let mut messages = Vec::new();
+        while let Ok(update) = buffer.pop_timeout(Duration::ZERO) {
+            messages.push(update.message);
+        }
+        messages
+    }
+
+    #[test]
+    fn full_buffer_coalesces_to_the_latest_event() {
+        let buffer = SseBuffer::new();
+        for i in 0..SSE_BUFFER_CAPACITY + 10 {
+            buffer.push(progress_update(&format!("[{}%] step {}", i, i)));
+        }
+        let messages = messages(&buffer);
+        assert_eq!(messages.len(), SSE_BUFFER_CAPACITY);
+        // the oldest events go out as queued, the newest replaces the overflow
+        assert_eq!(messages[0], "step 0");
+        assert_eq!(
+            messages[SSE_BUFFER_CAPACITY - 2],
+            format!("step {}", SSE_BUFFER_CAPACITY - 2)
+        );
+        assert_eq!(
+            messages[SSE_BUFFER_CAPACITY - 1],
+            format!("step {}", SSE_BUFFER_CAPACITY + 9)
+        );
+    }
+
+    #[test]
+    fn closed_buffer_drains_then_ends() {
+        let buffer = SseBuffer::new();
+        assert!(matches!(
+            buffer.pop_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        ));
+
+        buffer.push(progress_update("[100%] Scan complete"));
+        buffer.close();
+        // nothing more is queued once closed
+        buffer.push(progress_update("late"));
+        assert_eq!(buffer.pop().unwrap().progress, Some(100));
+        assert!(buffer.pop().is_none());
+        assert!(matches!(
+            buffer.pop_timeout(Duration::from_secs(5)),
+            Err(RecvTimeoutError::Disconnected)
+        ));
+    }
+
+    #[test]
+    fn slow_reader_never_blocks_the_producer() {
+        let buffer = Arc::new(SseBuffer::new());
+        let started = Instant::now();
+        for i in 0..100_000 {
+            buffer.push(progress_update(&format!("[{}%] step", i % 100)));
+        }
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let reader = {
+            let buffer = buffer.clone();
+            thread::spawn(move || {
+                let mut received = 0;
+                while buffer.pop().is_some() {
+                    received += 1;
+                    thread::sleep(Duration::from_millis(1));
+                }
+                received
+            })
+        };
+        buffer.close();
+        assert_eq!(reader.join().unwrap(), SSE_BUFFER_CAPACITY);
+    }
+
+    #[test]
+    fn watcher_forwards_new_logs_until_the_scan_ends() {
+        let scan_store: ScanStore = Arc::new(Mutex::new(HashMap::new()));
+        let mut scanning = scan_result("scanning");
+        scanning.logs = vec!["[0%] Initializing scan...".to_string()];
+        scan_store
+            .lock()
+            .unwrap()
+            .insert("scan-test".to_string(), scanning);
+
+        let buffer = Arc::new(SseBuffer::new());
+        let watcher = {
+            let (scan_store, buffer) = (scan_store.clone(), buffer.clone());
+            thread::spawn(move || watch_scan(scan_store, "scan-test".to_string(), buffer))
+        };
+        assert_eq!(buffer.pop().unwrap().message, "Initializing scan...");
+
+        {
+            let mut store = scan_store.lock().unwrap();
+            let result = store.get_mut("scan-test").unwrap();
+            result.logs.push("[100%] Scan complete".to_string());
+            result.status = "safe".to_string();
+        }
+        watcher.join().unwrap();
+        assert_eq!(buffer.pop().unwrap().message, "Scan complete");
+        assert!(buffer.pop().is_none());
+    }
+
+    #[test]
+    fn watcher_stops_when_the_scan_is_deleted() {
+        let scan_store: ScanStore = Arc::new(Mutex::new(HashMap::new()));
+        let buffer = Arc::new(SseBuffer::new());
+        watch_scan(scan_store, "scan-missing".to_string(), buffer.clone());
+        assert!(buffer.is_closed());
+        assert!(buffer.pop().is_none());
+    }
+}
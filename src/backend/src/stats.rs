@@ -0,0 +1,207 @@
+use crate::config::*;
+use crate::types::{MetricsResponse, StatsResponse, WindowStats};
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+pub type SharedStats = Arc<Mutex<StatsTracker>>;
+pub type SharedMetrics = Arc<Mutex<Metrics>>;
+
+const WINDOWS: &[(&str, Duration)] = &[
+    ("1m", Duration::from_secs(60)),
+    ("5m", Duration::from_secs(5 * 60)),
+    ("1h", Duration::from_secs(60 * 60)),
+];
+
+#[derive(Clone)]
+pub enum StatsEvent {
+    Upload,
+    // carries the final status of the scan
+    Completion(String),
+}
+
+// ring buffer of timestamped events, oldest first; anything older than the
+// largest window is pruned on insert so counting only walks recent events
+#[derive(Default)]
+pub struct StatsTracker {
+    events: VecDeque<(Instant, StatsEvent)>,
+}
+
+impl StatsTracker {
+    pub fn record(&mut self, event: StatsEvent, now: Instant) {
+        let horizon = WINDOWS.iter().map(|(_, d)| *d).max().unwrap_or_default();
+        while let Some((at, _)) = self.events.front() {
+            if now.duration_since(*at) > horizon || self.events.len() >= STATS_MAX_EVENTS {
+                self.events.pop_front();
+            } else {
+                break;
+            }
+        }
+        self.events.push_back((now, event));
+    }
+
+    pub fn window(&self, window: Duration, now: Instant) -> WindowStats {
+        let mut uploads = 0;
+        let mut completions = 0;
+        let mut verdicts: BTreeMap<String, usize> = BTreeMap::new();
+
+        for (_, event) in self
+            .events
+            .iter()
+            .rev()
+            .take_while(|(at, _)| now.duration_since(*at) <= window)
+        {
+            match event {
+                StatsEvent::Upload => uploads += 1,
+                StatsEvent::Completion(status) => {
+                    completions += 1;
+                    *verdicts.entry(status.clone()).or_insert(0) += 1;
+                }
+            }
+        }
+
+        let verdict_rates = verdicts
+            .iter()
+            .map(|(status, count)| (status.clone(), *count as f64 / completions as f64))
+            .collect();
+
+        WindowStats {
+            uploads,
+            completions,
+            verdicts,
+            verdict_rates,
+        }
+    }
+
+    pub fn snapshot(&self, now: Instant) -> StatsResponse {
+        StatsResponse {
+            windows: WINDOWS
+                .iter()
+                .map(|(name, window)| (name.to_string(), self.window(*window, now)))
+                .collect(),
+        }
+    }
+}
+
+pub fn record_event(stats: &SharedStats, event: StatsEvent) {
+    stats.lock().unwrap().record(event, Instant::now());
+}
+
+// lifetime counters since startup, unlike the windowed stats above
+#[derive(Default)]
+pub struct Metrics {
+    total_scans: u64,
+    bytes_processed: u64,
+    // scans uploaded but not yet finished
+    queue_depth: u64,
+    // final status of every scan that finished
+    by_status: BTreeMap<String, u64>,
+}
+
+impl Metrics {
+    pub fn scan_started(&mut self, size: u64) {
+        self.total_scans += 1;
+        self.bytes_processed += size;
+        self.queue_depth += 1;
+    }
+
+    // status is None when the scan was deleted before it finished
+    pub fn scan_finished(&mut self, status: Option<String>) {
+        self.queue_depth = self.queue_depth.saturating_sub(1);
+        if let Some(status) = status {
+            *self.by_status.entry(status).or_insert(0) += 1;
+        }
+    }
+
+    pub fn snapshot(&self) -> MetricsResponse {
+        MetricsResponse {
+            total_scans: self.total_scans,
+            scans_by_status: self.by_status.clone(),
+            bytes_processed: self.bytes_processed,
+            queue_depth: self.queue_depth,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    fn completion(status: &str) -> StatsEvent {
+        StatsEvent::Completion(status.to_string())
+    }
+
+    #[test]
+    fn windows_count_only_recent_events() {
+        let start = Instant::now();
+        let mut tracker = StatsTracker::default();
+        // an hour of history, newest last: 50 minutes ago, 3 minutes ago, just now
+        tracker.record(StatsEvent::Upload, start);
+        tracker.record(completion("safe"), start);
+        tracker.record(StatsEvent::Upload, start + 47 * MINUTE);
+        tracker.record(completion("malicious"), start + 47 * MINUTE);
+        tracker.record(StatsEvent::Upload, start + 50 * MINUTE);
+        tracker.record(completion("safe"), start + 50 * MINUTE);
+        tracker.record(completion("suspicious"), start + 50 * MINUTE);
+
+        let now = start + 50 * MINUTE;
+        let last_minute = tracker.window(MINUTE, now);
+        assert_eq!(last_minute.uploads, 1);
+        assert_eq!(last_minute.completions, 2);
+        assert_eq!(last_minute.verdicts["safe"], 1);
+        assert_eq!(last_minute.verdict_rates["suspicious"], 0.5);
+
+        let snapshot = tracker.snapshot(now);
+        assert_eq!(snapshot.windows["1m"].completions, 2);
+        assert_eq!(snapshot.windows["5m"].uploads, 2);
+        assert_eq!(snapshot.windows["5m"].verdicts["malicious"], 1);
+        assert_eq!(snapshot.windows["1h"].uploads, 3);
+        assert_eq!(snapshot.windows["1h"].completions, 4);
+        assert_eq!(snapshot.windows["1h"].verdict_rates["safe"], 0.5);
+    }
+
+    #[test]
+    fn empty_windows_have_no_rates() {
+        let start = Instant::now();
+        let mut tracker = StatsTracker::default();
+        tracker.record(StatsEvent::Upload, start);
+
+        let window = tracker.window(MINUTE, start + 2 * MINUTE);
+        assert_eq!(window.uploads, 0);
+        assert_eq!(window.completions, 0);
+        assert!(window.verdict_rates.is_empty());
+    }
+
+    #[test]
+    fn events_past_the_largest_window_are_pruned() {
+        let start = Instant::now();
+        let mut tracker = StatsTracker::default();
+        tracker.record(StatsEvent::Upload, start);
+        tracker.record(StatsEvent::Upload, start + 30 * MINUTE);
+        tracker.record(StatsEvent::Upload, start + 61 * MINUTE);
+
+        assert_eq!(tracker.events.len(), 2);
+        let hour = tracker.window(60 * MINUTE, start + 61 * MINUTE);
+        assert_eq!(hour.uploads, 2);
+    }
+
+    #[test]
+    fn metrics_track_the_queue() {
+        let mut metrics = Metrics::default();
+        metrics.scan_started(100);
+        metrics.scan_started(50);
+        metrics.scan_finished(Some("safe".to_string()));
+        // deleted before it finished
+        metrics.scan_finished(None);
+        metrics.scan_finished(None);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.total_scans, 2);
+        assert_eq!(snapshot.bytes_processed, 150);
+        assert_eq!(snapshot.queue_depth, 0);
+        assert_eq!(snapshot.scans_by_status.len(), 1);
+        assert_eq!(snapshot.scans_by_status["safe"], 1);
+    }
+}
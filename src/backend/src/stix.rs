@@ -0,0 +1,252 @@
+use crate::types::{ScanResult, Threat};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+// namespace the STIX 2.1 spec reserves for deterministic identifiers
+const STIX_NAMESPACE: Uuid = uuid::uuid!("00abedb4-aa42-466c-9c01-fed23315a9b7");
+
+// ids are derived from their content so exporting the same scan twice yields
+// the same objects, letting a TIP deduplicate instead of piling up copies
+fn stix_id(object_type: &str, seed: &str) -> String {
+    format!(
+        "{}--{}",
+        object_type,
+        Uuid::new_v5(&STIX_NAMESPACE, seed.as_bytes())
+    )
+}
+
+fn indicator_type(threat: &Threat) -> &'static str {
+    match threat.severity.as_str() {
+        "malicious" => "malicious-activity",
+        "suspicious" => "anomalous-activity",
+        _ => "benign",
+    }
+}
+
+fn relationship(scan_id: &str, kind: &str, source: &str, target: &str, at: &str) -> Value {
+    json!({
+        "type": "relationship",
+        "spec_version": "2.1",
+        "id": stix_id("relationship", &format!("{}/{}/{}/{}", scan_id, kind, source, target)),
+        "created": at,
+        "modified": at,
+        "relationship_type": kind,
+        "source_ref": source,
+        "target_ref": target,
+    })
+}
+
+// a file SCO for the sample, an indicator per threat keyed on the sample's
+// SHA-256, and a malware SDO when the verdict or a family tag warrants one;
+// None if the scan never recorded file hashes
+pub fn stix_bundle(scan_id: &str, result: &ScanResult) -> Option<Value> {
+    let file_info = result.file_info.as_ref()?;
+    let at = result.created_at.as_str();
+    let mut objects = Vec::new();
+
+    let file_seed = json!({"hashes": {"SHA-256": file_info.sha256}, "name": file_info.filename});
+    let file_id = stix_id("file", &file_seed.to_string());
+    objects.push(json!({
+        "type": "file",
+        "spec_version": "2.1",
+        "id": file_id,
+        "name": file_info.filename,
+        "size": file_info.size,
+        "hashes": {
+            "MD5": file_info.md5,
+            "SHA-1": file_info.sha1,
+            "SHA-256": file_info.sha256,
+        },
+    }));
+
+    let malware_id = if result.status == "unsafe" || result.family.is_some() {
+        let id = stix_id("malware", &format!("{}/malware", scan_id));
+        let name = match &result.family {
+            Some(family) => family.name.clone(),
+            None => file_info.filename.clone(),
+        };
+        objects.push(json!({
+            "type": "malware",
+            "spec_version": "2.1",
+            "id": id,
+            "created": at,
+            "modified": at,
+            "name": name,
+            "is_family": result.family.is_some(),
+            "sample_refs": [file_id],
+        }));
+        Some(id)
+    } else {
+        None
+    };
+
+    let pattern = format!("[file:hashes.'SHA-256' = '{}']", file_info.sha256);
+    // threat ids can repeat (one P003 per packed section), so seed by position
+    for (i, threat) in result.threats.iter().enumerate() {
+        let indicator_id = stix_id("indicator", &format!("{}/indicator/{}", scan_id, i));
+        objects.push(json!({
+            "type": "indicator",
+            "spec_version": "2.1",
+            "id": indicator_id,
+            "created": at,
+            "modified": at,
+            "name": threat.threat_type,
+            "description": threat.details,
+            "indicator_types": [indicator_type(threat)],
+            "pattern": pattern,
+            "pattern_type": "stix",
+            "valid_from": at,
+            "labels": [threat.threat_id],
+        }));
+        objects.push(match &malware_id {
+            Some(malware_id) => relationship(scan_id, "indicates", &indicator_id, malware_id, at),
+            None => relationship(scan_id, "related-to", &indicator_id, &file_id, at),
+        });
+    }
+
+    Some(json!({
+        "type": "bundle",
+        "id": stix_id("bundle", scan_id),
+        "objects": objects,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::scan_result;
+    use crate::types::{FamilyMatch, FileInfo};
+
+    fn scanned(status: &str, threats: &[(&str, &str)]) -> ScanResult {
+        let mut result = scan_result(status);
+        result.created_at = "2024-05-01T12:00:00.000Z".to_string();
+        result.file_info = Some(FileInfo {
+            filename: "dropper.exe".to_string(),
+            size: 4096,
+            sha256: "ab".repeat(32),
+            md5: "cd".repeat(16),
+            sha1: "ef".repeat(20),
+            imphash: None,
+            authentihash: None,
+        });
+        result.threats = threats
+            .iter()
+            .map(|(threat_id, severity)| Threat {
+                threat_type: format!("Threat {}", threat_id),
+                details: "details".to_string(),
+                severity: severity.to_string(),
+                threat_id: threat_id.to_string(),
+            })
+            .collect();
+        result
+    }
+
+    fn of_type<'a>(bundle: &'a Value, object_type: &str) -> Vec<&'a Value> {
+        bundle["objects"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|object| object["type"] == object_type)
+            .collect()
+    }
+
+    #[test]
+    fn unsafe_scan_becomes_file_malware_and_indicators() {
+        let result = scanned("unsafe", &[("S002", "malicious"), ("P003", "suspicious")]);
+        let bundle = stix_bundle("scan-x", &result).unwrap();
+        assert_eq!(bundle["type"], "bundle");
+        assert!(bundle["id"].as_str().unwrap().starts_with("bundle--"));
+
+        let files = of_type(&bundle, "file");
+        assert_eq!(files.len(), 1);
+        let file = files[0];
+        assert_eq!(file["name"], "dropper.exe");
+        assert_eq!(file["size"], 4096);
+        assert_eq!(file["hashes"]["SHA-256"], "ab".repeat(32));
+        assert_eq!(file["hashes"]["SHA-1"], "ef".repeat(20));
+        assert_eq!(file["hashes"]["MD5"], "cd".repeat(16));
+
+        let malware = of_type(&bundle, "malware");
+        assert_eq!(malware.len(), 1);
+        assert_eq!(malware[0]["name"], "dropper.exe");
+        assert_eq!(malware[0]["is_family"], false);
+        assert_eq!(malware[0]["sample_refs"][0], file["id"]);
+
+        let indicators = of_type(&bundle, "indicator");
+        assert_eq!(indicators.len(), 2);
+        assert_eq!(indicators[0]["indicator_types"][0], "malicious-activity");
+        assert_eq!(indicators[1]["indicator_types"][0], "anomalous-activity");
+        assert_eq!(indicators[1]["labels"][0], "P003");
+        assert_eq!(
+            indicators[0]["pattern"],
+            format!("[file:hashes.'SHA-256' = '{}']", "ab".repeat(32))
+        );
+
+        let relationships = of_type(&bundle, "relationship");
+        assert_eq!(relationships.len(), 2);
+        for (relationship, indicator) in relationships.iter().zip(&indicators) {
+            assert_eq!(relationship["relationship_type"], "indicates");
+            assert_eq!(relationship["source_ref"], indicator["id"]);
+            assert_eq!(relationship["target_ref"], malware[0]["id"]);
+        }
+        for object in bundle["objects"].as_array().unwrap() {
+            if object["type"] != "file" {
+                assert_eq!(object["created"], "2024-05-01T12:00:00.000Z");
+            }
+            assert_eq!(object["spec_version"], "2.1");
+        }
+    }
+
+    #[test]
+    fn safe_scan_relates_indicators_to_the_file() {
+        let bundle = stix_bundle("scan-x", &scanned("safe", &[("P006", "neutral")])).unwrap();
+        assert!(of_type(&bundle, "malware").is_empty());
+        let indicator = of_type(&bundle, "indicator")[0];
+        assert_eq!(indicator["indicator_types"][0], "benign");
+        let relationship = of_type(&bundle, "relationship")[0];
+        assert_eq!(relationship["relationship_type"], "related-to");
+        assert_eq!(relationship["source_ref"], indicator["id"]);
+        assert_eq!(
+            relationship["target_ref"],
+            of_type(&bundle, "file")[0]["id"]
+        );
+    }
+
+    #[test]
+    fn family_tag_names_the_malware() {
+        let mut result = scanned("suspicious", &[]);
+        result.family = Some(FamilyMatch {
+            name: "Ladybird".to_string(),
+            confidence: 0.8,
+            evidence: vec![],
+        });
+        let bundle = stix_bundle("scan-x", &result).unwrap();
+        let malware = of_type(&bundle, "malware");
+        assert_eq!(malware[0]["name"], "Ladybird");
+        assert_eq!(malware[0]["is_family"], true);
+    }
+
+    #[test]
+    fn ids_are_stable_per_scan() {
+        let result = scanned("unsafe", &[("S002", "malicious"), ("S002", "malicious")]);
+        let first = stix_bundle("scan-x", &result).unwrap();
+        assert_eq!(first, stix_bundle("scan-x", &result).unwrap());
+
+        // repeated threat ids still get distinct indicators
+        let indicators = of_type(&first, "indicator");
+        assert_ne!(indicators[0]["id"], indicators[1]["id"]);
+
+        let other = stix_bundle("scan-y", &result).unwrap();
+        assert_ne!(first["id"], other["id"]);
+        // the same sample is the same file object whichever scan saw it
+        assert_eq!(
+            of_type(&first, "file")[0]["id"],
+            of_type(&other, "file")[0]["id"]
+        );
+    }
+
+    #[test]
+    fn scans_without_hashes_have_no_bundle() {
+        assert!(stix_bundle("scan-x", &scan_result("error")).is_none());
+    }
+}
@@ -0,0 +1,904 @@
+use crate::config::*;
+use crate::types::{ScanResult, ScanStore};
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use rusqlite::types::ToSql;
+use rusqlite::{params, Connection};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+pub type SharedStorage = Arc<dyn ResultStore>;
+
+// filters for `ResultStore::query`; unset fields match everything, `tag` is
+// the family name (any case), and `since`/`until` compare against
+// `created_at`, which sorts as a string
+#[derive(Default)]
+pub struct ScanQuery {
+    pub sha256: Option<String>,
+    pub status: Option<String>,
+    pub verdict: Option<String>,
+    pub tag: Option<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+}
+
+fn passes(filter: &Option<String>, test: impl Fn(&str) -> bool) -> bool {
+    match filter {
+        Some(value) => test(value),
+        None => true,
+    }
+}
+
+impl ScanQuery {
+    pub fn matches(&self, result: &ScanResult) -> bool {
+        let sha256 = result.file_info.as_ref().map(|f| f.sha256.as_str());
+        passes(&self.sha256, |h| sha256 == Some(h.to_lowercase().as_str()))
+            && passes(&self.status, |s| result.status == s)
+            && passes(&self.verdict, |v| result.verdict.as_deref() == Some(v))
+            && passes(&self.tag, |t| {
+                let family = result.family.as_ref();
+                family.is_some_and(|f| f.name.eq_ignore_ascii_case(t))
+            })
+            && passes(&self.since, |t| result.created_at.as_str() >= t)
+            && passes(&self.until, |t| result.created_at.as_str() <= t)
+    }
+}
+
+// durable copy of the scan store; the in-memory map stays the source for
+// reads and SSE polling, rows are only written on creation and terminal states
+pub trait ResultStore: Send + Sync {
+    fn save(&self, scan_id: &str, result: &ScanResult) -> Result<(), String>;
+    fn remove(&self, scan_id: &str) -> Result<(), String>;
+    fn get(&self, scan_id: &str) -> Result<Option<ScanResult>, String>;
+    fn list(&self) -> Result<Vec<(String, ScanResult)>, String>;
+
+    // backends that can't do better filter the full list
+    fn query(&self, query: &ScanQuery) -> Result<Vec<(String, ScanResult)>, String> {
+        let mut scans = self.list()?;
+        scans.retain(|(_, result)| query.matches(result));
+        Ok(scans)
+    }
+}
+
+pub fn open_storage(backend: StoreBackend) -> Result<SharedStorage, String> {
+    Ok(match backend {
+        StoreBackend::Memory => Arc::new(MemoryStore::default()),
+        StoreBackend::Json { compress, shard } => {
+            Arc::new(JsonStore::open(Path::new(RESULTS_DIR), compress, shard)?)
+        }
+        StoreBackend::Sqlite => Arc::new(SqliteStore::open(Path::new(DB_PATH))?),
+    })
+}
+
+// scans that were still running when the server went down can't resume,
+// so they come back as errors rather than hanging in "scanning" forever
+pub fn load_all(storage: &dyn ResultStore) -> Result<HashMap<String, ScanResult>, String> {
+    let mut scans = HashMap::new();
+    for (scan_id, mut result) in storage.list()? {
+        if result.status == "scanning" {
+            result.status = "error".to_string();
+            result
+                .logs
+                .push("Scan interrupted by server restart".to_string());
+            storage.save(&scan_id, &result)?;
+        }
+        scans.insert(scan_id, result);
+    }
+    Ok(scans)
+}
+
+// writes the store's current copy of a scan through to disk
+pub fn persist(storage: &SharedStorage, scan_store: &ScanStore, scan_id: &str) {
+    let result = scan_store.lock().unwrap().get(scan_id).cloned();
+    if let Some(result) = result {
+        if let Err(e) = storage.save(scan_id, &result) {
+            println!("Failed to persist scan {}: {}", scan_id, e);
+        }
+    }
+}
+
+// keeps nothing across restarts
+#[derive(Default)]
+pub struct MemoryStore {
+    scans: Mutex<HashMap<String, ScanResult>>,
+}
+
+impl ResultStore for MemoryStore {
+    fn save(&self, scan_id: &str, result: &ScanResult) -> Result<(), String> {
+        let mut scans = self.scans.lock().unwrap();
+        scans.insert(scan_id.to_string(), result.clone());
+        Ok(())
+    }
+
+    fn remove(&self, scan_id: &str) -> Result<(), String> {
+        self.scans.lock().unwrap().remove(scan_id);
+        Ok(())
+    }
+
+    fn get(&self, scan_id: &str) -> Result<Option<ScanResult>, String> {
+        Ok(self.scans.lock().unwrap().get(scan_id).cloned())
+    }
+
+    fn list(&self) -> Result<Vec<(String, ScanResult)>, String> {
+        let scans = self.scans.lock().unwrap();
+        Ok(scans
+            .iter()
+            .map(|(scan_id, result)| (scan_id.clone(), result.clone()))
+            .collect())
+    }
+}
+
+// result files are named after their scan id and either extension
+const JSON_EXTENSION: &str = ".json";
+const GZIP_EXTENSION: &str = ".json.gz";
+const GZIP_MAGIC: &[u8] = b"\x1f\x8b";
+// leading characters of the id (after "scan-") naming a sharded file's subdirectory
+const SHARD_PREFIX_LEN: usize = 2;
+
+// one `<scanId>.json` file per scan, or `.json.gz` with `compress`, placed
+// under `<dir>/<prefix>/` with `shard` so no single directory grows huge;
+// files written with other settings are still read and are moved on resave.
+// The files are only read once, on open; listings and queries are answered
+// from `index`, which every save and remove keeps in step with the disk
+pub struct JsonStore {
+    dir: PathBuf,
+    compress: bool,
+    shard: bool,
+    index: Mutex<HashMap<String, ScanResult>>,
+}
+
+impl JsonStore {
+    pub fn open(dir: &Path, compress: bool, shard: bool) -> Result<Self, String> {
+        fs::create_dir_all(dir).map_err(|e| format!("cannot create {}: {}", dir.display(), e))?;
+        let store = JsonStore {
+            dir: dir.to_path_buf(),
+            compress,
+            shard,
+            index: Mutex::default(),
+        };
+        *store.index.lock().unwrap() = store.read_all()?;
+        Ok(store)
+    }
+
+    fn location(&self, scan_id: &str, compress: bool, shard: bool) -> PathBuf {
+        let extension = if compress {
+            GZIP_EXTENSION
+        } else {
+            JSON_EXTENSION
+        };
+        let file = format!("{}{}", scan_id, extension);
+        if shard {
+            let id = scan_id.strip_prefix("scan-").unwrap_or(scan_id);
+            let prefix: String = id.chars().take(SHARD_PREFIX_LEN).collect();
+            self.dir.join(prefix).join(file)
+        } else {
+            self.dir.join(file)
+        }
+    }
+
+    // where the scan is written under the current settings, then every
+    // other place an earlier configuration could have left it
+    fn paths(&self, scan_id: &str) -> Result<Vec<PathBuf>, String> {
+        // ids become file names, so nothing that could leave the directory
+        if scan_id.is_empty() || scan_id.starts_with('.') || scan_id.contains(['/', '\\']) {
+            return Err(format!("invalid scan id {:?}", scan_id));
+        }
+        let mut paths = vec![self.location(scan_id, self.compress, self.shard)];
+        for compress in [false, true] {
+            for shard in [false, true] {
+                if (compress, shard) != (self.compress, self.shard) {
+                    paths.push(self.location(scan_id, compress, shard));
+                }
+            }
+        }
+        Ok(paths)
+    }
+
+    // result files directly in `dir` and one level of shard directories below
+    fn files(&self) -> Result<Vec<PathBuf>, String> {
+        let mut files = Vec::new();
+        for entry in fs::read_dir(&self.dir).map_err(|e| e.to_string())? {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            if path.is_dir() {
+                for entry in fs::read_dir(&path).map_err(|e| e.to_string())? {
+                    files.push(entry.map_err(|e| e.to_string())?.path());
+                }
+            } else {
+                files.push(path);
+            }
+        }
+        Ok(files)
+    }
+
+    // every stored scan, read from disk
+    fn read_all(&self) -> Result<HashMap<String, ScanResult>, String> {
+        // a scan found in more than one place keeps the copy at its current location
+        let mut found: HashMap<String, PathBuf> = HashMap::new();
+        for path in self.files()? {
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            let Some(scan_id) = name
+                .strip_suffix(GZIP_EXTENSION)
+                .or_else(|| name.strip_suffix(JSON_EXTENSION))
+            else {
+                continue;
+            };
+            let current = self.location(scan_id, self.compress, self.shard);
+            if path == current || !found.contains_key(scan_id) {
+                found.insert(scan_id.to_string(), path);
+            }
+        }
+
+        let mut scans = HashMap::new();
+        for (scan_id, path) in found {
+            match fs::read(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|bytes| decode_result(&bytes))
+            {
+                Ok(result) => {
+                    scans.insert(scan_id, result);
+                }
+                Err(e) => println!("Skipping unreadable stored scan {}: {}", scan_id, e),
+            }
+        }
+        Ok(scans)
+    }
+}
+
+fn remove_if_present(path: &Path) -> Result<(), String> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.to_string()),
+        _ => Ok(()),
+    }
+}
+
+// gzip is recognized by its magic bytes rather than the file name
+fn decode_result(bytes: &[u8]) -> Result<ScanResult, String> {
+    if bytes.starts_with(GZIP_MAGIC) {
+        let mut json = Vec::new();
+        GzDecoder::new(bytes)
+            .read_to_end(&mut json)
+            .map_err(|e| e.to_string())?;
+        serde_json::from_slice(&json).map_err(|e| e.to_string())
+    } else {
+        serde_json::from_slice(bytes).map_err(|e| e.to_string())
+    }
+}
+
+impl ResultStore for JsonStore {
+    fn save(&self, scan_id: &str, result: &ScanResult) -> Result<(), String> {
+        let paths = self.paths(scan_id)?;
+        let (path, stale) = paths.split_first().unwrap();
+        let json = serde_json::to_vec(result).unwrap();
+        let bytes = if self.compress {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(&json).map_err(|e| e.to_string())?;
+            encoder.finish().map_err(|e| e.to_string())?
+        } else {
+            json
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        // written aside and renamed so a crash never leaves half a file
+        let mut partial = path.clone().into_os_string();
+        partial.push(".partial");
+        fs::write(&partial, bytes).map_err(|e| e.to_string())?;
+        fs::rename(&partial, path).map_err(|e| e.to_string())?;
+
+        let mut index = self.index.lock().unwrap();
+        index.insert(scan_id.to_string(), result.clone());
+        stale.iter().try_for_each(|path| remove_if_present(path))
+    }
+
+    fn remove(&self, scan_id: &str) -> Result<(), String> {
+        let paths = self.paths(scan_id)?;
+        self.index.lock().unwrap().remove(scan_id);
+        paths.iter().try_for_each(|path| remove_if_present(path))
+    }
+
+    fn get(&self, scan_id: &str) -> Result<Option<ScanResult>, String> {
+        Ok(self.index.lock().unwrap().get(scan_id).cloned())
+    }
+
+    fn list(&self) -> Result<Vec<(String, ScanResult)>, String> {
+        let index = self.index.lock().unwrap();
+        Ok(index
+            .iter()
+            .map(|(scan_id, result)| (scan_id.clone(), result.clone()))
+            .collect())
+    }
+}
+
+// bumped whenever the schema changes; older databases are migrated on open
+const SCHEMA_VERSION: i32 = 2;
+
+// `scans.result` holds the whole ScanResult as JSON; the other columns and
+// the threats table copy out what queries filter on
+pub struct SqliteStore {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let conn = Connection::open(path).map_err(|e| e.to_string())?;
+        Self::from_connection(conn).map_err(|e| e.to_string())
+    }
+
+    fn from_connection(conn: Connection) -> rusqlite::Result<Self> {
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS scans (
+                scan_id TEXT PRIMARY KEY,
+                status TEXT NOT NULL,
+                created_at TEXT NOT NULL,
+                result TEXT NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS threats (
+                scan_id TEXT NOT NULL,
+                threat_id TEXT NOT NULL,
+                type TEXT NOT NULL,
+                severity TEXT NOT NULL,
+                details TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS threats_scan_id ON threats (scan_id);
+            CREATE INDEX IF NOT EXISTS threats_threat_id ON threats (threat_id);",
+        )?;
+
+        let version: i32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version < 1 {
+            // databases from before version 1 only had the first four columns
+            conn.execute_batch(
+                "ALTER TABLE scans ADD COLUMN verdict TEXT;
+                ALTER TABLE scans ADD COLUMN filename TEXT;
+                ALTER TABLE scans ADD COLUMN size INTEGER;
+                ALTER TABLE scans ADD COLUMN sha256 TEXT;
+                ALTER TABLE scans ADD COLUMN sha1 TEXT;
+                ALTER TABLE scans ADD COLUMN md5 TEXT;
+                CREATE INDEX IF NOT EXISTS scans_sha256 ON scans (sha256);
+                CREATE INDEX IF NOT EXISTS scans_status ON scans (status);
+                CREATE INDEX IF NOT EXISTS scans_created_at ON scans (created_at);",
+            )?;
+        }
+        if version < 2 {
+            conn.execute_batch(
+                "ALTER TABLE scans ADD COLUMN family TEXT;
+                CREATE INDEX IF NOT EXISTS scans_family ON scans (family COLLATE NOCASE);",
+            )?;
+        }
+
+        let store = SqliteStore {
+            conn: Mutex::new(conn),
+        };
+        if version < SCHEMA_VERSION {
+            // re-saving fills the new columns and tables from the stored JSON
+            for (scan_id, result) in store.rows("", &[])? {
+                store.write(&scan_id, &result)?;
+            }
+            let conn = store.conn.lock().unwrap();
+            conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        }
+        Ok(store)
+    }
+
+    fn write(&self, scan_id: &str, result: &ScanResult) -> rusqlite::Result<()> {
+        let json = serde_json::to_string(result).unwrap();
+        let file_info = result.file_info.as_ref();
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "INSERT OR REPLACE INTO scans
+                (scan_id, status, created_at, result, verdict, family,
+                 filename, size, sha256, sha1, md5)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+            params![
+                scan_id,
+                result.status,
+                result.created_at,
+                json,
+                result.verdict,
+                result.family.as_ref().map(|f| &f.name),
+                file_info.map(|f| &f.filename),
+                file_info.map(|f| f.size),
+                file_info.map(|f| &f.sha256),
+                file_info.map(|f| &f.sha1),
+                file_info.map(|f| &f.md5),
+            ],
+        )?;
+        tx.execute("DELETE FROM threats WHERE scan_id = ?1", params![scan_id])?;
+        for threat in &result.threats {
+            tx.execute(
+                "INSERT INTO threats (scan_id, threat_id, type, severity, details)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    scan_id,
+                    threat.threat_id,
+                    threat.threat_type,
+                    threat.severity,
+                    threat.details
+                ],
+            )?;
+        }
+        tx.commit()
+    }
+
+    // `filter` is empty or a WHERE clause over `scans`
+    fn rows(
+        &self,
+        filter: &str,
+        args: &[&dyn ToSql],
+    ) -> rusqlite::Result<Vec<(String, ScanResult)>> {
+        let rows: Vec<(String, String)> = {
+            let conn = self.conn.lock().unwrap();
+            let mut stmt =
+                conn.prepare(&format!("SELECT scan_id, result FROM scans {}", filter))?;
+            let rows = stmt.query_map(args, |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+
+        let mut scans = Vec::new();
+        for (scan_id, json) in rows {
+            match serde_json::from_str(&json) {
+                Ok(result) => scans.push((scan_id, result)),
+                Err(e) => println!("Skipping unreadable stored scan {}: {}", scan_id, e),
+            }
+        }
+        Ok(scans)
+    }
+}
+
+impl ResultStore for SqliteStore {
+    fn save(&self, scan_id: &str, result: &ScanResult) -> Result<(), String> {
+        self.write(scan_id, result).map_err(|e| e.to_string())
+    }
+
+    fn remove(&self, scan_id: &str) -> Result<(), String> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(|e| e.to_string())?;
+        for table in ["scans", "threats"] {
+            tx.execute(
+                &format!("DELETE FROM {} WHERE scan_id = ?1", table),
+                params![scan_id],
+            )
+            .map_err(|e| e.to_string())?;
+        }
+        tx.commit().map_err(|e| e.to_string())
+    }
+
+    fn get(&self, scan_id: &str) -> Result<Option<ScanResult>, String> {
+        let rows = self.rows("WHERE scan_id = ?", &[&scan_id]);
+        let mut rows = rows.map_err(|e| e.to_string())?;
+        Ok(rows.pop().map(|(_, result)| result))
+    }
+
+    fn list(&self) -> Result<Vec<(String, ScanResult)>, String> {
+        self.rows("", &[]).map_err(|e| e.to_string())
+    }
+
+    fn query(&self, query: &ScanQuery) -> Result<Vec<(String, ScanResult)>, String> {
+        let sha256 = query.sha256.as_ref().map(|h| h.to_lowercase());
+        let filters = [
+            ("sha256 = ?", sha256.as_ref()),
+            ("status = ?", query.status.as_ref()),
+            ("verdict = ?", query.verdict.as_ref()),
+            ("family = ? COLLATE NOCASE", query.tag.as_ref()),
+            ("created_at >= ?", query.since.as_ref()),
+            ("created_at <= ?", query.until.as_ref()),
+        ];
+        let (clauses, args): (Vec<&str>, Vec<&dyn ToSql>) = filters
+            .iter()
+            .filter_map(|(clause, value)| value.map(|v| (*clause, v as &dyn ToSql)))
+            .unzip();
+        let filter = if clauses.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", clauses.join(" AND "))
+        };
+        self.rows(&filter, &args).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{scan_result, temp_dir};
+    use crate::types::{FamilyMatch, FileInfo, Threat};
+
+    fn scan(status: &str, sha256: &str, created_at: &str) -> ScanResult {
+        let mut result = scan_result(status);
+        result.verdict = Some("clean".to_string());
+        result.created_at = created_at.to_string();
+        result.file_info = Some(FileInfo {
+            filename: "sample.exe".to_string(),
+            size: 4,
+            sha256: sha256.to_string(),
+            md5: "m".to_string(),
+            sha1: "s".to_string(),
+            imphash: None,
+            authentihash: None,
+        });
+        result
+    }
+
+    fn ids(mut scans: Vec<(String, ScanResult)>) -> Vec<String> {
+        scans.sort_by(|a, b| a.0.cmp(&b.0));
+        scans.into_iter().map(|(scan_id, _)| scan_id).collect()
+    }
+
+    fn query(storage: &dyn ResultStore, query: ScanQuery) -> Vec<String> {
+        ids(storage.query(&query).unwrap())
+    }
+
+    fn tagged(mut result: ScanResult, family: &str) -> ScanResult {
+        result.family = Some(FamilyMatch {
+            name: family.to_string(),
+            confidence: 0.8,
+            evidence: vec!["imphash".to_string()],
+        });
+        result
+    }
+
+    // save, get, list, query, update and remove through any backend
+    fn round_trip(storage: &dyn ResultStore) {
+        storage
+            .save("scan-a", &scan("safe", "aa", "2024-01-01T00:00:00.000Z"))
+            .unwrap();
+        let b = tagged(scan("unsafe", "bb", "2024-02-01T00:00:00.000Z"), "Ladybird");
+        storage.save("scan-b", &b).unwrap();
+        assert_eq!(ids(storage.list().unwrap()), ["scan-a", "scan-b"]);
+
+        let stored = storage.get("scan-b").unwrap().unwrap();
+        assert_eq!(stored.status, "unsafe");
+        assert_eq!(stored.family.unwrap().name, "Ladybird");
+        assert_eq!(stored.file_info.unwrap().sha256, "bb");
+        assert!(storage.get("scan-missing").unwrap().is_none());
+
+        let by_hash = ScanQuery {
+            sha256: Some("BB".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query(storage, by_hash), ["scan-b"]);
+        let by_status = ScanQuery {
+            status: Some("safe".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query(storage, by_status), ["scan-a"]);
+        let by_range = ScanQuery {
+            since: Some("2024-01-15T00:00:00.000Z".to_string()),
+            until: Some("2024-03-01T00:00:00.000Z".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query(storage, by_range), ["scan-b"]);
+        let by_verdict = ScanQuery {
+            verdict: Some("malicious".to_string()),
+            ..Default::default()
+        };
+        assert!(query(storage, by_verdict).is_empty());
+        let by_tag = ScanQuery {
+            tag: Some("LADYBIRD".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query(storage, by_tag), ["scan-b"]);
+        let by_other_tag = ScanQuery {
+            tag: Some("Emotet".to_string()),
+            status: Some("unsafe".to_string()),
+            ..Default::default()
+        };
+        assert!(query(storage, by_other_tag).is_empty());
+
+        // saving again replaces the earlier copy
+        storage
+            .save("scan-a", &scan("unsafe", "aa", "2024-01-01T00:00:00.000Z"))
+            .unwrap();
+        let unsafe_scans = ScanQuery {
+            status: Some("unsafe".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query(storage, unsafe_scans), ["scan-a", "scan-b"]);
+
+        assert_eq!(storage.get("scan-a").unwrap().unwrap().status, "unsafe");
+
+        storage.remove("scan-a").unwrap();
+        assert_eq!(ids(storage.list().unwrap()), ["scan-b"]);
+        assert!(storage.get("scan-a").unwrap().is_none());
+        // removing something that isn't there is fine
+        storage.remove("scan-a").unwrap();
+    }
+
+    #[test]
+    fn memory_store_round_trip() {
+        round_trip(&MemoryStore::default());
+    }
+
+    fn in_memory_sqlite() -> SqliteStore {
+        SqliteStore::from_connection(Connection::open_in_memory().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn in_memory_sqlite_store_round_trip() {
+        round_trip(&in_memory_sqlite());
+    }
+
+    #[test]
+    fn in_memory_sqlite_inserts_and_filters() {
+        let store = in_memory_sqlite();
+        let mut result = scan("unsafe", "cc", "2024-03-01T00:00:00.000Z");
+        result.threats = vec![threat("S002", "malicious")];
+        store.save("scan-c", &result).unwrap();
+        store
+            .save("scan-d", &scan("safe", "dd", "2024-03-02T00:00:00.000Z"))
+            .unwrap();
+        store
+            .save("scan-e", &scan("safe", "cc", "2024-03-03T00:00:00.000Z"))
+            .unwrap();
+
+        let by_hash = ScanQuery {
+            sha256: Some("cc".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query(&store, by_hash), ["scan-c", "scan-e"]);
+        let by_status = ScanQuery {
+            status: Some("safe".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query(&store, by_status), ["scan-d", "scan-e"]);
+        let both = ScanQuery {
+            sha256: Some("CC".to_string()),
+            status: Some("unsafe".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query(&store, both), ["scan-c"]);
+        let stored = store.get("scan-c").unwrap().unwrap();
+        assert_eq!(stored.threats[0].threat_id, "S002");
+    }
+
+    #[test]
+    fn sqlite_store_round_trip() {
+        let dir = temp_dir();
+        let path = dir.join("peroxide.db");
+        round_trip(&SqliteStore::open(&path).unwrap());
+        // and it's still there after reopening
+        let reopened = SqliteStore::open(&path).unwrap();
+        assert_eq!(ids(reopened.list().unwrap()), ["scan-b"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn json_store_round_trip() {
+        for (compress, shard) in [(false, false), (true, false), (false, true), (true, true)] {
+            let dir = temp_dir();
+            round_trip(&JsonStore::open(&dir, compress, shard).unwrap());
+            let reopened = JsonStore::open(&dir, compress, shard).unwrap();
+            assert_eq!(ids(reopened.list().unwrap()), ["scan-b"]);
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn json_store_moves_files_written_with_other_settings() {
+        let dir = temp_dir();
+        let plain = JsonStore::open(&dir, false, false).unwrap();
+        plain
+            .save("scan-ab", &scan("safe", "aa", "2024-01-01T00:00:00.000Z"))
+            .unwrap();
+        assert!(dir.join("scan-ab.json").exists());
+
+        let sharded = JsonStore::open(&dir, true, true).unwrap();
+        assert_eq!(ids(sharded.list().unwrap()), ["scan-ab"]);
+        sharded
+            .save("scan-ab", &scan("unsafe", "aa", "2024-01-01T00:00:00.000Z"))
+            .unwrap();
+        assert!(!dir.join("scan-ab.json").exists());
+        assert!(dir.join("ab").join("scan-ab.json.gz").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn json_store_writes_compressed_sharded_results() {
+        let dir = temp_dir();
+        let store = JsonStore::open(&dir, true, true).unwrap();
+        let mut result = scan("unsafe", "aa", "2024-01-01T00:00:00.000Z");
+        result.threats = vec![threat("S001", "high")];
+        result.logs = vec!["line".repeat(1000)];
+        store.save("scan-cd12", &result).unwrap();
+
+        let path = dir.join("cd").join("scan-cd12.json.gz");
+        let bytes = fs::read(&path).unwrap();
+        assert!(bytes.starts_with(GZIP_MAGIC));
+        assert!(bytes.len() < serde_json::to_vec(&result).unwrap().len() / 10);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        let reopened = JsonStore::open(&dir, true, true).unwrap();
+        let stored = reopened.list().unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].0, "scan-cd12");
+        assert_eq!(
+            serde_json::to_value(&stored[0].1).unwrap(),
+            serde_json::to_value(&result).unwrap()
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn json_store_loads_plain_compressed_and_sharded_files_together() {
+        let dir = temp_dir();
+        let json = |status: &str| {
+            serde_json::to_vec(&scan(status, "aa", "2024-01-01T00:00:00.000Z")).unwrap()
+        };
+        fs::create_dir_all(dir.join("bb")).unwrap();
+        fs::write(dir.join("scan-aa.json"), json("safe")).unwrap();
+        fs::write(
+            dir.join("bb").join("scan-bb.json.gz"),
+            gzip(&json("unsafe")),
+        )
+        .unwrap();
+        // compression is told by content, not by the name
+        fs::write(dir.join("scan-cc.json"), gzip(&json("suspicious"))).unwrap();
+        fs::write(dir.join("scan-dd.json.gz"), b"not a result").unwrap();
+        fs::write(dir.join("notes.txt"), b"ignored").unwrap();
+        // the copy at the current location wins over a stale one
+        fs::write(dir.join("scan-bb.json"), json("safe")).unwrap();
+
+        let store = JsonStore::open(&dir, true, true).unwrap();
+        let mut stored = store.list().unwrap();
+        stored.sort_by(|a, b| a.0.cmp(&b.0));
+        let statuses: Vec<(&str, &str)> = stored
+            .iter()
+            .map(|(scan_id, result)| (scan_id.as_str(), result.status.as_str()))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                ("scan-aa", "safe"),
+                ("scan-bb", "unsafe"),
+                ("scan-cc", "suspicious")
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn json_store_rejects_ids_that_leave_the_directory() {
+        let dir = temp_dir();
+        let store = JsonStore::open(&dir, false, false).unwrap();
+        let result = scan("safe", "aa", "2024-01-01T00:00:00.000Z");
+        for scan_id in ["", "../escape", ".hidden", "a/b", "a\\b"] {
+            assert!(store.save(scan_id, &result).is_err(), "{:?}", scan_id);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn interrupted_scans_load_as_errors() {
+        let storage = MemoryStore::default();
+        storage
+            .save(
+                "scan-a",
+                &scan("scanning", "aa", "2024-01-01T00:00:00.000Z"),
+            )
+            .unwrap();
+        let scans = load_all(&storage).unwrap();
+        assert_eq!(scans["scan-a"].status, "error");
+        assert_eq!(storage.list().unwrap()[0].1.status, "error");
+    }
+
+    fn threat(threat_id: &str, severity: &str) -> Threat {
+        Threat {
+            threat_type: "Test Threat".to_string(),
+            details: format!("details of {}", threat_id),
+            severity: severity.to_string(),
+            threat_id: threat_id.to_string(),
+        }
+    }
+
+    #[test]
+    fn sqlite_reopen_keeps_threats_and_file_info() {
+        let dir = temp_dir();
+        let path = dir.join("peroxide.db");
+        let mut result = scan("malicious", "cc", "2024-03-01T00:00:00.000Z");
+        result.threats = vec![threat("S002", "malicious"), threat("P003", "suspicious")];
+        result.logs = vec!["[100%] Scan complete".to_string()];
+        if let Some(file_info) = result.file_info.as_mut() {
+            file_info.imphash = Some("c2e2dc9e792dd11b0a6ed0e4e55e5640".to_string());
+        }
+        SqliteStore::open(&path)
+            .unwrap()
+            .save("scan-c", &result)
+            .unwrap();
+
+        let reopened = SqliteStore::open(&path).unwrap();
+        let scans = load_all(&reopened).unwrap();
+        assert_eq!(
+            serde_json::to_value(&scans["scan-c"]).unwrap(),
+            serde_json::to_value(&result).unwrap()
+        );
+
+        // the threats table mirrors the JSON for queries by threat
+        let conn = reopened.conn.lock().unwrap();
+        let stored: Vec<(String, String)> = conn
+            .prepare("SELECT threat_id, severity FROM threats WHERE scan_id = 'scan-c' ORDER BY threat_id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            stored,
+            [
+                ("P003".to_string(), "suspicious".to_string()),
+                ("S002".to_string(), "malicious".to_string())
+            ]
+        );
+        drop(conn);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn sqlite_migrates_databases_from_before_version_1() {
+        let dir = temp_dir();
+        let path = dir.join("peroxide.db");
+        let result = tagged(scan("safe", "dd", "2024-04-01T00:00:00.000Z"), "Ladybird");
+        {
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE scans (
+                    scan_id TEXT PRIMARY KEY,
+                    status TEXT NOT NULL,
+                    created_at TEXT NOT NULL,
+                    result TEXT NOT NULL
+                );",
+            )
+            .unwrap();
+            conn.execute(
+                "INSERT INTO scans VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    "scan-d",
+                    result.status,
+                    result.created_at,
+                    serde_json::to_string(&result).unwrap()
+                ],
+            )
+            .unwrap();
+            conn.execute(
+                "INSERT INTO scans VALUES ('scan-bad', 'safe', '2024-04-02T00:00:00.000Z', 'not json')",
+                [],
+            )
+            .unwrap();
+        }
+
+        let store = SqliteStore::open(&path).unwrap();
+        // the new sha256 column was filled from the stored JSON
+        let by_hash = ScanQuery {
+            sha256: Some("dd".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query(&store, by_hash), ["scan-d"]);
+        // and so was the family column added in version 2
+        let by_tag = ScanQuery {
+            tag: Some("ladybird".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(query(&store, by_tag), ["scan-d"]);
+        // a row that no longer parses is skipped, not fatal
+        assert_eq!(ids(store.list().unwrap()), ["scan-d"]);
+        let version: i32 = store
+            .conn
+            .lock()
+            .unwrap()
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, SCHEMA_VERSION);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
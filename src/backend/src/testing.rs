@@ -0,0 +1,548 @@
+// fixtures shared by the unit tests; not every test module uses all of them
+#![allow(dead_code)]
+
+use crate::types::ScanResult;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::fs;
+use std::io::{Cursor, Write};
+use std::path::PathBuf;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+// the system allocator, keeping count of the heap each test thread holds
+// so a test can put a budget on what a pass over a large file allocates
+struct CountingAllocator;
+
+thread_local! {
+    static HEAP_IN_USE: Cell<usize> = const { Cell::new(0) };
+    static HEAP_PEAK: Cell<usize> = const { Cell::new(0) };
+}
+
+fn count_heap(grow: usize, shrink: usize) {
+    let _ = HEAP_IN_USE.try_with(|in_use| {
+        let now = (in_use.get() + grow).saturating_sub(shrink);
+        in_use.set(now);
+        let _ = HEAP_PEAK.try_with(|peak| peak.set(peak.get().max(now)));
+    });
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        count_heap(layout.size(), 0);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        count_heap(0, layout.size());
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        count_heap(new_size, layout.size());
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// runs `f` and returns the most heap it had allocated at once on this thread
+pub fn peak_heap<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let base = HEAP_IN_USE.with(|in_use| in_use.get());
+    HEAP_PEAK.with(|peak| peak.set(base));
+    let value = f();
+    (value, HEAP_PEAK.with(|peak| peak.get()) - base)
+}
+
+// a stored scan with no findings, in `status`
+pub fn scan_result(status: &str) -> ScanResult {
+    serde_json::from_value(serde_json::json!({
+        "status": status,
+        "threats": [],
+        "stats": {"threatsFound": 0, "malicious": 0, "suspicious": 0, "neutral": 0},
+        "logs": ["[0%] Initializing scan..."],
+        "imports": [],
+        "entropy": 0.0,
+        "guids": [],
+        "createdAt": "2024-01-01T00:00:00.000Z",
+    }))
+    .unwrap()
+}
+
+// a fresh, empty directory under the system temp dir; callers remove it
+pub fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("peroxide-test-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// `len` bytes of deterministic noise with near-maximal entropy
+pub fn noise(len: usize) -> Vec<u8> {
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            (state >> 32) as u8
+        })
+        .collect()
+}
+
+// a zip of `files`, stored uncompressed
+pub fn zip_archive(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+    for (name, data) in files {
+        writer.start_file(*name, options).unwrap();
+        writer.write_all(data).unwrap();
+    }
+    writer.finish().unwrap().into_inner()
+}
+
+// one DER tag-length-value, long-form length when needed
+pub fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut out = vec![tag];
+    match content.len() {
+        len @ 0..=0x7f => out.push(len as u8),
+        len @ 0x80..=0xff => out.extend_from_slice(&[0x81, len as u8]),
+        len => {
+            out.push(0x82);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+    }
+    out.extend_from_slice(content);
+    out
+}
+
+// an X.501 name holding just a common name
+fn der_name(common_name: &str) -> Vec<u8> {
+    let attribute = [
+        der(0x06, &[0x55, 0x04, 0x03]),
+        der(0x0c, common_name.as_bytes()),
+    ]
+    .concat();
+    der(0x30, &der(0x31, &der(0x30, &attribute)))
+}
+
+// a PKCS#7 SignedData blob carrying one certificate per (issuer, subject)
+// pair; the certificates are shaped right but signed by nobody
+pub fn pkcs7_signed_data(certificates: &[(&str, &str)]) -> Vec<u8> {
+    let sha256_rsa = der(
+        0x30,
+        &der(
+            0x06,
+            &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0b],
+        ),
+    );
+    let validity = der(
+        0x30,
+        &[der(0x17, b"240101000000Z"), der(0x17, b"340101000000Z")].concat(),
+    );
+    let certificates: Vec<u8> = certificates
+        .iter()
+        .flat_map(|(issuer, subject)| {
+            let tbs = [
+                der(0xa0, &der(0x02, &[2])),
+                der(0x02, &[1]),
+                sha256_rsa.clone(),
+                der_name(issuer),
+                validity.clone(),
+                der_name(subject),
+                der(0x30, &[]),
+            ]
+            .concat();
+            der(
+                0x30,
+                &[der(0x30, &tbs), sha256_rsa.clone(), der(0x03, &[0])].concat(),
+            )
+        })
+        .collect();
+    let signed_data = [
+        der(0x02, &[1]),
+        der(0x31, &[]),
+        der(0x30, &[]),
+        der(0xa0, &certificates),
+        der(0x31, &[]),
+    ]
+    .concat();
+    // contentType 1.2.840.113549.1.7.2, signedData
+    let oid = der(
+        0x06,
+        &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x07, 0x02],
+    );
+    der(0x30, &[oid, der(0xa0, &der(0x30, &signed_data))].concat())
+}
+
+pub const SECTION_CODE: u32 = 0x6000_0020;
+pub const SECTION_DATA: u32 = 0xc000_0040;
+pub const SECTION_READ_ONLY: u32 = 0x4000_0040;
+
+const FILE_ALIGNMENT: usize = 0x200;
+const SECTION_ALIGNMENT: u32 = 0x1000;
+const DOS_HEADER_SIZE: usize = 0x80;
+// .text and .idata come first, so added sections start here
+pub const FIRST_EXTRA_RVA: u32 = 0x3000;
+
+// builds small but well-formed PE32/PE32+ images: .text at 0x1000, .idata
+// at 0x2000 holding the imports, then any added sections a page apart
+// (larger ones take more pages), then the certificate table and overlay
+pub struct PeBuilder {
+    pe64: bool,
+    imports: Vec<(String, Vec<String>)>,
+    text: Vec<u8>,
+    sections: Vec<(String, Vec<u8>, u32)>,
+    directories: Vec<(usize, u32, u32)>,
+    certificate: Option<Vec<u8>>,
+    overlay: Vec<u8>,
+    data_directories: u32,
+    characteristics: u16,
+    checksum: bool,
+}
+
+impl PeBuilder {
+    pub fn new() -> Self {
+        PeBuilder {
+            pe64: false,
+            imports: Vec::new(),
+            text: vec![0x90; 16],
+            sections: Vec::new(),
+            directories: Vec::new(),
+            certificate: None,
+            overlay: Vec::new(),
+            data_directories: 16,
+            // IMAGE_FILE_EXECUTABLE_IMAGE | IMAGE_FILE_32BIT_MACHINE
+            characteristics: 0x0102,
+            checksum: false,
+        }
+    }
+
+    pub fn pe64(mut self) -> Self {
+        self.pe64 = true;
+        self
+    }
+
+    // functions written "#N" are imported by ordinal
+    pub fn import(mut self, dll: &str, functions: &[&str]) -> Self {
+        let functions = functions.iter().map(|f| f.to_string()).collect();
+        self.imports.push((dll.to_string(), functions));
+        self
+    }
+
+    // the entry point is the start of .text
+    pub fn text(mut self, code: &[u8]) -> Self {
+        self.text = code.to_vec();
+        self
+    }
+
+    pub fn section(mut self, name: &str, data: &[u8], characteristics: u32) -> Self {
+        self.sections
+            .push((name.to_string(), data.to_vec(), characteristics));
+        self
+    }
+
+    pub fn directory(mut self, index: usize, rva: u32, size: u32) -> Self {
+        self.directories.push((index, rva, size));
+        self
+    }
+
+    // a raw certificate table, see `win_certificate`
+    pub fn certificate(mut self, table: &[u8]) -> Self {
+        self.certificate = Some(table.to_vec());
+        self
+    }
+
+    pub fn overlay(mut self, data: &[u8]) -> Self {
+        self.overlay = data.to_vec();
+        self
+    }
+
+    pub fn data_directories(mut self, count: u32) -> Self {
+        self.data_directories = count;
+        self
+    }
+
+    pub fn characteristics(mut self, characteristics: u16) -> Self {
+        self.characteristics = characteristics;
+        self
+    }
+
+    // fills in the optional header checksum over the finished image
+    pub fn checksum(mut self) -> Self {
+        self.checksum = true;
+        self
+    }
+
+    pub fn optional_header_offset() -> usize {
+        DOS_HEADER_SIZE + 24
+    }
+
+    // file offset of the `index`th section header (.text is 0, .idata 1),
+    // for tests that patch its fields
+    pub fn section_header_offset(&self, index: usize) -> usize {
+        self.data_directory_offset() + self.data_directories as usize * 8 + 40 * index
+    }
+
+    fn data_directory_offset(&self) -> usize {
+        Self::optional_header_offset() + if self.pe64 { 112 } else { 96 }
+    }
+
+    fn idata(&self) -> (Vec<u8>, u32) {
+        const BASE: u32 = 0x2000;
+        let thunk_size = if self.pe64 { 8 } else { 4 };
+        let descriptors_size = 20 * (self.imports.len() + 1);
+        let thunks_size: usize = self
+            .imports
+            .iter()
+            .map(|(_, functions)| (functions.len() + 1) * thunk_size)
+            .sum();
+        let names_offset = (descriptors_size + thunks_size) as u32;
+
+        let mut descriptors = Vec::new();
+        let mut thunks = Vec::new();
+        let mut names: Vec<u8> = Vec::new();
+        let name_rva = |names: &mut Vec<u8>, bytes: &[u8]| {
+            let rva = BASE + names_offset + names.len() as u32;
+            names.extend_from_slice(bytes);
+            names.push(0);
+            if names.len() % 2 == 1 {
+                names.push(0);
+            }
+            rva
+        };
+        for (dll, functions) in &self.imports {
+            let thunk_rva = BASE + (descriptors_size + thunks.len()) as u32;
+            let dll_rva = name_rva(&mut names, dll.as_bytes());
+            for function in functions {
+                let entry = match function.strip_prefix('#') {
+                    Some(ordinal) => {
+                        let ordinal: u64 = ordinal.parse().unwrap();
+                        ordinal | if self.pe64 { 1 << 63 } else { 1 << 31 }
+                    }
+                    None => {
+                        let mut hint_name = vec![0, 0];
+                        hint_name.extend_from_slice(function.as_bytes());
+                        name_rva(&mut names, &hint_name) as u64
+                    }
+                };
+                thunks.extend_from_slice(&entry.to_le_bytes()[..thunk_size]);
+            }
+            thunks.extend_from_slice(&[0; 8][..thunk_size]);
+            for field in [thunk_rva, 0, 0, dll_rva, thunk_rva] {
+                descriptors.extend_from_slice(&field.to_le_bytes());
+            }
+        }
+        descriptors.extend_from_slice(&[0; 20]);
+
+        let mut idata = descriptors;
+        idata.extend(thunks);
+        idata.extend(names);
+        (idata, descriptors_size as u32)
+    }
+
+    pub fn build(&self) -> Vec<u8> {
+        let pad = |mut data: Vec<u8>| {
+            data.resize(align(data.len().max(1), FILE_ALIGNMENT), 0);
+            data
+        };
+        let (idata, descriptors_size) = self.idata();
+        let mut sections = vec![
+            (
+                ".text".to_string(),
+                0x1000,
+                pad(self.text.clone()),
+                SECTION_CODE,
+            ),
+            (".idata".to_string(), 0x2000, pad(idata), SECTION_DATA),
+        ];
+        let mut rva = FIRST_EXTRA_RVA;
+        for (name, data, characteristics) in &self.sections {
+            sections.push((name.clone(), rva, pad(data.clone()), *characteristics));
+            rva += align(data.len().max(1), SECTION_ALIGNMENT as usize) as u32;
+        }
+
+        let optional_size = if self.pe64 { 112 } else { 96 } + self.data_directories as usize * 8;
+        let headers_size = align(
+            DOS_HEADER_SIZE + 24 + optional_size + 40 * sections.len(),
+            FILE_ALIGNMENT,
+        );
+
+        let mut out = vec![0u8; DOS_HEADER_SIZE];
+        out[..2].copy_from_slice(b"MZ");
+        out[0x3c..0x40].copy_from_slice(&(DOS_HEADER_SIZE as u32).to_le_bytes());
+        out.extend_from_slice(b"PE\0\0");
+        let machine: u16 = if self.pe64 { 0x8664 } else { 0x14c };
+        out.extend_from_slice(&machine.to_le_bytes());
+        out.extend_from_slice(&(sections.len() as u16).to_le_bytes());
+        out.extend_from_slice(&0x5f00_0000u32.to_le_bytes());
+        out.extend_from_slice(&[0; 8]);
+        out.extend_from_slice(&(optional_size as u16).to_le_bytes());
+        out.extend_from_slice(&self.characteristics.to_le_bytes());
+
+        let mut optional = vec![0u8; optional_size];
+        let mut put = |offset: usize, bytes: &[u8]| {
+            optional[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+        put(0, &(if self.pe64 { 0x20bu16 } else { 0x10b }).to_le_bytes());
+        put(16, &0x1000u32.to_le_bytes());
+        put(32, &SECTION_ALIGNMENT.to_le_bytes());
+        put(36, &(FILE_ALIGNMENT as u32).to_le_bytes());
+        put(56, &rva.to_le_bytes());
+        put(60, &(headers_size as u32).to_le_bytes());
+        put(68, &3u16.to_le_bytes());
+        let directories = self.data_directory_offset() - Self::optional_header_offset();
+        put(directories - 4, &self.data_directories.to_le_bytes());
+        let mut set_directory = |index: usize, rva: u32, size: u32| {
+            if (index as u32) < self.data_directories {
+                put(directories + index * 8, &rva.to_le_bytes());
+                put(directories + index * 8 + 4, &size.to_le_bytes());
+            }
+        };
+        if !self.imports.is_empty() {
+            set_directory(1, 0x2000, descriptors_size);
+        }
+        for &(index, rva, size) in &self.directories {
+            set_directory(index, rva, size);
+        }
+        out.extend(optional);
+
+        let mut offset = headers_size;
+        for (name, rva, data, characteristics) in &sections {
+            let mut header = [0u8; 40];
+            header[..name.len()].copy_from_slice(name.as_bytes());
+            header[8..12].copy_from_slice(&(data.len() as u32).to_le_bytes());
+            header[12..16].copy_from_slice(&rva.to_le_bytes());
+            header[16..20].copy_from_slice(&(data.len() as u32).to_le_bytes());
+            header[20..24].copy_from_slice(&(offset as u32).to_le_bytes());
+            header[36..40].copy_from_slice(&characteristics.to_le_bytes());
+            out.extend_from_slice(&header);
+            offset += data.len();
+        }
+        out.resize(headers_size, 0);
+        for (_, _, data, _) in &sections {
+            out.extend_from_slice(data);
+        }
+
+        if let Some(table) = &self.certificate {
+            // the security directory holds a file offset, not an RVA
+            let entry = self.data_directory_offset() + 4 * 8;
+            if self.data_directories > 4 {
+                let offset = out.len() as u32;
+                out[entry..entry + 4].copy_from_slice(&offset.to_le_bytes());
+                out[entry + 4..entry + 8].copy_from_slice(&(table.len() as u32).to_le_bytes());
+            }
+            out.extend_from_slice(table);
+        }
+        out.extend_from_slice(&self.overlay);
+
+        if self.checksum {
+            let offset = Self::optional_header_offset() + 64;
+            let checksum = pe_checksum(&out, offset);
+            out[offset..offset + 4].copy_from_slice(&checksum.to_le_bytes());
+        }
+        out
+    }
+}
+
+// the optional header checksum, skipping its own four bytes
+pub fn pe_checksum(data: &[u8], checksum_offset: usize) -> u32 {
+    let mut sum: u64 = 0;
+    for (i, word) in data.chunks(2).enumerate() {
+        if (checksum_offset..checksum_offset + 4).contains(&(i * 2)) {
+            continue;
+        }
+        sum += u16::from_le_bytes([word[0], *word.get(1).unwrap_or(&0)]) as u64;
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    sum = (sum & 0xffff) + (sum >> 16);
+    (sum as u32).wrapping_add(data.len() as u32)
+}
+
+// a WIN_CERTIFICATE entry wrapping `blob`, padded to 8 bytes;
+// revision 0x200 and type 2 (PKCS#7) are what Authenticode uses
+pub fn win_certificate(blob: &[u8], revision: u16, certificate_type: u16) -> Vec<u8> {
+    let mut entry = Vec::new();
+    entry.extend_from_slice(&(8 + blob.len() as u32).to_le_bytes());
+    entry.extend_from_slice(&revision.to_le_bytes());
+    entry.extend_from_slice(&certificate_type.to_le_bytes());
+    entry.extend_from_slice(blob);
+    entry.resize(align(entry.len(), 8), 0);
+    entry
+}
+
+// an IMAGE_BASE_RELOCATION block of IMAGE_REL_BASED_HIGHLOW entries, padded
+// to 4 bytes with an absolute entry as linkers do
+pub fn relocation_block(page_rva: u32, offsets: &[u16]) -> Vec<u8> {
+    let mut entries: Vec<u16> = offsets.iter().map(|offset| 0x3000 | offset).collect();
+    if entries.len() % 2 == 1 {
+        entries.push(0);
+    }
+    let mut block = Vec::new();
+    block.extend_from_slice(&page_rva.to_le_bytes());
+    block.extend_from_slice(&(8 + 2 * entries.len() as u32).to_le_bytes());
+    for entry in entries {
+        block.extend_from_slice(&entry.to_le_bytes());
+    }
+    block
+}
+
+// an IMAGE_RESOURCE_DIRECTORY holding `entries` of (name field, target)
+fn resource_directory(named: bool, entries: &[(u32, u32)]) -> Vec<u8> {
+    let mut directory = vec![0; 12];
+    let count = entries.len() as u16;
+    let (named, ids) = if named { (count, 0) } else { (0, count) };
+    directory.extend_from_slice(&named.to_le_bytes());
+    directory.extend_from_slice(&ids.to_le_bytes());
+    for (name, target) in entries {
+        directory.extend_from_slice(&name.to_le_bytes());
+        directory.extend_from_slice(&target.to_le_bytes());
+    }
+    directory
+}
+
+// the contents of a .rsrc section mapped at `rva`, one type / name / language
+// branch per (type id, name, language, data); names written "#N" are ids
+pub fn resource_section(rva: u32, resources: &[(u32, &str, u32, &[u8])]) -> Vec<u8> {
+    const SUBDIRECTORY: u32 = 0x8000_0000;
+    let root_size = 16 + 8 * resources.len();
+    let mut types = Vec::new();
+    let mut body = Vec::new();
+    for (type_id, name, language, data) in resources {
+        let name_directory = (root_size + body.len()) as u32;
+        let language_directory = name_directory + 24;
+        let data_entry = language_directory + 24;
+        let mut string = Vec::new();
+        let name_field = match name.strip_prefix('#') {
+            Some(id) => id.parse().unwrap(),
+            None => {
+                string.extend_from_slice(&(name.len() as u16).to_le_bytes());
+                string.extend(name.encode_utf16().flat_map(u16::to_le_bytes));
+                string.resize(align(string.len(), 4), 0);
+                SUBDIRECTORY | (data_entry + 16)
+            }
+        };
+        let data_offset = data_entry + 16 + string.len() as u32;
+
+        types.push((*type_id, SUBDIRECTORY | name_directory));
+        body.extend(resource_directory(
+            !string.is_empty(),
+            &[(name_field, SUBDIRECTORY | language_directory)],
+        ));
+        body.extend(resource_directory(false, &[(*language, data_entry)]));
+        for field in [rva + data_offset, data.len() as u32, 0, 0] {
+            body.extend_from_slice(&field.to_le_bytes());
+        }
+        body.extend(string);
+        body.extend_from_slice(data);
+        body.resize(align(body.len(), 4), 0);
+    }
+    [resource_directory(false, &types), body].concat()
+}
+
+fn align(value: usize, alignment: usize) -> usize {
+    match value % alignment {
+        0 => value,
+        rest => value + alignment - rest,
+    }
+}
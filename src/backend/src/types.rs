@@ -1,54 +1,294 @@
-use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-
-// shared state for storing scan results
-pub type ScanStore = Arc<Mutex<HashMap<String, ScanResult>>>;
-
-#[derive(Clone, Serialize, Deserialize)]
-pub struct UploadResponse {
-    #[serde(rename = "scanId")]
-    pub scan_id: String,
-}
-
-#[derive(Clone, Serialize, Deserialize)]
-pub struct ScanResult {
-    pub status: String,
-    pub threats: Vec<Threat>,
-    pub stats: ScanStats,
-    pub logs: Vec<String>,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub file_info: Option<FileInfo>,
-}
-
-#[derive(Clone, Serialize, Deserialize)]
-pub struct FileInfo {
-    pub filename: String,
-    pub size: u64,
-    pub sha256: String,
-}
-
-#[derive(Clone, Serialize, Deserialize)]
-pub struct Threat {
-    #[serde(rename = "type")]
-    pub threat_type: String,
-    pub details: String,
-    pub severity: String, // "malicious", "suspicious", or "neutral"
-    #[serde(rename = "threatId")]
-    pub threat_id: String,
-}
-
-#[derive(Clone, Serialize, Deserialize)]
-pub struct ScanStats {
-    #[serde(rename = "threatsFound")]
-    pub threats_found: usize,
-    pub malicious: usize,
-    pub suspicious: usize,
-    pub neutral: usize,
-}
-
-#[derive(Clone, Serialize, Deserialize)]
-pub struct ProgressUpdate {
-    pub progress: u32,
-    pub message: String,
-}
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+
+// shared state for storing scan results
+pub type ScanStore = Arc<Mutex<HashMap<String, ScanResult>>>;
+
+// the body of every error response: a stable `code` for clients to branch on
+// next to the human-readable message, which stays under "error" where
+// existing clients already read it; `status` is the HTTP status it goes out with
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ApiError {
+    #[serde(skip)]
+    pub status: u16,
+    pub code: String,
+    #[serde(rename = "error")]
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn new(status: u16, code: &str, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            code: code.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct UploadResponse {
+    #[serde(rename = "scanId")]
+    pub scan_id: String,
+    // true when the scan of an identical earlier upload is returned instead
+    #[serde(default)]
+    pub cached: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ScanResult {
+    pub status: String,
+    // score-based counterpart to `status`, set once the scan completes
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub verdict: Option<String>,
+    pub threats: Vec<Threat>,
+    pub stats: ScanStats,
+    pub logs: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_info: Option<FileInfo>,
+    #[serde(rename = "peInfo", skip_serializing_if = "Option::is_none")]
+    pub pe_info: Option<PeInfo>,
+    pub imports: Vec<ImportedDll>,
+    #[serde(default)]
+    pub resources: Vec<Resource>,
+    // whether the PE carries a well-formed PKCS#7 Authenticode entry; the
+    // chain itself isn't validated
+    #[serde(default)]
+    pub signed: bool,
+    // size of the certificate table in bytes, when there is one
+    #[serde(
+        rename = "certificateSize",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub certificate_size: Option<u32>,
+    // bytes after the last section, not counting the certificate table
+    #[serde(rename = "overlaySize", default)]
+    pub overlay_size: u64,
+    // whole-file Shannon entropy, 0-8 bits per byte
+    pub entropy: f64,
+    // share of zero bytes, 0-1, from the same byte histogram as `entropy`
+    #[serde(rename = "zeroRatio", default)]
+    pub zero_ratio: f64,
+    pub guids: Vec<String>,
+    // capped for the response, see `cap_strings`; defaulted so rows stored
+    // before strings were reported still load
+    #[serde(default)]
+    pub strings: ExtractedStrings,
+    // total ASCII and UTF-16LE strings found, before the reporting caps
+    #[serde(rename = "stringsCount", default)]
+    pub strings_count: usize,
+    // heuristic best guess, see `confidence`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub family: Option<FamilyMatch>,
+    // packer named by a section name signature
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub packer: Option<String>,
+    // SHA-256 of `canonical_result`, set once the scan completes; equal
+    // hashes mean equal findings regardless of field or threat order
+    #[serde(
+        rename = "resultHash",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub result_hash: Option<String>,
+    // RFC 3339 UTC with fixed millisecond precision, so it sorts as a string
+    #[serde(rename = "createdAt")]
+    pub created_at: String,
+    // set by the cancel endpoint, checked by the scanner between phases
+    #[serde(skip)]
+    pub cancel_flag: Arc<AtomicBool>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FileInfo {
+    pub filename: String,
+    pub size: u64,
+    pub sha256: String,
+    pub md5: String,
+    pub sha1: String,
+    // only present for PEs with an import table
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub imphash: Option<String>,
+    // signature-excluded hash, stable across re-signing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authentihash: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PeInfo {
+    pub machine: String,
+    #[serde(rename = "numberOfSections")]
+    pub number_of_sections: u16,
+    pub timestamp: u32,
+    pub subsystem: String,
+    #[serde(rename = "is64Bit")]
+    pub is_64bit: bool,
+    // raw IMAGE_FILE_* flags from the file header
+    #[serde(default)]
+    pub characteristics: u16,
+    #[serde(rename = "largeAddressAware", default)]
+    pub large_address_aware: bool,
+    // see `PeHeaders::header_anomalies`
+    #[serde(rename = "headerAnomalies", default)]
+    pub header_anomalies: Vec<String>,
+    // declared data directory count, normally 16
+    #[serde(rename = "numberOfRvaAndSizes", default)]
+    pub number_of_rva_and_sizes: u32,
+    // "self-signed", "test" or "chained" when an Authenticode signature is present
+    #[serde(rename = "signatureTrust", skip_serializing_if = "Option::is_none")]
+    pub signature_trust: Option<String>,
+}
+
+// one leaf of the PE resource tree
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Resource {
+    // a standard type such as "ICON" or "RCDATA", "#<id>" otherwise, or the type's name
+    #[serde(rename = "type")]
+    pub resource_type: String,
+    // "#<id>" or the resource's name
+    pub name: String,
+    pub language: u32,
+    pub size: u32,
+    // "MZ", "PK", "PNG" or "XML" when the content is recognized
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ImportedDll {
+    pub dll: String,
+    // function names, or "#<ordinal>" for imports by ordinal
+    pub functions: Vec<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FamilyMatch {
+    pub name: String,
+    // share of the family signature's weighted evidence found, 0-1
+    pub confidence: f64,
+    pub evidence: Vec<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Threat {
+    #[serde(rename = "type")]
+    pub threat_type: String,
+    pub details: String,
+    pub severity: String, // "malicious", "suspicious", or "neutral"
+    #[serde(rename = "threatId")]
+    pub threat_id: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ScanStats {
+    #[serde(rename = "threatsFound")]
+    pub threats_found: usize,
+    pub malicious: usize,
+    pub suspicious: usize,
+    pub neutral: usize,
+    // weighted 0-100 summary of the threats, see `scanner::threat_score`
+    #[serde(default)]
+    pub score: u32,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ProgressUpdate {
+    // None for log lines that carry no percentage, such as a failure message
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub progress: Option<u32>,
+    pub message: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct IntegrityResponse {
+    #[serde(rename = "scanId")]
+    pub scan_id: String,
+    pub verified: bool,
+    #[serde(rename = "expectedSha256")]
+    pub expected_sha256: String,
+    #[serde(rename = "actualSha256")]
+    pub actual_sha256: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StringsResponse {
+    #[serde(rename = "scanId")]
+    pub scan_id: String,
+    #[serde(rename = "minLen")]
+    pub min_len: usize,
+    pub ascii: Vec<String>,
+    pub wide: Vec<String>,
+    // strings found before the reporting caps, so clients know the list is partial
+    #[serde(rename = "totalCount")]
+    pub total_count: usize,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ExtractedStrings {
+    pub ascii: Vec<String>,
+    // UTF-16LE strings, as found in Windows resources and wide-char APIs
+    pub wide: Vec<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CancelResponse {
+    #[serde(rename = "scanId")]
+    pub scan_id: String,
+    pub status: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WindowStats {
+    pub uploads: usize,
+    pub completions: usize,
+    // completed scans by final status
+    pub verdicts: BTreeMap<String, usize>,
+    #[serde(rename = "verdictRates")]
+    pub verdict_rates: BTreeMap<String, f64>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StatsResponse {
+    // keyed by window: "1m", "5m", "1h"
+    pub windows: BTreeMap<String, WindowStats>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct HealthResponse {
+    pub status: String,
+    pub uptime_secs: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MetricsResponse {
+    #[serde(rename = "totalScans")]
+    pub total_scans: u64,
+    // only scans that finished since startup
+    #[serde(rename = "scansByStatus")]
+    pub scans_by_status: BTreeMap<String, u64>,
+    #[serde(rename = "bytesProcessed")]
+    pub bytes_processed: u64,
+    #[serde(rename = "queueDepth")]
+    pub queue_depth: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ScanSummary {
+    #[serde(rename = "scanId")]
+    pub scan_id: String,
+    pub status: String,
+    pub filename: String,
+    #[serde(rename = "threatsFound")]
+    pub threats_found: usize,
+    pub timestamp: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DeleteResponse {
+    #[serde(rename = "scanId")]
+    pub scan_id: String,
+    pub deleted: bool,
+}
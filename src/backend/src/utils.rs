@@ -1,63 +1,736 @@
-use crate::types::ScanStore;
-use sha2::{Digest, Sha256};
-use tiny_http::{Header, Response};
-
-pub fn add_cors_headers<R: std::io::Read>(response: Response<R>) -> Response<R> {
-    response
-        .with_header(Header::from_bytes(&b"Access-Control-Allow-Origin"[..], &b"*"[..]).unwrap())
-        .with_header(
-            Header::from_bytes(
-                &b"Access-Control-Allow-Methods"[..],
-                &b"GET, POST, OPTIONS"[..],
-            )
-            .unwrap(),
-        )
-        .with_header(
-            Header::from_bytes(&b"Access-Control-Allow-Headers"[..], &b"Content-Type"[..]).unwrap(),
-        )
-}
-
-pub fn parse_multipart(body: &[u8], boundary: &str) -> Result<(String, Vec<u8>), String> {
-    let body_str = String::from_utf8_lossy(body);
-
-    let parts: Vec<&str> = body_str.split(&format!("--{}", boundary)).collect();
-
-    for part in parts {
-        if part.contains("Content-Disposition") && part.contains("filename=") {
-            let filename = part
-                .lines()
-                .find(|line| line.contains("filename="))
-                .and_then(|line| {
-                    line.split("filename=\"")
-                        .nth(1)
-                        .and_then(|s| s.split('"').next())
-                })
-                .unwrap_or("uploaded_file")
-                .to_string();
-
-            if let Some(data_start) = part.find("\r\n\r\n") {
-                let data_section = &part[data_start + 4..];
-                let data_end = data_section.find("\r\n--").unwrap_or(data_section.len());
-                let file_data = data_section.as_bytes()[..data_end].to_vec();
-
-                return Ok((filename, file_data));
-            }
-        }
-    }
-
-    Err("No file found in multipart data".to_string())
-}
-
-pub fn send_progress(scan_id: &str, progress: u32, message: &str, scan_store: &ScanStore) {
-    let mut store = scan_store.lock().unwrap();
-    if let Some(result) = store.get_mut(scan_id) {
-        result.logs.push(format!("[{}%] {}", progress, message));
-    }
-}
-
-pub fn calculate_sha256(data: &[u8]) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(data);
-    let result = hasher.finalize();
-    format!("{:x}", result)
-}
+use crate::config::*;
+use crate::types::{ApiError, ImportedDll, ScanResult, ScanStore};
+use chrono::{SecondsFormat, Utc};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use md5::Md5;
+use memmap2::Mmap;
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{Cursor, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use tiny_http::{Header, Request, Response};
+
+// the Access-Control-Allow-Origin decision for one request, made in the
+// accept loop and handed to whatever ends up responding
+#[derive(Clone)]
+pub struct Cors {
+    // None leaves the header out, so browsers refuse the cross-origin read
+    allow_origin: Option<String>,
+}
+
+impl Cors {
+    // any origin when none are configured (or "*" is), otherwise the
+    // request's own Origin only if it's on the list
+    pub fn new(request: &Request, allowed_origins: &[String]) -> Self {
+        let origin = request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Origin"))
+            .map(|h| h.value.as_str());
+        Cors::for_origin(origin, allowed_origins)
+    }
+
+    // `new` given the request's Origin header, if it sent one
+    pub fn for_origin(origin: Option<&str>, allowed_origins: &[String]) -> Self {
+        if allowed_origins.is_empty() || allowed_origins.iter().any(|o| o == "*") {
+            return Cors {
+                allow_origin: Some("*".to_string()),
+            };
+        }
+        Cors {
+            allow_origin: origin
+                .filter(|origin| allowed_origins.iter().any(|o| o == origin))
+                .map(str::to_string),
+        }
+    }
+
+    // false only when an allow-list is configured and the request's Origin
+    // isn't on it (or it sent none)
+    pub fn allows_origin(&self) -> bool {
+        self.allow_origin.is_some()
+    }
+}
+
+// a response carrying `body`, gzipped when the request's Accept-Encoding
+// allows it and the body is at least GZIP_MIN_SIZE; streamed responses (SSE,
+// WebSocket) write their own frames and never come through here
+pub fn compressed_response(request: &Request, body: String) -> Response<Cursor<Vec<u8>>> {
+    if body.len() < GZIP_MIN_SIZE {
+        return Response::from_string(body);
+    }
+    // whichever way this goes, the body depends on Accept-Encoding
+    let vary = Header::from_bytes(&b"Vary"[..], &b"Accept-Encoding"[..]).unwrap();
+    if !accepts_gzip(request) {
+        return Response::from_string(body).with_header(vary);
+    }
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    match encoder
+        .write_all(body.as_bytes())
+        .and_then(|_| encoder.finish())
+    {
+        Ok(gzipped) => Response::from_data(gzipped)
+            .with_header(Header::from_bytes(&b"Content-Encoding"[..], &b"gzip"[..]).unwrap())
+            .with_header(vary),
+        Err(_) => Response::from_string(body).with_header(vary),
+    }
+}
+
+pub fn json_response(request: &Request, body: String) -> Response<Cursor<Vec<u8>>> {
+    compressed_response(request, body)
+        .with_header(Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap())
+}
+
+// "gzip" listed in Accept-Encoding without q=0
+fn accepts_gzip(request: &Request) -> bool {
+    request
+        .headers()
+        .iter()
+        .filter(|h| h.field.equiv("Accept-Encoding"))
+        .flat_map(|h| h.value.as_str().split(','))
+        .any(|coding| {
+            let mut params = coding.split(';').map(str::trim);
+            let name = params.next().unwrap_or("");
+            let refused = params.any(|p| {
+                p.strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            name.eq_ignore_ascii_case("gzip") && !refused
+        })
+}
+
+pub fn error_response(error: &ApiError, cors: &Cors) -> Response<Cursor<Vec<u8>>> {
+    let response = Response::from_string(serde_json::to_string(error).unwrap())
+        .with_status_code(error.status)
+        .with_header(Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap());
+    add_cors_headers(response, cors)
+}
+
+pub fn add_cors_headers<R: std::io::Read>(response: Response<R>, cors: &Cors) -> Response<R> {
+    // with an allow-list the headers depend on the request's Origin, so
+    // caches must key on it whether or not it was allowed
+    let vary = Header::from_bytes(&b"Vary"[..], &b"Origin"[..]).unwrap();
+    let response = match cors.allow_origin.as_deref() {
+        None => response.with_header(vary),
+        Some("*") => response.with_header(
+            Header::from_bytes(&b"Access-Control-Allow-Origin"[..], &b"*"[..]).unwrap(),
+        ),
+        Some(origin) => response
+            .with_header(
+                Header::from_bytes(&b"Access-Control-Allow-Origin"[..], origin.as_bytes()).unwrap(),
+            )
+            .with_header(vary),
+    };
+    response
+        .with_header(
+            Header::from_bytes(
+                &b"Access-Control-Allow-Methods"[..],
+                &b"GET, POST, DELETE, OPTIONS"[..],
+            )
+            .unwrap(),
+        )
+        .with_header(
+            Header::from_bytes(
+                &b"Access-Control-Allow-Headers"[..],
+                &b"Content-Type, X-API-Key"[..],
+            )
+            .unwrap(),
+        )
+}
+
+// reads a request body in READ_BUFFER_SIZE chunks, pre-sizing from Content-Length
+// when given (capped so a bogus header can't reserve more than an upload may use)
+pub fn read_body<R: Read + ?Sized>(
+    reader: &mut R,
+    content_length: Option<usize>,
+    max_file_size: u64,
+) -> std::io::Result<Vec<u8>> {
+    let capacity = content_length
+        .map(|len| len.min(max_file_size as usize))
+        .unwrap_or(READ_BUFFER_SIZE);
+    let mut body = Vec::with_capacity(capacity);
+    let mut chunk = vec![0u8; READ_BUFFER_SIZE];
+    loop {
+        match reader.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => body.extend_from_slice(&chunk[..n]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(body)
+}
+
+// every file part of a multipart/form-data body, in order; the bodies are
+// sliced out as raw bytes since samples are rarely valid UTF-8
+pub fn parse_multipart(body: &[u8], boundary: &str) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let delimiter = format!("--{}", boundary);
+    let mut files = Vec::new();
+
+    for part in split_bytes(body, delimiter.as_bytes()) {
+        let Some(headers_end) = find_bytes(part, b"\r\n\r\n") else {
+            continue;
+        };
+        let headers = String::from_utf8_lossy(&part[..headers_end]);
+        if !(headers.contains("Content-Disposition") && headers.contains("filename=")) {
+            continue;
+        }
+
+        let filename = headers
+            .lines()
+            .find(|line| line.contains("filename="))
+            .and_then(|line| {
+                line.split("filename=\"")
+                    .nth(1)
+                    .and_then(|s| s.split('"').next())
+            })
+            .unwrap_or("uploaded_file")
+            .to_string();
+
+        // the CRLF ahead of the next delimiter belongs to the delimiter
+        let data = &part[headers_end + 4..];
+        let data = data.strip_suffix(b"\r\n").unwrap_or(data);
+        files.push((filename, data.to_vec()));
+    }
+
+    if files.is_empty() {
+        return Err("No file found in multipart data".to_string());
+    }
+    Ok(files)
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn split_bytes<'a>(data: &'a [u8], separator: &[u8]) -> Vec<&'a [u8]> {
+    let mut pieces = Vec::new();
+    let mut rest = data;
+    while let Some(pos) = find_bytes(rest, separator) {
+        pieces.push(&rest[..pos]);
+        rest = &rest[pos + separator.len()..];
+    }
+    pieces.push(rest);
+    pieces
+}
+
+// first value for `key` in a raw query string, percent-decoded (with "+"
+// as a space) so e.g. `since=2024-01-01T00%3A00%3A00Z` works
+pub fn query_param(query: &str, key: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(k, _)| percent_decode(k) == key)
+        .map(|(_, v)| percent_decode(v))
+}
+
+// malformed escapes are kept as they are, and invalid UTF-8 is replaced
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match bytes.get(i..i + 3) {
+            Some([b'%', high, low]) if high.is_ascii_hexdigit() && low.is_ascii_hexdigit() => {
+                std::str::from_utf8(&bytes[i + 1..i + 3])
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            }
+            _ => None,
+        };
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(if bytes[i] == b'+' { b' ' } else { bytes[i] });
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+// an earlier scan of the same file whose result can be handed out again:
+// the newest finished one, else one still running; failed scans don't count
+pub fn find_reusable_scan(scan_store: &ScanStore, sha256: &str) -> Option<String> {
+    let store = scan_store.lock().unwrap();
+    let same_file =
+        |result: &&ScanResult| result.file_info.as_ref().map(|f| f.sha256.as_str()) == Some(sha256);
+    let finished = store
+        .iter()
+        .filter(|(_, result)| matches!(result.status.as_str(), "safe" | "suspicious" | "unsafe"))
+        .filter(|(_, result)| same_file(result))
+        .max_by(|(_, a), (_, b)| a.created_at.cmp(&b.created_at));
+    let running = || {
+        store
+            .iter()
+            .filter(|(_, result)| result.status == "scanning")
+            .find(|(_, result)| same_file(result))
+    };
+    finished
+        .or_else(running)
+        .map(|(scan_id, _)| scan_id.clone())
+}
+
+pub fn send_progress(scan_id: &str, progress: u32, message: &str, scan_store: &ScanStore) {
+    let mut store = scan_store.lock().unwrap();
+    // a scan that already timed out keeps its final log line last
+    if let Some(result) = store.get_mut(scan_id).filter(|r| r.status == "scanning") {
+        result.logs.push(format!("[{}%] {}", progress, message));
+    }
+}
+
+pub fn calculate_sha256(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    let result = hasher.finalize();
+    format!("{:x}", result)
+}
+
+// hashes a file from disk without loading it all into memory
+pub fn calculate_sha256_file(path: &Path) -> std::io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut chunk = vec![0u8; READ_BUFFER_SIZE];
+    loop {
+        match file.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(n) => hasher.update(&chunk[..n]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+// maps a sample read-only for analysis, so a scan works straight from the
+// page cache instead of a heap copy, and passes that only look at the headers
+// only ever fault in the first few pages
+pub fn map_file(path: &Path) -> std::io::Result<Mmap> {
+    let file = File::open(path)?;
+    // SAFETY: samples are written in full before their scan starts and are
+    // only removed, never rewritten or truncated, while it runs
+    unsafe { Mmap::map(&file) }
+}
+
+pub fn sample_path(upload_dir: &Path, scan_id: &str, filename: &str) -> PathBuf {
+    upload_dir.join(format!("{}_{}", scan_id, filename))
+}
+
+// MD5 and SHA1 in one pass so large files are only walked once
+pub fn calculate_md5_sha1(data: &[u8]) -> (String, String) {
+    let mut md5 = Md5::new();
+    let mut sha1 = Sha1::new();
+    for chunk in data.chunks(READ_BUFFER_SIZE) {
+        md5.update(chunk);
+        sha1.update(chunk);
+    }
+    (
+        format!("{:x}", md5.finalize()),
+        format!("{:x}", sha1.finalize()),
+    )
+}
+
+// follows pefile's get_imphash: lowercased "dll.function" pairs joined by
+// commas and MD5'd, with ordinal imports written as "ord<N>"
+pub fn calculate_imphash(imports: &[ImportedDll]) -> Option<String> {
+    let mut entries = Vec::new();
+    for import in imports {
+        let dll = import.dll.to_ascii_lowercase();
+        let dll = match dll.rsplit_once('.') {
+            Some((stem, "dll" | "ocx" | "sys")) => stem.to_string(),
+            _ => dll,
+        };
+        for function in &import.functions {
+            let function = match function.strip_prefix('#') {
+                Some(ordinal) => format!("ord{}", ordinal),
+                None => function.to_ascii_lowercase(),
+            };
+            entries.push(format!("{}.{}", dll, function));
+        }
+    }
+
+    if entries.is_empty() {
+        return None;
+    }
+
+    let mut hasher = Md5::new();
+    hasher.update(entries.join(",").as_bytes());
+    Some(format!("{:x}", hasher.finalize()))
+}
+
+pub fn timestamp_now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pe::{parse_headers, parse_imports};
+    use crate::testing::{noise, peak_heap, scan_result, temp_dir, PeBuilder};
+    use crate::types::FileInfo;
+    use flate2::read::GzDecoder;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn query_params_are_percent_decoded() {
+        let query = "since=2024-01-01T00%3A00%3A00.000Z&name=a+b%20c&format=csv";
+        assert_eq!(
+            query_param(query, "since").as_deref(),
+            Some("2024-01-01T00:00:00.000Z")
+        );
+        assert_eq!(query_param(query, "name").as_deref(), Some("a b c"));
+        assert_eq!(query_param(query, "format").as_deref(), Some("csv"));
+        assert_eq!(query_param(query, "until"), None);
+        // the key is decoded too, and the first value wins
+        assert_eq!(
+            query_param("f%6Frmat=txt&format=csv", "format").as_deref(),
+            Some("txt")
+        );
+    }
+
+    #[test]
+    fn malformed_escapes_are_kept() {
+        assert_eq!(query_param("q=100%", "q").as_deref(), Some("100%"));
+        assert_eq!(query_param("q=%zz%4", "q").as_deref(), Some("%zz%4"));
+        assert_eq!(query_param("q=%+5", "q").as_deref(), Some("% 5"));
+        assert_eq!(query_param("q=%ff", "q").as_deref(), Some("\u{fffd}"));
+    }
+
+    // hands out at most `step` bytes per read, interrupted every other call
+    struct Trickle {
+        data: Vec<u8>,
+        step: usize,
+        interrupt: bool,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(ErrorKind::Interrupted.into());
+            }
+            let n = self.step.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data.drain(..n);
+            Ok(n)
+        }
+    }
+
+    fn trickle(len: usize) -> Trickle {
+        Trickle {
+            data: (0..len).map(|i| i as u8).collect(),
+            step: 1000,
+            interrupt: false,
+        }
+    }
+
+    #[test]
+    fn declared_length_presizes_the_body() {
+        let len = 3 * READ_BUFFER_SIZE + 17;
+        let body = read_body(&mut trickle(len), Some(len), u64::MAX).unwrap();
+        assert_eq!(body, trickle(len).data);
+        // filled in place, never grown past what was reserved
+        assert_eq!(body.capacity(), len);
+
+        // without a length the buffer has to grow, overshooting as it does
+        let body = read_body(&mut trickle(len), None, u64::MAX).unwrap();
+        assert_eq!(body.len(), len);
+        assert!(body.capacity() > len);
+    }
+
+    #[test]
+    fn declared_length_is_capped_by_the_limit() {
+        let body = read_body(&mut trickle(10), Some(usize::MAX), 64).unwrap();
+        assert_eq!(body.len(), 10);
+        assert_eq!(body.capacity(), 64);
+    }
+
+    #[test]
+    fn read_errors_are_returned() {
+        struct Broken;
+        impl Read for Broken {
+            fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+                Err(ErrorKind::ConnectionReset.into())
+            }
+        }
+        let error = read_body(&mut Broken, None, u64::MAX).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ConnectionReset);
+    }
+
+    #[test]
+    fn hashes_match_known_vectors() {
+        assert_eq!(
+            calculate_md5_sha1(b""),
+            (
+                "d41d8cd98f00b204e9800998ecf8427e".to_string(),
+                "da39a3ee5e6b4b0d3255bfef95601890afd80709".to_string()
+            )
+        );
+        assert_eq!(
+            calculate_md5_sha1(b"abc"),
+            (
+                "900150983cd24fb0d6963f7d28e17f72".to_string(),
+                "a9993e364706816aba3e25717850c26c9cd0d89d".to_string()
+            )
+        );
+        // spans many READ_BUFFER_SIZE chunks
+        assert_eq!(
+            calculate_md5_sha1(&vec![b'a'; 1_000_000]),
+            (
+                "7707d6ae4e027c70eea2a935c2296f21".to_string(),
+                "34aa973cd4c4daa4f61eeb2bdbad27316534016f".to_string()
+            )
+        );
+        assert_eq!(
+            calculate_sha256(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn imphash_follows_pefile() {
+        let pe = PeBuilder::new()
+            .import("KERNEL32.dll", &["ExitProcess", "VirtualAlloc"])
+            .import("USER32.dll", &["MessageBoxA", "#2000"])
+            .import("msvcrt.dll", &["printf"])
+            .build();
+        let imports = parse_imports(&pe, &parse_headers(&pe).unwrap());
+        // md5 of "kernel32.exitprocess,kernel32.virtualalloc,user32.messageboxa,
+        // user32.ord2000,msvcrt.printf", as pefile's get_imphash gives
+        assert_eq!(
+            calculate_imphash(&imports).as_deref(),
+            Some("c2e2dc9e792dd11b0a6ed0e4e55e5640")
+        );
+        assert_eq!(calculate_imphash(&[]), None);
+    }
+
+    fn stored_scan(status: &str, sha256: &str, created_at: &str) -> ScanResult {
+        let mut result = scan_result(status);
+        result.created_at = created_at.to_string();
+        result.file_info = Some(FileInfo {
+            filename: "sample.exe".to_string(),
+            size: 6,
+            sha256: sha256.to_string(),
+            md5: "m".to_string(),
+            sha1: "s".to_string(),
+            imphash: None,
+            authentihash: None,
+        });
+        result
+    }
+
+    fn store_of(scans: &[(&str, ScanResult)]) -> ScanStore {
+        let scans: HashMap<String, ScanResult> = scans
+            .iter()
+            .map(|(scan_id, result)| (scan_id.to_string(), result.clone()))
+            .collect();
+        Arc::new(Mutex::new(scans))
+    }
+
+    #[test]
+    fn reusable_scan_is_the_newest_finished_one() {
+        let store = store_of(&[
+            (
+                "scan-old",
+                stored_scan("safe", "aa", "2024-01-01T00:00:00.000Z"),
+            ),
+            (
+                "scan-new",
+                stored_scan("unsafe", "aa", "2024-03-01T00:00:00.000Z"),
+            ),
+            (
+                "scan-running",
+                stored_scan("scanning", "aa", "2024-04-01T00:00:00.000Z"),
+            ),
+            (
+                "scan-other",
+                stored_scan("safe", "bb", "2024-05-01T00:00:00.000Z"),
+            ),
+        ]);
+        assert_eq!(
+            find_reusable_scan(&store, "aa").as_deref(),
+            Some("scan-new")
+        );
+        assert_eq!(
+            find_reusable_scan(&store, "bb").as_deref(),
+            Some("scan-other")
+        );
+        assert_eq!(find_reusable_scan(&store, "cc"), None);
+    }
+
+    #[test]
+    fn running_scan_is_joined_when_none_finished() {
+        let store = store_of(&[
+            (
+                "scan-failed",
+                stored_scan("error", "aa", "2024-03-01T00:00:00.000Z"),
+            ),
+            (
+                "scan-running",
+                stored_scan("scanning", "aa", "2024-01-01T00:00:00.000Z"),
+            ),
+        ]);
+        assert_eq!(
+            find_reusable_scan(&store, "aa").as_deref(),
+            Some("scan-running")
+        );
+    }
+
+    #[test]
+    fn failed_and_cancelled_scans_are_never_reused() {
+        let store = store_of(&[
+            (
+                "scan-failed",
+                stored_scan("error", "aa", "2024-01-01T00:00:00.000Z"),
+            ),
+            (
+                "scan-cancelled",
+                stored_scan("cancelled", "aa", "2024-01-01T00:00:00.000Z"),
+            ),
+            ("scan-unnamed", scan_result("safe")),
+        ]);
+        assert_eq!(find_reusable_scan(&store, "aa"), None);
+    }
+
+    const LARGE_FILE_SIZE: usize = 64 * 1024 * 1024;
+
+    // a large sample written a chunk at a time, and its SHA256 hashed alongside
+    fn large_file(path: &Path) -> String {
+        let chunk = noise(1024 * 1024);
+        let mut file = File::create(path).unwrap();
+        let mut reference = Sha256::new();
+        for _ in 0..LARGE_FILE_SIZE / chunk.len() {
+            file.write_all(&chunk).unwrap();
+            reference.update(&chunk);
+        }
+        format!("{:x}", reference.finalize())
+    }
+
+    #[test]
+    fn large_files_are_hashed_without_loading_them() {
+        let dir = temp_dir();
+        let path = dir.join("large.bin");
+        let reference = large_file(&path);
+
+        let (sha256, heap) = peak_heap(|| calculate_sha256_file(&path).unwrap());
+        assert_eq!(sha256, reference);
+        assert!(heap < 2 * READ_BUFFER_SIZE, "{} bytes of heap", heap);
+
+        let (hashes, heap) = peak_heap(|| {
+            let content = map_file(&path).unwrap();
+            assert_eq!(content.len(), LARGE_FILE_SIZE);
+            (calculate_sha256(&content), calculate_md5_sha1(&content))
+        });
+        assert_eq!(hashes.0, reference);
+        assert_eq!(hashes.1 .0.len(), 32);
+        assert_eq!(hashes.1 .1.len(), 40);
+        assert!(heap < 64 * 1024, "{} bytes of heap", heap);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn mapped_files_read_like_their_contents() {
+        let dir = temp_dir();
+        let path = dir.join("sample.bin");
+        fs::write(&path, b"MZ sample").unwrap();
+        assert_eq!(&map_file(&path).unwrap()[..], b"MZ sample");
+        assert!(map_file(&dir.join("missing.bin")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn requested_with(accept_encoding: Option<&str>) -> Request {
+        let request = tiny_http::TestRequest::new();
+        let request = match accept_encoding {
+            Some(value) => request.with_header(
+                Header::from_bytes(&b"Accept-Encoding"[..], value.as_bytes()).unwrap(),
+            ),
+            None => request,
+        };
+        request.into()
+    }
+
+    fn header<R: Read>(response: &Response<R>, name: &'static str) -> Option<String> {
+        response
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv(name))
+            .map(|h| h.value.to_string())
+    }
+
+    // the body a client ends up with, and whether it came gzipped
+    fn received(response: Response<Cursor<Vec<u8>>>) -> (String, bool) {
+        let gzipped = header(&response, "Content-Encoding").as_deref() == Some("gzip");
+        let bytes = response.into_reader().into_inner();
+        let mut body = String::new();
+        if gzipped {
+            GzDecoder::new(&bytes[..])
+                .read_to_string(&mut body)
+                .unwrap();
+        } else {
+            body = String::from_utf8(bytes).unwrap();
+        }
+        (body, gzipped)
+    }
+
+    #[test]
+    fn large_responses_are_gzipped_when_accepted() {
+        let body = "{\"logs\": \"".to_string() + &"scan line ".repeat(500) + "\"}";
+        let response = json_response(&requested_with(Some("gzip, deflate")), body.clone());
+        assert_eq!(
+            header(&response, "Vary").as_deref(),
+            Some("Accept-Encoding")
+        );
+        assert_eq!(
+            header(&response, "Content-Type").as_deref(),
+            Some("application/json")
+        );
+        assert!(response.data_length().unwrap() < body.len() / 10);
+        assert_eq!(received(response), (body.clone(), true));
+
+        let response = json_response(&requested_with(None), body.clone());
+        assert_eq!(
+            header(&response, "Vary").as_deref(),
+            Some("Accept-Encoding")
+        );
+        assert_eq!(received(response), (body, false));
+    }
+
+    #[test]
+    fn small_responses_are_never_gzipped() {
+        let body = "x".repeat(GZIP_MIN_SIZE - 1);
+        let response = compressed_response(&requested_with(Some("gzip")), body.clone());
+        assert_eq!(header(&response, "Vary"), None);
+        assert_eq!(received(response), (body, false));
+    }
+
+    #[test]
+    fn accept_encoding_is_parsed_per_coding() {
+        for (value, gzip) in [
+            ("gzip", true),
+            ("deflate, GZIP;q=0.5", true),
+            ("br;q=1.0, gzip ; q=0.8", true),
+            ("gzip;q=0", false),
+            ("gzip; q=0.0, deflate", false),
+            ("x-gzip, identity", false),
+            ("", false),
+        ] {
+            assert_eq!(
+                accepts_gzip(&requested_with(Some(value))),
+                gzip,
+                "{:?}",
+                value
+            );
+        }
+        assert!(!accepts_gzip(&requested_with(None)));
+    }
+}